@@ -1,34 +1,40 @@
 use aws_config::BehaviorVersion;
-use aws_sdk_dynamodb::Client as DynamoDbClient;
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use lambda_runtime::{Error, LambdaEvent, run, service_fn};
-use nanoid::nanoid;
-use serde_json::Value;
+use serde_json::{Value, json};
 use std::env;
-use tracing::{error, instrument};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use validator::Validate;
+use std::sync::Arc;
+use tracing::{error, instrument, warn};
 
-use squrl_shared::dynamodb::DynamoDbClient as UrlDynamoDbClient;
+use squrl_shared::client_ip::extract_client_ip;
+use squrl_shared::config::RuntimeConfig;
+use squrl_shared::dynamodb::{
+    ClientTuning, CreateOutcome, DedupPolicy, DynamoDbClient as UrlDynamoDbClient,
+};
 use squrl_shared::error::UrlShortenerError;
+use squrl_shared::idempotency::{IDEMPOTENCY_KEY_HEADER, IdempotencyKey};
 use squrl_shared::models::{
-    ApiGatewayProxyEvent, ApiGatewayProxyResponse, CreateUrlRequest, CreateUrlResponse,
-    ErrorResponse, UrlItem, is_api_gateway_event,
+    ApiGatewayProxyEvent, ApiGatewayProxyResponse, CreateDryRunResponse, CreateUrlRequest,
+    CreateUrlResponse, ErrorResponse, EventSource, MinimalCreateUrlResponse, UrlItem,
+    build_short_url, classify_event, expires_at_rfc3339, is_api_gateway_event, is_warmup_event,
+    prefers_minimal, request_origin, warmup_response,
 };
-use squrl_shared::validation::{validate_custom_code, validate_url};
-
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "debug".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer().json())
-        .init();
-}
+use squrl_shared::password::hash_password;
+use squrl_shared::short_code::{
+    CodeAlphabet, CodeGenerator, check_code_space, code_generator_from_env,
+};
+use squrl_shared::signing::{SIGNATURE_HEADER, SIGNATURE_TIMESTAMP_HEADER, SigningSecret};
+use squrl_shared::telemetry::{LogFormat, init_tracing, log_bodies, redact_event_for_log};
+use squrl_shared::validation::{NormalizedCreate, check_body_size, validate_create_request};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    init_tracing();
+    let runtime_config = RuntimeConfig::from_env()?;
+    let _telemetry = init_tracing(
+        "squrl-create-url",
+        &runtime_config.log_filter_or("debug"),
+        LogFormat::Json,
+    )?;
 
     tracing::info!("Starting create-url Lambda function");
     tracing::info!("Environment variables:");
@@ -44,42 +50,66 @@ async fn main() -> Result<(), Error> {
 
     let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
 
-    let dynamodb_client = if let Ok(endpoint_url) = env::var("AWS_ENDPOINT_URL") {
-        tracing::info!("Using LocalStack endpoint: {}", endpoint_url);
-        // Local development with LocalStack
-        let dynamodb_config = aws_sdk_dynamodb::config::Builder::from(&config)
-            .endpoint_url(endpoint_url)
-            .build();
-        DynamoDbClient::from_conf(dynamodb_config)
-    } else {
-        tracing::info!("Using AWS DynamoDB");
-        DynamoDbClient::new(&config)
-    };
-
-    let table_name = env::var("DYNAMODB_TABLE_NAME").unwrap_or_else(|_| "squrl-urls".to_string());
-    tracing::info!("Using DynamoDB table: {}", table_name);
+    let db_client = UrlDynamoDbClient::from_config(
+        &config,
+        runtime_config.aws_endpoint_url.as_deref(),
+        runtime_config.table_name.clone(),
+        ClientTuning::from_env(),
+    )
+    .with_dedup_policy(DedupPolicy::from_env());
 
-    let db_client = UrlDynamoDbClient::new(dynamodb_client, table_name);
+    // Fail at cold start rather than on the first request
+    let code_alphabet = CodeAlphabet::from_env()?;
+    let stored_items = db_client
+        .approximate_item_count()
+        .await
+        .inspect_err(|e| warn!("Could not read table item count: {}", e))
+        .ok();
+    check_code_space(&code_alphabet, stored_items);
+    let code_generator = code_generator_from_env(code_alphabet)?;
+    let signing_secret = SigningSecret::from_env()?;
 
     run(service_fn(move |event| {
-        function_handler(event, db_client.clone())
+        function_handler(
+            event,
+            db_client.clone(),
+            code_generator.clone(),
+            signing_secret.clone(),
+            runtime_config.clone(),
+        )
     }))
     .await
 }
 
-#[instrument(skip(db_client))]
+#[instrument(
+    skip(event, db_client, code_generator, signing_secret, runtime_config),
+    fields(request_id = %event.context.request_id)
+)]
 async fn function_handler(
     event: LambdaEvent<Value>,
     db_client: UrlDynamoDbClient,
+    code_generator: Arc<dyn CodeGenerator>,
+    signing_secret: Option<SigningSecret>,
+    runtime_config: RuntimeConfig,
 ) -> Result<Value, Error> {
-    tracing::info!(
-        "Received event: {}",
-        serde_json::to_string_pretty(&event.payload)
-            .unwrap_or_else(|_| "Unable to serialize".to_string())
-    );
+    if is_warmup_event(&event.payload) {
+        tracing::info!("Warmup ping");
+        return Ok(warmup_response());
+    }
 
-    let is_api_gateway = is_api_gateway_event(&event.payload);
-    let is_local_http = env::var("CARGO_LAMBDA_INVOKE_PORT").is_ok();
+    if log_bodies() {
+        tracing::info!(
+            "Received event: {}",
+            serde_json::to_string_pretty(&redact_event_for_log(&event.payload))
+                .unwrap_or_else(|_| "Unable to serialize".to_string())
+        );
+    }
+
+    let event_source = classify_event(&event.payload);
+    let is_api_gateway = event_source != EventSource::Direct;
+    let is_local_http = runtime_config.local_invoke;
+    let dry_run = is_dry_run(&event.payload);
+    let origin = request_origin(&event.payload);
 
     tracing::info!(
         "is_api_gateway: {}, is_local_http: {}",
@@ -87,14 +117,22 @@ async fn function_handler(
         is_local_http
     );
 
-    match handler_impl(event.payload, &db_client).await {
+    match handler_impl(
+        event.payload,
+        &db_client,
+        code_generator.as_ref(),
+        signing_secret.as_ref(),
+        &runtime_config,
+    )
+    .await
+    {
         Ok(response) => {
             tracing::info!("Handler succeeded, creating response");
             // Always return API Gateway format for local HTTP server or actual API Gateway
             if is_api_gateway || is_local_http {
-                let result = create_api_gateway_success_response(response);
+                let result = create_api_gateway_success_response(response, origin.as_deref());
                 tracing::info!("API Gateway response created");
-                Ok(result)
+                Ok(event_source.shape_response(result))
             } else {
                 tracing::info!("Direct response created");
                 Ok(response)
@@ -102,66 +140,95 @@ async fn function_handler(
         }
         Err(err) => {
             error!("Function error: {}", err);
-            let result = create_error_response(&err, is_api_gateway || is_local_http);
+            let result = create_error_response(
+                &err,
+                is_api_gateway || is_local_http,
+                origin.as_deref(),
+                dry_run,
+            );
             error!("Error response created");
-            Ok(result)
+            Ok(event_source.shape_response(result))
         }
     }
 }
 
+/// `signing_secret` is set when creates must be signed. Only API Gateway
+/// requests are checked; direct invocations are already IAM-authorized.
 async fn handler_impl(
     payload: Value,
     db_client: &UrlDynamoDbClient,
+    code_generator: &dyn CodeGenerator,
+    signing_secret: Option<&SigningSecret>,
+    runtime_config: &RuntimeConfig,
 ) -> Result<Value, UrlShortenerError> {
-    let request: CreateUrlRequest = if is_api_gateway_event(&payload) {
+    let dry_run = is_dry_run(&payload);
+    let minimal = wants_minimal(&payload);
+
+    let (request, host, source_ip, idempotency_key) = if is_api_gateway_event(&payload) {
         // Parse API Gateway event
-        let api_event: ApiGatewayProxyEvent = serde_json::from_value(payload).map_err(|e| {
-            UrlShortenerError::ValidationError(format!("Invalid API Gateway event: {}", e))
-        })?;
+        let api_event: ApiGatewayProxyEvent = ApiGatewayProxyEvent::from_payload(payload)?;
+        let host = api_event.header("Host").map(str::to_string);
+        let source_ip = extract_client_ip(&api_event, runtime_config.trust_xff);
 
         // Extract body and parse as JSON
-        let body = api_event.body.ok_or_else(|| {
+        let body = api_event.body.as_deref().ok_or_else(|| {
             UrlShortenerError::ValidationError("Missing request body".to_string())
         })?;
+        check_body_size(body, runtime_config.max_body_bytes)?;
+
+        if let Some(signing_secret) = signing_secret {
+            signing_secret.verify(
+                body,
+                api_event.header(SIGNATURE_HEADER),
+                api_event.header(SIGNATURE_TIMESTAMP_HEADER),
+                Utc::now().timestamp(),
+            )?;
+        }
 
-        serde_json::from_str(&body).map_err(|e| {
-            UrlShortenerError::ValidationError(format!("Invalid JSON in body: {}", e))
-        })?
+        let request = CreateUrlRequest::from_json(body)?;
+        let idempotency_key =
+            IdempotencyKey::from_header(api_event.header(IDEMPOTENCY_KEY_HEADER), &request)?;
+        (request, host, source_ip, idempotency_key)
     } else {
         // Direct Lambda invocation
-        serde_json::from_value(payload)
-            .map_err(|e| UrlShortenerError::ValidationError(e.to_string()))?
+        let request = CreateUrlRequest::from_value(payload)?;
+        (request, None, None, None)
     };
 
-    request
-        .validate()
-        .map_err(|e| UrlShortenerError::ValidationError(e.to_string()))?;
+    let short_url_base = runtime_config.short_url_base_for(host.as_deref())?;
 
-    let _validated_url = validate_url(&request.original_url)?;
+    let NormalizedCreate {
+        url: validated_url,
+        ttl,
+        ttl_source,
+        redirect_status,
+    } = validate_create_request(&request, &runtime_config.validation_config())?;
 
-    if let Some(custom_code) = &request.custom_code {
-        validate_custom_code(custom_code)?;
+    if dry_run {
+        return dry_run_response(&request, db_client, code_generator).await;
     }
 
-    // Check for existing URL
-    if let Some(existing_item) = db_client.find_existing_url(&request.original_url).await? {
-        return Ok(create_success_response(existing_item));
+    // A retry of a create that already went through gets the same link back
+    if let Some(idempotency_key) = &idempotency_key
+        && let Some(prior) = db_client.idempotent_result(idempotency_key).await?
+    {
+        return create_success_response(prior, &short_url_base, minimal);
     }
 
     // Generate short code
     let short_code = if let Some(ref custom_code) = request.custom_code {
         custom_code.clone()
     } else {
-        generate_short_code()
+        code_generator.generate()?
     };
 
     // Calculate expiration
     let now = Utc::now();
-    let expires_at = request
-        .ttl_hours
-        .map(|hours| (now + chrono::Duration::hours(hours as i64)).timestamp());
+    let expires_at = ttl.map(|ttl| (now + ttl).timestamp());
 
-    let url_item = UrlItem {
+    let password_hash = request.password.as_deref().map(hash_password).transpose()?;
+
+    let mut url_item = UrlItem {
         short_code: short_code.clone(),
         original_url: request.original_url.clone(),
         created_at: now.to_rfc3339(),
@@ -169,73 +236,239 @@ async fn handler_impl(
         click_count: 0,
         custom_code: request.custom_code.is_some(),
         status: "active".to_string(),
+        password_hash,
+        max_uses: request.max_uses,
+        redirect_status,
+        canonical_code: None,
+        wildcard: request.wildcard,
+        // Only ever the hash; without a hasher the IP isn't recorded at all
+        creator_ip: runtime_config
+            .creator_ip_hasher
+            .as_ref()
+            .zip(source_ip.as_deref())
+            .map(|(hasher, ip)| hasher.hash(ip)),
+        unique_visitors: None,
+        extra_headers: request.extra_headers.clone(),
+        ttl_source: Some(ttl_source),
     };
 
-    // Store in DynamoDB
-    db_client.put_url(&url_item).await?;
+    // A generated code may already be taken; draw a fresh one and try again
+    let mut retries_left = runtime_config.code_collision_retries;
+    let stored = loop {
+        // Store in DynamoDB. Dedup-eligible links reuse a live link for the
+        // URL, or claim it so concurrent creates of one URL can't mint two
+        // codes. Links with access rules (password, use limit) are never
+        // shared through dedup, in either direction. A create with an
+        // idempotency key reserves the key instead, and isn't deduplicated.
+        let stored = if let Some(idempotency_key) = &idempotency_key {
+            db_client
+                .create_with_idempotency(&url_item, idempotency_key)
+                .await
+        } else if request.allows_dedup() {
+            db_client
+                .get_or_create(validated_url.as_str(), url_item.clone())
+                .await
+                .map(CreateOutcome::from_get_or_create)
+        } else {
+            db_client
+                .put_url(&url_item)
+                .await
+                .map(|_| CreateOutcome::Created)
+        };
+
+        match stored {
+            Err(e) if retries_left > 0 && e.is_collision_retryable(url_item.custom_code) => {
+                warn!(
+                    "Generated code {} is taken, drawing another",
+                    url_item.short_code
+                );
+                retries_left -= 1;
+                url_item.short_code = code_generator.generate()?;
+            }
+            stored => break stored,
+        }
+    };
 
-    Ok(create_success_response(url_item))
+    match stored {
+        Ok(CreateOutcome::Created) => create_success_response(url_item, &short_url_base, minimal),
+        // Lost a race with a concurrent create of the same URL
+        Ok(CreateOutcome::Existing(existing_item)) => {
+            create_success_response(*existing_item, &short_url_base, minimal)
+        }
+        Err(UrlShortenerError::ShortCodeExists(code)) if url_item.custom_code => {
+            Err(custom_code_taken(db_client, code).await)
+        }
+        Err(e) => Err(e),
+    }
 }
 
-fn generate_short_code() -> String {
-    // Use nanoid for collision-resistant ID generation
-    let id = nanoid!(8, &nanoid::alphabet::SAFE);
-    id
+/// Whether the create only validates, via `?dry_run=1` (or `true`) or a
+/// `"dry_run": true` body field
+fn is_dry_run(payload: &Value) -> bool {
+    let in_query = payload
+        .get("queryStringParameters")
+        .and_then(|params| params.get("dry_run"))
+        .and_then(Value::as_str)
+        .is_some_and(|value| matches!(value, "1" | "true"));
+    let in_body = |body: &Value| body.get("dry_run").and_then(Value::as_bool) == Some(true);
+
+    in_query
+        || in_body(payload)
+        || payload
+            .get("body")
+            .and_then(Value::as_str)
+            .and_then(|body| serde_json::from_str::<Value>(body).ok())
+            .is_some_and(|body| in_body(&body))
 }
 
-fn create_success_response(url_item: UrlItem) -> Value {
-    let base_url = env::var("SHORT_URL_BASE").unwrap_or_else(|_| "https://sqrl.co".to_string());
-    let short_url = format!("{}/{}", base_url, url_item.short_code);
-    let expires_at = url_item.expires_at.map(|ts| {
-        DateTime::from_timestamp(ts, 0)
-            .unwrap_or_else(Utc::now)
-            .to_rfc3339()
-    });
+/// `Prefer: return=minimal` or `?fields=short_code` on an HTTP create
+fn wants_minimal(payload: &Value) -> bool {
+    let prefer = payload
+        .get("headers")
+        .and_then(Value::as_object)
+        .and_then(|headers| {
+            headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("prefer"))
+        })
+        .and_then(|(_, value)| value.as_str());
+    let fields = payload
+        .get("queryStringParameters")
+        .and_then(|params| params.get("fields"))
+        .and_then(Value::as_str);
+
+    prefers_minimal(prefer, fields)
+}
+
+/// The validated request's outcome without writing anything: the code it
+/// would get and whether that code is free. A taken custom code is the usual
+/// 409.
+async fn dry_run_response(
+    request: &CreateUrlRequest,
+    db_client: &UrlDynamoDbClient,
+    code_generator: &dyn CodeGenerator,
+) -> Result<Value, UrlShortenerError> {
+    let short_code = match &request.custom_code {
+        Some(custom_code) => custom_code.clone(),
+        None => code_generator.generate()?,
+    };
+    let available = !db_client.exists(&short_code).await?;
+
+    if !available && request.custom_code.is_some() {
+        return Err(custom_code_taken(db_client, short_code).await);
+    }
 
+    Ok(serde_json::to_value(CreateDryRunResponse {
+        dry_run: true,
+        valid: true,
+        available,
+        short_code_preview: short_code,
+    })?)
+}
+
+// Turn a custom-code conflict into a 409 that offers free alternatives
+async fn custom_code_taken(db_client: &UrlDynamoDbClient, code: String) -> UrlShortenerError {
+    let suggestions = db_client
+        .suggest_available_codes(&code, 3)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to look up custom code suggestions: {}", e);
+            Vec::new()
+        });
+
+    UrlShortenerError::CustomCodeTaken { code, suggestions }
+}
+
+/// `minimal` trims the body to `MinimalCreateUrlResponse`
+fn create_success_response(
+    url_item: UrlItem,
+    short_url_base: &str,
+    minimal: bool,
+) -> Result<Value, UrlShortenerError> {
+    let short_url = build_short_url(short_url_base, &url_item.short_code)?;
+    let expires_at = expires_at_rfc3339(url_item.expires_at);
+
+    let ttl_source = url_item.ttl_source();
     let response = CreateUrlResponse {
         short_code: url_item.short_code,
         original_url: url_item.original_url,
         short_url,
         created_at: url_item.created_at,
         expires_at,
+        ttl_source,
     };
 
-    serde_json::to_value(response).unwrap()
+    if minimal {
+        return Ok(serde_json::to_value(MinimalCreateUrlResponse::from(
+            response,
+        ))?);
+    }
+    Ok(serde_json::to_value(response)?)
 }
 
-fn create_api_gateway_success_response(response_data: Value) -> Value {
-    let api_response =
+fn create_api_gateway_success_response(response_data: Value, origin: Option<&str>) -> Value {
+    let mut api_response =
         ApiGatewayProxyResponse::new(200, serde_json::to_string(&response_data).unwrap());
+    if let Some(origin) = origin {
+        api_response = api_response.with_cors_origin(origin);
+    }
 
     serde_json::to_value(api_response).unwrap()
 }
 
-fn create_error_response(err: &UrlShortenerError, is_api_gateway: bool) -> Value {
-    let error_response = ErrorResponse {
+/// `dry_run` marks the body so callers can tell a rejected dry run from a
+/// rejected create
+fn create_error_response(
+    err: &UrlShortenerError,
+    is_api_gateway: bool,
+    origin: Option<&str>,
+    dry_run: bool,
+) -> Value {
+    let mut error_response = serde_json::to_value(ErrorResponse {
         error: err.error_type().to_string(),
         message: err.to_string(),
-        details: None,
-    };
+        details: err.details(),
+    })
+    .unwrap();
+    if dry_run {
+        error_response["dry_run"] = json!(true);
+    }
 
     if is_api_gateway {
-        let api_response = ApiGatewayProxyResponse::new(
-            err.status_code(),
-            serde_json::to_string(&error_response).unwrap(),
-        );
+        let mut api_response =
+            ApiGatewayProxyResponse::new(err.status_code(), error_response.to_string());
+        for (name, value) in err.headers() {
+            api_response = api_response.with_header(name, &value);
+        }
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
         serde_json::to_value(api_response).unwrap()
     } else {
-        serde_json::to_value(error_response).unwrap()
+        error_response
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use aws_sdk_dynamodb::Client as DynamoDbClient;
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::operation::put_item::PutItemOutput;
+    use aws_sdk_dynamodb::operation::query::QueryOutput;
+    use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsOutput;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use aws_smithy_mocks::{RuleMode, mock, mock_client};
+    use lambda_runtime::Context;
+    use squrl_shared::config::DEFAULT_TTL_HOURS_ENV;
+    use squrl_shared::creator_ip::CreatorIpHasher;
+    use squrl_shared::dynamodb::IDEMPOTENCY_KEY_PREFIX;
+    use squrl_shared::short_code::NanoidGenerator;
+    use std::collections::HashMap;
 
     #[test]
     fn test_generate_short_code() {
-        let code = generate_short_code();
+        let code = NanoidGenerator::default().generate().unwrap();
         assert_eq!(code.len(), 8);
         assert!(
             code.chars()
@@ -243,6 +476,14 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_generate_short_code_alphanumeric() {
+        let alphabet = CodeAlphabet::parse("alphanumeric").unwrap();
+        let code = NanoidGenerator::new(alphabet).generate().unwrap();
+        assert_eq!(code.len(), 8);
+        assert!(code.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
     #[test]
     fn test_api_gateway_event_detection() {
         // Test API Gateway event
@@ -265,6 +506,309 @@ mod tests {
         assert!(!is_api_gateway_event(&direct_event));
     }
 
+    #[tokio::test]
+    async fn test_warmup_ping_skips_dynamodb() {
+        // No rules: any DynamoDB call would fail the test
+        let db_client =
+            UrlDynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "test-table".to_string());
+
+        let response = function_handler(
+            LambdaEvent::new(json!({"warmup": true}), Context::default()),
+            db_client,
+            Arc::new(NanoidGenerator::default()),
+            None,
+            RuntimeConfig::from_lookup(|_| None).unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response, warmup_response());
+    }
+
+    #[tokio::test]
+    async fn test_creator_ip_stored_hashed() {
+        let hasher = CreatorIpHasher::new("pepper");
+        let expected = hasher.hash("203.0.113.7");
+        let put_rule = mock!(DynamoDbClient::put_item)
+            .match_requests(move |req| {
+                let item = req.item().unwrap();
+                item.get("creator_ip") == Some(&AttributeValue::S(expected.clone()))
+                    && !item
+                        .values()
+                        .any(|value| value.as_s().is_ok_and(|s| s.contains("203.0.113.7")))
+            })
+            .then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let runtime_config = RuntimeConfig {
+            creator_ip_hasher: Some(hasher),
+            ..RuntimeConfig::from_lookup(|_| None).unwrap()
+        };
+
+        // A use limit skips dedup, so the create is a single put
+        let payload = json!({
+            "httpMethod": "POST",
+            "requestContext": {"identity": {"sourceIp": "203.0.113.7"}},
+            "body": "{\"original_url\": \"https://example.com\", \"max_uses\": 1}"
+        });
+        handler_impl(
+            payload,
+            &db_client,
+            &NanoidGenerator::default(),
+            None,
+            &runtime_config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(put_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dedup_create_goes_through_get_or_create() {
+        let existing = QueryOutput::builder()
+            .items(HashMap::from([
+                (
+                    "short_code".to_string(),
+                    AttributeValue::S("old123".to_string()),
+                ),
+                (
+                    "original_url".to_string(),
+                    AttributeValue::S("https://example.com".to_string()),
+                ),
+                (
+                    "created_at".to_string(),
+                    AttributeValue::S(Utc::now().to_rfc3339()),
+                ),
+            ]))
+            .build();
+        let query_rule = mock!(DynamoDbClient::query)
+            .sequence()
+            .output(|| QueryOutput::builder().build())
+            .output(move || existing.clone())
+            .build();
+        let transact_rule = mock!(DynamoDbClient::transact_write_items)
+            .then_output(|| TransactWriteItemsOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&query_rule, &transact_rule]
+        );
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let code_generator = NanoidGenerator::default();
+        let runtime_config = RuntimeConfig::from_lookup(|_| None).unwrap();
+        let create = || {
+            handler_impl(
+                json!({"original_url": "https://example.com"}),
+                &db_client,
+                &code_generator,
+                None,
+                &runtime_config,
+            )
+        };
+
+        // A new URL is claimed together with its dedup marker
+        let created = create().await.unwrap();
+        assert_ne!(created["short_code"], "old123");
+        assert_eq!(transact_rule.num_calls(), 1);
+
+        // A live link for the URL is handed back without a write
+        let reused = create().await.unwrap();
+        assert_eq!(reused["short_code"], "old123");
+        assert_eq!(transact_rule.num_calls(), 1);
+        assert_eq!(query_rule.num_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_first_create() {
+        let request =
+            CreateUrlRequest::from_json(r#"{"original_url": "https://example.com"}"#).unwrap();
+        let fingerprint = request.fingerprint();
+        let key_rule = mock!(DynamoDbClient::get_item)
+            .match_requests(|req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| code.starts_with(IDEMPOTENCY_KEY_PREFIX))
+            })
+            .sequence()
+            .output(|| GetItemOutput::builder().build())
+            .output(move || {
+                GetItemOutput::builder()
+                    .item("target_code", AttributeValue::S("first1".to_string()))
+                    .item("fingerprint", AttributeValue::S(fingerprint.clone()))
+                    .build()
+            })
+            .build();
+        let prior_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("short_code", AttributeValue::S("first1".to_string()))
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("created_at", AttributeValue::S(Utc::now().to_rfc3339()))
+                .build()
+        });
+        let transact_rule = mock!(DynamoDbClient::transact_write_items)
+            .then_output(|| TransactWriteItemsOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&key_rule, &prior_rule, &transact_rule]
+        );
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let code_generator = NanoidGenerator::default();
+        let runtime_config = RuntimeConfig::from_lookup(|_| None).unwrap();
+        let create = || {
+            handler_impl(
+                json!({
+                    "httpMethod": "POST",
+                    "headers": {"Idempotency-Key": "retry-me"},
+                    "body": "{\"original_url\": \"https://example.com\"}"
+                }),
+                &db_client,
+                &code_generator,
+                None,
+                &runtime_config,
+            )
+        };
+
+        // The first create reserves the key with its link
+        create().await.unwrap();
+        assert_eq!(transact_rule.num_calls(), 1);
+
+        // The retry reads the first link back instead of writing again
+        let replayed = create().await.unwrap();
+        assert_eq!(replayed["short_code"], "first1");
+        assert_eq!(transact_rule.num_calls(), 1);
+    }
+
+    /// The body a use-limited (so dedup-free) create with `extra` event
+    /// fields gets back
+    async fn created_body(extra: Value) -> Value {
+        let put_rule =
+            mock!(DynamoDbClient::put_item).then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let mut payload = json!({
+            "httpMethod": "POST",
+            "body": "{\"original_url\": \"https://example.com\", \"max_uses\": 1}"
+        });
+        payload
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+
+        handler_impl(
+            payload,
+            &db_client,
+            &NanoidGenerator::default(),
+            None,
+            &RuntimeConfig::from_lookup(|_| None).unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_minimal_create_response() {
+        let full = created_body(json!({})).await;
+        assert!(full["created_at"].is_string());
+        assert_eq!(full["original_url"], "https://example.com");
+
+        for extra in [
+            json!({"headers": {"Prefer": "return=minimal"}}),
+            json!({"queryStringParameters": {"fields": "short_code"}}),
+        ] {
+            let minimal = created_body(extra).await;
+            let fields: Vec<_> = minimal.as_object().unwrap().keys().cloned().collect();
+            assert_eq!(fields, ["short_code", "short_url"]);
+            assert!(
+                minimal["short_url"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with(minimal["short_code"].as_str().unwrap())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_body_size_limit() {
+        let put_rule = mock!(DynamoDbClient::put_item)
+            .sequence()
+            .output(|| PutItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&put_rule]);
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let body = r#"{"original_url": "https://example.com/page", "max_uses": 1}"#;
+        let create = |max_body_bytes: usize| {
+            let runtime_config = RuntimeConfig {
+                max_body_bytes,
+                ..RuntimeConfig::from_lookup(|_| None).unwrap()
+            };
+            let db_client = &db_client;
+            async move {
+                handler_impl(
+                    json!({"httpMethod": "POST", "body": body}),
+                    db_client,
+                    &NanoidGenerator::default(),
+                    None,
+                    &runtime_config,
+                )
+                .await
+            }
+        };
+
+        // Exactly at the limit is fine
+        assert!(create(body.len()).await.is_ok());
+
+        let err = create(body.len() - 1).await.unwrap_err();
+        assert!(matches!(err, UrlShortenerError::PayloadTooLarge(_)));
+        assert_eq!(put_rule.num_calls(), 1);
+
+        let response = create_error_response(&err, true, None, false);
+        assert_eq!(response["statusCode"], 413);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_source_reported() {
+        let put_rule = mock!(DynamoDbClient::put_item)
+            .sequence()
+            .output(|| PutItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&put_rule]);
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let with_default =
+            RuntimeConfig::from_lookup(|key| (key == DEFAULT_TTL_HOURS_ENV).then(|| "24".into()))
+                .unwrap();
+        let without_default = RuntimeConfig::from_lookup(|_| None).unwrap();
+
+        for (ttl, runtime_config, expected) in [
+            (json!(2), &with_default, "user"),
+            (Value::Null, &with_default, "default"),
+            (Value::Null, &without_default, "none"),
+        ] {
+            let body = json!({
+                "original_url": "https://example.com",
+                "max_uses": 1,
+                "ttl_hours": ttl,
+            });
+            let response = handler_impl(
+                json!({"httpMethod": "POST", "body": body.to_string()}),
+                &db_client,
+                &NanoidGenerator::default(),
+                None,
+                runtime_config,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(response["ttl_source"], expected);
+            assert_eq!(response["expires_at"].is_string(), expected != "none");
+        }
+    }
+
     #[test]
     fn test_api_gateway_response_format() {
         let response_data = json!({
@@ -274,13 +818,215 @@ mod tests {
             "created_at": "2025-08-24T10:30:00Z"
         });
 
-        let api_response = create_api_gateway_success_response(response_data);
+        let api_response = create_api_gateway_success_response(response_data, None);
 
         assert_eq!(api_response["statusCode"], 200);
         assert!(api_response["headers"].is_object());
         assert!(api_response["body"].is_string());
         assert_eq!(api_response["isBase64Encoded"], false);
     }
+
+    #[test]
+    fn test_is_dry_run() {
+        assert!(is_dry_run(&json!({
+            "httpMethod": "POST",
+            "queryStringParameters": {"dry_run": "1"},
+            "body": "{}"
+        })));
+        assert!(is_dry_run(&json!({
+            "httpMethod": "POST",
+            "body": "{\"original_url\": \"https://example.com\", \"dry_run\": true}"
+        })));
+        assert!(is_dry_run(
+            &json!({"original_url": "https://example.com", "dry_run": true})
+        ));
+
+        assert!(!is_dry_run(&json!({
+            "httpMethod": "POST",
+            "queryStringParameters": {"dry_run": "0"},
+            "body": "{}"
+        })));
+        assert!(!is_dry_run(&json!({"original_url": "https://example.com"})));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_valid_does_not_write() {
+        let get_rule =
+            mock!(DynamoDbClient::get_item).then_output(|| GetItemOutput::builder().build());
+        let put_rule =
+            mock!(DynamoDbClient::put_item).then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule, &put_rule]);
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let runtime_config = RuntimeConfig::from_lookup(|_| None).unwrap();
+
+        let payload = json!({
+            "original_url": "https://example.com",
+            "custom_code": "promo",
+            "dry_run": true
+        });
+        let response = handler_impl(
+            payload,
+            &db_client,
+            &NanoidGenerator::default(),
+            None,
+            &runtime_config,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response["dry_run"], true);
+        assert_eq!(response["valid"], true);
+        assert_eq!(response["available"], true);
+        assert_eq!(response["short_code_preview"], "promo");
+        assert_eq!(put_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_conflicting_custom_code() {
+        let taken_rule = mock!(DynamoDbClient::get_item)
+            .match_requests(|req| {
+                req.key().and_then(|key| key.get("short_code"))
+                    == Some(&AttributeValue::S("promo".to_string()))
+            })
+            .then_output(|| {
+                GetItemOutput::builder()
+                    .item("short_code", AttributeValue::S("promo".to_string()))
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://other.com".to_string()),
+                    )
+                    .item(
+                        "created_at",
+                        AttributeValue::S("2025-08-24T10:30:00Z".to_string()),
+                    )
+                    .build()
+            });
+        // Suggestion lookups all come back free
+        let free_rule = mock!(DynamoDbClient::get_item)
+            .sequence()
+            .output(|| GetItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let put_rule =
+            mock!(DynamoDbClient::put_item).then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&taken_rule, &free_rule, &put_rule]
+        );
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let runtime_config = RuntimeConfig::from_lookup(|_| None).unwrap();
+
+        let payload = json!({
+            "httpMethod": "POST",
+            "queryStringParameters": {"dry_run": "1"},
+            "body": "{\"original_url\": \"https://example.com\", \"custom_code\": \"promo\"}"
+        });
+        let err = handler_impl(
+            payload,
+            &db_client,
+            &NanoidGenerator::default(),
+            None,
+            &runtime_config,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::CustomCodeTaken { .. }));
+        assert_eq!(put_rule.num_calls(), 0);
+
+        let response = create_error_response(&err, true, None, true);
+        assert_eq!(response["statusCode"], 409);
+        let body: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        assert_eq!(body["dry_run"], true);
+        assert_eq!(body["error"], err.error_type());
+    }
+
+    #[test]
+    fn test_rate_limited_response_carries_reset_info() {
+        let err = UrlShortenerError::RateLimitExceeded {
+            limit: 10,
+            reset_seconds: 30,
+        };
+
+        let response = create_error_response(&err, true, None, false);
+        assert_eq!(response["statusCode"], 429);
+        assert_eq!(response["headers"]["X-RateLimit-Limit"], "10");
+        assert_eq!(response["headers"]["X-RateLimit-Remaining"], "0");
+        assert_eq!(response["headers"]["X-RateLimit-Reset"], "30");
+        let body: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        assert_eq!(body["error"], "RateLimitExceeded");
+        assert_eq!(
+            body["details"],
+            json!({"limit": 10, "remaining": 0, "reset_seconds": 30})
+        );
+    }
+
+    /// The error body a create with `payload` gets, parsed from its response
+    async fn rejected_body(payload: Value, is_api_gateway: bool) -> (Value, Value) {
+        let client = mock_client!(aws_sdk_dynamodb, []);
+        let db_client = UrlDynamoDbClient::new(client, "test-table".to_string());
+        let runtime_config = RuntimeConfig::from_lookup(|_| None).unwrap();
+
+        let err = handler_impl(
+            payload,
+            &db_client,
+            &NanoidGenerator::default(),
+            None,
+            &runtime_config,
+        )
+        .await
+        .unwrap_err();
+        let response = create_error_response(&err, is_api_gateway, None, false);
+        if !is_api_gateway {
+            return (Value::Null, response);
+        }
+        let body = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        (response["statusCode"].clone(), body)
+    }
+
+    #[tokio::test]
+    async fn test_unparseable_body_is_invalid_request() {
+        let (status, body) = rejected_body(
+            json!({"httpMethod": "POST", "body": "original_url=https://example.com"}),
+            true,
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "InvalidRequest");
+        assert!(
+            body["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("Invalid request: Invalid JSON in body")
+        );
+
+        // Valid JSON of the wrong shape is rejected the same way
+        let (status, body) = rejected_body(
+            json!({"httpMethod": "POST", "body": "{\"original_url\": 42}"}),
+            true,
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "InvalidRequest");
+        assert!(body["message"].as_str().unwrap().contains("invalid type"));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_event_is_invalid_request() {
+        // An API Gateway event whose fields have the wrong types
+        let (status, body) = rejected_body(json!({"httpMethod": "POST", "headers": 7}), true).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"], "InvalidRequest");
+
+        let (_, body) = rejected_body(json!(["not", "a", "request"]), false).await;
+        assert_eq!(body["error"], "InvalidRequest");
+        assert!(
+            body["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("Invalid request: Invalid create request")
+        );
+    }
 }
 
 // Handler removed - using only Lambda runtime handler
@@ -1,62 +1,47 @@
-use aws_config::BehaviorVersion;
-use aws_sdk_dynamodb::Client as DynamoDbClient;
 use lambda_runtime::{Error, LambdaEvent, run, service_fn};
-use serde::Serialize;
 use serde_json::{Value, json};
 use std::env;
+use std::sync::Arc;
 use tracing::{error, info, instrument};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use squrl_shared::analytics::AnalyticsStore;
+use squrl_shared::config::RuntimeConfig;
 use squrl_shared::dynamodb::DynamoDbClient as UrlDynamoDbClient;
 use squrl_shared::error::UrlShortenerError;
 use squrl_shared::models::{
-    ApiGatewayProxyEvent, ApiGatewayProxyResponse, ErrorResponse, is_api_gateway_event,
+    ApiGatewayProxyEvent, ApiGatewayProxyResponse, ErrorResponse, EventSource, PreviewResponse,
+    ReferrerCount, StatsResponse, UrlItem, classify_event, is_api_gateway_event, is_warmup_event,
+    request_origin, warmup_response,
 };
+use squrl_shared::password::{LINK_PASSWORD_HEADER, verify_link_password};
+use squrl_shared::telemetry::{LogFormat, init_tracing};
+
+/// Referrer hosts listed by `?breakdown=referrer`
+const TOP_REFERRERS_LIMIT: usize = 10;
 
 #[derive(Clone)]
 struct AppState {
     db_client: UrlDynamoDbClient,
-}
-
-#[derive(Debug, Serialize)]
-struct StatsResponse {
-    short_code: String,
-    original_url: String,
-    click_count: u64,
-    created_at: String,
-    expires_at: Option<i64>,
-}
-
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer().json())
-        .init();
+    /// Per-click event storage, needed for breakdowns. No deployment has
+    /// one yet, so `?breakdown=` is ignored until it lands.
+    analytics: Option<Arc<dyn AnalyticsStore>>,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    init_tracing();
-
-    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    let runtime_config = RuntimeConfig::from_env()?;
+    let _telemetry = init_tracing(
+        "squrl-get-stats",
+        &runtime_config.log_filter_or("info"),
+        LogFormat::Json,
+    )?;
 
-    let dynamodb_client = if let Ok(endpoint_url) = env::var("AWS_ENDPOINT_URL") {
-        // Local development with LocalStack
-        let dynamodb_config = aws_sdk_dynamodb::config::Builder::from(&config)
-            .endpoint_url(endpoint_url)
-            .build();
-        DynamoDbClient::from_conf(dynamodb_config)
-    } else {
-        DynamoDbClient::new(&config)
+    let db_client = UrlDynamoDbClient::from_runtime_config(&runtime_config).await;
+    let app_state = AppState {
+        db_client,
+        analytics: None,
     };
 
-    let table_name = env::var("DYNAMODB_TABLE_NAME").unwrap_or_else(|_| "squrl-urls".to_string());
-
-    let db_client = UrlDynamoDbClient::new(dynamodb_client, table_name);
-    let app_state = AppState { db_client };
-
     run(service_fn(move |event| {
         function_handler(event, app_state.clone())
     }))
@@ -65,54 +50,94 @@ async fn main() -> Result<(), Error> {
 
 #[instrument(skip(app_state), fields(request_id = %event.context.request_id))]
 async fn function_handler(event: LambdaEvent<Value>, app_state: AppState) -> Result<Value, Error> {
+    if is_warmup_event(&event.payload) {
+        info!("Warmup ping");
+        return Ok(warmup_response());
+    }
+
     info!("Handling stats request");
 
-    let is_api_gateway = is_api_gateway_event(&event.payload);
+    let event_source = classify_event(&event.payload);
+    let is_api_gateway = event_source != EventSource::Direct;
     let is_local_http = env::var("CARGO_LAMBDA_INVOKE_PORT").is_ok();
+    let origin = request_origin(&event.payload);
 
     match handler_impl(event.payload, &app_state).await {
         Ok(response) => {
             // Always return API Gateway format for local HTTP server or actual API Gateway
             if is_api_gateway || is_local_http {
-                Ok(create_api_gateway_stats_response(response))
+                Ok(
+                    event_source.shape_response(create_api_gateway_stats_response(
+                        response,
+                        origin.as_deref(),
+                    )),
+                )
             } else {
                 Ok(response)
             }
         }
         Err(err) => {
             error!("Function error: {}", err);
-            Ok(create_error_response(&err, is_api_gateway || is_local_http))
+            Ok(event_source.shape_response(create_error_response(
+                &err,
+                is_api_gateway || is_local_http,
+                origin.as_deref(),
+            )))
         }
     }
 }
 
 async fn handler_impl(payload: Value, app_state: &AppState) -> Result<Value, UrlShortenerError> {
-    let short_code = if is_api_gateway_event(&payload) {
+    let (short_code, preview, password, breakdown) = if is_api_gateway_event(&payload) {
         // Parse API Gateway event
-        let api_event: ApiGatewayProxyEvent = serde_json::from_value(payload).map_err(|e| {
-            UrlShortenerError::ValidationError(format!("Invalid API Gateway event: {}", e))
-        })?;
+        let api_event: ApiGatewayProxyEvent = ApiGatewayProxyEvent::from_payload(payload)?;
 
-        // Extract short_code from path parameters
-        api_event
-            .path_parameters
-            .as_ref()
-            .and_then(|params| params.get("short_code"))
+        // From path parameters, or the path itself for ALB events
+        let (short_code, _) = api_event
+            .short_code_and_suffix(&["/stats/", "/preview/"])
             .ok_or_else(|| {
                 UrlShortenerError::ValidationError(
                     "Missing short_code in path parameters".to_string(),
                 )
-            })?
-            .clone()
+            })?;
+
+        // /preview/{short_code} is served by this function as well
+        let preview = api_event
+            .path
+            .as_deref()
+            .is_some_and(|path| path.contains("/preview/"));
+        let password = api_event.header(LINK_PASSWORD_HEADER).map(String::from);
+        let breakdown = api_event
+            .query_string_parameters
+            .as_ref()
+            .and_then(|params| params.get("breakdown"))
+            .cloned();
+
+        (short_code, preview, password, breakdown)
     } else {
         // Direct Lambda invocation - expect short_code in payload
-        payload
+        let short_code = payload
             .get("short_code")
             .and_then(|v| v.as_str())
             .ok_or_else(|| {
                 UrlShortenerError::ValidationError("Missing short_code in payload".to_string())
             })?
-            .to_string()
+            .to_string();
+
+        let preview = payload
+            .get("preview")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let password = payload
+            .get("password")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        let breakdown = payload
+            .get("breakdown")
+            .and_then(|v| v.as_str())
+            .map(String::from);
+
+        (short_code, preview, password, breakdown)
     };
 
     info!("Fetching stats for short_code: {}", short_code);
@@ -126,46 +151,421 @@ async fn handler_impl(payload: Value, app_state: &AppState) -> Result<Value, Url
 
     info!("Found URL item for short_code: {}", short_code);
 
-    let stats_response = StatsResponse {
-        short_code: url_item.short_code.clone(),
-        original_url: url_item.original_url.clone(),
-        click_count: url_item.click_count,
-        created_at: url_item.created_at.clone(),
-        expires_at: url_item.expires_at,
-    };
+    if preview {
+        return Ok(serde_json::to_value(build_preview_response(
+            url_item,
+            password.as_deref(),
+        )?)?);
+    }
+
+    // Stats carry the destination, so protected links need the password here
+    // as well as on preview
+    verify_link_password(url_item.password_hash.as_deref(), password.as_deref())?;
 
-    Ok(serde_json::to_value(stats_response)?)
+    let mut stats = StatsResponse::from(url_item);
+    // Breakdowns stay off until analytics storage is configured; without it
+    // the parameter is ignored rather than refused
+    if let Some(analytics) = &app_state.analytics {
+        match breakdown.as_deref() {
+            None => {}
+            Some("referrer") => {
+                let ranked = analytics
+                    .top_referrers(&short_code, TOP_REFERRERS_LIMIT)
+                    .await?;
+                stats.top_referrers = Some(
+                    ranked
+                        .into_iter()
+                        .map(|(host, clicks)| ReferrerCount { host, clicks })
+                        .collect(),
+                );
+            }
+            Some(other) => {
+                return Err(UrlShortenerError::ValidationError(format!(
+                    "Unknown breakdown '{}'; supported: referrer",
+                    other
+                )));
+            }
+        }
+    }
+
+    Ok(serde_json::to_value(stats)?)
 }
 
-fn create_api_gateway_stats_response(response_data: Value) -> Value {
-    let api_response = ApiGatewayProxyResponse::new(200, response_data.to_string());
+// Preview never touches the click count, but it must not reveal more than a
+// redirect would: protected links need the password, used-up links are gone
+fn build_preview_response(
+    url_item: UrlItem,
+    password: Option<&str>,
+) -> Result<PreviewResponse, UrlShortenerError> {
+    verify_link_password(url_item.password_hash.as_deref(), password)?;
+
+    if url_item.is_exhausted() {
+        return Err(UrlShortenerError::UrlExhausted);
+    }
+
+    Ok(PreviewResponse::from(url_item))
+}
+
+fn create_api_gateway_stats_response(response_data: Value, origin: Option<&str>) -> Value {
+    let mut api_response = ApiGatewayProxyResponse::new(200, response_data.to_string());
+    if let Some(origin) = origin {
+        api_response = api_response.with_cors_origin(origin);
+    }
     serde_json::to_value(api_response).unwrap_or_else(|_| json!({"statusCode": 500, "body": "{}"}))
 }
 
-fn create_error_response(error: &UrlShortenerError, is_api_gateway: bool) -> Value {
-    let (status_code, error_message) = match error {
-        UrlShortenerError::ShortCodeNotFound(code) => {
-            (404, format!("URL not found for short code: {}", code))
-        }
-        UrlShortenerError::ValidationError(msg) => (400, msg.clone()),
-        _ => (500, "Internal server error".to_string()),
-    };
+fn create_error_response(
+    err: &UrlShortenerError,
+    is_api_gateway: bool,
+    origin: Option<&str>,
+) -> Value {
+    let error_response = serde_json::to_value(ErrorResponse {
+        error: err.error_type().to_string(),
+        message: err.to_string(),
+        details: err.details(),
+    })
+    .unwrap();
 
     if is_api_gateway {
-        let error_response = ErrorResponse {
-            error: "error".to_string(),
-            message: error_message,
-            details: None,
-        };
-        let error_json =
-            serde_json::to_string(&error_response).unwrap_or_else(|_| "{}".to_string());
-        let api_response = ApiGatewayProxyResponse::new(status_code, error_json);
-        serde_json::to_value(api_response)
-            .unwrap_or_else(|_| json!({"statusCode": 500, "body": "{}"}))
+        let mut api_response =
+            ApiGatewayProxyResponse::new(err.status_code(), error_response.to_string());
+        for (name, value) in err.headers() {
+            api_response = api_response.with_header(name, &value);
+        }
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
+        serde_json::to_value(api_response).unwrap()
     } else {
-        json!({
-            "error": "error",
-            "message": error_message
-        })
+        error_response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::Client as DynamoDbClient;
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use aws_smithy_mocks::{mock, mock_client};
+    use chrono::DateTime;
+    use futures::future::BoxFuture;
+    use lambda_runtime::Context;
+    use squrl_shared::password::hash_password;
+
+    fn url_item(expires_at: Option<i64>) -> UrlItem {
+        UrlItem {
+            short_code: "abc123".to_string(),
+            original_url: "https://example.com".to_string(),
+            created_at: "2023-11-01T00:00:00+00:00".to_string(),
+            expires_at,
+            click_count: 7,
+            custom_code: false,
+            status: "active".to_string(),
+            password_hash: None,
+            max_uses: None,
+            redirect_status: None,
+            canonical_code: None,
+            wildcard: false,
+            creator_ip: None,
+            unique_visitors: None,
+            extra_headers: None,
+            ttl_source: None,
+        }
+    }
+
+    #[test]
+    fn test_stats_expires_at_is_rfc3339() {
+        let response =
+            serde_json::to_value(StatsResponse::from(url_item(Some(1_700_000_000)))).unwrap();
+
+        assert_eq!(response["expires_at"], "2023-11-14T22:13:20+00:00");
+        assert!(DateTime::parse_from_rfc3339(response["expires_at"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_stats_never_expiring_link() {
+        let response = serde_json::to_value(StatsResponse::from(url_item(None))).unwrap();
+
+        assert!(response["expires_at"].is_null());
+        assert!(response.get("unique_visitors").is_none());
+    }
+
+    #[test]
+    fn test_stats_include_unique_visitors_when_tracked() {
+        let mut item = url_item(None);
+        item.unique_visitors = Some(3);
+        let response = serde_json::to_value(StatsResponse::from(item)).unwrap();
+
+        assert_eq!(response["unique_visitors"], 3);
+    }
+
+    #[test]
+    fn test_error_response_uses_the_error_status() {
+        let response = create_error_response(
+            &UrlShortenerError::DatabaseTimeout("timed out".to_string()),
+            true,
+            None,
+        );
+        assert_eq!(response["statusCode"], 503);
+
+        let response = create_error_response(&UrlShortenerError::PasswordRequired, true, None);
+        assert_eq!(response["statusCode"], 401);
+        let body: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            body["error"],
+            UrlShortenerError::PasswordRequired.error_type()
+        );
+        assert_eq!(body["details"]["requires_password"], true);
+
+        let direct = create_error_response(
+            &UrlShortenerError::ShortCodeNotFound("abc123".to_string()),
+            false,
+            None,
+        );
+        assert_eq!(direct["error"], "NotFound");
+    }
+
+    #[tokio::test]
+    async fn test_warmup_ping_skips_dynamodb() {
+        // No rules: any DynamoDB call would fail the test
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, []),
+                "test-table".to_string(),
+            ),
+            analytics: None,
+        };
+
+        let response = function_handler(
+            LambdaEvent::new(
+                json!({"source": "serverless-plugin-warmup"}),
+                Context::default(),
+            ),
+            app_state,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response, warmup_response());
+    }
+
+    #[tokio::test]
+    async fn test_preview_does_not_change_click_count() {
+        let get_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("short_code", AttributeValue::S("abc123".to_string()))
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item(
+                    "created_at",
+                    AttributeValue::S("2023-11-01T00:00:00+00:00".to_string()),
+                )
+                .item("click_count", AttributeValue::N("5".to_string()))
+                .build()
+        });
+        let update_rule = mock!(DynamoDbClient::update_item).then_output(|| {
+            aws_sdk_dynamodb::operation::update_item::UpdateItemOutput::builder().build()
+        });
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule, &update_rule]);
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            analytics: None,
+        };
+
+        let payload = json!({
+            "httpMethod": "GET",
+            "path": "/preview/abc123",
+            "pathParameters": {"short_code": "abc123"}
+        });
+        let response = handler_impl(payload, &app_state).await.unwrap();
+
+        assert_eq!(response["original_url"], "https://example.com");
+        assert_eq!(response["clicks"], 5);
+        assert!(response.get("click_count").is_none());
+        assert_eq!(update_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_protected_stats_need_the_password() {
+        let get_rule = mock!(DynamoDbClient::get_item)
+            .sequence()
+            .output(|| {
+                GetItemOutput::builder()
+                    .item("short_code", AttributeValue::S("abc123".to_string()))
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://example.com".to_string()),
+                    )
+                    .item(
+                        "created_at",
+                        AttributeValue::S("2023-11-01T00:00:00+00:00".to_string()),
+                    )
+                    .item(
+                        "password_hash",
+                        AttributeValue::S(hash_password("s3cret").unwrap()),
+                    )
+                    .build()
+            })
+            .repeatedly()
+            .build();
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, [&get_rule]),
+                "test-table".to_string(),
+            ),
+            analytics: None,
+        };
+        let stats = |password: Option<&str>| {
+            let mut headers = serde_json::Map::new();
+            if let Some(password) = password {
+                headers.insert(LINK_PASSWORD_HEADER.to_string(), json!(password));
+            }
+            LambdaEvent::new(
+                json!({
+                    "httpMethod": "GET",
+                    "path": "/stats/abc123",
+                    "pathParameters": {"short_code": "abc123"},
+                    "headers": headers
+                }),
+                Context::default(),
+            )
+        };
+
+        let response = function_handler(stats(None), app_state.clone())
+            .await
+            .unwrap();
+        assert_eq!(response["statusCode"], 401);
+        assert!(!response["body"].as_str().unwrap().contains("example.com"));
+
+        let response = function_handler(stats(Some("s3cret")), app_state)
+            .await
+            .unwrap();
+        assert_eq!(response["statusCode"], 200);
+        assert!(response["body"].as_str().unwrap().contains("example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_alb_preview_takes_code_from_path() {
+        let get_rule = mock!(DynamoDbClient::get_item)
+            .match_requests(|req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .is_some_and(|code| code.as_s().is_ok_and(|code| code == "abc123"))
+            })
+            .then_output(|| {
+                GetItemOutput::builder()
+                    .item("short_code", AttributeValue::S("abc123".to_string()))
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://example.com".to_string()),
+                    )
+                    .item(
+                        "created_at",
+                        AttributeValue::S("2023-11-01T00:00:00+00:00".to_string()),
+                    )
+                    .build()
+            });
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, [&get_rule]),
+                "test-table".to_string(),
+            ),
+            analytics: None,
+        };
+
+        // ALB events have no pathParameters
+        let payload = json!({
+            "requestContext": {"elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/squrl/abc"}},
+            "httpMethod": "GET",
+            "path": "/preview/abc123"
+        });
+        let response = handler_impl(payload, &app_state).await.unwrap();
+
+        assert_eq!(response["original_url"], "https://example.com");
+        assert_eq!(get_rule.num_calls(), 1);
+    }
+
+    /// Every click came from the same referrer host
+    struct OneReferrer(&'static str);
+
+    impl AnalyticsStore for OneReferrer {
+        fn top_referrers<'a>(
+            &'a self,
+            _short_code: &'a str,
+            _limit: usize,
+        ) -> BoxFuture<'a, Result<Vec<(String, u64)>, UrlShortenerError>> {
+            Box::pin(async move { Ok(vec![(self.0.to_string(), 5)]) })
+        }
+    }
+
+    async fn stats_with_breakdown(
+        breakdown: &str,
+        analytics: Option<Arc<dyn AnalyticsStore>>,
+    ) -> Result<Value, UrlShortenerError> {
+        let get_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("short_code", AttributeValue::S("abc123".to_string()))
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item(
+                    "created_at",
+                    AttributeValue::S("2023-11-01T00:00:00+00:00".to_string()),
+                )
+                .build()
+        });
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, [&get_rule]),
+                "test-table".to_string(),
+            ),
+            analytics,
+        };
+
+        let payload = json!({
+            "httpMethod": "GET",
+            "path": "/stats/abc123",
+            "pathParameters": {"short_code": "abc123"},
+            "queryStringParameters": {"breakdown": breakdown}
+        });
+        handler_impl(payload, &app_state).await
+    }
+
+    #[tokio::test]
+    async fn test_referrer_breakdown() {
+        let response = stats_with_breakdown("referrer", Some(Arc::new(OneReferrer("t.co"))))
+            .await
+            .unwrap();
+        assert_eq!(
+            response["top_referrers"],
+            json!([{"host": "t.co", "clicks": 5}])
+        );
+
+        let err = stats_with_breakdown("country", Some(Arc::new(OneReferrer("t.co"))))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status_code(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_breakdown_is_ignored_without_analytics() {
+        let response = stats_with_breakdown("referrer", None).await.unwrap();
+        assert_eq!(response["original_url"], "https://example.com");
+        assert!(response.get("top_referrers").is_none());
+
+        let response = stats_with_breakdown("country", None).await.unwrap();
+        assert!(response.get("top_referrers").is_none());
+    }
+
+    #[test]
+    fn test_preview_of_exhausted_link_is_gone() {
+        let mut item = url_item(None);
+        item.max_uses = Some(7);
+
+        let err = build_preview_response(item, None).unwrap_err();
+        assert_eq!(err.status_code(), 410);
     }
 }
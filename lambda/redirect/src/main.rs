@@ -1,142 +1,386 @@
-use aws_config::BehaviorVersion;
-use aws_sdk_dynamodb::Client as DynamoDbClient;
+use chrono::Utc;
 use lambda_runtime::{Error, LambdaEvent, run, service_fn};
 use serde_json::{Value, json};
 use std::env;
-use tracing::{error, info, instrument, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::sync::Arc;
+use tracing::{Span, error, field, info, instrument, warn};
 
-use squrl_shared::dynamodb::DynamoDbClient as UrlDynamoDbClient;
+use squrl_shared::config::RuntimeConfig;
+use squrl_shared::dynamodb::{DynamoDbClient as UrlDynamoDbClient, strong_reads_from_env};
 use squrl_shared::error::UrlShortenerError;
+use squrl_shared::link_cache::LinkCacheConfig;
 use squrl_shared::models::{
-    ApiGatewayProxyEvent, ApiGatewayProxyResponse, ErrorResponse, RedirectRequest,
-    RedirectResponse, is_api_gateway_event,
+    AnalyticsEvent, ApiGatewayProxyEvent, ApiGatewayProxyResponse, ErrorResponse, EventSource,
+    RedirectRequest, RedirectResponse, RedirectTarget, RedirectType, ResolvedUrlResponse,
+    build_short_url, classify_event, default_redirect_status, is_api_gateway_event,
+    is_warmup_event, prefers_json, request_origin, shortlink_header, warmup_response,
 };
+use squrl_shared::password::{LINK_PASSWORD_HEADER, verify_link_password};
+use squrl_shared::telemetry::{LogFormat, init_tracing};
+use squrl_shared::validation::{compose_wildcard_url, is_protected_header, sanitize_location};
+use squrl_shared::visitors::{VisitorKeys, visit_day};
 
 #[derive(Clone)]
 struct AppState {
     db_client: UrlDynamoDbClient,
-}
-
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer().json())
-        .init();
+    /// Set when unique visitors are tracked
+    visitor_keys: Option<VisitorKeys>,
+    /// Cache lifetimes, shortlink and interstitial settings
+    runtime_config: Arc<RuntimeConfig>,
+    /// Take the visitor's address from `X-Forwarded-For`
+    trust_xff: bool,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    init_tracing();
-
-    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    let runtime_config = RuntimeConfig::from_env()?;
+    let _telemetry = init_tracing(
+        "squrl-redirect",
+        &runtime_config.log_filter_or("info"),
+        LogFormat::Json,
+    )?;
 
-    let dynamodb_client = if let Ok(endpoint_url) = env::var("AWS_ENDPOINT_URL") {
-        // Local development with LocalStack
-        let dynamodb_config = aws_sdk_dynamodb::config::Builder::from(&config)
-            .endpoint_url(endpoint_url.clone())
-            .build();
-        DynamoDbClient::from_conf(dynamodb_config)
-    } else {
-        DynamoDbClient::new(&config)
+    let db_client = UrlDynamoDbClient::from_runtime_config(&runtime_config)
+        .await
+        .with_consistent_reads(strong_reads_from_env())
+        .with_link_cache(LinkCacheConfig::from_env());
+    let app_state = AppState {
+        db_client,
+        visitor_keys: VisitorKeys::from_env()?,
+        trust_xff: runtime_config.trust_xff,
+        runtime_config: Arc::new(runtime_config),
     };
 
-    let table_name = env::var("DYNAMODB_TABLE_NAME").unwrap_or_else(|_| "squrl-urls".to_string());
-
-    let db_client = UrlDynamoDbClient::new(dynamodb_client, table_name);
-    let app_state = AppState { db_client };
-
     run(service_fn(move |event| {
         function_handler(event, app_state.clone())
     }))
     .await
 }
 
-#[instrument(skip(app_state))]
+// `short_code` is recorded once the event is parsed, so everything below
+// (DynamoDB calls and the final error log included) carries it
+#[instrument(
+    skip(event, app_state),
+    fields(request_id = %event.context.request_id, short_code = field::Empty)
+)]
 async fn function_handler(event: LambdaEvent<Value>, app_state: AppState) -> Result<Value, Error> {
-    let is_api_gateway = is_api_gateway_event(&event.payload);
+    if is_warmup_event(&event.payload) {
+        info!("Warmup ping");
+        return Ok(warmup_response());
+    }
+
+    let event_source = classify_event(&event.payload);
+    let is_api_gateway = event_source != EventSource::Direct;
     let is_local_http = env::var("CARGO_LAMBDA_INVOKE_PORT").is_ok();
+    let origin = request_origin(&event.payload);
 
     match handler_impl(event.payload, &app_state).await {
         Ok(response) => {
             // Always return API Gateway format for local HTTP server or actual API Gateway
             if is_api_gateway || is_local_http {
-                Ok(create_api_gateway_redirect_response(response))
+                Ok(
+                    event_source.shape_response(create_api_gateway_redirect_response(
+                        response,
+                        origin.as_deref(),
+                        &app_state.runtime_config,
+                    )),
+                )
             } else {
                 Ok(response)
             }
         }
         Err(err) => {
             error!("Function error: {}", err);
-            Ok(create_error_response(&err, is_api_gateway || is_local_http))
+            Ok(event_source.shape_response(create_error_response(
+                &err,
+                is_api_gateway || is_local_http,
+                origin.as_deref(),
+                &app_state.runtime_config,
+            )))
         }
     }
 }
 
-async fn handler_impl(payload: Value, app_state: &AppState) -> Result<Value, UrlShortenerError> {
-    let (short_code, http_method) = if is_api_gateway_event(&payload) {
+/// What a redirect request asks for, from either event shape
+struct RedirectInput {
+    short_code: String,
+    http_method: String,
+    password: Option<String>,
+    path_suffix: Option<String>,
+    wants_json: bool,
+    if_none_match: Option<String>,
+    /// Who asked, for HTTP events; feeds unique-visitor counting
+    analytics: Option<AnalyticsEvent>,
+    /// `Host` the request arrived on, for the canonical short URL
+    host: Option<String>,
+}
+
+fn parse_redirect_input(
+    payload: Value,
+    trust_xff: bool,
+) -> Result<RedirectInput, UrlShortenerError> {
+    if is_api_gateway_event(&payload) {
         // Parse API Gateway event
-        let api_event: ApiGatewayProxyEvent = serde_json::from_value(payload).map_err(|e| {
-            UrlShortenerError::ValidationError(format!("Invalid API Gateway event: {}", e))
-        })?;
-
-        // Extract short_code from path parameters
-        let short_code = api_event
-            .path_parameters
-            .as_ref()
-            .and_then(|params| params.get("short_code"))
-            .ok_or_else(|| {
+        let api_event: ApiGatewayProxyEvent = ApiGatewayProxyEvent::from_payload(payload)?;
+
+        // The greedy `{proxy+}` segment after the code is set for wildcard
+        // paths. ALB events have neither, so both come from the path.
+        let (short_code, path_suffix) =
+            api_event.short_code_and_suffix(&["/"]).ok_or_else(|| {
                 UrlShortenerError::ValidationError(
                     "Missing short_code in path parameters".to_string(),
                 )
-            })?
-            .clone();
+            })?;
 
-        let http_method = api_event.http_method.clone();
+        let analytics = api_event.analytics_event(&short_code, Utc::now(), trust_xff);
 
-        (short_code, http_method)
+        Ok(RedirectInput {
+            short_code,
+            http_method: api_event.http_method.clone(),
+            password: api_event.header(LINK_PASSWORD_HEADER).map(String::from),
+            path_suffix,
+            wants_json: prefers_json(api_event.header("Accept")),
+            if_none_match: api_event.header("If-None-Match").map(String::from),
+            analytics: Some(analytics),
+            host: api_event.header("Host").map(String::from),
+        })
     } else {
         // Direct Lambda invocation
         let request: RedirectRequest = serde_json::from_value(payload)
-            .map_err(|e| UrlShortenerError::ValidationError(e.to_string()))?;
+            .map_err(|e| UrlShortenerError::invalid_input("Invalid redirect request", e))?;
 
-        (request.short_code, "GET".to_string())
-    };
+        Ok(RedirectInput {
+            short_code: request.short_code,
+            http_method: "GET".to_string(),
+            password: request.password,
+            path_suffix: request.path_suffix,
+            wants_json: false,
+            if_none_match: None,
+            analytics: None,
+            host: None,
+        })
+    }
+}
 
+async fn handler_impl(payload: Value, app_state: &AppState) -> Result<Value, UrlShortenerError> {
+    let RedirectInput {
+        short_code,
+        http_method,
+        password,
+        path_suffix,
+        wants_json,
+        if_none_match,
+        analytics,
+        host,
+    } = parse_redirect_input(payload, app_state.trust_xff)?;
+
+    Span::current().record("short_code", field::display(&short_code));
     info!("Processing redirect request for: {}", short_code);
 
-    // Look up the URL
+    // Look up only what the redirect needs
     let url_item = app_state
         .db_client
-        .get_url(&short_code)
+        .get_redirect_target(&short_code)
         .await?
         .ok_or_else(|| UrlShortenerError::ShortCodeNotFound(short_code.clone()))?;
 
-    // For HEAD requests, we skip click count increments
-    // as they're typically used just to check if a URL exists
-    if http_method != "HEAD" {
-        // Increment click count asynchronously
-        if let Err(e) = app_state.db_client.increment_click_count(&short_code).await {
-            warn!("Failed to increment click count: {}", e);
-        }
-    } else {
-        info!("HEAD request - skipping click count");
+    // Refuse a target that can't be sent as a Location before anything is
+    // counted for it
+    let destination = sanitize_location(&redirect_destination(
+        &short_code,
+        &url_item,
+        path_suffix.as_deref(),
+    )?)?;
+
+    // Protected links only redirect (and count a click) with the right password
+    verify_link_password(url_item.password_hash.as_deref(), password.as_deref())?;
+
+    // HEAD gets the same answer a GET would, minus the redirect, and never
+    // counts a click or a visitor
+    if http_method == "HEAD" {
+        return Ok(json!({ "short_code": short_code, "exists": true }));
     }
 
-    let response = RedirectResponse {
-        original_url: url_item.original_url,
-        redirect_type: "301".to_string(),
+    let cache_control = url_item.redirect_cache_control(
+        Utc::now().timestamp(),
+        app_state.runtime_config.redirect_cache_seconds,
+    );
+
+    // A cache revalidating an unchanged link: nothing is served, so nothing
+    // is counted
+    if !wants_json && url_item.not_modified(if_none_match.as_deref()) {
+        return Ok(json!({
+            "not_modified": true,
+            "etag": url_item.etag(),
+            "cache_control": cache_control
+        }));
+    }
+
+    // An alias counts against its canonical link
+    let counter_code = url_item.canonical_code.as_deref().unwrap_or(&short_code);
+    let mut clicks = url_item.click_count;
+    let client_ip = analytics
+        .as_ref()
+        .and_then(|event| event.source_ip.as_deref());
+    // With uniques tracked, the visit rides on the click's update
+    let recorded = match app_state.visitor_keys.as_ref().zip(client_ip) {
+        Some((visitor_keys, ip)) => {
+            let day = visit_day(Utc::now());
+            app_state
+                .db_client
+                .record_click_with_visit(
+                    counter_code,
+                    url_item.max_uses,
+                    &visitor_keys.key(ip, &day),
+                    &day,
+                )
+                .await
+                .map(|_| ())
+        }
+        None => {
+            app_state
+                .db_client
+                .record_click(counter_code, url_item.max_uses)
+                .await
+        }
     };
+    match recorded {
+        Ok(()) => clicks += 1,
+        // Limited links only redirect once the use has been recorded
+        Err(e) if url_item.max_uses.is_some() => return Err(e),
+        // Otherwise the click count is best effort
+        Err(e) => warn!("Failed to increment click count: {}", e),
+    }
+
+    // API clients can resolve the link without following it
+    if wants_json {
+        let response = ResolvedUrlResponse {
+            original_url: destination,
+            clicks,
+        };
+        return Ok(serde_json::to_value(response)?);
+    }
+
+    let redirect_type =
+        RedirectType::from_status(url_item.redirect_status_or(default_redirect_status()));
+
+    let mut response = RedirectResponse::new(destination, redirect_type, cache_control)
+        .with_etag(url_item.etag())
+        .with_interstitial(app_state.runtime_config.interstitial)
+        .with_extra_headers(url_item.extra_headers.clone());
+    if app_state.runtime_config.redirect_shortlink {
+        let base = app_state
+            .runtime_config
+            .short_url_base_for(host.as_deref())?;
+        response = response.with_short_url(build_short_url(&base, &short_code)?);
+    }
 
     Ok(serde_json::to_value(response)?)
 }
 
-fn create_api_gateway_redirect_response(response_data: Value) -> Value {
+/// Where a request for `short_code` (plus any trailing path) should go. Only
+/// wildcard links answer below their code; for anything else a suffix is a
+/// miss.
+fn redirect_destination(
+    short_code: &str,
+    target: &RedirectTarget,
+    path_suffix: Option<&str>,
+) -> Result<String, UrlShortenerError> {
+    match path_suffix.filter(|suffix| !suffix.trim_matches('/').is_empty()) {
+        None => Ok(target.original_url.clone()),
+        Some(suffix) if target.wildcard => compose_wildcard_url(&target.original_url, suffix),
+        Some(_) => Err(UrlShortenerError::ShortCodeNotFound(short_code.to_string())),
+    }
+}
+
+fn create_api_gateway_redirect_response(
+    response_data: Value,
+    origin: Option<&str>,
+    runtime_config: &RuntimeConfig,
+) -> Value {
+    // HEAD existence check: no redirect, just a bodyless 200
+    if response_data.get("exists").and_then(Value::as_bool) == Some(true) {
+        let mut api_response = ApiGatewayProxyResponse::new(200, String::new());
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
+        return serde_json::to_value(api_response).unwrap();
+    }
+
+    if response_data.get("not_modified").and_then(Value::as_bool) == Some(true) {
+        let mut api_response =
+            ApiGatewayProxyResponse::new(304, String::new()).with_header("Vary", "Accept");
+        for (field, header) in [("etag", "ETag"), ("cache_control", "Cache-Control")] {
+            if let Some(value) = response_data.get(field).and_then(Value::as_str) {
+                api_response = api_response.with_header(header, value);
+            }
+        }
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
+        return serde_json::to_value(api_response).unwrap();
+    }
+
+    // JSON resolution: the destination as a body, nothing to follow. The
+    // click count changes on every request, so it isn't cached.
+    if response_data.get("clicks").is_some() {
+        let mut api_response = ApiGatewayProxyResponse::new(200, response_data.to_string())
+            .with_header("Cache-Control", "no-store")
+            .with_header("Vary", "Accept");
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
+        return serde_json::to_value(api_response).unwrap();
+    }
+
     // Extract the original_url from the response data
     if let Some(original_url) = response_data.get("original_url").and_then(|v| v.as_str()) {
-        let api_response = ApiGatewayProxyResponse::redirect(original_url.to_string());
+        let status_code = match response_data.get("status_code").and_then(Value::as_u64) {
+            Some(302) => 302,
+            Some(_) => 301,
+            None => match response_data.get("redirect_type").and_then(|v| v.as_str()) {
+                Some("302") => 302,
+                _ => 301,
+            },
+        };
+        let built = if response_data.get("interstitial").and_then(Value::as_bool) == Some(true) {
+            ApiGatewayProxyResponse::interstitial(original_url)
+        } else {
+            ApiGatewayProxyResponse::redirect_with_status(original_url.to_string(), status_code)
+        };
+        let mut api_response = match built {
+            Ok(api_response) => api_response,
+            Err(err) => {
+                error!("Refusing to redirect: {}", err);
+                return create_error_response(&err, true, origin, runtime_config);
+            }
+        };
+        // Per-link headers go first, so nothing the redirect itself relies on
+        // can be replaced by them
+        if let Some(extra_headers) = response_data
+            .get("extra_headers")
+            .and_then(Value::as_object)
+        {
+            for (name, value) in extra_headers {
+                if let Some(value) = value.as_str().filter(|_| !is_protected_header(name)) {
+                    api_response = api_response.with_header(name, value);
+                }
+            }
+        }
+        if let Some(cache_control) = response_data.get("cache_control").and_then(|v| v.as_str()) {
+            api_response = api_response.with_header("Cache-Control", cache_control);
+        }
+        if let Some(etag) = response_data.get("etag").and_then(|v| v.as_str()) {
+            api_response = api_response.with_header("ETag", etag);
+        }
+        if let Some(short_url) = response_data.get("short_url").and_then(|v| v.as_str()) {
+            api_response = api_response.with_header("Link", &shortlink_header(short_url));
+        }
+        // Cached redirects must not be served to JSON clients, or vice versa
+        api_response = api_response.with_header("Vary", "Accept");
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
         serde_json::to_value(api_response).unwrap()
     } else {
         // Fallback to error response if URL not found
@@ -149,25 +393,42 @@ fn create_api_gateway_redirect_response(response_data: Value) -> Value {
     }
 }
 
-fn create_error_response(err: &UrlShortenerError, is_api_gateway: bool) -> Value {
+fn create_error_response(
+    err: &UrlShortenerError,
+    is_api_gateway: bool,
+    origin: Option<&str>,
+    runtime_config: &RuntimeConfig,
+) -> Value {
     let error_response = ErrorResponse {
         error: err.error_type().to_string(),
         message: err.to_string(),
-        details: None,
+        details: err.details(),
     };
 
+    // 404s are cached only briefly, so a code created moments later soon
+    // resolves; other failures (a 401 for a request that simply lacked the
+    // password, any 5xx) never stick in CloudFront
+    let cache_control = runtime_config.cache_control_for(err.status_code(), None);
     if is_api_gateway {
-        let api_response = ApiGatewayProxyResponse::new(
+        let mut api_response = ApiGatewayProxyResponse::new(
             err.status_code(),
             serde_json::to_string(&error_response).unwrap(),
-        );
+        )
+        .with_header("Cache-Control", &cache_control);
+        for (name, value) in err.headers() {
+            api_response = api_response.with_header(name, &value);
+        }
+        if let Some(origin) = origin {
+            api_response = api_response.with_cors_origin(origin);
+        }
         serde_json::to_value(api_response).unwrap()
     } else {
         json!({
             "statusCode": err.status_code(),
             "body": serde_json::to_string(&error_response).unwrap(),
             "headers": {
-                "Content-Type": "application/json"
+                "Content-Type": "application/json",
+                "Cache-Control": cache_control
             }
         })
     }
@@ -178,7 +439,370 @@ fn create_error_response(err: &UrlShortenerError, is_api_gateway: bool) -> Value
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aws_sdk_dynamodb::Client as DynamoDbClient;
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::operation::update_item::UpdateItemOutput;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use aws_smithy_mocks::{RuleMode, mock, mock_client};
+    use lambda_runtime::Context;
     use serde_json::json;
+    use squrl_shared::models::{SHORT_URL_HOSTS_ENV, redirect_etag};
+    use squrl_shared::password::hash_password;
+    use tracing_test::traced_test;
+
+    /// Settings as deployed with no overrides
+    fn test_config() -> RuntimeConfig {
+        RuntimeConfig::from_lookup(|_| None).unwrap()
+    }
+
+    /// Redirect `abc123` (4 clicks so far) with the given request headers.
+    /// Returns the API Gateway response and how many clicks were recorded.
+    async fn redirect_with_headers(headers: Value) -> (Value, usize) {
+        let get_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("click_count", AttributeValue::N("4".to_string()))
+                .build()
+        });
+        let update_rule =
+            mock!(DynamoDbClient::update_item).then_output(|| UpdateItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule, &update_rule]);
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            visitor_keys: None,
+            runtime_config: Arc::new(
+                RuntimeConfig::from_lookup(|key| {
+                    (key == SHORT_URL_HOSTS_ENV).then(|| "sqrl.link".to_string())
+                })
+                .unwrap(),
+            ),
+            trust_xff: false,
+        };
+
+        let payload = json!({
+            "httpMethod": "GET",
+            "path": "/abc123",
+            "pathParameters": {"short_code": "abc123"},
+            "headers": headers
+        });
+        let response = handler_impl(payload, &app_state).await.unwrap();
+        (
+            create_api_gateway_redirect_response(response, None, &test_config()),
+            update_rule.num_calls(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_location_is_refused_before_counting() {
+        let get_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com/\r\nSet-Cookie: a=b".to_string()),
+                )
+                .build()
+        });
+        let update_rule =
+            mock!(DynamoDbClient::update_item).then_output(|| UpdateItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule, &update_rule]);
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            visitor_keys: None,
+            runtime_config: Arc::new(test_config()),
+            trust_xff: false,
+        };
+
+        let payload = json!({
+            "httpMethod": "GET",
+            "path": "/abc123",
+            "pathParameters": {"short_code": "abc123"}
+        });
+        assert!(handler_impl(payload, &app_state).await.is_err());
+        assert_eq!(update_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_alias_counts_against_canonical_link() {
+        let alias_rule = mock!(DynamoDbClient::get_item)
+            .match_requests(|req| {
+                req.key().and_then(|key| key.get("short_code"))
+                    == Some(&AttributeValue::S("summer".to_string()))
+            })
+            .then_output(|| {
+                GetItemOutput::builder()
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://example.com".to_string()),
+                    )
+                    .item("canonical_code", AttributeValue::S("abc123".to_string()))
+                    .build()
+            });
+        let canonical_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("max_uses", AttributeValue::N("3".to_string()))
+                .build()
+        });
+        let update_rule = mock!(DynamoDbClient::update_item)
+            .match_requests(|req| {
+                req.key().and_then(|key| key.get("short_code"))
+                    == Some(&AttributeValue::S("abc123".to_string()))
+                    && req.condition_expression() == Some("click_count < :max")
+            })
+            .then_output(|| UpdateItemOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&alias_rule, &canonical_rule, &update_rule]
+        );
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            visitor_keys: None,
+            runtime_config: Arc::new(test_config()),
+            trust_xff: false,
+        };
+
+        let payload = json!({
+            "httpMethod": "GET",
+            "path": "/summer",
+            "pathParameters": {"short_code": "summer"}
+        });
+        handler_impl(payload, &app_state).await.unwrap();
+        assert_eq!(update_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_head_checks_like_get_without_counting() {
+        let get_rule = mock!(DynamoDbClient::get_item)
+            .sequence()
+            .output(|| {
+                GetItemOutput::builder()
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://example.com".to_string()),
+                    )
+                    .build()
+            })
+            .output(|| {
+                GetItemOutput::builder()
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://example.com".to_string()),
+                    )
+                    .item(
+                        "password_hash",
+                        AttributeValue::S(hash_password("s3cret").unwrap()),
+                    )
+                    .build()
+            })
+            .output(|| GetItemOutput::builder().build())
+            .build();
+        let update_rule =
+            mock!(DynamoDbClient::update_item).then_output(|| UpdateItemOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&get_rule, &update_rule]
+        );
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            visitor_keys: None,
+            runtime_config: Arc::new(test_config()),
+            trust_xff: false,
+        };
+        let head = || {
+            json!({
+                "httpMethod": "HEAD",
+                "path": "/abc123",
+                "pathParameters": {"short_code": "abc123"}
+            })
+        };
+
+        let response = handler_impl(head(), &app_state).await.unwrap();
+        assert_eq!(response["exists"], true);
+        // Protected and missing links answer as a GET would
+        assert!(matches!(
+            handler_impl(head(), &app_state).await,
+            Err(UrlShortenerError::PasswordRequired)
+        ));
+        assert!(matches!(
+            handler_impl(head(), &app_state).await,
+            Err(UrlShortenerError::ShortCodeNotFound(_))
+        ));
+        assert_eq!(update_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_warmup_ping_skips_dynamodb() {
+        // No rules: any DynamoDB call would fail the test
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, []),
+                "test-table".to_string(),
+            ),
+            visitor_keys: None,
+            runtime_config: Arc::new(test_config()),
+            trust_xff: false,
+        };
+
+        let response = function_handler(
+            LambdaEvent::new(json!({"warmup": true}), Context::default()),
+            app_state,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response, warmup_response());
+    }
+
+    fn redirect_event(short_code: &str) -> LambdaEvent<Value> {
+        LambdaEvent::new(
+            json!({
+                "httpMethod": "GET",
+                "path": format!("/{}", short_code),
+                "pathParameters": {"short_code": short_code}
+            }),
+            Context::default(),
+        )
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_redirect_span_carries_short_code() {
+        let get_rule = mock!(DynamoDbClient::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .build()
+        });
+        let update_rule =
+            mock!(DynamoDbClient::update_item).then_output(|| UpdateItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule, &update_rule]);
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            visitor_keys: None,
+            runtime_config: Arc::new(test_config()),
+            trust_xff: false,
+        };
+
+        let response = function_handler(redirect_event("abc123"), app_state)
+            .await
+            .unwrap();
+        assert_eq!(response["statusCode"], 301);
+
+        logs_assert(|lines: &[&str]| {
+            match lines
+                .iter()
+                .find(|line| line.contains("Processing redirect request"))
+            {
+                Some(line) if line.contains("short_code=abc123") => Ok(()),
+                other => Err(format!("short_code missing from span: {:?}", other)),
+            }
+        });
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_redirect_error_span_carries_short_code() {
+        let get_rule =
+            mock!(DynamoDbClient::get_item).then_output(|| GetItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule]);
+        let app_state = AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            visitor_keys: None,
+            runtime_config: Arc::new(test_config()),
+            trust_xff: false,
+        };
+
+        let response = function_handler(redirect_event("missing"), app_state)
+            .await
+            .unwrap();
+        assert_eq!(response["statusCode"], 404);
+
+        logs_assert(|lines: &[&str]| {
+            match lines.iter().find(|line| line.contains("Function error")) {
+                Some(line) if line.contains("short_code=missing") => Ok(()),
+                other => Err(format!("short_code missing from error span: {:?}", other)),
+            }
+        });
+    }
+
+    async fn redirect_with_accept(accept: &str) -> Value {
+        redirect_with_headers(json!({ "Accept": accept })).await.0
+    }
+
+    #[tokio::test]
+    async fn test_matching_if_none_match_is_not_modified() {
+        let etag = redirect_etag("https://example.com", None);
+        let (api_response, clicks) = redirect_with_headers(json!({ "If-None-Match": etag })).await;
+
+        assert_eq!(api_response["statusCode"], 304);
+        assert_eq!(api_response["body"], "");
+        assert_eq!(api_response["headers"]["ETag"], etag.as_str());
+        assert!(api_response["headers"].get("Location").is_none());
+        assert_eq!(clicks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_if_none_match_redirects() {
+        let (api_response, clicks) =
+            redirect_with_headers(json!({ "If-None-Match": "W/\"stale\"" })).await;
+
+        assert_eq!(api_response["statusCode"], 301);
+        assert_eq!(api_response["headers"]["Location"], "https://example.com");
+        assert_eq!(
+            api_response["headers"]["ETag"],
+            redirect_etag("https://example.com", None).as_str()
+        );
+        assert_eq!(clicks, 1);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_carries_shortlink() {
+        let (api_response, _) = redirect_with_headers(json!({})).await;
+        assert_eq!(
+            api_response["headers"]["Link"],
+            "<https://sqrl.co/abc123>; rel=\"shortlink\""
+        );
+
+        // An allowed alternate host names itself
+        let (api_response, _) = redirect_with_headers(json!({ "Host": "sqrl.link" })).await;
+        assert_eq!(
+            api_response["headers"]["Link"],
+            "<https://sqrl.link/abc123>; rel=\"shortlink\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_accept_json_resolves_without_redirect() {
+        let api_response = redirect_with_accept("application/json").await;
+
+        assert_eq!(api_response["statusCode"], 200);
+        assert!(api_response["headers"].get("Location").is_none());
+        assert_eq!(api_response["headers"]["Vary"], "Accept");
+
+        let body: Value = serde_json::from_str(api_response["body"].as_str().unwrap()).unwrap();
+        assert_eq!(body["original_url"], "https://example.com");
+        assert_eq!(body["clicks"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_browser_accept_redirects() {
+        let api_response =
+            redirect_with_accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8")
+                .await;
+
+        assert_eq!(api_response["statusCode"], 301);
+        assert_eq!(api_response["headers"]["Location"], "https://example.com");
+        assert_eq!(api_response["headers"]["Vary"], "Accept");
+    }
 
     #[tokio::test]
     async fn test_handler_impl() {
@@ -186,6 +810,77 @@ mod tests {
         // Requires setting up DynamoDB mock or LocalStack
     }
 
+    fn redirect_target(original_url: &str, wildcard: bool) -> RedirectTarget {
+        RedirectTarget {
+            original_url: original_url.to_string(),
+            expires_at: None,
+            status: "active".to_string(),
+            password_hash: None,
+            max_uses: None,
+            redirect_status: None,
+            wildcard,
+            click_count: 0,
+            extra_headers: None,
+            canonical_code: None,
+        }
+    }
+
+    #[test]
+    fn test_malformed_direct_request_is_client_error() {
+        let err = parse_redirect_input(json!({"short_code": 42}), false)
+            .err()
+            .unwrap();
+
+        assert_eq!(err.status_code(), 400);
+        assert_eq!(err.error_type(), "InvalidRequest");
+    }
+
+    #[test]
+    fn test_redirect_destination_exact() {
+        let target = redirect_target("https://target.com/docs", false);
+        assert_eq!(
+            redirect_destination("docs", &target, None).unwrap(),
+            "https://target.com/docs"
+        );
+
+        // Non-wildcard links don't answer below their code
+        assert!(matches!(
+            redirect_destination("docs", &target, Some("intro")),
+            Err(UrlShortenerError::ShortCodeNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_redirect_destination_wildcard_suffix() {
+        let target = redirect_target("https://target.com/docs", true);
+        assert_eq!(
+            redirect_destination("docs", &target, Some("intro")).unwrap(),
+            "https://target.com/docs/intro"
+        );
+        assert_eq!(
+            redirect_destination("docs", &target, Some("guide/setup")).unwrap(),
+            "https://target.com/docs/guide/setup"
+        );
+        assert!(redirect_destination("docs", &target, Some("../admin")).is_err());
+    }
+
+    #[test]
+    fn test_redirect_destination_wildcard_empty_suffix() {
+        let target = redirect_target("https://target.com/docs", true);
+        assert_eq!(
+            redirect_destination("docs", &target, None).unwrap(),
+            "https://target.com/docs"
+        );
+        assert_eq!(
+            redirect_destination("docs", &target, Some("")).unwrap(),
+            "https://target.com/docs"
+        );
+        assert_eq!(
+            redirect_destination("docs", &target, Some("/")).unwrap(),
+            "https://target.com/docs"
+        );
+    }
+
     #[test]
     fn test_api_gateway_redirect_response() {
         let redirect_data = json!({
@@ -193,7 +888,8 @@ mod tests {
             "redirect_type": "301"
         });
 
-        let api_response = create_api_gateway_redirect_response(redirect_data);
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
 
         assert_eq!(api_response["statusCode"], 301);
         assert!(api_response["headers"].is_object());
@@ -201,17 +897,133 @@ mod tests {
         assert_eq!(api_response["body"], "");
     }
 
+    #[test]
+    fn test_redirect_carries_extra_headers() {
+        let redirect_data = json!({
+            "original_url": "https://example.com",
+            "redirect_type": "302",
+            "cache_control": "public, max-age=90",
+            "extra_headers": {
+                "X-Campaign": "spring",
+                "Cache-Control": "no-cache",
+                "Location": "https://evil.example"
+            }
+        });
+
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
+
+        assert_eq!(api_response["statusCode"], 302);
+        assert_eq!(api_response["headers"]["X-Campaign"], "spring");
+        // Protected headers in a stored map never reach the response
+        assert_eq!(api_response["headers"]["Location"], "https://example.com");
+        assert_eq!(
+            api_response["headers"]["Cache-Control"],
+            "public, max-age=90"
+        );
+    }
+
+    #[test]
+    fn test_interstitial_response() {
+        let redirect_data = json!({
+            "original_url": "https://example.com/\"><script>alert(1)</script>",
+            "redirect_type": "301",
+            "cache_control": "public, max-age=90",
+            "interstitial": true
+        });
+
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
+
+        assert_eq!(api_response["statusCode"], 200);
+        assert!(api_response["headers"].get("Location").is_none());
+        assert_eq!(
+            api_response["headers"]["Content-Type"],
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            api_response["headers"]["Cache-Control"],
+            "public, max-age=90"
+        );
+        let body = api_response["body"].as_str().unwrap();
+        assert!(body.contains("https://example.com/&quot;&gt;&lt;script&gt;"));
+        assert!(!body.contains("<script>"));
+    }
+
+    #[test]
+    fn test_redirect_response_refuses_crlf_location() {
+        let redirect_data = json!({
+            "original_url": "https://example.com/\r\nSet-Cookie: a=b",
+            "redirect_type": "301"
+        });
+
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
+
+        assert_eq!(api_response["statusCode"], 500);
+        assert!(api_response["headers"].get("Location").is_none());
+    }
+
+    #[test]
+    fn test_head_existence_response() {
+        let api_response = create_api_gateway_redirect_response(
+            json!({ "short_code": "abc123", "exists": true }),
+            None,
+            &test_config(),
+        );
+
+        assert_eq!(api_response["statusCode"], 200);
+        assert_eq!(api_response["body"], "");
+        assert!(api_response["headers"].get("Location").is_none());
+    }
+
+    #[test]
+    fn test_link_redirect_type_sets_status() {
+        let redirect_data = json!({
+            "original_url": "https://example.com",
+            "redirect_type": "302"
+        });
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
+        assert_eq!(api_response["statusCode"], 302);
+
+        let redirect_data = json!({
+            "original_url": "https://example.com",
+            "redirect_type": "301"
+        });
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
+        assert_eq!(api_response["statusCode"], 301);
+    }
+
+    #[test]
+    fn test_api_gateway_redirect_response_cache_control() {
+        let redirect_data = json!({
+            "original_url": "https://example.com",
+            "redirect_type": "301",
+            "cache_control": "public, max-age=90"
+        });
+
+        let api_response =
+            create_api_gateway_redirect_response(redirect_data, None, &test_config());
+
+        assert_eq!(
+            api_response["headers"]["Cache-Control"],
+            "public, max-age=90"
+        );
+    }
+
     #[test]
     fn test_api_gateway_error_response() {
         let error = UrlShortenerError::ShortCodeNotFound("abc123".to_string());
-        let api_response_value = create_error_response(&error, true);
+        let api_response_value = create_error_response(&error, true, None, &test_config());
 
         assert_eq!(api_response_value["statusCode"], 404);
         assert!(api_response_value["headers"].is_object());
         assert!(api_response_value["body"].is_string());
 
         // Test legacy response format
-        let legacy_response_value = create_error_response(&error, false);
+        let legacy_response_value = create_error_response(&error, false, None, &test_config());
         assert_eq!(legacy_response_value["statusCode"], 404);
         assert_eq!(
             legacy_response_value["headers"]["Content-Type"],
@@ -219,6 +1031,62 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_not_found_response_is_cached_briefly() {
+        let error = UrlShortenerError::ShortCodeNotFound("abc123".to_string());
+
+        let api_response = create_error_response(&error, true, None, &test_config());
+        assert_eq!(api_response["statusCode"], 404);
+        assert_eq!(
+            api_response["headers"]["Cache-Control"],
+            "public, max-age=10"
+        );
+
+        let legacy_response = create_error_response(&error, false, None, &test_config());
+        assert_eq!(
+            legacy_response["headers"]["Cache-Control"],
+            "public, max-age=10"
+        );
+    }
+
+    #[test]
+    fn test_server_error_response_is_not_cached() {
+        let error = UrlShortenerError::DatabaseError("boom".to_string());
+
+        let api_response = create_error_response(&error, true, None, &test_config());
+        assert_eq!(api_response["statusCode"], 500);
+        assert_eq!(api_response["headers"]["Cache-Control"], "no-store");
+    }
+
+    #[test]
+    fn test_password_required_error_response() {
+        let api_response_value = create_error_response(
+            &UrlShortenerError::PasswordRequired,
+            true,
+            None,
+            &test_config(),
+        );
+
+        assert_eq!(api_response_value["statusCode"], 401);
+        let body: Value =
+            serde_json::from_str(api_response_value["body"].as_str().unwrap()).unwrap();
+        assert_eq!(body["details"]["requires_password"], true);
+    }
+
+    #[test]
+    fn test_alb_event_code_comes_from_path() {
+        let alb_event = json!({
+            "requestContext": {"elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/squrl/abc"}},
+            "httpMethod": "GET",
+            "path": "/docs/guide",
+            "headers": {"host": "sqrl.co"}
+        });
+
+        let input = parse_redirect_input(alb_event, false).unwrap();
+        assert_eq!(input.short_code, "docs");
+        assert_eq!(input.path_suffix.as_deref(), Some("guide"));
+    }
+
     #[test]
     fn test_path_parameter_extraction() {
         let api_gateway_event = json!({
@@ -0,0 +1,122 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use url::Url;
+
+use crate::error::UrlShortenerError;
+
+/// Bucket for clicks without a usable `Referer`
+pub const DIRECT_REFERRER: &str = "(direct)";
+
+/// One recorded redirect, as an analytics store keeps it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClickEvent {
+    pub short_code: String,
+    /// Raw `Referer` header, if the client sent one
+    pub referer: Option<String>,
+    /// Unix seconds
+    pub timestamp: i64,
+}
+
+/// Collapse a `Referer` to its lowercase host, so paths and query strings
+/// don't each become their own bucket. Missing or unparseable referrers count
+/// as direct traffic.
+pub fn referrer_host(referer: Option<&str>) -> String {
+    referer
+        .and_then(|referer| Url::parse(referer.trim()).ok())
+        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+        .unwrap_or_else(|| DIRECT_REFERRER.to_string())
+}
+
+/// Count `events` by referrer host, most clicks first (ties by host name),
+/// keeping the top `limit`
+pub fn rank_referrers<'a>(
+    events: impl IntoIterator<Item = &'a ClickEvent>,
+    limit: usize,
+) -> Vec<(String, u64)> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for event in events {
+        *counts
+            .entry(referrer_host(event.referer.as_deref()))
+            .or_default() += 1;
+    }
+
+    let mut ranked: Vec<(String, u64)> = counts.into_iter().collect();
+    ranked.sort_by(|(a_host, a_count), (b_host, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_host.cmp(b_host))
+    });
+    ranked.truncate(limit);
+    ranked
+}
+
+/// Per-click event storage, queried for breakdowns that the link item's
+/// counters can't answer. Boxed futures keep it usable as a trait object.
+pub trait AnalyticsStore: Send + Sync {
+    /// Stored events for `short_code`, ranked by referrer host
+    fn top_referrers<'a>(
+        &'a self,
+        short_code: &'a str,
+        limit: usize,
+    ) -> BoxFuture<'a, Result<Vec<(String, u64)>, UrlShortenerError>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    struct MemoryStore(Vec<ClickEvent>);
+
+    impl AnalyticsStore for MemoryStore {
+        fn top_referrers<'a>(
+            &'a self,
+            short_code: &'a str,
+            limit: usize,
+        ) -> BoxFuture<'a, Result<Vec<(String, u64)>, UrlShortenerError>> {
+            let events = self.0.iter().filter(|e| e.short_code == short_code);
+            let ranked = rank_referrers(events, limit);
+            async move { Ok(ranked) }.boxed()
+        }
+    }
+
+    fn click(short_code: &str, referer: Option<&str>) -> ClickEvent {
+        ClickEvent {
+            short_code: short_code.to_string(),
+            referer: referer.map(str::to_string),
+            timestamp: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn test_referrer_host_normalization() {
+        assert_eq!(
+            referrer_host(Some("https://News.Example.com/item?id=1")),
+            "news.example.com"
+        );
+        assert_eq!(referrer_host(Some("not a url")), DIRECT_REFERRER);
+        assert_eq!(referrer_host(None), DIRECT_REFERRER);
+    }
+
+    #[tokio::test]
+    async fn test_top_referrers_ranks_hosts() {
+        let store = MemoryStore(vec![
+            click("abc123", Some("https://t.co/x1")),
+            click("abc123", Some("https://news.example.com/a")),
+            click("abc123", Some("https://t.co/x2")),
+            click("abc123", None),
+            click("abc123", Some("https://news.example.com/b?utm=1")),
+            click("abc123", Some("https://t.co/x3")),
+            click("other", Some("https://t.co/x4")),
+        ]);
+
+        assert_eq!(
+            store.top_referrers("abc123", 10).await.unwrap(),
+            vec![
+                ("t.co".to_string(), 3),
+                ("news.example.com".to_string(), 2),
+                (DIRECT_REFERRER.to_string(), 1),
+            ]
+        );
+        assert_eq!(store.top_referrers("abc123", 1).await.unwrap().len(), 1);
+        assert!(store.top_referrers("missing", 10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+
+use crate::error::UrlShortenerError;
+
+/// Request header carrying an admin API key
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Environment variables named `API_KEY_<NAME>` each define one key
+pub const API_KEY_ENV_PREFIX: &str = "API_KEY_";
+
+/// Load API keys from `API_KEY_<NAME>=<value>` variables, keyed by lowercased
+/// name. This is the environment format `AppConfig` falls back to.
+pub fn api_keys_from_env() -> HashMap<String, String> {
+    env::vars()
+        .filter_map(|(key, value)| {
+            key.strip_prefix(API_KEY_ENV_PREFIX)
+                .map(|name| (name.to_lowercase(), value))
+        })
+        .collect()
+}
+
+/// Check the key sent with an admin request: missing is `ApiKeyMissing`
+/// (401), unknown is `ApiKeyInvalid` (403). With no keys configured every
+/// request is refused.
+pub fn check_api_key(
+    provided: Option<&str>,
+    api_keys: &HashMap<String, String>,
+) -> Result<(), UrlShortenerError> {
+    let provided = provided.ok_or(UrlShortenerError::ApiKeyMissing)?;
+
+    // Compare fixed-length digests byte by byte without stopping early, and
+    // check every key, so timing says nothing about how close a guess was
+    let provided = Sha256::digest(provided.as_bytes());
+    let matched = api_keys.values().fold(false, |matched, key| {
+        let key = Sha256::digest(key.as_bytes());
+        let diff = key
+            .iter()
+            .zip(provided.iter())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+        matched | (diff == 0)
+    });
+
+    if matched {
+        Ok(())
+    } else {
+        Err(UrlShortenerError::ApiKeyInvalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_api_key() {
+        let api_keys = HashMap::from([("ops".to_string(), "s3cret".to_string())]);
+
+        assert!(matches!(
+            check_api_key(None, &api_keys),
+            Err(UrlShortenerError::ApiKeyMissing)
+        ));
+        assert!(matches!(
+            check_api_key(Some("guess"), &api_keys),
+            Err(UrlShortenerError::ApiKeyInvalid)
+        ));
+        assert!(check_api_key(Some("s3cret"), &api_keys).is_ok());
+        assert!(check_api_key(Some("s3cret"), &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::net::{IpAddr, SocketAddr};
+
+use crate::models::ApiGatewayProxyEvent;
+
+/// Set to `true` when every request passes through a proxy that sets
+/// `X-Forwarded-For` (CloudFront, an ALB). Otherwise clients could put any
+/// address they like in the header, so it is ignored.
+pub const TRUST_XFF_ENV: &str = "TRUST_XFF";
+
+/// Whether `ip` can be a client on the internet, rather than a private,
+/// loopback, link-local or otherwise non-routable hop
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_ip(IpAddr::V4(v4)),
+            None => {
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_unique_local()
+                    || v6.is_unicast_link_local()
+                    || v6.is_multicast())
+            }
+        },
+    }
+}
+
+/// One `X-Forwarded-For` entry, which proxies sometimes write with a port
+fn parse_forwarded(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// The address of the client behind `event`: with `trust_xff`, the right-most
+/// public address in `X-Forwarded-For`, otherwise (or when it has none) the
+/// `sourceIp` API Gateway saw.
+///
+/// Each proxy appends the address it received the request from, so entries
+/// left of the one our own proxy wrote are whatever the client sent and can't
+/// be trusted. Private hops on the right are our own infrastructure.
+pub fn extract_client_ip(event: &ApiGatewayProxyEvent, trust_xff: bool) -> Option<String> {
+    let forwarded = trust_xff
+        .then(|| event.header("X-Forwarded-For"))
+        .flatten()
+        .and_then(|header| {
+            header
+                .rsplit(',')
+                .filter_map(parse_forwarded)
+                .find(|ip| is_public_ip(*ip))
+        });
+
+    forwarded
+        .map(|ip| ip.to_string())
+        .or_else(|| event.source_ip().map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(forwarded_for: Option<&str>) -> ApiGatewayProxyEvent {
+        let mut payload = json!({
+            "httpMethod": "GET",
+            "path": "/abc123",
+            "requestContext": {"identity": {"sourceIp": "198.51.100.20"}}
+        });
+        if let Some(forwarded_for) = forwarded_for {
+            payload["headers"] = json!({"x-forwarded-for": forwarded_for});
+        }
+        ApiGatewayProxyEvent::from_payload(payload).unwrap()
+    }
+
+    #[test]
+    fn test_last_public_forwarded_address_wins() {
+        let behind_proxies = event(Some("1.1.1.1, 8.8.8.8:4431, 10.0.0.4"));
+        assert_eq!(
+            extract_client_ip(&behind_proxies, true).as_deref(),
+            Some("8.8.8.8")
+        );
+
+        // A client-supplied entry left of the real one is ignored
+        let spoofed = event(Some("9.9.9.9, 203.0.113.7"));
+        assert_eq!(
+            extract_client_ip(&spoofed, true).as_deref(),
+            Some("203.0.113.7")
+        );
+
+        let ipv6 = event(Some("[2001:4860::8888]:443, 192.168.1.1"));
+        assert_eq!(
+            extract_client_ip(&ipv6, true).as_deref(),
+            Some("2001:4860::8888")
+        );
+    }
+
+    #[test]
+    fn test_falls_back_to_source_ip() {
+        assert_eq!(
+            extract_client_ip(&event(None), true).as_deref(),
+            Some("198.51.100.20")
+        );
+        // Nothing public, or nothing parseable, in the header
+        assert_eq!(
+            extract_client_ip(&event(Some("10.1.2.3, 127.0.0.1, unknown")), true).as_deref(),
+            Some("198.51.100.20")
+        );
+    }
+
+    #[test]
+    fn test_forwarded_for_ignored_unless_trusted() {
+        let spoofed = event(Some("8.8.8.8"));
+        assert_eq!(
+            extract_client_ip(&spoofed, false).as_deref(),
+            Some("198.51.100.20")
+        );
+    }
+
+    #[test]
+    fn test_public_ranges() {
+        for ip in ["8.8.8.8", "2606:4700::1111", "::ffff:8.8.4.4"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "10.0.0.1",
+            "172.16.5.5",
+            "100.64.0.1",
+            "169.254.1.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+}
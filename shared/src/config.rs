@@ -0,0 +1,564 @@
+use crate::client_ip::TRUST_XFF_ENV;
+use crate::creator_ip::CreatorIpHasher;
+use crate::error::UrlShortenerError;
+use crate::interstitial::INTERSTITIAL_ENV;
+use crate::models::{
+    DEFAULT_NOT_FOUND_CACHE_SECONDS, DEFAULT_REDIRECT_CACHE_SECONDS, DEFAULT_SHORT_URL_BASE,
+    MAX_TTL_HOURS, NOT_FOUND_CACHE_SECONDS_ENV, REDIRECT_CACHE_SECONDS_ENV, REDIRECT_SHORTLINK_ENV,
+    SHORT_URL_BASE_ENV, SHORT_URL_HOSTS_ENV, cache_control_with, resolve_short_url_base,
+};
+use crate::validation::{DEFAULT_MAX_HOST_LABELS, MAX_HOST_LABELS_ENV, ValidationConfig};
+use std::env;
+use url::Url;
+
+pub const TABLE_NAME_ENV: &str = "DYNAMODB_TABLE_NAME";
+pub const DEFAULT_TABLE_NAME: &str = "squrl-urls";
+
+/// Set to point the DynamoDB client at LocalStack
+pub const AWS_ENDPOINT_URL_ENV: &str = "AWS_ENDPOINT_URL";
+
+pub const LOG_FILTER_ENV: &str = "RUST_LOG";
+
+/// Set by `cargo lambda watch` when serving a function over local HTTP
+pub const LOCAL_INVOKE_PORT_ENV: &str = "CARGO_LAMBDA_INVOKE_PORT";
+
+/// `true` to allow links whose target is one of our own short hosts
+pub const ALLOW_SELF_LINKS_ENV: &str = "ALLOW_SELF_LINKS";
+
+/// `true` to reject link targets whose host has no dot (`https://foo`)
+pub const REQUIRE_TLD_ENV: &str = "REQUIRE_TLD";
+
+/// How many times a create draws a fresh generated code after a collision
+pub const CODE_COLLISION_RETRIES_ENV: &str = "CODE_COLLISION_RETRIES";
+pub const DEFAULT_CODE_COLLISION_RETRIES: u32 = 2;
+
+/// Largest request body accepted, in bytes. Oversized bodies get a 413 before
+/// any deserialization happens.
+pub const MAX_BODY_BYTES_ENV: &str = "MAX_BODY_BYTES";
+pub const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024;
+
+/// Hours a link lives when the create asks for no expiry; unset or 0 keeps
+/// such links forever
+pub const DEFAULT_TTL_HOURS_ENV: &str = "DEFAULT_TTL_HOURS";
+
+/// Process-wide settings, read and validated once at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub table_name: String,
+    /// Base for returned short URLs when the request host is not allowed
+    pub short_url_base: String,
+    /// Comma-separated hosts that may replace the base's host
+    pub short_url_hosts: String,
+    pub aws_endpoint_url: Option<String>,
+    pub log_filter: Option<String>,
+    pub local_invoke: bool,
+    pub allow_self_links: bool,
+    /// Passed to `validate_url_with` for link targets
+    pub require_tld: bool,
+    /// Set when creator IPs are recorded (always hashed)
+    pub creator_ip_hasher: Option<CreatorIpHasher>,
+    /// Read the creator's address from `X-Forwarded-For`, see `TRUST_XFF`
+    pub trust_xff: bool,
+    /// Fresh codes to try when a generated code is already taken
+    pub code_collision_retries: u32,
+    /// Lifetime for links created without `ttl_hours` or `expires_in`
+    pub default_ttl_hours: Option<u32>,
+    /// Request bodies above this many bytes are refused unparsed
+    pub max_body_bytes: usize,
+    /// Most dot-separated labels a link target's host may have
+    pub max_host_labels: usize,
+    /// Longest `max-age` for a redirect, see `REDIRECT_CACHE_SECONDS`
+    pub redirect_cache_seconds: u64,
+    /// `max-age` for a 404, `0` to never cache one
+    pub not_found_cache_seconds: u64,
+    /// Name the link's short URL in a `rel="shortlink"` header on redirects
+    pub redirect_shortlink: bool,
+    /// Answer redirects with an HTML interstitial
+    pub interstitial: bool,
+}
+
+impl RuntimeConfig {
+    pub fn from_env() -> Result<Self, UrlShortenerError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    /// Build from `lookup` instead of the process environment
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, UrlShortenerError> {
+        // Reject a malformed base now rather than on every create
+        let short_url_base = resolve_short_url_base(
+            None,
+            &lookup(SHORT_URL_BASE_ENV).unwrap_or_else(|| DEFAULT_SHORT_URL_BASE.to_string()),
+            "",
+        )?;
+
+        Ok(Self {
+            table_name: lookup(TABLE_NAME_ENV).unwrap_or_else(|| DEFAULT_TABLE_NAME.to_string()),
+            short_url_base,
+            short_url_hosts: lookup(SHORT_URL_HOSTS_ENV).unwrap_or_default(),
+            aws_endpoint_url: lookup(AWS_ENDPOINT_URL_ENV),
+            log_filter: lookup(LOG_FILTER_ENV),
+            local_invoke: lookup(LOCAL_INVOKE_PORT_ENV).is_some(),
+            allow_self_links: lookup(ALLOW_SELF_LINKS_ENV)
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("true")),
+            require_tld: lookup(REQUIRE_TLD_ENV)
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("true")),
+            creator_ip_hasher: CreatorIpHasher::from_lookup(&lookup)?,
+            trust_xff: lookup(TRUST_XFF_ENV)
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("true")),
+            code_collision_retries: match lookup(CODE_COLLISION_RETRIES_ENV) {
+                Some(value) => value.trim().parse().map_err(|_| {
+                    UrlShortenerError::InternalError(anyhow::anyhow!(
+                        "{} must be a non-negative integer, got {:?}",
+                        CODE_COLLISION_RETRIES_ENV,
+                        value
+                    ))
+                })?,
+                None => DEFAULT_CODE_COLLISION_RETRIES,
+            },
+            default_ttl_hours: match lookup(DEFAULT_TTL_HOURS_ENV) {
+                Some(value) => match value.trim().parse::<u32>() {
+                    Ok(0) => None,
+                    Ok(hours) if hours <= MAX_TTL_HOURS => Some(hours),
+                    _ => {
+                        return Err(UrlShortenerError::InternalError(anyhow::anyhow!(
+                            "{} must be a number of hours up to {}, got {:?}",
+                            DEFAULT_TTL_HOURS_ENV,
+                            MAX_TTL_HOURS,
+                            value
+                        )));
+                    }
+                },
+                None => None,
+            },
+            max_body_bytes: match lookup(MAX_BODY_BYTES_ENV) {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|bytes| *bytes > 0)
+                    .ok_or_else(|| {
+                        UrlShortenerError::InternalError(anyhow::anyhow!(
+                            "{} must be a positive number of bytes, got {:?}",
+                            MAX_BODY_BYTES_ENV,
+                            value
+                        ))
+                    })?,
+                None => DEFAULT_MAX_BODY_BYTES,
+            },
+            max_host_labels: match lookup(MAX_HOST_LABELS_ENV) {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|labels| *labels > 0)
+                    .ok_or_else(|| {
+                        UrlShortenerError::InternalError(anyhow::anyhow!(
+                            "{} must be a positive number of labels, got {:?}",
+                            MAX_HOST_LABELS_ENV,
+                            value
+                        ))
+                    })?,
+                None => DEFAULT_MAX_HOST_LABELS,
+            },
+            redirect_cache_seconds: match lookup(REDIRECT_CACHE_SECONDS_ENV) {
+                Some(value) => value.trim().parse().map_err(|_| {
+                    UrlShortenerError::InternalError(anyhow::anyhow!(
+                        "{} must be a non-negative number of seconds, got {:?}",
+                        REDIRECT_CACHE_SECONDS_ENV,
+                        value
+                    ))
+                })?,
+                None => DEFAULT_REDIRECT_CACHE_SECONDS,
+            },
+            not_found_cache_seconds: match lookup(NOT_FOUND_CACHE_SECONDS_ENV) {
+                Some(value) => value.trim().parse().map_err(|_| {
+                    UrlShortenerError::InternalError(anyhow::anyhow!(
+                        "{} must be a non-negative number of seconds, got {:?}",
+                        NOT_FOUND_CACHE_SECONDS_ENV,
+                        value
+                    ))
+                })?,
+                None => DEFAULT_NOT_FOUND_CACHE_SECONDS,
+            },
+            redirect_shortlink: !lookup(REDIRECT_SHORTLINK_ENV)
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("false")),
+            interstitial: lookup(INTERSTITIAL_ENV)
+                .is_some_and(|value| value.trim().eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// `Cache-Control` for a redirect-path response with `status`. Hits (200,
+    /// 301, 302, 304) are cached for `redirect_cache_seconds`, cut short to
+    /// `remaining_ttl` for expiring links; 404s for `not_found_cache_seconds`.
+    /// Anything else, 5xx included, is `no-store`: a 401 or 410 depends on the
+    /// request or may change, and an outage must not outlive itself in caches.
+    pub fn cache_control_for(&self, status: u16, remaining_ttl: Option<i64>) -> String {
+        cache_control_with(
+            status,
+            remaining_ttl,
+            self.redirect_cache_seconds,
+            self.not_found_cache_seconds,
+        )
+    }
+
+    /// `resolve_short_url_base` for a request's `Host`
+    pub fn short_url_base_for(&self, host: Option<&str>) -> Result<String, UrlShortenerError> {
+        resolve_short_url_base(host, &self.short_url_base, &self.short_url_hosts)
+    }
+
+    /// Extra short hosts from `SHORT_URL_HOSTS`
+    fn extra_hosts(&self) -> impl Iterator<Item = &str> {
+        self.short_url_hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+    }
+
+    /// The short URL base's host and every extra short host
+    fn own_hosts(&self) -> Vec<String> {
+        let base_host = Url::parse(&self.short_url_base)
+            .ok()
+            .and_then(|base| base.host_str().map(str::to_string));
+
+        base_host
+            .into_iter()
+            .chain(self.extra_hosts().map(str::to_string))
+            .collect()
+    }
+
+    /// The settings `validate_create_request` checks against
+    pub fn validation_config(&self) -> ValidationConfig {
+        ValidationConfig {
+            require_tld: self.require_tld,
+            allow_self_links: self.allow_self_links,
+            own_hosts: self.own_hosts(),
+            default_ttl_hours: self.default_ttl_hours,
+            max_host_labels: self.max_host_labels,
+        }
+    }
+
+    /// The code in one of our own short URLs. The host must be the base's or
+    /// an extra short host; anything else is not ours to expand.
+    pub fn short_code_from_url(&self, short_url: &str) -> Result<String, UrlShortenerError> {
+        let url = Url::parse(short_url.trim())
+            .map_err(|e| UrlShortenerError::InvalidUrl(format!("{}: {}", short_url, e)))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| UrlShortenerError::InvalidUrl(format!("{}: no host", short_url)))?;
+        let host_port = url.port().map(|port| format!("{}:{}", host, port));
+        let is_host = |candidate: &str| {
+            candidate.eq_ignore_ascii_case(host)
+                || host_port
+                    .as_deref()
+                    .is_some_and(|host_port| candidate.eq_ignore_ascii_case(host_port))
+        };
+
+        // Only the base can carry a path prefix; extra hosts serve codes at the root
+        let base = Url::parse(&self.short_url_base).ok();
+        let prefix = match base
+            .as_ref()
+            .filter(|base| base.host_str().is_some_and(is_host))
+        {
+            Some(base) => base.path().trim_end_matches('/'),
+            None if self.extra_hosts().any(is_host) => "",
+            None => {
+                return Err(UrlShortenerError::InvalidUrl(format!(
+                    "{} is not one of our short URLs",
+                    short_url
+                )));
+            }
+        };
+
+        url.path()
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .and_then(|rest| rest.split('/').next())
+            .filter(|code| !code.is_empty())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                UrlShortenerError::InvalidUrl(format!("{} has no short code", short_url))
+            })
+    }
+
+    /// The `RUST_LOG` filter, or `default` when unset
+    pub fn log_filter_or(&self, default: &str) -> String {
+        self.log_filter
+            .clone()
+            .unwrap_or_else(|| default.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateUrlRequest;
+    use crate::validation::validate_create_request;
+    use std::collections::HashMap;
+
+    fn config_from(vars: &[(&str, &str)]) -> Result<RuntimeConfig, UrlShortenerError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        RuntimeConfig::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = config_from(&[]).unwrap();
+
+        assert_eq!(config.table_name, DEFAULT_TABLE_NAME);
+        assert_eq!(config.short_url_base, DEFAULT_SHORT_URL_BASE);
+        assert_eq!(config.short_url_hosts, "");
+        assert_eq!(config.aws_endpoint_url, None);
+        assert_eq!(config.log_filter_or("info"), "info");
+        assert!(!config.local_invoke);
+        assert!(!config.allow_self_links);
+        assert!(!config.require_tld);
+        assert_eq!(
+            config.code_collision_retries,
+            DEFAULT_CODE_COLLISION_RETRIES
+        );
+        assert!(config.redirect_shortlink);
+        assert!(!config.interstitial);
+    }
+
+    #[test]
+    fn test_cache_control_for_status() {
+        let config = config_from(&[]).unwrap();
+        assert_eq!(config.cache_control_for(301, None), "public, max-age=3600");
+        assert_eq!(
+            config.cache_control_for(302, Some(90)),
+            "public, max-age=90"
+        );
+        assert_eq!(config.cache_control_for(301, Some(-5)), "public, max-age=0");
+        assert_eq!(config.cache_control_for(404, None), "public, max-age=10");
+        assert_eq!(config.cache_control_for(500, None), "no-store");
+        assert_eq!(config.cache_control_for(503, None), "no-store");
+        assert_eq!(config.cache_control_for(401, None), "no-store");
+
+        // Both lifetimes are configurable; a zero 404 lifetime disables caching
+        let config = config_from(&[
+            (REDIRECT_CACHE_SECONDS_ENV, "600"),
+            (NOT_FOUND_CACHE_SECONDS_ENV, "0"),
+        ])
+        .unwrap();
+        assert_eq!(config.cache_control_for(301, None), "public, max-age=600");
+        assert_eq!(config.cache_control_for(404, None), "no-store");
+
+        for env in [REDIRECT_CACHE_SECONDS_ENV, NOT_FOUND_CACHE_SECONDS_ENV] {
+            for value in ["-1", "an hour"] {
+                assert!(config_from(&[(env, value)]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_redirect_flags() {
+        let config = config_from(&[
+            (REDIRECT_SHORTLINK_ENV, "FALSE"),
+            (INTERSTITIAL_ENV, "true"),
+        ])
+        .unwrap();
+
+        assert!(!config.redirect_shortlink);
+        assert!(config.interstitial);
+    }
+
+    #[test]
+    fn test_short_code_from_url() {
+        let config = config_from(&[
+            (SHORT_URL_BASE_ENV, "https://sqrl.co/go"),
+            (SHORT_URL_HOSTS_ENV, "links.example.com, localhost:3000"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            config
+                .short_code_from_url("https://sqrl.co/go/abc123")
+                .unwrap(),
+            "abc123"
+        );
+        assert_eq!(
+            config
+                .short_code_from_url("https://SQRL.co/go/abc123/")
+                .unwrap(),
+            "abc123"
+        );
+        assert_eq!(
+            config
+                .short_code_from_url("https://links.example.com/xyz")
+                .unwrap(),
+            "xyz"
+        );
+        assert_eq!(
+            config
+                .short_code_from_url("http://localhost:3000/dev1")
+                .unwrap(),
+            "dev1"
+        );
+
+        // Foreign hosts, malformed URLs and URLs without a code
+        for short_url in [
+            "https://evil.example/abc123",
+            "https://sqrl.co.evil.example/go/abc123",
+            "not a url",
+            "mailto:someone@sqrl.co",
+            "https://sqrl.co/go/",
+            "https://sqrl.co/abc123",
+        ] {
+            assert!(
+                matches!(
+                    config.short_code_from_url(short_url),
+                    Err(UrlShortenerError::InvalidUrl(_))
+                ),
+                "{} should be rejected",
+                short_url
+            );
+        }
+    }
+
+    #[test]
+    fn test_code_collision_retries() {
+        let config = config_from(&[(CODE_COLLISION_RETRIES_ENV, "0")]).unwrap();
+        assert_eq!(config.code_collision_retries, 0);
+
+        assert!(config_from(&[(CODE_COLLISION_RETRIES_ENV, "-1")]).is_err());
+    }
+
+    #[test]
+    fn test_max_body_bytes() {
+        assert_eq!(
+            config_from(&[]).unwrap().max_body_bytes,
+            DEFAULT_MAX_BODY_BYTES
+        );
+        assert_eq!(
+            config_from(&[(MAX_BODY_BYTES_ENV, "1024")])
+                .unwrap()
+                .max_body_bytes,
+            1024
+        );
+        assert!(config_from(&[(MAX_BODY_BYTES_ENV, "0")]).is_err());
+        assert!(config_from(&[(MAX_BODY_BYTES_ENV, "lots")]).is_err());
+    }
+
+    #[test]
+    fn test_default_ttl_hours() {
+        assert_eq!(config_from(&[]).unwrap().default_ttl_hours, None);
+        assert_eq!(
+            config_from(&[(DEFAULT_TTL_HOURS_ENV, "0")])
+                .unwrap()
+                .default_ttl_hours,
+            None
+        );
+        assert_eq!(
+            config_from(&[(DEFAULT_TTL_HOURS_ENV, "720")])
+                .unwrap()
+                .default_ttl_hours,
+            Some(720)
+        );
+        assert!(config_from(&[(DEFAULT_TTL_HOURS_ENV, "forever")]).is_err());
+        assert!(config_from(&[(DEFAULT_TTL_HOURS_ENV, "100000")]).is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let config = config_from(&[
+            (TABLE_NAME_ENV, "other-table"),
+            (SHORT_URL_BASE_ENV, "https://sq.rl"),
+            (SHORT_URL_HOSTS_ENV, "go.example.com"),
+            (AWS_ENDPOINT_URL_ENV, "http://localhost:4566"),
+            (LOG_FILTER_ENV, "debug"),
+            (LOCAL_INVOKE_PORT_ENV, "9000"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.table_name, "other-table");
+        assert_eq!(
+            config.aws_endpoint_url.as_deref(),
+            Some("http://localhost:4566")
+        );
+        assert_eq!(config.log_filter_or("info"), "debug");
+        assert!(config.local_invoke);
+        assert_eq!(
+            config.short_url_base_for(Some("go.example.com")).unwrap(),
+            "https://go.example.com"
+        );
+        assert_eq!(config.short_url_base_for(None).unwrap(), "https://sq.rl");
+    }
+
+    fn check_target(config: &RuntimeConfig, target: &str) -> Result<(), UrlShortenerError> {
+        let request: CreateUrlRequest =
+            serde_json::from_value(serde_json::json!({ "original_url": target })).unwrap();
+        validate_create_request(&request, &config.validation_config()).map(|_| ())
+    }
+
+    #[test]
+    fn test_self_links_rejected_by_default() {
+        let config = config_from(&[
+            (SHORT_URL_BASE_ENV, "https://sq.rl"),
+            (SHORT_URL_HOSTS_ENV, "go.example.com"),
+        ])
+        .unwrap();
+
+        for target in ["https://sq.rl/abc123", "http://go.example.com/x"] {
+            assert!(
+                matches!(
+                    check_target(&config, target),
+                    Err(UrlShortenerError::ValidationError(_))
+                ),
+                "{} should be rejected",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn test_self_links_allowed_with_flag() {
+        let config = config_from(&[
+            (SHORT_URL_BASE_ENV, "https://sq.rl"),
+            (ALLOW_SELF_LINKS_ENV, "true"),
+        ])
+        .unwrap();
+
+        assert!(config.allow_self_links);
+        assert!(check_target(&config, "https://sq.rl/abc123").is_ok());
+    }
+
+    #[test]
+    fn test_unrelated_url_accepted() {
+        let config = config_from(&[(SHORT_URL_BASE_ENV, "https://sq.rl")]).unwrap();
+
+        assert!(check_target(&config, "https://example.com/sq.rl").is_ok());
+    }
+
+    #[test]
+    fn test_max_host_labels() {
+        assert_eq!(
+            config_from(&[]).unwrap().max_host_labels,
+            DEFAULT_MAX_HOST_LABELS
+        );
+
+        let config = config_from(&[(MAX_HOST_LABELS_ENV, "3")]).unwrap();
+        assert!(check_target(&config, "https://www.example.com").is_ok());
+        assert!(check_target(&config, "https://a.www.example.com").is_err());
+
+        for value in ["0", "many"] {
+            assert!(config_from(&[(MAX_HOST_LABELS_ENV, value)]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_invalid_base_url_rejected() {
+        for base in ["sq.rl", "ftp://sq.rl", "not a url"] {
+            assert!(
+                matches!(
+                    config_from(&[(SHORT_URL_BASE_ENV, base)]),
+                    Err(UrlShortenerError::InternalError(_))
+                ),
+                "{} should be rejected",
+                base
+            );
+        }
+    }
+}
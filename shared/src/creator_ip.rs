@@ -0,0 +1,104 @@
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+use crate::error::UrlShortenerError;
+
+/// Set to `true` to record a salted hash of each creator's IP, so abuse from
+/// one address can be correlated. The raw address is never stored.
+pub const HASH_CREATOR_IP_ENV: &str = "HASH_CREATOR_IP";
+
+/// Salt for creator IP hashes. Deployments inject it from Secrets Manager;
+/// rotating it breaks correlation with links created before.
+pub const CREATOR_IP_SALT_ENV: &str = "CREATOR_IP_SALT";
+
+/// Turns creator IPs into the opaque value stored as `creator_ip`
+#[derive(Clone, PartialEq)]
+pub struct CreatorIpHasher(Vec<u8>);
+
+impl std::fmt::Debug for CreatorIpHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CreatorIpHasher(<redacted>)")
+    }
+}
+
+impl CreatorIpHasher {
+    pub fn new(salt: impl Into<Vec<u8>>) -> Self {
+        Self(salt.into())
+    }
+
+    /// The hasher when `HASH_CREATOR_IP=true`, `None` when creator IPs aren't
+    /// recorded. Hashing without a salt is a configuration error.
+    pub fn from_lookup(
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Self>, UrlShortenerError> {
+        let enabled = lookup(HASH_CREATOR_IP_ENV)
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+        if !enabled {
+            return Ok(None);
+        }
+
+        match lookup(CREATOR_IP_SALT_ENV) {
+            Some(salt) if !salt.is_empty() => Ok(Some(Self::new(salt))),
+            _ => Err(UrlShortenerError::InternalError(anyhow::anyhow!(
+                "{} is set but {} is missing",
+                HASH_CREATOR_IP_ENV,
+                CREATOR_IP_SALT_ENV
+            ))),
+        }
+    }
+
+    /// Hex SHA-256 of the salt and the address. Parseable addresses are
+    /// canonicalized first, so `::1` and `0:0:0:0:0:0:0:1` hash alike.
+    pub fn hash(&self, ip: &str) -> String {
+        let ip = ip.trim();
+        let canonical = ip
+            .parse::<IpAddr>()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| ip.to_string());
+
+        let mut hasher = Sha256::new();
+        hasher.update(&self.0);
+        hasher.update(b"\0");
+        hasher.update(canonical.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_ip_hashes_consistently() {
+        let hasher = CreatorIpHasher::new("pepper");
+
+        let hash = hasher.hash("203.0.113.7");
+        assert_eq!(hash, hasher.hash("203.0.113.7"));
+        assert_eq!(hash, hasher.hash(" 203.0.113.7 "));
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("203.0.113.7"));
+
+        assert_eq!(hasher.hash("::1"), hasher.hash("0:0:0:0:0:0:0:1"));
+        assert_ne!(hash, hasher.hash("203.0.113.8"));
+        assert_ne!(hash, CreatorIpHasher::new("salt").hash("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_from_lookup() {
+        assert_eq!(CreatorIpHasher::from_lookup(|_| None).unwrap(), None);
+
+        let enabled = |salt: Option<&'static str>| {
+            move |key: &str| match key {
+                HASH_CREATOR_IP_ENV => Some("true".to_string()),
+                CREATOR_IP_SALT_ENV => salt.map(str::to_string),
+                _ => None,
+            }
+        };
+        assert_eq!(
+            CreatorIpHasher::from_lookup(enabled(Some("pepper"))).unwrap(),
+            Some(CreatorIpHasher::new("pepper"))
+        );
+        assert!(CreatorIpHasher::from_lookup(enabled(None)).is_err());
+        assert!(CreatorIpHasher::from_lookup(enabled(Some(""))).is_err());
+    }
+}
@@ -1,198 +1,2522 @@
+use aws_config::{BehaviorVersion, SdkConfig};
 use aws_sdk_dynamodb::Client;
-use aws_sdk_dynamodb::types::AttributeValue;
-use chrono::Utc;
-use std::collections::HashMap;
-use tracing::{info, instrument};
+use aws_sdk_dynamodb::config::interceptors::{
+    BeforeSerializationInterceptorContextRef, FinalizerInterceptorContextRef,
+};
+use aws_sdk_dynamodb::config::retry::RetryConfig;
+use aws_sdk_dynamodb::config::timeout::TimeoutConfig;
+use aws_sdk_dynamodb::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_dynamodb::error::{BoxError, BuildError, DisplayErrorContext, SdkError};
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::operation::update_item::UpdateItemError;
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, AttributeValue, BillingMode, CancellationReason, DeleteRequest,
+    GlobalSecondaryIndex, KeySchemaElement, KeyType, KeysAndAttributes, Projection, ProjectionType,
+    Put, ReturnValuesOnConditionCheckFailure, ScalarAttributeType, Select, TimeToLiveSpecification,
+    TransactWriteItem, WriteRequest,
+};
+use aws_smithy_types::config_bag::{Storable, StoreReplace};
+use chrono::{DateTime, Duration, Utc};
+use futures::{Stream, StreamExt, TryStreamExt, stream};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::{Arc, Once};
+use std::time::Instant;
+use tracing::{debug, info, instrument, warn};
 
+use crate::config::RuntimeConfig;
+use crate::creator_ip::CreatorIpHasher;
 use crate::error::UrlShortenerError;
-use crate::models::UrlItem;
+use crate::idempotency::IdempotencyKey;
+use crate::link_cache::{LinkCache, LinkCacheConfig};
+use crate::models::{RedirectTarget, TtlSource, UrlItem, UrlPage, expiry_grace_seconds};
+use crate::visitors::{MAX_DAILY_VISITOR_KEYS, VisitOutcome, classify_visit};
 
-#[derive(Clone)]
-pub struct DynamoDbClient {
-    client: Client,
-    table_name: String,
+/// Key of the reserved item that holds the sequential code counter. It shares the
+/// table with real links, so aggregates over the table must skip it.
+pub const COUNTER_ITEM_KEY: &str = "__counter__";
+
+/// Prefix shared by all reserved (non-link) items such as the counter and
+/// dedup markers. Table-wide scans filter these out.
+pub const INTERNAL_KEY_PREFIX: &str = "__";
+
+/// Prefix of the marker items that claim an original URL for dedup. The rest
+/// of the key is the hex SHA-256 of the normalized URL, so keys stay short
+/// whatever the URL's length.
+pub const DEDUP_MARKER_PREFIX: &str = "__dedup__#";
+
+/// Prefix of the items that record which link an idempotency key created.
+/// The rest of the key is the hex SHA-256 of the caller's idempotency key.
+pub const IDEMPOTENCY_KEY_PREFIX: &str = "__idem__#";
+
+/// How long an idempotency key is remembered before TTL removes its record
+pub const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+/// Set to `true` to read links with strongly consistent reads, so a redirect
+/// issued right after create can't miss the new item. Each such read costs
+/// twice the RCUs of an eventually consistent one.
+pub const STRONG_READS_ENV: &str = "STRONG_READS";
+
+pub fn strong_reads_from_env() -> bool {
+    env::var(STRONG_READS_ENV)
+        .map(|v| v.to_lowercase() == "true")
+        .unwrap_or(false)
 }
 
-impl DynamoDbClient {
-    pub fn new(client: Client, table_name: String) -> Self {
-        Self { client, table_name }
+/// Set to `true` to have the dev server create the table and its indexes
+/// when missing. Meant for LocalStack only; real tables come from Terraform.
+pub const AUTO_CREATE_TABLE_ENV: &str = "AUTO_CREATE_TABLE";
+
+pub fn auto_create_table_from_env() -> bool {
+    env::var(AUTO_CREATE_TABLE_ENV)
+        .map(|v| v.to_lowercase() == "true")
+        .unwrap_or(false)
+}
+
+/// GSI on `original_url`, used to dedup creates
+pub const ORIGINAL_URL_INDEX: &str = "original_url_index";
+
+/// Per-operation deadline for DynamoDB calls, in milliseconds, covering all
+/// retry attempts
+pub const DYNAMODB_TIMEOUT_MS_ENV: &str = "DYNAMODB_TIMEOUT_MS";
+pub const DEFAULT_DYNAMODB_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Attempts per DynamoDB call, the first included
+pub const DYNAMODB_MAX_ATTEMPTS_ENV: &str = "DYNAMODB_MAX_ATTEMPTS";
+pub const DEFAULT_DYNAMODB_MAX_ATTEMPTS: u32 = 3;
+
+/// Set to `false` for emulators without `TransactWriteItems`; `get_or_create`
+/// then falls back to a lookup and a conditional put
+pub const DYNAMODB_TRANSACTIONS_ENV: &str = "DYNAMODB_TRANSACTIONS";
+
+/// Timeout and retry settings applied to the SDK client, so a hung
+/// connection fails the call instead of holding the Lambda until it dies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientTuning {
+    pub operation_timeout: std::time::Duration,
+    pub max_attempts: u32,
+    pub transactions: bool,
+}
+
+impl Default for ClientTuning {
+    fn default() -> Self {
+        Self {
+            operation_timeout: DEFAULT_DYNAMODB_TIMEOUT,
+            max_attempts: DEFAULT_DYNAMODB_MAX_ATTEMPTS,
+            transactions: true,
+        }
     }
+}
 
-    #[instrument(skip(self), fields(short_code = %short_code))]
-    pub async fn get_url(&self, short_code: &str) -> Result<Option<UrlItem>, UrlShortenerError> {
-        info!("Retrieving URL for short code");
+impl ClientTuning {
+    /// Read from the environment; unset or unparseable values keep the default
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
 
-        let result = self
-            .client
-            .get_item()
-            .table_name(&self.table_name)
-            .key("short_code", AttributeValue::S(short_code.to_string()))
-            .send()
-            .await
-            .map_err(|e| UrlShortenerError::DatabaseError(e.to_string()))?;
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Self::default();
+        Self {
+            operation_timeout: lookup(DYNAMODB_TIMEOUT_MS_ENV)
+                .and_then(|ms| ms.trim().parse().ok())
+                .filter(|ms| *ms > 0)
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(defaults.operation_timeout),
+            max_attempts: lookup(DYNAMODB_MAX_ATTEMPTS_ENV)
+                .and_then(|n| n.trim().parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or(defaults.max_attempts),
+            transactions: lookup(DYNAMODB_TRANSACTIONS_ENV)
+                .map(|v| v.trim().to_lowercase() != "false")
+                .unwrap_or(defaults.transactions),
+        }
+    }
+}
 
-        if let Some(item) = result.item {
-            let url_item = self.item_to_url_item(item)?;
+/// How long `ensure_schema` waits for a new table to become active
+const SCHEMA_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+const SCHEMA_POLL_ATTEMPTS: usize = 60;
 
-            // Check if URL has expired
-            if let Some(expires_at) = url_item.expires_at {
-                let now = Utc::now().timestamp();
-                if now > expires_at {
-                    return Err(UrlShortenerError::UrlExpired);
-                }
-            }
+const MAX_CREATE_ATTEMPTS: usize = 3;
 
-            Ok(Some(url_item))
+/// Attributes read by `get_redirect_target`. `status` is a reserved word.
+const REDIRECT_PROJECTION: &str = "original_url, expires_at, #status, password_hash, max_uses, \
+     redirect_status, wildcard, click_count, extra_headers, canonical_code";
+
+/// Attributes read into a `UrlItem`. Leaves out the day's `visitor_keys`
+/// set, which can grow to `MAX_DAILY_VISITOR_KEYS` entries and is only
+/// needed by the conditional updates that maintain it.
+const URL_ITEM_PROJECTION: &str = "short_code, original_url, created_at, expires_at, click_count, \
+     custom_code, #status, password_hash, max_uses, redirect_status, canonical_code, wildcard, \
+     creator_ip, unique_visitors, extra_headers, ttl_source, schema_version";
+
+/// Layout version stamped on every stored link as `schema_version`. Items
+/// written before the attribute existed are version 1. Bump it when a new
+/// attribute needs filling in on old items, and teach `migrate_item` how.
+pub const CURRENT_SCHEMA_VERSION: u8 = 2;
+
+/// Most `UpdateItem` calls a click flush keeps in flight at once
+const CLICK_FLUSH_CONCURRENCY: usize = 16;
+
+/// `BatchWriteItem` accepts at most 25 requests per call
+const MAX_BATCH_WRITE: i32 = 25;
+const MAX_UNPROCESSED_RETRIES: usize = 3;
+
+/// `BatchGetItem` accepts at most 100 keys per call
+const MAX_BATCH_GET: usize = 100;
+
+/// Whether the table can serve requests, per `describe_table_status`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableStatus {
+    /// Serving requests, including while its settings are being updated
+    Active,
+    Creating,
+    NotFound,
+    /// Exists but can't serve requests (being deleted, archived, or its
+    /// encryption key is inaccessible); holds DynamoDB's status
+    Unavailable(String),
+}
+
+impl TableStatus {
+    fn from_sdk(status: Option<&aws_sdk_dynamodb::types::TableStatus>) -> Self {
+        use aws_sdk_dynamodb::types::TableStatus as Sdk;
+
+        match status {
+            Some(Sdk::Active | Sdk::Updating) => TableStatus::Active,
+            Some(Sdk::Creating) => TableStatus::Creating,
+            Some(other) => TableStatus::Unavailable(other.as_str().to_string()),
+            None => TableStatus::Unavailable("UNKNOWN".to_string()),
+        }
+    }
+}
+
+/// The form of `original_url` that dedup matches on: the URL as the parser
+/// serializes it, which dedup markers are claimed under
+pub fn dedup_key(original_url: &str) -> Option<String> {
+    url::Url::parse(original_url).ok().map(String::from)
+}
+
+/// Key of the dedup marker that claims `normalized_url`
+pub fn dedup_marker_key(normalized_url: &str) -> String {
+    format!(
+        "{}{}",
+        DEDUP_MARKER_PREFIX,
+        hex::encode(Sha256::digest(normalized_url.as_bytes()))
+    )
+}
+
+/// A dedup marker write: the marker item, and the condition under which it
+/// may replace whatever already holds its key
+struct MarkerClaim {
+    item: HashMap<String, AttributeValue>,
+    condition: String,
+    values: HashMap<String, AttributeValue>,
+}
+
+/// Totals from `reindex_dedup`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReindexReport {
+    pub scanned: u64,
+    /// Markers written for links that had lost theirs
+    pub restored: u64,
+}
+
+/// Bring a stored link up to `CURRENT_SCHEMA_VERSION` in memory, one version
+/// at a time. Nothing is written back; the item is upgraded on its next put.
+/// Items from a newer version are passed through, since readers ignore
+/// attributes they don't know.
+pub fn migrate_item(mut item: HashMap<String, AttributeValue>) -> HashMap<String, AttributeValue> {
+    let mut version = item
+        .get("schema_version")
+        .and_then(|v| v.as_n().ok())
+        .and_then(|n| n.parse::<u8>().ok())
+        .unwrap_or(1);
+
+    while version < CURRENT_SCHEMA_VERSION {
+        // v1 items predate click counting and status flags
+        if version == 1 {
+            item.entry("click_count".to_string())
+                .or_insert_with(|| AttributeValue::N("0".to_string()));
+            item.entry("custom_code".to_string())
+                .or_insert(AttributeValue::Bool(false));
+            item.entry("status".to_string())
+                .or_insert_with(|| AttributeValue::S("active".to_string()));
+        }
+        version += 1;
+    }
+
+    item.insert(
+        "schema_version".to_string(),
+        AttributeValue::N(version.to_string()),
+    );
+    item
+}
+
+/// String attribute definition for a key attribute
+fn attribute_definition(name: &str) -> Result<AttributeDefinition, UrlShortenerError> {
+    AttributeDefinition::builder()
+        .attribute_name(name)
+        .attribute_type(ScalarAttributeType::S)
+        .build()
+        .map_err(|e| UrlShortenerError::InternalError(e.into()))
+}
+
+fn hash_key(name: &str) -> Result<KeySchemaElement, UrlShortenerError> {
+    KeySchemaElement::builder()
+        .attribute_name(name)
+        .key_type(KeyType::Hash)
+        .build()
+        .map_err(|e| UrlShortenerError::InternalError(e.into()))
+}
+
+// LocalStack and older SDK paths don't always surface a typed
+// `ResourceNotFoundException`, so the rendered error is checked as well
+fn is_table_not_found(message: &str) -> bool {
+    message.contains("ResourceNotFoundException")
+}
+
+/// Whether an SDK error is a timeout: the operation timeout or a
+/// connect/read timeout in the HTTP client
+fn is_timeout<E, R>(e: &SdkError<E, R>) -> bool {
+    match e {
+        SdkError::TimeoutError(_) => true,
+        SdkError::DispatchFailure(failure) => failure.is_timeout(),
+        _ => false,
+    }
+}
+
+/// A request builder missing a required field, which is a bug on our side
+fn build_error(e: BuildError) -> UrlShortenerError {
+    UrlShortenerError::InternalError(e.into())
+}
+
+/// Map an SDK error to `DatabaseTimeout` or `DatabaseError`, keeping the
+/// full error chain in the message
+fn database_error<E, R>(e: SdkError<E, R>) -> UrlShortenerError
+where
+    E: std::error::Error + 'static,
+    R: std::fmt::Debug,
+{
+    let message = DisplayErrorContext(&e).to_string();
+    if is_timeout(&e) {
+        UrlShortenerError::DatabaseTimeout(message)
+    } else {
+        UrlShortenerError::DatabaseError(message)
+    }
+}
+
+/// What a dedup marker points at
+enum MarkerTarget {
+    /// A link for the marker's URL that may be reused
+    Live(Box<UrlItem>),
+    /// Nothing reusable. Holds the marker's `target_code`, if there is a
+    /// marker, so the next claim overwrites only that stale marker.
+    Stale(Option<String>),
+}
+
+/// Result of `create_url_transactional`
+#[derive(Debug)]
+pub enum CreateOutcome {
+    /// The item and its dedup marker were written
+    Created,
+    /// Another request claimed the URL first; this is its link
+    Existing(Box<UrlItem>),
+}
+
+impl CreateOutcome {
+    /// Read `get_or_create`'s `(item, created)` the same way
+    pub fn from_get_or_create((item, created): (UrlItem, bool)) -> Self {
+        if created {
+            Self::Created
         } else {
-            Ok(None)
+            Self::Existing(Box::new(item))
         }
     }
+}
 
-    #[instrument(skip(self), fields(original_url = %original_url))]
-    pub async fn find_existing_url(
-        &self,
-        original_url: &str,
-    ) -> Result<Option<UrlItem>, UrlShortenerError> {
-        info!("Checking for existing URL");
+/// Which write in the create transaction lost
+#[derive(Debug, PartialEq)]
+enum TransactConflict {
+    DuplicateUrl,
+    ShortCodeTaken,
+    Other,
+}
 
-        let result = self
-            .client
-            .query()
-            .table_name(&self.table_name)
-            .index_name("original_url_index")
-            .key_condition_expression("original_url = :url")
-            .expression_attribute_values(":url", AttributeValue::S(original_url.to_string()))
-            .send()
-            .await
-            .map_err(|e| UrlShortenerError::DatabaseError(e.to_string()))?;
+/// Reasons are positional: 0 is the URL item, 1 the dedup marker. A URL
+/// conflict wins over a code conflict since the caller then returns the
+/// existing link and the code no longer matters.
+fn classify_cancellation(reasons: &[CancellationReason]) -> TransactConflict {
+    let failed_condition =
+        |index: usize| reasons.get(index).and_then(|r| r.code()) == Some("ConditionalCheckFailed");
 
-        if let Some(items) = result.items
-            && let Some(item) = items.into_iter().next()
-        {
-            let url_item = self.item_to_url_item(item)?;
-            return Ok(Some(url_item));
+    if failed_condition(1) {
+        TransactConflict::DuplicateUrl
+    } else if failed_condition(0) {
+        TransactConflict::ShortCodeTaken
+    } else {
+        TransactConflict::Other
+    }
+}
+
+/// The error for a cancelled idempotent create. The key record is the second
+/// write, where the dedup marker usually sits; a used key wins over a code
+/// conflict, since the caller then reads back the earlier result.
+fn idempotent_create_error(
+    reasons: &[CancellationReason],
+    short_code: &str,
+    idempotency_key: &str,
+) -> Option<UrlShortenerError> {
+    match classify_cancellation(reasons) {
+        TransactConflict::DuplicateUrl => Some(UrlShortenerError::IdempotencyConflict(
+            idempotency_key.to_string(),
+        )),
+        TransactConflict::ShortCodeTaken => {
+            Some(UrlShortenerError::ShortCodeExists(short_code.to_string()))
         }
+        TransactConflict::Other => None,
+    }
+}
 
-        Ok(None)
+/// Largest item `put_url` will attempt. DynamoDB's limit is 400 KB; the
+/// margin leaves room for attributes added after create, such as the daily
+/// visitor set.
+pub const MAX_ITEM_SIZE_BYTES: usize = 350 * 1024;
+
+/// Approximate stored size of `item`, counted the way DynamoDB bills it:
+/// attribute names plus values, with numbers at about a byte per two digits
+pub fn estimate_item_size(item: &UrlItem) -> usize {
+    url_item_attributes(item)
+        .iter()
+        .map(|(name, value)| name.len() + attribute_value_size(value))
+        .sum()
+}
+
+fn attribute_value_size(value: &AttributeValue) -> usize {
+    match value {
+        AttributeValue::S(s) => s.len(),
+        AttributeValue::N(n) => n.len().div_ceil(2) + 1,
+        AttributeValue::B(b) => b.as_ref().len(),
+        AttributeValue::Ss(values) => values.iter().map(String::len).sum(),
+        AttributeValue::Ns(values) => values.iter().map(|n| n.len().div_ceil(2) + 1).sum(),
+        AttributeValue::L(values) => {
+            3 + values
+                .iter()
+                .map(|v| 1 + attribute_value_size(v))
+                .sum::<usize>()
+        }
+        AttributeValue::M(values) => {
+            3 + values
+                .iter()
+                .map(|(name, v)| 1 + name.len() + attribute_value_size(v))
+                .sum::<usize>()
+        }
+        _ => 1,
     }
+}
 
-    #[instrument(skip(self, url_item))]
-    pub async fn put_url(&self, url_item: &UrlItem) -> Result<(), UrlShortenerError> {
-        info!("Storing URL item");
+/// The `extra_headers` map attribute, skipping any non-string entries
+fn extra_headers_from(item: &HashMap<String, AttributeValue>) -> Option<HashMap<String, String>> {
+    let headers = item.get("extra_headers")?.as_m().ok()?;
+    Some(
+        headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value.as_s().ok()?.clone())))
+            .collect(),
+    )
+}
+
+/// Refuse `item` up front if DynamoDB would, so the caller gets an
+/// actionable error instead of an opaque service failure
+fn check_item_size(item: &UrlItem) -> Result<(), UrlShortenerError> {
+    let size = estimate_item_size(item);
+    if size > MAX_ITEM_SIZE_BYTES {
+        return Err(UrlShortenerError::PayloadTooLarge(format!(
+            "item too large: about {} bytes, at most {} allowed; shorten the URL or its headers",
+            size, MAX_ITEM_SIZE_BYTES
+        )));
+    }
+    Ok(())
+}
 
-        let mut item = HashMap::new();
+fn url_item_attributes(url_item: &UrlItem) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::new();
+    item.insert(
+        "schema_version".to_string(),
+        AttributeValue::N(CURRENT_SCHEMA_VERSION.to_string()),
+    );
+    item.insert(
+        "short_code".to_string(),
+        AttributeValue::S(url_item.short_code.clone()),
+    );
+    item.insert(
+        "original_url".to_string(),
+        AttributeValue::S(url_item.original_url.clone()),
+    );
+    item.insert(
+        "created_at".to_string(),
+        AttributeValue::S(url_item.created_at.clone()),
+    );
+    // Numeric copy of created_at so range filters compare numbers, not strings
+    if let Ok(created_at) = DateTime::parse_from_rfc3339(&url_item.created_at) {
         item.insert(
-            "short_code".to_string(),
-            AttributeValue::S(url_item.short_code.clone()),
+            "created_ts".to_string(),
+            AttributeValue::N(created_at.timestamp().to_string()),
+        );
+    }
+    item.insert(
+        "click_count".to_string(),
+        AttributeValue::N(url_item.click_count.to_string()),
+    );
+    item.insert(
+        "custom_code".to_string(),
+        AttributeValue::Bool(url_item.custom_code),
+    );
+    item.insert(
+        "status".to_string(),
+        AttributeValue::S(url_item.status.clone()),
+    );
+
+    if let Some(expires_at) = url_item.expires_at {
+        item.insert(
+            "expires_at".to_string(),
+            AttributeValue::N(expires_at.to_string()),
         );
+    }
+
+    if let Some(password_hash) = &url_item.password_hash {
         item.insert(
-            "original_url".to_string(),
-            AttributeValue::S(url_item.original_url.clone()),
+            "password_hash".to_string(),
+            AttributeValue::S(password_hash.clone()),
         );
+    }
+
+    if let Some(max_uses) = url_item.max_uses {
         item.insert(
-            "created_at".to_string(),
-            AttributeValue::S(url_item.created_at.clone()),
+            "max_uses".to_string(),
+            AttributeValue::N(max_uses.to_string()),
+        );
+    }
+
+    if let Some(redirect_status) = url_item.redirect_status {
+        item.insert(
+            "redirect_status".to_string(),
+            AttributeValue::N(redirect_status.to_string()),
+        );
+    }
+
+    if let Some(canonical_code) = &url_item.canonical_code {
+        item.insert(
+            "canonical_code".to_string(),
+            AttributeValue::S(canonical_code.clone()),
         );
+    }
+
+    if url_item.wildcard {
+        item.insert("wildcard".to_string(), AttributeValue::Bool(true));
+    }
+
+    if let Some(creator_ip) = &url_item.creator_ip {
         item.insert(
-            "click_count".to_string(),
-            AttributeValue::N(url_item.click_count.to_string()),
+            "creator_ip".to_string(),
+            AttributeValue::S(creator_ip.clone()),
         );
+    }
+
+    if let Some(unique_visitors) = url_item.unique_visitors {
         item.insert(
-            "custom_code".to_string(),
-            AttributeValue::Bool(url_item.custom_code),
+            "unique_visitors".to_string(),
+            AttributeValue::N(unique_visitors.to_string()),
         );
+    }
+
+    if let Some(extra_headers) = &url_item.extra_headers {
         item.insert(
-            "status".to_string(),
-            AttributeValue::S(url_item.status.clone()),
+            "extra_headers".to_string(),
+            AttributeValue::M(
+                extra_headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), AttributeValue::S(value.clone())))
+                    .collect(),
+            ),
         );
+    }
 
-        if let Some(expires_at) = url_item.expires_at {
-            item.insert(
-                "expires_at".to_string(),
-                AttributeValue::N(expires_at.to_string()),
-            );
+    if let Some(ttl_source) = url_item.ttl_source {
+        item.insert(
+            "ttl_source".to_string(),
+            AttributeValue::S(ttl_source.as_str().to_string()),
+        );
+    }
+
+    item
+}
+
+const MAX_SUGGESTION_LOOKUPS: usize = 10;
+const MAX_CUSTOM_CODE_LEN: usize = 20;
+
+fn suggestion_candidates(code: &str) -> impl Iterator<Item = String> + '_ {
+    let with_suffix = move |suffix: String| {
+        // Trim the base so the suggestion still passes validate_custom_code
+        let max_base = MAX_CUSTOM_CODE_LEN - suffix.len() - 1;
+        let base: String = code.chars().take(max_base).collect();
+        format!("{}-{}", base, suffix)
+    };
+
+    (2..=4)
+        .map(|n| n.to_string())
+        .chain(std::iter::repeat_with(|| {
+            nanoid::nanoid!(3, &nanoid::alphabet::SAFE)
+        }))
+        .map(with_suffix)
+}
+
+/// Parse an RFC 3339 `from..=to` range into unix seconds
+fn created_ts_range(from: &str, to: &str) -> Result<(i64, i64), UrlShortenerError> {
+    let parse = |value: &str| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|ts| ts.timestamp())
+            .map_err(|_| {
+                UrlShortenerError::ValidationError(format!(
+                    "Expected an RFC 3339 timestamp, got \"{}\"",
+                    value
+                ))
+            })
+    };
+
+    let (from, to) = (parse(from)?, parse(to)?);
+    if from > to {
+        return Err(UrlShortenerError::ValidationError(
+            "Range start must not be after its end".to_string(),
+        ));
+    }
+
+    Ok((from, to))
+}
+
+/// Set to `false` to let creates reuse an expired link for the same URL
+pub const DEDUP_IGNORE_EXPIRED_ENV: &str = "DEDUP_IGNORE_EXPIRED";
+
+/// Only reuse links created within this many hours; unset for no limit
+pub const DEDUP_MAX_AGE_HOURS_ENV: &str = "DEDUP_MAX_AGE_HOURS";
+
+/// Which existing items `find_existing_url` may hand back for deduplication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupPolicy {
+    /// Skip items whose `expires_at` has passed
+    pub ignore_expired: bool,
+    /// Only match items created within this many hours
+    pub max_age_hours: Option<u64>,
+}
+
+impl Default for DedupPolicy {
+    fn default() -> Self {
+        Self {
+            ignore_expired: true,
+            max_age_hours: None,
         }
+    }
+}
 
-        self.client
-            .put_item()
-            .table_name(&self.table_name)
-            .set_item(Some(item))
-            .condition_expression("attribute_not_exists(short_code)")
-            .send()
-            .await
-            .map_err(|e| {
-                if e.to_string().contains("ConditionalCheckFailedException") {
-                    UrlShortenerError::ShortCodeExists(url_item.short_code.clone())
-                } else {
-                    UrlShortenerError::DatabaseError(e.to_string())
-                }
-            })?;
+impl DedupPolicy {
+    /// Read `DEDUP_IGNORE_EXPIRED` (default `true`) and `DEDUP_MAX_AGE_HOURS`
+    /// (default unset, i.e. no age limit)
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
 
-        Ok(())
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Self::default();
+        Self {
+            ignore_expired: lookup(DEDUP_IGNORE_EXPIRED_ENV)
+                .map(|v| v.trim().to_lowercase() != "false")
+                .unwrap_or(defaults.ignore_expired),
+            max_age_hours: lookup(DEDUP_MAX_AGE_HOURS_ENV)
+                .and_then(|v| v.trim().parse().ok())
+                .or(defaults.max_age_hours),
+        }
     }
 
-    #[instrument(skip(self), fields(short_code = %short_code))]
-    pub async fn increment_click_count(&self, short_code: &str) -> Result<(), UrlShortenerError> {
-        info!("Incrementing click count");
+    /// Whether `item` may be reused at time `now`
+    pub fn matches(&self, item: &UrlItem, now: DateTime<Utc>) -> bool {
+        if self.ignore_expired
+            && let Some(expires_at) = item.expires_at
+            && now.timestamp() > expires_at
+        {
+            return false;
+        }
 
-        self.client
-            .update_item()
-            .table_name(&self.table_name)
-            .key("short_code", AttributeValue::S(short_code.to_string()))
-            .update_expression("ADD click_count :inc")
-            .expression_attribute_values(":inc", AttributeValue::N("1".to_string()))
-            .send()
-            .await
-            .map_err(|e| UrlShortenerError::DatabaseError(e.to_string()))?;
+        if let Some(max_age_hours) = self.max_age_hours {
+            // An unparseable created_at can't prove it is recent enough
+            let Ok(created_at) = DateTime::parse_from_rfc3339(&item.created_at) else {
+                return false;
+            };
+            if now.signed_duration_since(created_at) > Duration::hours(max_age_hours as i64) {
+                return false;
+            }
+        }
 
-        Ok(())
+        true
     }
+}
 
-    fn item_to_url_item(
-        &self,
-        item: HashMap<String, AttributeValue>,
-    ) -> Result<UrlItem, UrlShortenerError> {
-        let short_code = item
-            .get("short_code")
-            .and_then(|v| v.as_s().ok())
-            .ok_or_else(|| UrlShortenerError::InternalError(anyhow::anyhow!("Missing short_code")))?
-            .clone();
+/// Hot links held in front of `get_url` and `get_redirect_target`
+struct LinkCaches {
+    urls: LinkCache<UrlItem>,
+    redirect_targets: LinkCache<RedirectTarget>,
+}
 
-        let original_url = item
-            .get("original_url")
-            .and_then(|v| v.as_s().ok())
-            .ok_or_else(|| {
-                UrlShortenerError::InternalError(anyhow::anyhow!("Missing original_url"))
-            })?
-            .clone();
+/// Flips once per container: clones of a client share it, and each Lambda
+/// builds its client once at startup
+#[derive(Debug, Clone)]
+struct FirstCall(Arc<Once>);
 
-        let created_at = item
-            .get("created_at")
-            .and_then(|v| v.as_s().ok())
-            .ok_or_else(|| UrlShortenerError::InternalError(anyhow::anyhow!("Missing created_at")))?
-            .clone();
+impl Default for FirstCall {
+    fn default() -> Self {
+        Self(Arc::new(Once::new()))
+    }
+}
 
-        let expires_at = item
-            .get("expires_at")
-            .and_then(|v| v.as_n().ok())
-            .and_then(|s| s.parse().ok());
+impl FirstCall {
+    /// `true` for the first caller only
+    fn claim(&self) -> bool {
+        let mut first = false;
+        self.0.call_once(|| first = true);
+        first
+    }
+}
 
-        let click_count = item
-            .get("click_count")
-            .and_then(|v| v.as_n().ok())
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0);
+/// When the call in flight started
+#[derive(Debug, Clone)]
+struct CallStarted(Instant);
 
-        let custom_code = item
-            .get("custom_code")
-            .and_then(|v| v.as_bool().ok().copied())
-            .unwrap_or(false);
+impl Storable for CallStarted {
+    type Storer = StoreReplace<Self>;
+}
 
-        let status = item
-            .get("status")
-            .and_then(|v| v.as_s().ok())
-            .map(String::from)
-            .unwrap_or_else(|| "active".to_string());
+/// Times every call the SDK client makes, whatever the operation. The
+/// container's first one, connection setup included, is logged as
+/// `first_call_latency_ms` and later ones as `call_latency_ms` at debug, so
+/// cold starts show up on their own.
+#[derive(Debug)]
+struct CallTimer(FirstCall);
+
+impl Intercept for CallTimer {
+    fn name(&self) -> &'static str {
+        "CallTimer"
+    }
+
+    fn read_before_execution(
+        &self,
+        _context: &BeforeSerializationInterceptorContextRef<'_>,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        cfg.interceptor_state()
+            .store_put(CallStarted(Instant::now()));
+        Ok(())
+    }
+
+    fn read_after_execution(
+        &self,
+        _context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if let Some(CallStarted(started)) = cfg.load::<CallStarted>() {
+            let latency_ms = started.elapsed().as_millis() as u64;
+            if self.0.claim() {
+                info!(
+                    first_call_latency_ms = latency_ms,
+                    "First DynamoDB call in this container"
+                );
+            } else {
+                debug!(call_latency_ms = latency_ms, "DynamoDB call");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `client` with a `CallTimer` reporting its first call through `first_call`
+fn with_call_timer(client: Client, first_call: FirstCall) -> Client {
+    Client::from_conf(
+        client
+            .config()
+            .to_builder()
+            .interceptor(CallTimer(first_call))
+            .build(),
+    )
+}
+
+#[derive(Clone)]
+pub struct DynamoDbClient {
+    client: Client,
+    table_name: String,
+    dedup_policy: DedupPolicy,
+    consistent_reads: bool,
+    transactions: bool,
+    link_caches: Option<Arc<LinkCaches>>,
+}
+
+impl DynamoDbClient {
+    pub fn new(client: Client, table_name: String) -> Self {
+        Self {
+            client: with_call_timer(client, FirstCall::default()),
+            table_name,
+            dedup_policy: DedupPolicy::default(),
+            consistent_reads: false,
+            transactions: true,
+            link_caches: None,
+        }
+    }
+
+    /// Load the default AWS config and build via `from_config`, with the
+    /// table and endpoint from `runtime_config` and the `DYNAMODB_*` tuning
+    /// variables
+    pub async fn from_runtime_config(runtime_config: &RuntimeConfig) -> Self {
+        let sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+
+        Self::from_config(
+            &sdk_config,
+            runtime_config.aws_endpoint_url.as_deref(),
+            runtime_config.table_name.clone(),
+            ClientTuning::from_env(),
+        )
+    }
+
+    /// Point at `endpoint_url` (LocalStack) when given, otherwise at AWS
+    pub fn from_config(
+        sdk_config: &SdkConfig,
+        endpoint_url: Option<&str>,
+        table_name: String,
+        tuning: ClientTuning,
+    ) -> Self {
+        let mut builder = aws_sdk_dynamodb::config::Builder::from(sdk_config)
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .operation_timeout(tuning.operation_timeout)
+                    .build(),
+            )
+            .retry_config(RetryConfig::standard().with_max_attempts(tuning.max_attempts));
+        match endpoint_url {
+            Some(endpoint_url) => {
+                info!("Using LocalStack endpoint: {}", endpoint_url);
+                builder = builder.endpoint_url(endpoint_url);
+            }
+            None => info!("Using AWS DynamoDB"),
+        }
+        info!("Using DynamoDB table: {}", table_name);
+
+        Self::new(Client::from_conf(builder.build()), table_name)
+            .with_transactions(tuning.transactions)
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    pub fn with_dedup_policy(mut self, dedup_policy: DedupPolicy) -> Self {
+        self.dedup_policy = dedup_policy;
+        self
+    }
+
+    /// Use strongly consistent reads for `get_url` and `exists`
+    pub fn with_consistent_reads(mut self, consistent_reads: bool) -> Self {
+        self.consistent_reads = consistent_reads;
+        self
+    }
+
+    /// Whether `get_or_create` may use `TransactWriteItems`. Turn off for
+    /// emulators that don't implement it.
+    pub fn with_transactions(mut self, transactions: bool) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    /// Serve repeat `get_url` and `get_redirect_target` reads from memory.
+    /// Overwrites and deletes through this client drop the link from the
+    /// cache; writes by other instances show once the entry's TTL runs out,
+    /// as do click counts.
+    pub fn with_link_cache(mut self, config: Option<LinkCacheConfig>) -> Self {
+        self.link_caches = config.map(|config| {
+            Arc::new(LinkCaches {
+                urls: LinkCache::new(config),
+                redirect_targets: LinkCache::new(config),
+            })
+        });
+        self
+    }
+
+    /// Forget any cached copy of `short_code`
+    pub fn invalidate_cached(&self, short_code: &str) {
+        if let Some(caches) = &self.link_caches {
+            caches.urls.invalidate(short_code);
+            caches.redirect_targets.invalidate(short_code);
+        }
+    }
+
+    #[instrument(skip(self), fields(short_code = %short_code))]
+    pub async fn get_url(&self, short_code: &str) -> Result<Option<UrlItem>, UrlShortenerError> {
+        let now = Utc::now().timestamp();
+        if let Some(cached) = self
+            .link_caches
+            .as_ref()
+            .and_then(|caches| caches.urls.get(short_code, now))
+        {
+            return Ok(Some(cached));
+        }
+
+        info!("Retrieving URL for short code");
+
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .projection_expression(URL_ITEM_PROJECTION)
+            .expression_attribute_names("#status", "status")
+            .consistent_read(self.consistent_reads)
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        if let Some(item) = result.item {
+            let url_item = self.item_to_url_item(item)?;
+
+            if url_item.is_expired(now, expiry_grace_seconds()) {
+                return Err(UrlShortenerError::UrlExpired);
+            }
+
+            if let Some(caches) = &self.link_caches {
+                caches
+                    .urls
+                    .insert(short_code, url_item.clone(), url_item.expires_at, now);
+            }
+            Ok(Some(url_item))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Find up to `count` free variants of a taken custom code: `code-2`,
+    /// `code-3`, ... then random suffixes. Lookups are capped so a crowded
+    /// namespace can't stall the error response.
+    #[instrument(skip(self), fields(short_code = %code))]
+    pub async fn suggest_available_codes(
+        &self,
+        code: &str,
+        count: usize,
+    ) -> Result<Vec<String>, UrlShortenerError> {
+        let mut suggestions = Vec::with_capacity(count);
+
+        for candidate in suggestion_candidates(code).take(MAX_SUGGESTION_LOOKUPS) {
+            if suggestions.len() == count {
+                break;
+            }
+
+            match self.get_url(&candidate).await {
+                Ok(None) => suggestions.push(candidate),
+                // Expired items still occupy their code until TTL deletes them
+                Ok(Some(_)) | Err(UrlShortenerError::UrlExpired) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(suggestions)
+    }
+
+    /// Like `get_url`, but reads only the attributes a redirect needs. An
+    /// alias resolves to its canonical link, so the two share one password,
+    /// use limit and click counter, and an alias stops resolving once its
+    /// canonical link is gone.
+    #[instrument(skip(self), fields(short_code = %short_code))]
+    pub async fn get_redirect_target(
+        &self,
+        short_code: &str,
+    ) -> Result<Option<RedirectTarget>, UrlShortenerError> {
+        let now = Utc::now().timestamp();
+        if let Some(cached) = self
+            .link_caches
+            .as_ref()
+            .and_then(|caches| caches.redirect_targets.get(short_code, now))
+        {
+            return Ok(Some(cached));
+        }
+
+        let Some(mut target) = self.read_redirect_target(short_code).await? else {
+            return Ok(None);
+        };
+        if let Some(canonical_code) = target.canonical_code.take() {
+            let Some(canonical) = self.read_redirect_target(&canonical_code).await? else {
+                return Ok(None);
+            };
+            target = RedirectTarget {
+                canonical_code: Some(canonical_code),
+                ..canonical
+            };
+        }
+
+        if target.is_expired(now, expiry_grace_seconds()) {
+            return Err(UrlShortenerError::UrlExpired);
+        }
+
+        if let Some(caches) = &self.link_caches {
+            caches
+                .redirect_targets
+                .insert(short_code, target.clone(), target.expires_at, now);
+        }
+        Ok(Some(target))
+    }
+
+    /// One stored item's redirect attributes, without following aliases
+    async fn read_redirect_target(
+        &self,
+        short_code: &str,
+    ) -> Result<Option<RedirectTarget>, UrlShortenerError> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .projection_expression(REDIRECT_PROJECTION)
+            .expression_attribute_names("#status", "status")
+            .consistent_read(self.consistent_reads)
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        result
+            .item
+            .map(|item| self.item_to_redirect_target(item))
+            .transpose()
+    }
+
+    /// Whether an item is stored under `short_code`. Only the key is read back,
+    /// so this is cheaper than `get_url`, but it says nothing about expiry,
+    /// passwords or use limits.
+    #[instrument(skip(self), fields(short_code = %short_code))]
+    pub async fn exists(&self, short_code: &str) -> Result<bool, UrlShortenerError> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .projection_expression("short_code")
+            .consistent_read(self.consistent_reads)
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        Ok(result.item.is_some())
+    }
+
+    /// Look up many links at once, one `BatchGetItem` per 100 distinct codes,
+    /// for callers that would otherwise `get_url` once per event. Codes that
+    /// don't exist, or whose link has expired, are absent from the result.
+    #[instrument(skip(self, short_codes), fields(codes = short_codes.len()))]
+    pub async fn batch_get_urls(
+        &self,
+        short_codes: &[String],
+    ) -> Result<HashMap<String, UrlItem>, UrlShortenerError> {
+        let distinct: Vec<&String> = short_codes
+            .iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let now = Utc::now().timestamp();
+        let mut found = HashMap::new();
+
+        for chunk in distinct.chunks(MAX_BATCH_GET) {
+            let mut keys: Vec<HashMap<String, AttributeValue>> = chunk
+                .iter()
+                .map(|code| {
+                    HashMap::from([(
+                        "short_code".to_string(),
+                        AttributeValue::S(code.to_string()),
+                    )])
+                })
+                .collect();
+
+            for _ in 0..=MAX_UNPROCESSED_RETRIES {
+                if keys.is_empty() {
+                    break;
+                }
+
+                let request = KeysAndAttributes::builder()
+                    .set_keys(Some(keys))
+                    .consistent_read(self.consistent_reads)
+                    .build()
+                    .map_err(build_error)?;
+                let result = self
+                    .client
+                    .batch_get_item()
+                    .request_items(&self.table_name, request)
+                    .send()
+                    .await
+                    .map_err(database_error)?;
+
+                for item in result
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                    .unwrap_or_default()
+                {
+                    let url_item = self.item_to_url_item(item)?;
+                    if !url_item.is_expired(now, expiry_grace_seconds()) {
+                        found.insert(url_item.short_code.clone(), url_item);
+                    }
+                }
+
+                keys = result
+                    .unprocessed_keys
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                    .map(|unprocessed| unprocessed.keys)
+                    .unwrap_or_default();
+            }
+
+            if !keys.is_empty() {
+                return Err(UrlShortenerError::DatabaseError(format!(
+                    "{} keys left unprocessed by BatchGetItem",
+                    keys.len()
+                )));
+            }
+        }
+
+        Ok(found)
+    }
+
+    #[instrument(skip(self), fields(original_url = %original_url))]
+    pub async fn find_existing_url(
+        &self,
+        original_url: &str,
+    ) -> Result<Option<UrlItem>, UrlShortenerError> {
+        info!("Checking for existing URL");
+
+        let result = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .index_name(ORIGINAL_URL_INDEX)
+            .key_condition_expression("original_url = :url")
+            .expression_attribute_values(":url", AttributeValue::S(original_url.to_string()))
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        // Stale matches (expired, or older than the dedup window) are skipped so
+        // the caller creates a fresh code instead
+        let now = Utc::now();
+        for item in result.items.unwrap_or_default() {
+            let url_item = self.item_to_url_item(item)?;
+            if self.dedup_policy.matches(&url_item, now) {
+                return Ok(Some(url_item));
+            }
+        }
+
+        Ok(None)
+    }
+
+    #[instrument(skip(self, url_item))]
+    pub async fn put_url(&self, url_item: &UrlItem) -> Result<(), UrlShortenerError> {
+        info!("Storing URL item");
+
+        check_item_size(url_item)?;
+        let item = self.url_item_to_item(url_item);
+
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(short_code)")
+            .send()
+            .await
+            .map_err(|e| {
+                if e.as_service_error()
+                    .is_some_and(|se| se.is_conditional_check_failed_exception())
+                {
+                    UrlShortenerError::ShortCodeExists(url_item.short_code.clone())
+                } else {
+                    database_error(e)
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// Store `alias_code` as another code for `canonical`'s destination,
+    /// copying its expiry, password, use limit and redirect type. Redirects
+    /// follow the alias to the canonical link and count clicks there (see
+    /// `get_redirect_target`). An alias of an alias
+    /// points at the original canonical code. Fails with `ShortCodeExists`
+    /// if the alias is taken.
+    #[instrument(skip(self, canonical), fields(short_code = %canonical.short_code))]
+    pub async fn create_alias(
+        &self,
+        canonical: &UrlItem,
+        alias_code: &str,
+    ) -> Result<UrlItem, UrlShortenerError> {
+        let alias = UrlItem {
+            short_code: alias_code.to_string(),
+            original_url: canonical.original_url.clone(),
+            created_at: Utc::now().to_rfc3339(),
+            expires_at: canonical.expires_at,
+            click_count: 0,
+            custom_code: true,
+            status: "active".to_string(),
+            password_hash: canonical.password_hash.clone(),
+            max_uses: canonical.max_uses,
+            redirect_status: canonical.redirect_status,
+            canonical_code: Some(
+                canonical
+                    .canonical_code
+                    .clone()
+                    .unwrap_or_else(|| canonical.short_code.clone()),
+            ),
+            wildcard: canonical.wildcard,
+            creator_ip: None,
+            unique_visitors: None,
+            extra_headers: canonical.extra_headers.clone(),
+            ttl_source: canonical.ttl_source,
+        };
+
+        self.put_url(&alias).await?;
+        Ok(alias)
+    }
+
+    /// Codes aliased to `canonical_code`. This is a filtered full scan, so it
+    /// suits admin and campaign tooling rather than hot paths. Aliases stop
+    /// redirecting once their canonical link is removed, but their items stay
+    /// until they expire alongside it.
+    #[instrument(skip(self))]
+    pub async fn list_aliases(
+        &self,
+        canonical_code: &str,
+    ) -> Result<Vec<String>, UrlShortenerError> {
+        let mut aliases = Vec::new();
+        let mut exclusive_start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("canonical_code = :code")
+                .expression_attribute_values(":code", AttributeValue::S(canonical_code.to_string()))
+                .projection_expression("short_code")
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            aliases.extend(
+                result
+                    .items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|item| item.get("short_code")?.as_s().ok().cloned()),
+            );
+
+            match result.last_evaluated_key {
+                Some(key) if !key.is_empty() => exclusive_start_key = Some(key),
+                _ => break,
+            }
+        }
+
+        aliases.sort();
+        Ok(aliases)
+    }
+
+    /// Links created from `ip`, for abuse correlation. Only the hash is stored,
+    /// so the address is hashed the same way before matching. Like
+    /// `list_aliases` this is a full scan, meant for admin tooling.
+    #[instrument(skip(self, ip, hasher))]
+    pub async fn find_by_creator_ip(
+        &self,
+        ip: &str,
+        hasher: &CreatorIpHasher,
+    ) -> Result<Vec<UrlItem>, UrlShortenerError> {
+        let creator_ip = hasher.hash(ip);
+        let mut items = Vec::new();
+        let mut exclusive_start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("creator_ip = :creator_ip")
+                .expression_attribute_values(":creator_ip", AttributeValue::S(creator_ip.clone()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            for item in result.items.unwrap_or_default() {
+                items.push(self.item_to_url_item(item)?);
+            }
+
+            match result.last_evaluated_key {
+                Some(key) if !key.is_empty() => exclusive_start_key = Some(key),
+                _ => break,
+            }
+        }
+
+        items.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(items)
+    }
+
+    /// Unconditionally write `url_item`, replacing any existing item with the
+    /// same short code. `created_at` and `click_count` are stored as given.
+    ///
+    /// Only for admin imports and migrations; user-facing creates must go
+    /// through `put_url` so codes are never silently reassigned.
+    #[instrument(skip(self, url_item), fields(short_code = %url_item.short_code))]
+    pub async fn put_url_overwrite(&self, url_item: &UrlItem) -> Result<(), UrlShortenerError> {
+        info!("Overwriting URL item");
+        check_item_size(url_item)?;
+
+        let item = self.url_item_to_item(url_item);
+
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(database_error)?;
+        self.invalidate_cached(&url_item.short_code);
+
+        Ok(())
+    }
+
+    /// Write `url_item` together with a dedup marker for `normalized_url` in
+    /// one transaction, so two concurrent creates of the same URL can't both
+    /// mint a code. The loser gets the winner's link back as `Existing`.
+    ///
+    /// A marker whose link has expired, fallen out of the dedup age window,
+    /// been deleted or now points elsewhere may be claimed again.
+    #[instrument(skip(self, url_item), fields(short_code = %url_item.short_code))]
+    pub async fn create_url_transactional(
+        &self,
+        url_item: &UrlItem,
+        normalized_url: &str,
+    ) -> Result<CreateOutcome, UrlShortenerError> {
+        check_item_size(url_item)?;
+        let marker_key = dedup_marker_key(normalized_url);
+        let mut stale_target: Option<String> = None;
+
+        for attempt in 1..=MAX_CREATE_ATTEMPTS {
+            let claim =
+                self.dedup_marker_claim(&marker_key, url_item, stale_target.as_deref(), Utc::now());
+
+            let put_item = Put::builder()
+                .table_name(&self.table_name)
+                .set_item(Some(self.url_item_to_item(url_item)))
+                .condition_expression("attribute_not_exists(short_code)")
+                .build()
+                .map_err(build_error)?;
+            let put_marker = Put::builder()
+                .table_name(&self.table_name)
+                .set_item(Some(claim.item))
+                .condition_expression(claim.condition)
+                .set_expression_attribute_values(Some(claim.values))
+                .build()
+                .map_err(build_error)?;
+
+            let result = self
+                .client
+                .transact_write_items()
+                .transact_items(TransactWriteItem::builder().put(put_item).build())
+                .transact_items(TransactWriteItem::builder().put(put_marker).build())
+                .send()
+                .await;
+
+            let err = match result {
+                Ok(_) => return Ok(CreateOutcome::Created),
+                Err(e) => e,
+            };
+
+            let conflict = match err.as_service_error() {
+                Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) => {
+                    classify_cancellation(cancelled.cancellation_reasons())
+                }
+                _ => TransactConflict::Other,
+            };
+
+            match conflict {
+                TransactConflict::ShortCodeTaken => {
+                    return Err(UrlShortenerError::ShortCodeExists(
+                        url_item.short_code.clone(),
+                    ));
+                }
+                TransactConflict::Other => {
+                    return Err(database_error(err));
+                }
+                TransactConflict::DuplicateUrl => {
+                    match self
+                        .dedup_marker_target(&marker_key, normalized_url)
+                        .await?
+                    {
+                        MarkerTarget::Live(winner) => return Ok(CreateOutcome::Existing(winner)),
+                        MarkerTarget::Stale(target_code) => stale_target = target_code,
+                    }
+                    // The marker's link is gone, stale or for another URL; the
+                    // next attempt may claim the marker
+                    info!(
+                        "Dedup marker has no live target, retrying (attempt {})",
+                        attempt
+                    );
+                }
+            }
+        }
+
+        Err(UrlShortenerError::DatabaseError(
+            "Gave up creating URL after repeated dedup conflicts".to_string(),
+        ))
+    }
+
+    /// Store `url_item` and reserve `idempotency_key` for it in one
+    /// transaction, so a failure can't leave a key without its link or the
+    /// other way round. A key that is still remembered gives
+    /// `IdempotencyConflict`; `idempotent_result` then returns the link it
+    /// created. The key record expires after `IDEMPOTENCY_KEY_TTL_HOURS`.
+    #[instrument(skip(self, url_item, idempotency_key), fields(short_code = %url_item.short_code))]
+    pub async fn transact_create_with_idempotency(
+        &self,
+        url_item: &UrlItem,
+        idempotency_key: &IdempotencyKey,
+    ) -> Result<(), UrlShortenerError> {
+        check_item_size(url_item)?;
+
+        let now = Utc::now();
+        let key_record = HashMap::from([
+            (
+                "short_code".to_string(),
+                AttributeValue::S(format!(
+                    "{}{}",
+                    IDEMPOTENCY_KEY_PREFIX,
+                    idempotency_key.digest()
+                )),
+            ),
+            (
+                "target_code".to_string(),
+                AttributeValue::S(url_item.short_code.clone()),
+            ),
+            (
+                "fingerprint".to_string(),
+                AttributeValue::S(idempotency_key.fingerprint().to_string()),
+            ),
+            (
+                "created_at".to_string(),
+                AttributeValue::S(url_item.created_at.clone()),
+            ),
+            (
+                "expires_at".to_string(),
+                AttributeValue::N(
+                    (now + Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS))
+                        .timestamp()
+                        .to_string(),
+                ),
+            ),
+        ]);
+
+        let put_item = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(self.url_item_to_item(url_item)))
+            .condition_expression("attribute_not_exists(short_code)")
+            .build()
+            .map_err(build_error)?;
+        // A record past its expiry may be waiting on TTL; the key is free again
+        let put_key = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(key_record))
+            .condition_expression("attribute_not_exists(short_code) OR expires_at < :now")
+            .expression_attribute_values(":now", AttributeValue::N(now.timestamp().to_string()))
+            .build()
+            .map_err(build_error)?;
+
+        let err = match self
+            .client
+            .transact_write_items()
+            .transact_items(TransactWriteItem::builder().put(put_item).build())
+            .transact_items(TransactWriteItem::builder().put(put_key).build())
+            .send()
+            .await
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+
+        if let Some(TransactWriteItemsError::TransactionCanceledException(cancelled)) =
+            err.as_service_error()
+            && let Some(conflict) = idempotent_create_error(
+                cancelled.cancellation_reasons(),
+                &url_item.short_code,
+                idempotency_key.key(),
+            )
+        {
+            return Err(conflict);
+        }
+        Err(database_error(err))
+    }
+
+    /// `transact_create_with_idempotency`, reading back the earlier link when
+    /// a concurrent request with the same key got there first
+    pub async fn create_with_idempotency(
+        &self,
+        url_item: &UrlItem,
+        idempotency_key: &IdempotencyKey,
+    ) -> Result<CreateOutcome, UrlShortenerError> {
+        match self
+            .transact_create_with_idempotency(url_item, idempotency_key)
+            .await
+        {
+            Ok(()) => Ok(CreateOutcome::Created),
+            Err(UrlShortenerError::IdempotencyConflict(key)) => {
+                match self.idempotent_result(idempotency_key).await? {
+                    Some(prior) => Ok(CreateOutcome::Existing(Box::new(prior))),
+                    None => Err(UrlShortenerError::IdempotencyConflict(key)),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The link an earlier create with `idempotency_key` stored, if the key
+    /// is still remembered and the link still live. A key first sent with a
+    /// different request gives `IdempotencyKeyMismatch`.
+    #[instrument(skip(self, idempotency_key))]
+    pub async fn idempotent_result(
+        &self,
+        idempotency_key: &IdempotencyKey,
+    ) -> Result<Option<UrlItem>, UrlShortenerError> {
+        let record = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(
+                "short_code",
+                AttributeValue::S(format!(
+                    "{}{}",
+                    IDEMPOTENCY_KEY_PREFIX,
+                    idempotency_key.digest()
+                )),
+            )
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        let Some(record) = record.item else {
+            return Ok(None);
+        };
+        let attribute = |name: &str| record.get(name).and_then(|v| v.as_s().ok());
+
+        // TTL deletes lag expiry, so an expired record is treated as gone
+        let expires_at = record
+            .get("expires_at")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|n| n.parse::<i64>().ok());
+        if expires_at.is_some_and(|expires_at| expires_at < Utc::now().timestamp()) {
+            return Ok(None);
+        }
+
+        if attribute("fingerprint").map(String::as_str) != Some(idempotency_key.fingerprint()) {
+            return Err(UrlShortenerError::IdempotencyKeyMismatch(
+                idempotency_key.key().to_string(),
+            ));
+        }
+
+        let Some(target_code) = attribute("target_code") else {
+            return Ok(None);
+        };
+
+        match self.get_url(target_code).await {
+            Err(UrlShortenerError::UrlExpired) => Ok(None),
+            result => result,
+        }
+    }
+
+    /// The link for `item.original_url` if one may be reused, otherwise
+    /// `item` once stored. The flag is `true` when `item` was written.
+    ///
+    /// With transactions this is `create_url_transactional`, so concurrent
+    /// callers for one URL agree on a single link. Without them it is a
+    /// lookup then a conditional put: a code conflict is re-checked against
+    /// the index in case a concurrent create of the same URL won, but two
+    /// creates with different codes can both succeed.
+    #[instrument(skip(self, item), fields(short_code = %item.short_code))]
+    pub async fn get_or_create(
+        &self,
+        normalized_url: &str,
+        item: UrlItem,
+    ) -> Result<(UrlItem, bool), UrlShortenerError> {
+        let reusable = |existing: Option<UrlItem>| existing.filter(UrlItem::is_dedup_candidate);
+
+        if let Some(existing) = reusable(self.find_existing_url(&item.original_url).await?) {
+            return Ok((existing, false));
+        }
+
+        if self.transactions {
+            return match self.create_url_transactional(&item, normalized_url).await? {
+                CreateOutcome::Created => Ok((item, true)),
+                CreateOutcome::Existing(existing) => Ok((*existing, false)),
+            };
+        }
+
+        match self.put_url(&item).await {
+            Ok(()) => Ok((item, true)),
+            Err(UrlShortenerError::ShortCodeExists(code)) => {
+                match reusable(self.find_existing_url(&item.original_url).await?) {
+                    Some(existing) => Ok((existing, false)),
+                    None => Err(UrlShortenerError::ShortCodeExists(code)),
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Marker `marker_key` pointing at `url_item`. It may take over a key
+    /// that is free, or whose marker has expired or fallen out of the dedup
+    /// age window. `stale_target` is a link the current marker was seen to
+    /// point at with nothing reusable behind it; the marker is replaced only
+    /// if it still points there.
+    fn dedup_marker_claim(
+        &self,
+        marker_key: &str,
+        url_item: &UrlItem,
+        stale_target: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> MarkerClaim {
+        let mut condition = "attribute_not_exists(short_code) OR expires_at < :now".to_string();
+        let mut values = HashMap::from([(
+            ":now".to_string(),
+            AttributeValue::N(now.timestamp().to_string()),
+        )]);
+        if let Some(max_age_hours) = self.dedup_policy.max_age_hours {
+            condition.push_str(" OR created_at < :cutoff");
+            values.insert(
+                ":cutoff".to_string(),
+                AttributeValue::S((now - Duration::hours(max_age_hours as i64)).to_rfc3339()),
+            );
+        }
+        if let Some(stale_target) = stale_target {
+            condition.push_str(" OR target_code = :stale_target");
+            values.insert(
+                ":stale_target".to_string(),
+                AttributeValue::S(stale_target.to_string()),
+            );
+        }
+
+        let mut item = HashMap::from([
+            (
+                "short_code".to_string(),
+                AttributeValue::S(marker_key.to_string()),
+            ),
+            (
+                "target_code".to_string(),
+                AttributeValue::S(url_item.short_code.clone()),
+            ),
+            (
+                "created_at".to_string(),
+                AttributeValue::S(url_item.created_at.clone()),
+            ),
+        ]);
+        if let Some(expires_at) = url_item.expires_at {
+            item.insert(
+                "expires_at".to_string(),
+                AttributeValue::N(expires_at.to_string()),
+            );
+        }
+
+        MarkerClaim {
+            item,
+            condition,
+            values,
+        }
+    }
+
+    /// The live link for `normalized_url` that a dedup marker points at
+    async fn dedup_marker_target(
+        &self,
+        marker_key: &str,
+        normalized_url: &str,
+    ) -> Result<MarkerTarget, UrlShortenerError> {
+        let marker = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(marker_key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        let Some(target_code) = marker
+            .item
+            .as_ref()
+            .and_then(|item| item.get("target_code"))
+            .and_then(|v| v.as_s().ok())
+        else {
+            return Ok(MarkerTarget::Stale(None));
+        };
+
+        match self.get_url(target_code).await {
+            Ok(Some(item))
+                if dedup_key(&item.original_url).as_deref() == Some(normalized_url)
+                    && self.dedup_policy.matches(&item, Utc::now()) =>
+            {
+                Ok(MarkerTarget::Live(Box::new(item)))
+            }
+            Ok(_) | Err(UrlShortenerError::UrlExpired) => {
+                Ok(MarkerTarget::Stale(Some(target_code.to_string())))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    #[instrument(skip(self), fields(short_code = %short_code))]
+    pub async fn increment_click_count(&self, short_code: &str) -> Result<(), UrlShortenerError> {
+        info!("Incrementing click count");
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .update_expression("ADD click_count :inc")
+            .expression_attribute_values(":inc", AttributeValue::N("1".to_string()))
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        Ok(())
+    }
+
+    /// Apply buffered `{short_code: delta}` click counts, a bounded number of
+    /// updates at a time. Deltas that fail are returned in
+    /// `ClickFlushIncomplete` so the caller can re-queue them; links deleted
+    /// in the meantime are dropped rather than recreated.
+    #[instrument(skip(self, deltas), fields(links = deltas.len()))]
+    pub async fn batch_increment_clicks(
+        &self,
+        deltas: &HashMap<String, u64>,
+    ) -> Result<(), UrlShortenerError> {
+        let failed: HashMap<String, u64> = stream::iter(deltas.iter().filter(|(_, d)| **d > 0))
+            .map(|(short_code, delta)| async move {
+                let result = self
+                    .client
+                    .update_item()
+                    .table_name(&self.table_name)
+                    .key("short_code", AttributeValue::S(short_code.clone()))
+                    .update_expression("ADD click_count :inc")
+                    .condition_expression("attribute_exists(short_code)")
+                    .expression_attribute_values(":inc", AttributeValue::N(delta.to_string()))
+                    .send()
+                    .await;
+
+                match result {
+                    Ok(_) => None,
+                    Err(e)
+                        if e.as_service_error()
+                            .is_some_and(|se| se.is_conditional_check_failed_exception()) =>
+                    {
+                        info!("Dropping {} clicks for deleted link {}", delta, short_code);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("Failed to add {} clicks to {}: {}", delta, short_code, e);
+                        Some((short_code.clone(), *delta))
+                    }
+                }
+            })
+            .buffer_unordered(CLICK_FLUSH_CONCURRENCY)
+            .filter_map(|failure| async move { failure })
+            .collect()
+            .await;
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(UrlShortenerError::ClickFlushIncomplete { failed })
+        }
+    }
+
+    /// Record a click on a link limited to `max_uses` redirects.
+    ///
+    /// The check and the increment are a single conditional update, so DynamoDB
+    /// serializes concurrent redirects and never lets the count pass the limit.
+    /// A failed condition means the link is used up and maps to `UrlExhausted`.
+    #[instrument(skip(self), fields(short_code = %short_code))]
+    pub async fn increment_click_count_limited(
+        &self,
+        short_code: &str,
+        max_uses: u64,
+    ) -> Result<(), UrlShortenerError> {
+        info!("Incrementing click count with use limit");
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .update_expression("ADD click_count :inc")
+            .condition_expression("click_count < :max")
+            .expression_attribute_values(":inc", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":max", AttributeValue::N(max_uses.to_string()))
+            .send()
+            .await
+            .map_err(|e| {
+                if e.as_service_error()
+                    .is_some_and(|se| se.is_conditional_check_failed_exception())
+                {
+                    UrlShortenerError::UrlExhausted
+                } else {
+                    database_error(e)
+                }
+            })?;
+
+        Ok(())
+    }
+
+    /// Record a click, limited to `max_uses` when set, and a plain click
+    /// otherwise.
+    pub async fn record_click(
+        &self,
+        short_code: &str,
+        max_uses: Option<u64>,
+    ) -> Result<(), UrlShortenerError> {
+        match max_uses {
+            Some(max_uses) => {
+                self.increment_click_count_limited(short_code, max_uses)
+                    .await
+            }
+            None => self.increment_click_count(short_code).await,
+        }
+    }
+
+    /// Record a click and count the visit from `visitor_key` towards
+    /// `unique_visitors`, at most once per key per `day`.
+    ///
+    /// Today's keys live in the `visitor_keys` string set, tagged with
+    /// `visitor_day`. The common case is a single conditional update that
+    /// bumps the click count, adds the key and counts the visitor. When its
+    /// condition fails, the old item says why: a used-up link maps to
+    /// `UrlExhausted`, a set left over from an earlier day is replaced in a
+    /// second conditional update, and a repeat or capped visit is recorded as
+    /// a plain click. Sets are capped at `MAX_DAILY_VISITOR_KEYS` to bound the
+    /// item size.
+    #[instrument(skip(self, visitor_key), fields(short_code = %short_code))]
+    pub async fn record_click_with_visit(
+        &self,
+        short_code: &str,
+        max_uses: Option<u64>,
+        visitor_key: &str,
+        day: &str,
+    ) -> Result<VisitOutcome, UrlShortenerError> {
+        let limit = if max_uses.is_some() {
+            " AND click_count < :max"
+        } else {
+            ""
+        };
+
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .update_expression("ADD click_count :one, visitor_keys :keys, unique_visitors :one")
+            .condition_expression(format!(
+                "visitor_day = :day AND NOT contains(visitor_keys, :key) \
+                 AND size(visitor_keys) < :cap{}",
+                limit
+            ))
+            .expression_attribute_values(":keys", AttributeValue::Ss(vec![visitor_key.to_string()]))
+            .expression_attribute_values(":key", AttributeValue::S(visitor_key.to_string()))
+            .expression_attribute_values(":day", AttributeValue::S(day.to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(
+                ":cap",
+                AttributeValue::N(MAX_DAILY_VISITOR_KEYS.to_string()),
+            )
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld);
+        if let Some(max_uses) = max_uses {
+            request = request
+                .expression_attribute_values(":max", AttributeValue::N(max_uses.to_string()));
+        }
+
+        let old_item = match request.send().await {
+            Ok(_) => return Ok(VisitOutcome::Counted),
+            Err(e) => match e.as_service_error() {
+                Some(UpdateItemError::ConditionalCheckFailedException(failed)) => {
+                    failed.item.clone().unwrap_or_default()
+                }
+                _ => return Err(database_error(e)),
+            },
+        };
+
+        if old_item.is_empty() && max_uses.is_none() {
+            return Ok(VisitOutcome::Skipped);
+        }
+        if let Some(max_uses) = max_uses {
+            let clicks = old_item
+                .get("click_count")
+                .and_then(|v| v.as_n().ok())
+                .and_then(|s| s.parse::<u64>().ok());
+            if clicks.is_none_or(|clicks| clicks >= max_uses) {
+                return Err(UrlShortenerError::UrlExhausted);
+            }
+        }
+
+        let stored_day = old_item.get("visitor_day").and_then(|v| v.as_s().ok());
+        let stored_keys: HashSet<String> = old_item
+            .get("visitor_keys")
+            .and_then(|v| v.as_ss().ok())
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let outcome = classify_visit(
+            stored_day.map(String::as_str),
+            &stored_keys,
+            visitor_key,
+            day,
+        );
+        match outcome {
+            VisitOutcome::NewDay => {}
+            // Seen or full, so only the click counts
+            VisitOutcome::Duplicate | VisitOutcome::Capped => {
+                self.record_click(short_code, max_uses).await?;
+                return Ok(outcome);
+            }
+            // The set changed between the update and the read; count the
+            // click alone rather than retry
+            VisitOutcome::Counted | VisitOutcome::Skipped => {
+                self.record_click(short_code, max_uses).await?;
+                return Ok(VisitOutcome::Skipped);
+            }
+        }
+
+        // Start today's set. A concurrent visit may have started it first, in
+        // which case this one is recorded as a plain click, uncounted.
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("short_code", AttributeValue::S(short_code.to_string()))
+            .update_expression(
+                "SET visitor_day = :day, visitor_keys = :keys \
+                 ADD unique_visitors :one, click_count :one",
+            )
+            .condition_expression(format!(
+                "attribute_exists(short_code) AND \
+                 (attribute_not_exists(visitor_day) OR visitor_day <> :day){}",
+                limit
+            ))
+            .expression_attribute_values(":keys", AttributeValue::Ss(vec![visitor_key.to_string()]))
+            .expression_attribute_values(":day", AttributeValue::S(day.to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()));
+        if let Some(max_uses) = max_uses {
+            request = request
+                .expression_attribute_values(":max", AttributeValue::N(max_uses.to_string()));
+        }
+
+        match request.send().await {
+            Ok(_) => Ok(VisitOutcome::NewDay),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|se| se.is_conditional_check_failed_exception()) =>
+            {
+                self.record_click(short_code, max_uses).await?;
+                Ok(VisitOutcome::Skipped)
+            }
+            Err(e) => Err(database_error(e)),
+        }
+    }
+
+    /// Item count from `DescribeTable`. DynamoDB refreshes it roughly every six
+    /// hours and it includes internal items, so treat it as an estimate.
+    #[instrument(skip(self))]
+    pub async fn approximate_item_count(&self) -> Result<u64, UrlShortenerError> {
+        let result = self
+            .client
+            .describe_table()
+            .table_name(&self.table_name)
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        Ok(result
+            .table
+            .and_then(|table| table.item_count)
+            .unwrap_or(0)
+            .max(0) as u64)
+    }
+
+    /// Whether the table exists and is active. A missing table is a status,
+    /// not an error, so callers can explain how to create it.
+    #[instrument(skip(self))]
+    pub async fn describe_table_status(&self) -> Result<TableStatus, UrlShortenerError> {
+        match self
+            .client
+            .describe_table()
+            .table_name(&self.table_name)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(TableStatus::from_sdk(
+                output.table.as_ref().and_then(|table| table.table_status()),
+            )),
+            Err(e) => {
+                let message = DisplayErrorContext(&e).to_string();
+                let not_found = e
+                    .as_service_error()
+                    .is_some_and(|e| e.is_resource_not_found_exception())
+                    || is_table_not_found(&message);
+
+                if not_found {
+                    Ok(TableStatus::NotFound)
+                } else {
+                    Err(database_error(e))
+                }
+            }
+        }
+    }
+
+    /// Create the table, keyed on `short_code` with the `original_url` GSI
+    /// and TTL on `expires_at`, unless it already exists, then wait for it to
+    /// become active. Safe to call on every startup. Returns whether the
+    /// table was created.
+    #[instrument(skip(self), fields(table = %self.table_name))]
+    pub async fn ensure_schema(&self) -> Result<bool, UrlShortenerError> {
+        let mut created = false;
+
+        if self.describe_table_status().await? == TableStatus::NotFound {
+            let result = self
+                .client
+                .create_table()
+                .table_name(&self.table_name)
+                .attribute_definitions(attribute_definition("short_code")?)
+                .attribute_definitions(attribute_definition("original_url")?)
+                .key_schema(hash_key("short_code")?)
+                .global_secondary_indexes(
+                    GlobalSecondaryIndex::builder()
+                        .index_name(ORIGINAL_URL_INDEX)
+                        .key_schema(hash_key("original_url")?)
+                        .projection(
+                            Projection::builder()
+                                .projection_type(ProjectionType::All)
+                                .build(),
+                        )
+                        .build()
+                        .map_err(|e| UrlShortenerError::InternalError(e.into()))?,
+                )
+                .billing_mode(BillingMode::PayPerRequest)
+                .send()
+                .await;
+
+            match result {
+                Ok(_) => {
+                    info!("Created table {}", self.table_name);
+                    created = true;
+                }
+                // Someone else created it in the meantime
+                Err(e)
+                    if e.as_service_error()
+                        .is_some_and(|se| se.is_resource_in_use_exception()) => {}
+                Err(e) => return Err(database_error(e)),
+            }
+        }
+
+        for _ in 0..SCHEMA_POLL_ATTEMPTS {
+            match self.describe_table_status().await? {
+                TableStatus::Active => {
+                    if created {
+                        self.enable_ttl().await?;
+                    }
+                    return Ok(created);
+                }
+                TableStatus::Unavailable(status) => {
+                    return Err(UrlShortenerError::DatabaseError(format!(
+                        "Table {} is {}",
+                        self.table_name, status
+                    )));
+                }
+                TableStatus::Creating | TableStatus::NotFound => {
+                    tokio::time::sleep(SCHEMA_POLL_INTERVAL).await;
+                }
+            }
+        }
+
+        Err(UrlShortenerError::DatabaseError(format!(
+            "Table {} did not become active in time",
+            self.table_name
+        )))
+    }
+
+    async fn enable_ttl(&self) -> Result<(), UrlShortenerError> {
+        self.client
+            .update_time_to_live()
+            .table_name(&self.table_name)
+            .time_to_live_specification(
+                TimeToLiveSpecification::builder()
+                    .enabled(true)
+                    .attribute_name("expires_at")
+                    .build()
+                    .map_err(|e| UrlShortenerError::InternalError(e.into()))?,
+            )
+            .send()
+            .await
+            .map_err(database_error)?;
+
+        Ok(())
+    }
+
+    /// Count every stored link, excluding reserved internal items.
+    ///
+    /// This is a paginated `Select=COUNT` scan, so it is O(table) in both latency
+    /// and consumed read capacity. Use it sparingly (admin pages), never on a
+    /// per-request path.
+    #[instrument(skip(self))]
+    pub async fn count_urls(&self) -> Result<u64, UrlShortenerError> {
+        info!("Counting URL items");
+
+        let mut total = 0u64;
+        let mut exclusive_start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .select(Select::Count)
+                .filter_expression("NOT begins_with(short_code, :internal)")
+                .expression_attribute_values(
+                    ":internal",
+                    AttributeValue::S(INTERNAL_KEY_PREFIX.to_string()),
+                )
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            total += result.count.max(0) as u64;
+
+            match result.last_evaluated_key {
+                Some(key) if !key.is_empty() => exclusive_start_key = Some(key),
+                _ => break,
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Links whose creation time falls within `from..=to` (RFC 3339), one scan
+    /// page at a time. `limit` caps the items DynamoDB examines, not the items
+    /// returned, so a page can come back short or empty with a cursor still
+    /// set. Matches on the numeric `created_ts`, so items written before that
+    /// attribute existed are not listed.
+    #[instrument(skip(self))]
+    pub async fn list_urls_created_between(
+        &self,
+        from: &str,
+        to: &str,
+        limit: i32,
+        cursor: Option<String>,
+    ) -> Result<UrlPage, UrlShortenerError> {
+        let (from_ts, to_ts) = created_ts_range(from, to)?;
+
+        let result =
+            self.client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression(
+                    "created_ts BETWEEN :from AND :to AND NOT begins_with(short_code, :internal)",
+                )
+                .expression_attribute_values(":from", AttributeValue::N(from_ts.to_string()))
+                .expression_attribute_values(":to", AttributeValue::N(to_ts.to_string()))
+                .expression_attribute_values(
+                    ":internal",
+                    AttributeValue::S(INTERNAL_KEY_PREFIX.to_string()),
+                )
+                .limit(limit.max(1))
+                .set_exclusive_start_key(cursor.map(|code| {
+                    HashMap::from([("short_code".to_string(), AttributeValue::S(code))])
+                }))
+                .send()
+                .await
+                .map_err(database_error)?;
+
+        let items = result
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| self.item_to_url_item(item))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let next_cursor = result
+            .last_evaluated_key
+            .and_then(|key| key.get("short_code")?.as_s().ok().cloned());
+
+        Ok(UrlPage { items, next_cursor })
+    }
+
+    /// Links expiring between now and `hours` from now, soonest first, at most
+    /// `limit` of them. Links without `expires_at` never match. This is a
+    /// filtered full scan, meant for a periodic renewal-notice job.
+    #[instrument(skip(self))]
+    pub async fn find_expiring_within(
+        &self,
+        hours: u32,
+        limit: usize,
+    ) -> Result<Vec<UrlItem>, UrlShortenerError> {
+        let now = Utc::now().timestamp();
+        let until = now + i64::from(hours) * 3600;
+
+        let mut items = Vec::new();
+        let mut exclusive_start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression(
+                    "expires_at BETWEEN :now AND :until AND NOT begins_with(short_code, :internal)",
+                )
+                .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+                .expression_attribute_values(":until", AttributeValue::N(until.to_string()))
+                .expression_attribute_values(
+                    ":internal",
+                    AttributeValue::S(INTERNAL_KEY_PREFIX.to_string()),
+                )
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            for item in result.items.unwrap_or_default() {
+                let url_item = self.item_to_url_item(item)?;
+                if url_item.expires_at.is_some() {
+                    items.push(url_item);
+                }
+            }
+
+            match result.last_evaluated_key {
+                Some(key) if !key.is_empty() => exclusive_start_key = Some(key),
+                _ => break,
+            }
+        }
+
+        items.sort_by_key(|item| item.expires_at);
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    /// Delete every item whose `expires_at` has passed by more than
+    /// `EXPIRY_GRACE_SECONDS`, `batch` keys per `BatchWriteItem` call
+    /// (clamped to 1..=25). Returns the number deleted.
+    ///
+    /// Expired links are already hidden at read time and DynamoDB's TTL removes
+    /// them eventually; this purges them on demand so scans and counts stay
+    /// accurate. It's self-contained, so a scheduled Lambda can simply call it.
+    #[instrument(skip(self))]
+    pub async fn sweep_expired(&self, batch: i32) -> Result<u64, UrlShortenerError> {
+        info!("Sweeping expired URL items");
+
+        let batch = batch.clamp(1, MAX_BATCH_WRITE) as usize;
+        // Links inside the grace window still resolve, so they stay
+        let cutoff = Utc::now().timestamp() - expiry_grace_seconds();
+        let mut purged = 0u64;
+        let mut exclusive_start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("expires_at < :now")
+                .expression_attribute_values(":now", AttributeValue::N(cutoff.to_string()))
+                .projection_expression("short_code")
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            let keys: Vec<_> = result
+                .items
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|item| item.get("short_code").cloned())
+                .collect();
+
+            for chunk in keys.chunks(batch) {
+                purged += self.delete_keys(chunk).await?;
+            }
+
+            match result.last_evaluated_key {
+                Some(key) if !key.is_empty() => exclusive_start_key = Some(key),
+                _ => break,
+            }
+        }
+
+        info!("Purged {} expired items", purged);
+        Ok(purged)
+    }
+
+    /// Restore the dedup markers of reusable links, for tables whose links
+    /// predate markers or lost them. A marker that another live link already
+    /// holds is left alone. Scans `batch` items per page (clamped to
+    /// 1..=1000) and logs progress after each page.
+    #[instrument(skip(self))]
+    pub async fn reindex_dedup(&self, batch: i32) -> Result<ReindexReport, UrlShortenerError> {
+        info!("Rebuilding dedup markers");
+
+        let mut report = ReindexReport::default();
+        let mut exclusive_start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("NOT begins_with(short_code, :internal)")
+                .expression_attribute_values(
+                    ":internal",
+                    AttributeValue::S(INTERNAL_KEY_PREFIX.to_string()),
+                )
+                .limit(batch.clamp(1, 1000))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            let now = Utc::now();
+            for item in result.items.unwrap_or_default() {
+                report.scanned += 1;
+                let url_item = self.item_to_url_item(item)?;
+                if !url_item.is_dedup_candidate() || !self.dedup_policy.matches(&url_item, now) {
+                    continue;
+                }
+                let Some(normalized) = dedup_key(&url_item.original_url) else {
+                    continue;
+                };
+
+                let claim =
+                    self.dedup_marker_claim(&dedup_marker_key(&normalized), &url_item, None, now);
+                let put = self
+                    .client
+                    .put_item()
+                    .table_name(&self.table_name)
+                    .set_item(Some(claim.item))
+                    .condition_expression(claim.condition)
+                    .set_expression_attribute_values(Some(claim.values))
+                    .send()
+                    .await;
+                match put {
+                    Ok(_) => report.restored += 1,
+                    // Another live link holds the marker
+                    Err(e)
+                        if e.as_service_error()
+                            .is_some_and(|se| se.is_conditional_check_failed_exception()) => {}
+                    Err(e) => return Err(database_error(e)),
+                }
+            }
+
+            info!(
+                "Dedup reindex progress: {} scanned, {} restored",
+                report.scanned, report.restored
+            );
+
+            match result.last_evaluated_key {
+                Some(key) if !key.is_empty() => exclusive_start_key = Some(key),
+                _ => break,
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Batch-delete the given short codes, resubmitting unprocessed requests a
+    /// few times. Returns how many deletes DynamoDB accepted.
+    async fn delete_keys(&self, keys: &[AttributeValue]) -> Result<u64, UrlShortenerError> {
+        let mut requests = keys
+            .iter()
+            .map(|key| {
+                DeleteRequest::builder()
+                    .key("short_code", key.clone())
+                    .build()
+                    .map(|delete| WriteRequest::builder().delete_request(delete).build())
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(build_error)?;
+        for short_code in keys.iter().filter_map(|key| key.as_s().ok()) {
+            self.invalidate_cached(short_code);
+        }
+
+        for _ in 0..=MAX_UNPROCESSED_RETRIES {
+            if requests.is_empty() {
+                break;
+            }
+
+            let result = self
+                .client
+                .batch_write_item()
+                .request_items(&self.table_name, requests)
+                .send()
+                .await
+                .map_err(database_error)?;
+
+            requests = result
+                .unprocessed_items
+                .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                .unwrap_or_default();
+        }
+
+        if !requests.is_empty() {
+            warn!("{} expired items left unprocessed", requests.len());
+        }
+
+        Ok((keys.len() - requests.len()) as u64)
+    }
+
+    /// Stream every URL item in the table, fetching the next scan page only
+    /// once the previous one has been consumed.
+    ///
+    /// Like `count_urls` this walks the whole table; meant for exports and
+    /// other offline tooling.
+    pub fn scan_stream(&self) -> impl Stream<Item = Result<UrlItem, UrlShortenerError>> + 'static {
+        let db_client = self.clone();
+
+        // State is the next page's start key; `None` once the last page is read
+        stream::try_unfold(Some(None), move |start_key| {
+            let db_client = db_client.clone();
+            async move {
+                let Some(exclusive_start_key) = start_key else {
+                    return Ok(None);
+                };
+
+                let result = db_client
+                    .client
+                    .scan()
+                    .table_name(&db_client.table_name)
+                    .filter_expression("NOT begins_with(short_code, :internal)")
+                    .expression_attribute_values(
+                        ":internal",
+                        AttributeValue::S(INTERNAL_KEY_PREFIX.to_string()),
+                    )
+                    .set_exclusive_start_key(exclusive_start_key)
+                    .send()
+                    .await
+                    .map_err(database_error)?;
+
+                let page: Vec<_> = result
+                    .items
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|item| db_client.item_to_url_item(item))
+                    .collect();
+
+                let next_start_key = match result.last_evaluated_key {
+                    Some(key) if !key.is_empty() => Some(Some(key)),
+                    _ => None,
+                };
+
+                Ok::<_, UrlShortenerError>(Some((stream::iter(page), next_start_key)))
+            }
+        })
+        .try_flatten()
+    }
+
+    fn url_item_to_item(&self, url_item: &UrlItem) -> HashMap<String, AttributeValue> {
+        url_item_attributes(url_item)
+    }
+
+    fn item_to_url_item(
+        &self,
+        item: HashMap<String, AttributeValue>,
+    ) -> Result<UrlItem, UrlShortenerError> {
+        let item = migrate_item(item);
+
+        let short_code = item
+            .get("short_code")
+            .and_then(|v| v.as_s().ok())
+            .ok_or_else(|| UrlShortenerError::InternalError(anyhow::anyhow!("Missing short_code")))?
+            .clone();
+
+        let original_url = item
+            .get("original_url")
+            .and_then(|v| v.as_s().ok())
+            .ok_or_else(|| {
+                UrlShortenerError::InternalError(anyhow::anyhow!("Missing original_url"))
+            })?
+            .clone();
+
+        let created_at = item
+            .get("created_at")
+            .and_then(|v| v.as_s().ok())
+            .ok_or_else(|| UrlShortenerError::InternalError(anyhow::anyhow!("Missing created_at")))?
+            .clone();
+
+        let expires_at = item
+            .get("expires_at")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse().ok());
+
+        let click_count = item
+            .get("click_count")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+
+        let custom_code = item
+            .get("custom_code")
+            .and_then(|v| v.as_bool().ok().copied())
+            .unwrap_or(false);
+
+        let status = item
+            .get("status")
+            .and_then(|v| v.as_s().ok())
+            .map(String::from)
+            .unwrap_or_else(|| "active".to_string());
+
+        let password_hash = item
+            .get("password_hash")
+            .and_then(|v| v.as_s().ok())
+            .map(String::from);
+
+        let max_uses = item
+            .get("max_uses")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse().ok());
+
+        let redirect_status = item
+            .get("redirect_status")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse().ok());
+
+        let canonical_code = item
+            .get("canonical_code")
+            .and_then(|v| v.as_s().ok())
+            .map(String::from);
+
+        let wildcard = item
+            .get("wildcard")
+            .and_then(|v| v.as_bool().ok().copied())
+            .unwrap_or(false);
+
+        let creator_ip = item
+            .get("creator_ip")
+            .and_then(|v| v.as_s().ok())
+            .map(String::from);
+
+        let unique_visitors = item
+            .get("unique_visitors")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse().ok());
+
+        let extra_headers = extra_headers_from(&item);
+
+        let ttl_source = item
+            .get("ttl_source")
+            .and_then(|v| v.as_s().ok())
+            .and_then(|s| TtlSource::parse(s));
 
         Ok(UrlItem {
             short_code,
@@ -202,6 +2526,2242 @@ impl DynamoDbClient {
             click_count,
             custom_code,
             status,
+            password_hash,
+            max_uses,
+            redirect_status,
+            canonical_code,
+            wildcard,
+            creator_ip,
+            unique_visitors,
+            extra_headers,
+            ttl_source,
+        })
+    }
+
+    fn item_to_redirect_target(
+        &self,
+        item: HashMap<String, AttributeValue>,
+    ) -> Result<RedirectTarget, UrlShortenerError> {
+        let original_url = item
+            .get("original_url")
+            .and_then(|v| v.as_s().ok())
+            .ok_or_else(|| {
+                UrlShortenerError::InternalError(anyhow::anyhow!("Missing original_url"))
+            })?
+            .clone();
+
+        let number = |name: &str| {
+            item.get(name)
+                .and_then(|v| v.as_n().ok())
+                .and_then(|s| s.parse::<i64>().ok())
+        };
+
+        Ok(RedirectTarget {
+            original_url,
+            expires_at: number("expires_at"),
+            status: item
+                .get("status")
+                .and_then(|v| v.as_s().ok())
+                .map(String::from)
+                .unwrap_or_else(|| "active".to_string()),
+            password_hash: item
+                .get("password_hash")
+                .and_then(|v| v.as_s().ok())
+                .map(String::from),
+            max_uses: number("max_uses").and_then(|n| u64::try_from(n).ok()),
+            redirect_status: number("redirect_status").and_then(|n| u16::try_from(n).ok()),
+            wildcard: item
+                .get("wildcard")
+                .and_then(|v| v.as_bool().ok().copied())
+                .unwrap_or(false),
+            click_count: number("click_count")
+                .and_then(|n| u64::try_from(n).ok())
+                .unwrap_or(0),
+            extra_headers: extra_headers_from(&item),
+            canonical_code: item
+                .get("canonical_code")
+                .and_then(|v| v.as_s().ok())
+                .map(String::from),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::error::ConnectorError;
+    use aws_sdk_dynamodb::operation::batch_get_item::BatchGetItemOutput;
+    use aws_sdk_dynamodb::operation::batch_write_item::BatchWriteItemOutput;
+    use aws_sdk_dynamodb::operation::create_table::{CreateTableError, CreateTableOutput};
+    use aws_sdk_dynamodb::operation::describe_table::{DescribeTableError, DescribeTableOutput};
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::operation::put_item::{PutItemError, PutItemOutput};
+    use aws_sdk_dynamodb::operation::query::QueryOutput;
+    use aws_sdk_dynamodb::operation::scan::ScanOutput;
+    use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsOutput;
+    use aws_sdk_dynamodb::operation::update_item::{UpdateItemError, UpdateItemOutput};
+    use aws_sdk_dynamodb::operation::update_time_to_live::UpdateTimeToLiveOutput;
+    use aws_sdk_dynamodb::types::error::{
+        ConditionalCheckFailedException, InternalServerError, ResourceInUseException,
+        ResourceNotFoundException,
+    };
+    use aws_sdk_dynamodb::types::{TableDescription, TableStatus as SdkTableStatus};
+    use aws_smithy_http_client::test_util::NeverClient;
+    use aws_smithy_mocks::{Rule, RuleMode, mock, mock_client};
+
+    fn url_item(created_at: DateTime<Utc>, expires_at: Option<i64>) -> UrlItem {
+        UrlItem {
+            short_code: "abc123".to_string(),
+            original_url: "https://example.com".to_string(),
+            created_at: created_at.to_rfc3339(),
+            expires_at,
+            click_count: 0,
+            custom_code: false,
+            status: "active".to_string(),
+            password_hash: None,
+            max_uses: None,
+            redirect_status: None,
+            canonical_code: None,
+            wildcard: false,
+            creator_ip: None,
+            unique_visitors: None,
+            extra_headers: None,
+            ttl_source: None,
+        }
+    }
+
+    fn query_output_for(item: &UrlItem) -> QueryOutput {
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            "short_code".to_string(),
+            AttributeValue::S(item.short_code.clone()),
+        );
+        attributes.insert(
+            "original_url".to_string(),
+            AttributeValue::S(item.original_url.clone()),
+        );
+        attributes.insert(
+            "created_at".to_string(),
+            AttributeValue::S(item.created_at.clone()),
+        );
+        if let Some(expires_at) = item.expires_at {
+            attributes.insert(
+                "expires_at".to_string(),
+                AttributeValue::N(expires_at.to_string()),
+            );
+        }
+        QueryOutput::builder().items(attributes).build()
+    }
+
+    #[test]
+    fn test_from_config_with_endpoint() {
+        let sdk_config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(aws_config::Region::new("us-east-1"))
+            .build();
+
+        let db_client = DynamoDbClient::from_config(
+            &sdk_config,
+            Some("http://localhost:4566"),
+            "test-table".to_string(),
+            ClientTuning::default(),
+        );
+        assert_eq!(db_client.table_name(), "test-table");
+
+        let db_client = DynamoDbClient::from_config(
+            &sdk_config,
+            None,
+            "other".to_string(),
+            ClientTuning::default(),
+        );
+        assert_eq!(db_client.table_name(), "other");
+    }
+
+    #[test]
+    fn test_client_tuning_from_lookup() {
+        assert_eq!(ClientTuning::from_lookup(|_| None), ClientTuning::default());
+
+        let tuning = ClientTuning::from_lookup(|key| match key {
+            DYNAMODB_TIMEOUT_MS_ENV => Some("750".to_string()),
+            DYNAMODB_MAX_ATTEMPTS_ENV => Some("1".to_string()),
+            DYNAMODB_TRANSACTIONS_ENV => Some("false".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            tuning.operation_timeout,
+            std::time::Duration::from_millis(750)
+        );
+        assert_eq!(tuning.max_attempts, 1);
+        assert!(!tuning.transactions);
+
+        let zeroed = ClientTuning::from_lookup(|_| Some("0".to_string()));
+        assert_eq!(zeroed, ClientTuning::default());
+    }
+
+    #[test]
+    fn test_timeouts_are_classified_apart_from_other_errors() {
+        type TestSdkError = SdkError<std::io::Error, ()>;
+
+        let operation_timeout: TestSdkError = SdkError::timeout_error("operation timeout");
+        let err = database_error(operation_timeout);
+        assert!(matches!(err, UrlShortenerError::DatabaseTimeout(_)));
+        assert_eq!(err.status_code(), 503);
+
+        let connect_timeout: TestSdkError =
+            SdkError::dispatch_failure(ConnectorError::timeout("HTTP connect timeout".into()));
+        assert!(matches!(
+            database_error(connect_timeout),
+            UrlShortenerError::DatabaseTimeout(_)
+        ));
+
+        // Not timeouts, whatever the message says
+        let refused: TestSdkError = SdkError::dispatch_failure(ConnectorError::io(
+            "request has timed out: connection refused".into(),
+        ));
+        assert!(matches!(
+            database_error(refused),
+            UrlShortenerError::DatabaseError(_)
+        ));
+        let construction: TestSdkError =
+            SdkError::construction_failure("dispatch failure: timeout");
+        assert!(matches!(
+            database_error(construction),
+            UrlShortenerError::DatabaseError(_)
+        ));
+    }
+
+    #[test]
+    fn test_dedup_policy_from_lookup() {
+        assert_eq!(DedupPolicy::from_lookup(|_| None), DedupPolicy::default());
+
+        let policy = DedupPolicy::from_lookup(|key| match key {
+            DEDUP_IGNORE_EXPIRED_ENV => Some("FALSE".to_string()),
+            DEDUP_MAX_AGE_HOURS_ENV => Some(" 24 ".to_string()),
+            _ => None,
+        });
+        assert!(!policy.ignore_expired);
+        assert_eq!(policy.max_age_hours, Some(24));
+
+        let malformed = DedupPolicy::from_lookup(|key| {
+            (key == DEDUP_MAX_AGE_HOURS_ENV).then(|| "a day".to_string())
+        });
+        assert_eq!(malformed, DedupPolicy::default());
+    }
+
+    #[test]
+    fn test_dedup_policy_window() {
+        let now = Utc::now();
+        let policy = DedupPolicy {
+            ignore_expired: true,
+            max_age_hours: Some(24),
+        };
+
+        assert!(policy.matches(&url_item(now - Duration::hours(1), None), now));
+        assert!(!policy.matches(&url_item(now - Duration::hours(25), None), now));
+        assert!(!policy.matches(
+            &url_item(now - Duration::hours(1), Some(now.timestamp() - 60)),
+            now
+        ));
+
+        let lenient = DedupPolicy {
+            ignore_expired: false,
+            max_age_hours: None,
+        };
+        assert!(lenient.matches(
+            &url_item(now - Duration::hours(1000), Some(now.timestamp() - 60)),
+            now
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_find_existing_url_returns_fresh_match() {
+        let fresh = url_item(Utc::now(), Some(Utc::now().timestamp() + 3600));
+        let query_output = query_output_for(&fresh);
+        let query_rule = mock!(Client::query).then_output(move || query_output.clone());
+        let client = mock_client!(aws_sdk_dynamodb, [&query_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let existing = db_client
+            .find_existing_url("https://example.com")
+            .await
+            .unwrap();
+
+        assert_eq!(existing.unwrap().short_code, "abc123");
+    }
+
+    #[tokio::test]
+    async fn test_reindex_dedup_restores_missing_markers() {
+        let link = |short_code: &str, original_url: &str| {
+            let mut item = url_item(Utc::now(), None);
+            item.short_code = short_code.to_string();
+            item.original_url = original_url.to_string();
+            item
+        };
+        let mut protected = link("locked", "https://example.com/private");
+        protected.password_hash = Some("hash".to_string());
+        let page = ScanOutput::builder()
+            .items(url_item_attributes(&link("abc123", "https://example.com")))
+            .items(url_item_attributes(&protected))
+            .items(url_item_attributes(&link("held", "https://example.org")))
+            .build();
+        let scan_rule = mock!(Client::scan)
+            .match_requests(|req| {
+                req.filter_expression() == Some("NOT begins_with(short_code, :internal)")
+            })
+            .then_output(move || page.clone());
+        let restored_rule = mock!(Client::put_item)
+            .match_requests(|req| {
+                let item = req.item().unwrap();
+                item.get("short_code")
+                    == Some(&AttributeValue::S(dedup_marker_key("https://example.com/")))
+                    && item.get("target_code") == Some(&AttributeValue::S("abc123".to_string()))
+            })
+            .then_output(|| PutItemOutput::builder().build());
+        let held_rule = mock!(Client::put_item)
+            .match_requests(|req| {
+                req.item().unwrap().get("target_code")
+                    == Some(&AttributeValue::S("held".to_string()))
+            })
+            .then_error(|| {
+                PutItemError::ConditionalCheckFailedException(
+                    ConditionalCheckFailedException::builder().build(),
+                )
+            });
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&scan_rule, &restored_rule, &held_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let report = db_client.reindex_dedup(100).await.unwrap();
+
+        // The protected link can't be handed out, so it gets no marker
+        assert_eq!(
+            report,
+            ReindexReport {
+                scanned: 3,
+                restored: 1
+            }
+        );
+        assert_eq!(restored_rule.num_calls(), 1);
+        assert_eq!(held_rule.num_calls(), 1);
+    }
+
+    #[test]
+    fn test_estimate_item_size() {
+        let small = url_item(Utc::now(), None);
+        let base = estimate_item_size(&small);
+        assert!(base > small.original_url.len() && base < 512, "{}", base);
+
+        // Each extra URL byte is one stored byte
+        let mut large = url_item(Utc::now(), None);
+        large.original_url = format!("https://example.com/{}", "a".repeat(MAX_ITEM_SIZE_BYTES));
+        assert_eq!(
+            estimate_item_size(&large) - base,
+            large.original_url.len() - small.original_url.len()
+        );
+        assert!(estimate_item_size(&large) > MAX_ITEM_SIZE_BYTES);
+    }
+
+    #[tokio::test]
+    async fn test_put_url_rejects_oversized_item() {
+        let put_rule = mock!(Client::put_item).then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let mut large = url_item(Utc::now(), None);
+        large.original_url = format!("https://example.com/{}", "a".repeat(400 * 1024));
+
+        let err = db_client.put_url(&large).await.unwrap_err();
+        assert!(
+            matches!(&err, UrlShortenerError::PayloadTooLarge(msg) if msg.starts_with("item too large"))
+        );
+        assert_eq!(err.status_code(), 413);
+        assert_eq!(put_rule.num_calls(), 0);
+
+        db_client
+            .put_url(&url_item(Utc::now(), None))
+            .await
+            .unwrap();
+        assert_eq!(put_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_put_url_overwrite_rejects_oversized_item() {
+        let put_rule = mock!(Client::put_item).then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        // Imports carry extra_headers as given
+        let mut large = url_item(Utc::now(), None);
+        large.extra_headers = Some(
+            (0..100)
+                .map(|i| (format!("X-Header-{}", i), "v".repeat(5 * 1024)))
+                .collect(),
+        );
+
+        let err = db_client.put_url_overwrite(&large).await.unwrap_err();
+        assert!(matches!(err, UrlShortenerError::PayloadTooLarge(_)));
+        assert_eq!(err.status_code(), 413);
+        assert_eq!(put_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_creates_when_url_is_new() {
+        let query_rule = mock!(Client::query).then_output(|| QueryOutput::builder().build());
+        let transact_rule = mock!(Client::transact_write_items)
+            .then_output(|| TransactWriteItemsOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&query_rule, &transact_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let (item, created) = db_client
+            .get_or_create("https://example.com/", url_item(Utc::now(), None))
+            .await
+            .unwrap();
+
+        assert!(created);
+        assert_eq!(item.short_code, "abc123");
+        assert_eq!(transact_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_returns_existing_link() {
+        let mut existing = url_item(Utc::now(), None);
+        existing.short_code = "older".to_string();
+        let query_output = query_output_for(&existing);
+        let query_rule = mock!(Client::query).then_output(move || query_output.clone());
+        let transact_rule = mock!(Client::transact_write_items)
+            .then_output(|| TransactWriteItemsOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&query_rule, &transact_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let (item, created) = db_client
+            .get_or_create("https://example.com/", url_item(Utc::now(), None))
+            .await
+            .unwrap();
+
+        assert!(!created);
+        assert_eq!(item.short_code, "older");
+        assert_eq!(transact_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_without_transactions() {
+        // Nothing at first; by the time the put conflicts, a concurrent
+        // create of the same URL is visible
+        let mut winner = url_item(Utc::now(), None);
+        winner.short_code = "winner".to_string();
+        let winner_output = query_output_for(&winner);
+        let query_rule = mock!(Client::query)
+            .sequence()
+            .output(|| QueryOutput::builder().build())
+            .output(move || winner_output.clone())
+            .build();
+        let put_rule = mock!(Client::put_item).then_error(|| {
+            PutItemError::ConditionalCheckFailedException(
+                ConditionalCheckFailedException::builder().build(),
+            )
+        });
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&query_rule, &put_rule]
+        );
+        let db_client =
+            DynamoDbClient::new(client, "test-table".to_string()).with_transactions(false);
+
+        let (item, created) = db_client
+            .get_or_create("https://example.com/", url_item(Utc::now(), None))
+            .await
+            .unwrap();
+
+        assert!(!created);
+        assert_eq!(item.short_code, "winner");
+        assert_eq!(put_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_find_existing_url_skips_expired_match() {
+        let expired = url_item(
+            Utc::now() - Duration::hours(48),
+            Some(Utc::now().timestamp() - 3600),
+        );
+        let query_output = query_output_for(&expired);
+        let query_rule = mock!(Client::query).then_output(move || query_output.clone());
+        let client = mock_client!(aws_sdk_dynamodb, [&query_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        // No reusable match, so the create path mints a new code
+        let existing = db_client
+            .find_existing_url("https://example.com")
+            .await
+            .unwrap();
+
+        assert!(existing.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_count_urls_sums_all_pages() {
+        let scan_rule = mock!(Client::scan)
+            .sequence()
+            .output(|| {
+                ScanOutput::builder()
+                    .count(3)
+                    .last_evaluated_key("short_code", AttributeValue::S("abc123".to_string()))
+                    .build()
+            })
+            .output(|| ScanOutput::builder().count(2).build())
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&scan_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let count = db_client.count_urls().await.unwrap();
+
+        assert_eq!(count, 5);
+        assert_eq!(scan_rule.num_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_limited_increment_last_use_then_exhausted() {
+        // max_uses = 3: the call at click_count 2 is the last allowed use, the
+        // next one fails the condition
+        let update_rule = mock!(Client::update_item)
+            .match_requests(|req| {
+                req.condition_expression() == Some("click_count < :max")
+                    && req
+                        .expression_attribute_values()
+                        .and_then(|values| values.get(":max"))
+                        == Some(&AttributeValue::N("3".to_string()))
+            })
+            .sequence()
+            .output(|| UpdateItemOutput::builder().build())
+            .error(|| {
+                UpdateItemError::ConditionalCheckFailedException(
+                    ConditionalCheckFailedException::builder().build(),
+                )
+            })
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&update_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(
+            db_client
+                .increment_click_count_limited("abc123", 3)
+                .await
+                .is_ok()
+        );
+
+        let err = db_client
+            .increment_click_count_limited("abc123", 3)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::UrlExhausted));
+        assert_eq!(err.status_code(), 410);
+    }
+
+    #[tokio::test]
+    async fn test_unique_visit_rides_on_the_click_update() {
+        let update_rule = mock!(aws_sdk_dynamodb::Client::update_item)
+            .match_requests(|req| {
+                req.update_expression().is_some_and(|e| {
+                    e.contains("click_count :one") && e.contains("unique_visitors :one")
+                }) && req
+                    .condition_expression()
+                    .is_some_and(|c| c.ends_with("click_count < :max"))
+            })
+            .then_output(|| UpdateItemOutput::builder().build());
+        let client = DynamoDbClient::new(
+            mock_client!(aws_sdk_dynamodb, [&update_rule]),
+            "t".to_string(),
+        );
+
+        let outcome = client
+            .record_click_with_visit("abc123", Some(5), "fedcba9876543210", "2024-03-09")
+            .await
+            .unwrap();
+        assert_eq!(outcome, VisitOutcome::Counted);
+        assert_eq!(update_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unique_visit_starts_new_day_set() {
+        // Yesterday's set rejects the ADD, so the set is replaced for today
+        let add_rule = mock!(aws_sdk_dynamodb::Client::update_item)
+            .match_requests(|req| {
+                req.update_expression()
+                    .is_some_and(|e| e.starts_with("ADD"))
+            })
+            .then_error(|| {
+                UpdateItemError::ConditionalCheckFailedException(
+                    ConditionalCheckFailedException::builder()
+                        .item("visitor_day", AttributeValue::S("2024-03-08".to_string()))
+                        .item(
+                            "visitor_keys",
+                            AttributeValue::Ss(vec!["0123456789abcdef".to_string()]),
+                        )
+                        .build(),
+                )
+            });
+        let reset_rule = mock!(aws_sdk_dynamodb::Client::update_item)
+            .match_requests(|req| {
+                req.update_expression()
+                    .is_some_and(|e| e.starts_with("SET") && e.contains("click_count :one"))
+                    && req
+                        .expression_attribute_values()
+                        .and_then(|v| v.get(":day"))
+                        == Some(&AttributeValue::S("2024-03-09".to_string()))
+            })
+            .then_output(|| UpdateItemOutput::builder().build());
+        let client = DynamoDbClient::new(
+            mock_client!(
+                aws_sdk_dynamodb,
+                RuleMode::MatchAny,
+                [&add_rule, &reset_rule]
+            ),
+            "t".to_string(),
+        );
+
+        let outcome = client
+            .record_click_with_visit("abc123", None, "fedcba9876543210", "2024-03-09")
+            .await
+            .unwrap();
+        assert_eq!(outcome, VisitOutcome::NewDay);
+        assert_eq!(reset_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unique_visit_repeat_counts_only_the_click() {
+        let add_rule = mock!(aws_sdk_dynamodb::Client::update_item)
+            .match_requests(|req| {
+                req.update_expression()
+                    .is_some_and(|e| e.contains("visitor_keys"))
+            })
+            .then_error(|| {
+                UpdateItemError::ConditionalCheckFailedException(
+                    ConditionalCheckFailedException::builder()
+                        .item("click_count", AttributeValue::N("1".to_string()))
+                        .item("visitor_day", AttributeValue::S("2024-03-09".to_string()))
+                        .item(
+                            "visitor_keys",
+                            AttributeValue::Ss(vec!["fedcba9876543210".to_string()]),
+                        )
+                        .build(),
+                )
+            });
+        let click_rule = mock!(aws_sdk_dynamodb::Client::update_item)
+            .match_requests(|req| req.update_expression() == Some("ADD click_count :inc"))
+            .then_output(|| UpdateItemOutput::builder().build());
+        let client = DynamoDbClient::new(
+            mock_client!(
+                aws_sdk_dynamodb,
+                RuleMode::MatchAny,
+                [&add_rule, &click_rule]
+            ),
+            "t".to_string(),
+        );
+
+        let outcome = client
+            .record_click_with_visit("abc123", Some(5), "fedcba9876543210", "2024-03-09")
+            .await
+            .unwrap();
+        assert_eq!(outcome, VisitOutcome::Duplicate);
+        assert_eq!(add_rule.num_calls(), 1);
+        assert_eq!(click_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unique_visit_on_used_up_link_is_exhausted() {
+        let add_rule = mock!(aws_sdk_dynamodb::Client::update_item).then_error(|| {
+            UpdateItemError::ConditionalCheckFailedException(
+                ConditionalCheckFailedException::builder()
+                    .item("click_count", AttributeValue::N("3".to_string()))
+                    .item("visitor_day", AttributeValue::S("2024-03-09".to_string()))
+                    .build(),
+            )
+        });
+        let client =
+            DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, [&add_rule]), "t".to_string());
+
+        let err = client
+            .record_click_with_visit("abc123", Some(3), "fedcba9876543210", "2024-03-09")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::UrlExhausted));
+        assert_eq!(add_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unique_visit_timeout_is_a_database_timeout() {
+        // A connection that never answers, cut off by the operation timeout
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(aws_sdk_dynamodb::config::Region::new("us-east-1"))
+            .credentials_provider(aws_sdk_dynamodb::config::Credentials::new(
+                "test", "test", None, None, "test",
+            ))
+            .http_client(NeverClient::new())
+            .timeout_config(
+                TimeoutConfig::builder()
+                    .operation_timeout(std::time::Duration::from_millis(50))
+                    .build(),
+            )
+            .build();
+        let client = DynamoDbClient::new(Client::from_conf(config), "t".to_string());
+
+        let err = client
+            .record_click_with_visit("abc123", Some(3), "fedcba9876543210", "2024-03-09")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::DatabaseTimeout(_)));
+        assert_eq!(err.status_code(), 503);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_leaves_out_visitor_keys() {
+        let stored = url_item_attributes(&url_item(Utc::now(), None));
+        let get_rule = mock!(aws_sdk_dynamodb::Client::get_item)
+            .match_requests(|req| {
+                req.projection_expression() == Some(URL_ITEM_PROJECTION)
+                    && !URL_ITEM_PROJECTION.contains("visitor_keys")
+            })
+            .then_output(move || {
+                GetItemOutput::builder()
+                    .set_item(Some(stored.clone()))
+                    .build()
+            });
+        let client =
+            DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, [&get_rule]), "t".to_string());
+
+        assert!(client.get_url("abc123").await.unwrap().is_some());
+
+        // Every attribute a link is written with is read back
+        let projected: HashSet<&str> = URL_ITEM_PROJECTION
+            .split(',')
+            .map(|name| name.trim().trim_start_matches('#'))
+            .collect();
+        let mut item = url_item(Utc::now(), Some(1_900_000_000));
+        item.custom_code = true;
+        item.password_hash = Some("hash".to_string());
+        item.max_uses = Some(3);
+        item.redirect_status = Some(302);
+        item.canonical_code = Some("canon".to_string());
+        item.wildcard = true;
+        item.creator_ip = Some("ip".to_string());
+        item.unique_visitors = Some(1);
+        item.extra_headers = Some(HashMap::from([("X-A".to_string(), "1".to_string())]));
+        item.ttl_source = Some(TtlSource::User);
+        for name in url_item_attributes(&item).keys() {
+            assert!(
+                projected.contains(name.as_str()) || name == "created_ts",
+                "{} is not read back",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_increment_clicks_collects_failures() {
+        let code_is = |req: &aws_sdk_dynamodb::operation::update_item::UpdateItemInput,
+                       code: &str| {
+            req.key().and_then(|key| key.get("short_code"))
+                == Some(&AttributeValue::S(code.to_string()))
+        };
+
+        let ok_rule = mock!(Client::update_item)
+            .match_requests(move |req| {
+                code_is(req, "ok")
+                    && req.condition_expression() == Some("attribute_exists(short_code)")
+                    && req
+                        .expression_attribute_values()
+                        .and_then(|values| values.get(":inc"))
+                        == Some(&AttributeValue::N("5".to_string()))
+            })
+            .then_output(|| UpdateItemOutput::builder().build());
+        let failing_rule = mock!(Client::update_item)
+            .match_requests(move |req| code_is(req, "flaky"))
+            .then_error(|| {
+                UpdateItemError::InternalServerError(InternalServerError::builder().build())
+            });
+        let deleted_rule = mock!(Client::update_item)
+            .match_requests(move |req| code_is(req, "deleted"))
+            .then_error(|| {
+                UpdateItemError::ConditionalCheckFailedException(
+                    ConditionalCheckFailedException::builder().build(),
+                )
+            });
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&ok_rule, &failing_rule, &deleted_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let deltas = HashMap::from([
+            ("ok".to_string(), 5),
+            ("flaky".to_string(), 3),
+            ("deleted".to_string(), 2),
+            ("idle".to_string(), 0),
+        ]);
+        let err = db_client.batch_increment_clicks(&deltas).await.unwrap_err();
+
+        let UrlShortenerError::ClickFlushIncomplete { failed } = err else {
+            panic!("expected ClickFlushIncomplete, got {:?}", err);
+        };
+        assert_eq!(failed, HashMap::from([("flaky".to_string(), 3)]));
+        assert_eq!(ok_rule.num_calls(), 1);
+        assert_eq!(deleted_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_put_url_overwrite_replaces_existing_fields() {
+        let mut imported = url_item(Utc::now() - Duration::days(30), None);
+        imported.original_url = "https://example.com/moved".to_string();
+        imported.click_count = 42;
+        let created_at = imported.created_at.clone();
+
+        // No attribute_not_exists guard, and imported counters are kept as-is
+        let put_rule = mock!(Client::put_item)
+            .match_requests(move |req| {
+                let item = req.item().unwrap();
+                req.condition_expression().is_none()
+                    && item.get("original_url")
+                        == Some(&AttributeValue::S("https://example.com/moved".to_string()))
+                    && item.get("click_count") == Some(&AttributeValue::N("42".to_string()))
+                    && item.get("created_at") == Some(&AttributeValue::S(created_at.clone()))
+            })
+            .then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        db_client.put_url_overwrite(&imported).await.unwrap();
+
+        assert_eq!(put_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_put_url_maps_condition_failure_to_short_code_exists() {
+        let put_rule = mock!(Client::put_item).then_error(|| {
+            PutItemError::ConditionalCheckFailedException(
+                ConditionalCheckFailedException::builder().build(),
+            )
+        });
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let err = db_client
+            .put_url(&url_item(Utc::now(), None))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, UrlShortenerError::ShortCodeExists(code) if code == "abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_suggested_codes_are_free() {
+        let taken = ["promo-2", "promo-3"];
+        let taken_rule = mock!(Client::get_item)
+            .match_requests(move |req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| taken.contains(&code.as_str()))
+            })
+            .sequence()
+            .output(|| {
+                let mut item = HashMap::new();
+                item.insert(
+                    "short_code".to_string(),
+                    AttributeValue::S("promo-x".to_string()),
+                );
+                item.insert(
+                    "original_url".to_string(),
+                    AttributeValue::S("https://example.com".to_string()),
+                );
+                item.insert(
+                    "created_at".to_string(),
+                    AttributeValue::S(Utc::now().to_rfc3339()),
+                );
+                GetItemOutput::builder().set_item(Some(item)).build()
+            })
+            .repeatedly()
+            .build();
+        let free_rule = mock!(Client::get_item)
+            .sequence()
+            .output(|| GetItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&taken_rule, &free_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let suggestions = db_client.suggest_available_codes("promo", 3).await.unwrap();
+
+        assert_eq!(suggestions.len(), 3);
+        assert_eq!(suggestions[0], "promo-4");
+        for suggestion in &suggestions {
+            assert!(!taken.contains(&suggestion.as_str()));
+            assert!(crate::validation::validate_custom_code(suggestion).is_ok());
+        }
+        assert_eq!(taken_rule.num_calls(), 2);
+    }
+
+    #[test]
+    fn test_suggestion_candidates_fit_code_length() {
+        let long_code = "a".repeat(20);
+        for candidate in suggestion_candidates(&long_code).take(5) {
+            assert!(candidate.len() <= MAX_CUSTOM_CODE_LEN);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_yields_items_across_pages() {
+        let page_for = |short_code: &str| {
+            let mut attributes = HashMap::new();
+            attributes.insert(
+                "short_code".to_string(),
+                AttributeValue::S(short_code.to_string()),
+            );
+            attributes.insert(
+                "original_url".to_string(),
+                AttributeValue::S("https://example.com".to_string()),
+            );
+            attributes.insert(
+                "created_at".to_string(),
+                AttributeValue::S(Utc::now().to_rfc3339()),
+            );
+            attributes
+        };
+        let first_page = page_for("first");
+        let second_page = page_for("second");
+        let start_key = HashMap::from([(
+            "short_code".to_string(),
+            AttributeValue::S("first".to_string()),
+        )]);
+
+        let scan_rule = mock!(Client::scan)
+            .sequence()
+            .output(move || {
+                ScanOutput::builder()
+                    .items(first_page.clone())
+                    .set_last_evaluated_key(Some(start_key.clone()))
+                    .build()
+            })
+            .output(move || ScanOutput::builder().items(second_page.clone()).build())
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&scan_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let items: Vec<UrlItem> = db_client.scan_stream().try_collect().await.unwrap();
+
+        let codes: Vec<_> = items.iter().map(|item| item.short_code.as_str()).collect();
+        assert_eq!(codes, ["first", "second"]);
+        assert_eq!(scan_rule.num_calls(), 2);
+    }
+
+    fn reason(code: &str) -> CancellationReason {
+        CancellationReason::builder().code(code).build()
+    }
+
+    #[test]
+    fn test_classify_transaction_cancellation() {
+        assert_eq!(
+            classify_cancellation(&[reason("None"), reason("ConditionalCheckFailed")]),
+            TransactConflict::DuplicateUrl
+        );
+        assert_eq!(
+            classify_cancellation(&[reason("ConditionalCheckFailed"), reason("None")]),
+            TransactConflict::ShortCodeTaken
+        );
+        // Same URL raced onto the same code: hand back the existing link
+        assert_eq!(
+            classify_cancellation(&[
+                reason("ConditionalCheckFailed"),
+                reason("ConditionalCheckFailed")
+            ]),
+            TransactConflict::DuplicateUrl
+        );
+        assert_eq!(
+            classify_cancellation(&[reason("ThrottlingError"), reason("None")]),
+            TransactConflict::Other
+        );
+        assert_eq!(classify_cancellation(&[]), TransactConflict::Other);
+    }
+
+    #[test]
+    fn test_classify_idempotent_create_cancellation() {
+        assert!(matches!(
+            idempotent_create_error(
+                &[reason("None"), reason("ConditionalCheckFailed")],
+                "abc123",
+                "key-1"
+            ),
+            Some(UrlShortenerError::IdempotencyConflict(key)) if key == "key-1"
+        ));
+        assert!(matches!(
+            idempotent_create_error(
+                &[reason("ConditionalCheckFailed"), reason("None")],
+                "abc123",
+                "key-1"
+            ),
+            Some(UrlShortenerError::ShortCodeExists(code)) if code == "abc123"
+        ));
+        // A retried request whose first attempt did land: report the key
+        assert!(matches!(
+            idempotent_create_error(
+                &[
+                    reason("ConditionalCheckFailed"),
+                    reason("ConditionalCheckFailed")
+                ],
+                "abc123",
+                "key-1"
+            ),
+            Some(UrlShortenerError::IdempotencyConflict(_))
+        ));
+        assert!(
+            idempotent_create_error(&[reason("ThrottlingError"), reason("None")], "abc123", "k")
+                .is_none()
+        );
+    }
+
+    fn idempotency_key(original_url: &str) -> IdempotencyKey {
+        let request = crate::models::CreateUrlRequest::from_json(&format!(
+            r#"{{"original_url": "{}"}}"#,
+            original_url
+        ))
+        .unwrap();
+        IdempotencyKey::from_header(Some("key-1"), &request)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_used_idempotency_key_reads_back_prior_link() {
+        let key = idempotency_key("https://example.com");
+        let transact_rule = mock!(Client::transact_write_items)
+            .match_requests(|req| {
+                req.transact_items()[1]
+                    .put()
+                    .and_then(|put| put.item().get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| {
+                        code.len() == IDEMPOTENCY_KEY_PREFIX.len() + 64 && !code.contains("key-1")
+                    })
+            })
+            .then_error(|| {
+                TransactWriteItemsError::TransactionCanceledException(
+                    aws_sdk_dynamodb::types::error::TransactionCanceledException::builder()
+                        .cancellation_reasons(reason("None"))
+                        .cancellation_reasons(reason("ConditionalCheckFailed"))
+                        .build(),
+                )
+            });
+        let fingerprint = key.fingerprint().to_string();
+        let key_rule = mock!(Client::get_item)
+            .match_requests(|req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| code.starts_with(IDEMPOTENCY_KEY_PREFIX))
+            })
+            .sequence()
+            .output(move || {
+                GetItemOutput::builder()
+                    .item("target_code", AttributeValue::S("first".to_string()))
+                    .item("fingerprint", AttributeValue::S(fingerprint.clone()))
+                    .item(
+                        "expires_at",
+                        AttributeValue::N((Utc::now().timestamp() + 3600).to_string()),
+                    )
+                    .build()
+            })
+            .repeatedly()
+            .build();
+        let prior_rule = mock!(Client::get_item)
+            .sequence()
+            .output(|| {
+                GetItemOutput::builder()
+                    .item("short_code", AttributeValue::S("first".to_string()))
+                    .item(
+                        "original_url",
+                        AttributeValue::S("https://example.com".to_string()),
+                    )
+                    .item("created_at", AttributeValue::S(Utc::now().to_rfc3339()))
+                    .build()
+            })
+            .repeatedly()
+            .build();
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&transact_rule, &key_rule, &prior_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let err = db_client
+            .transact_create_with_idempotency(&url_item(Utc::now(), None), &key)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::IdempotencyConflict(_)));
+        assert_eq!(err.status_code(), 409);
+
+        let prior = db_client.idempotent_result(&key).await.unwrap().unwrap();
+        assert_eq!(prior.short_code, "first");
+
+        // A retry racing the first create gets the first create's link
+        let outcome = db_client
+            .create_with_idempotency(&url_item(Utc::now(), None), &key)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, CreateOutcome::Existing(item) if item.short_code == "first"));
+
+        // The same key with another body is refused
+        let err = db_client
+            .idempotent_result(&idempotency_key("https://example.org"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::IdempotencyKeyMismatch(_)));
+        assert_eq!(err.status_code(), 422);
+    }
+
+    #[tokio::test]
+    async fn test_expired_idempotency_record_is_ignored() {
+        let key_rule = mock!(Client::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("target_code", AttributeValue::S("first".to_string()))
+                .item("fingerprint", AttributeValue::S("stale".to_string()))
+                .item(
+                    "expires_at",
+                    AttributeValue::N((Utc::now().timestamp() - 60).to_string()),
+                )
+                .build()
+        });
+        let client = mock_client!(aws_sdk_dynamodb, [&key_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let key = idempotency_key("https://example.com");
+        assert!(db_client.idempotent_result(&key).await.unwrap().is_none());
+        assert_eq!(key_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_transactional_create_loser_reads_winner() {
+        let transact_rule = mock!(Client::transact_write_items).then_error(|| {
+            TransactWriteItemsError::TransactionCanceledException(
+                aws_sdk_dynamodb::types::error::TransactionCanceledException::builder()
+                    .cancellation_reasons(reason("None"))
+                    .cancellation_reasons(reason("ConditionalCheckFailed"))
+                    .build(),
+            )
+        });
+        let marker_rule = mock!(Client::get_item)
+            .match_requests(|req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| code.starts_with(DEDUP_MARKER_PREFIX))
+            })
+            .then_output(|| {
+                GetItemOutput::builder()
+                    .item("target_code", AttributeValue::S("winner".to_string()))
+                    .build()
+            });
+        let winner_rule = mock!(Client::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("short_code", AttributeValue::S("winner".to_string()))
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("created_at", AttributeValue::S(Utc::now().to_rfc3339()))
+                .build()
+        });
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&transact_rule, &marker_rule, &winner_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let outcome = db_client
+            .create_url_transactional(&url_item(Utc::now(), None), "https://example.com/")
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, CreateOutcome::Existing(item) if item.short_code == "winner"));
+        assert_eq!(transact_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_racing_transactional_creates_share_one_link() {
+        // Both creates write the same marker conditionally; DynamoDB commits
+        // one transaction and cancels the other on the marker
+        let transact_rule = mock!(Client::transact_write_items)
+            .match_requests(|req| {
+                req.transact_items()[1].put().is_some_and(|put| {
+                    put.condition_expression()
+                        .is_some_and(|c| c.starts_with("attribute_not_exists(short_code)"))
+                })
+            })
+            .sequence()
+            .output(|| TransactWriteItemsOutput::builder().build())
+            .error(|| {
+                TransactWriteItemsError::TransactionCanceledException(
+                    aws_sdk_dynamodb::types::error::TransactionCanceledException::builder()
+                        .cancellation_reasons(reason("None"))
+                        .cancellation_reasons(reason("ConditionalCheckFailed"))
+                        .build(),
+                )
+            })
+            .build();
+        let marker_rule = mock!(Client::get_item)
+            .match_requests(|req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| code.starts_with(DEDUP_MARKER_PREFIX))
+            })
+            .then_output(|| {
+                GetItemOutput::builder()
+                    .item("target_code", AttributeValue::S("first".to_string()))
+                    .build()
+            });
+        let winner_rule = mock!(Client::get_item).then_output(|| {
+            GetItemOutput::builder()
+                .item("short_code", AttributeValue::S("first".to_string()))
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("created_at", AttributeValue::S(Utc::now().to_rfc3339()))
+                .build()
+        });
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&transact_rule, &marker_rule, &winner_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let mut first = url_item(Utc::now(), None);
+        first.short_code = "first".to_string();
+        let mut second = url_item(Utc::now(), None);
+        second.short_code = "second".to_string();
+        let (a, b) = tokio::join!(
+            db_client.create_url_transactional(&first, "https://example.com/"),
+            db_client.create_url_transactional(&second, "https://example.com/")
+        );
+
+        let outcomes = [a.unwrap(), b.unwrap()];
+        assert_eq!(
+            outcomes
+                .iter()
+                .filter(|o| matches!(o, CreateOutcome::Created))
+                .count(),
+            1
+        );
+        assert!(
+            outcomes
+                .iter()
+                .any(|o| matches!(o, CreateOutcome::Existing(item) if item.short_code == "first"))
+        );
+        assert_eq!(transact_rule.num_calls(), 2);
+        assert_eq!(marker_rule.num_calls(), 1);
+    }
+
+    #[test]
+    fn test_dedup_marker_key_is_hashed() {
+        let long_url = format!("https://example.com/{}", "a".repeat(4000));
+        let key = dedup_marker_key(&long_url);
+
+        assert!(key.starts_with(DEDUP_MARKER_PREFIX));
+        assert_eq!(key.len(), DEDUP_MARKER_PREFIX.len() + 64);
+        assert_eq!(key, dedup_marker_key(&long_url));
+        assert_ne!(key, dedup_marker_key("https://example.com/"));
+    }
+
+    /// The marker for the create points at `target`, which comes back from a
+    /// read as `target_item`. The first create is cancelled on the marker;
+    /// only a retry that overwrites the marker held by `target` succeeds.
+    async fn create_over_marker(
+        target_item: Option<(&'static str, &'static str)>,
+    ) -> (CreateOutcome, usize) {
+        let marker_key = dedup_marker_key("https://example.com/");
+        let overwrite_rule = mock!(Client::transact_write_items)
+            .match_requests(|req| {
+                req.transact_items()[1].put().is_some_and(|put| {
+                    put.condition_expression()
+                        .is_some_and(|c| c.contains("target_code = :stale_target"))
+                        && put
+                            .expression_attribute_values()
+                            .and_then(|values| values.get(":stale_target"))
+                            .and_then(|v| v.as_s().ok())
+                            .is_some_and(|target| target == "stale")
+                })
+            })
+            .then_output(|| TransactWriteItemsOutput::builder().build());
+        let cancelled_rule = mock!(Client::transact_write_items).then_error(|| {
+            TransactWriteItemsError::TransactionCanceledException(
+                aws_sdk_dynamodb::types::error::TransactionCanceledException::builder()
+                    .cancellation_reasons(reason("None"))
+                    .cancellation_reasons(reason("ConditionalCheckFailed"))
+                    .build(),
+            )
+        });
+        let marker_rule = mock!(Client::get_item)
+            .match_requests(move |req| {
+                req.key()
+                    .and_then(|key| key.get("short_code"))
+                    .and_then(|code| code.as_s().ok())
+                    .is_some_and(|code| *code == marker_key)
+            })
+            .sequence()
+            .output(|| {
+                GetItemOutput::builder()
+                    .item("target_code", AttributeValue::S("stale".to_string()))
+                    .build()
+            })
+            .repeatedly()
+            .build();
+        let target_rule = mock!(Client::get_item)
+            .sequence()
+            .output(move || match target_item {
+                Some((short_code, original_url)) => GetItemOutput::builder()
+                    .item("short_code", AttributeValue::S(short_code.to_string()))
+                    .item("original_url", AttributeValue::S(original_url.to_string()))
+                    .item("created_at", AttributeValue::S(Utc::now().to_rfc3339()))
+                    .build(),
+                None => GetItemOutput::builder().build(),
+            })
+            .repeatedly()
+            .build();
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&overwrite_rule, &cancelled_rule, &marker_rule, &target_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let outcome = db_client
+            .create_url_transactional(&url_item(Utc::now(), None), "https://example.com/")
+            .await
+            .unwrap();
+        (outcome, cancelled_rule.num_calls())
+    }
+
+    #[tokio::test]
+    async fn test_transactional_create_reclaims_stale_marker() {
+        // The marker's link was deleted
+        let (outcome, cancelled) = create_over_marker(None).await;
+        assert!(matches!(outcome, CreateOutcome::Created));
+        assert_eq!(cancelled, 1);
+
+        // The marker's code now holds a link for another URL
+        let (outcome, cancelled) =
+            create_over_marker(Some(("stale", "https://elsewhere.example/"))).await;
+        assert!(matches!(outcome, CreateOutcome::Created));
+        assert_eq!(cancelled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_expired_filters_and_batches_deletes() {
+        let expired_page = ScanOutput::builder()
+            .set_items(Some(
+                (0..5)
+                    .map(|i| {
+                        HashMap::from([(
+                            "short_code".to_string(),
+                            AttributeValue::S(format!("old{}", i)),
+                        )])
+                    })
+                    .collect(),
+            ))
+            .build();
+        let scan_rule = mock!(Client::scan)
+            .match_requests(|req| {
+                let cutoff = req
+                    .expression_attribute_values()
+                    .and_then(|values| values.get(":now"))
+                    .and_then(|now| now.as_n().ok())
+                    .and_then(|now| now.parse::<i64>().ok());
+                req.filter_expression() == Some("expires_at < :now")
+                    && req.projection_expression() == Some("short_code")
+                    && cutoff.is_some_and(|cutoff| {
+                        cutoff <= Utc::now().timestamp() - expiry_grace_seconds()
+                    })
+            })
+            .then_output(move || expired_page.clone());
+        let batch_rule = mock!(Client::batch_write_item)
+            .match_requests(|req| {
+                req.request_items()
+                    .and_then(|items| items.get("test-table"))
+                    .is_some_and(|requests| requests.len() <= 2)
+            })
+            .sequence()
+            .output(|| BatchWriteItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&scan_rule, &batch_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let purged = db_client.sweep_expired(2).await.unwrap();
+
+        assert_eq!(purged, 5);
+        // 5 keys in batches of 2
+        assert_eq!(batch_rule.num_calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_batch_get_urls_uses_one_call_per_batch() {
+        let db_client = DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "t".to_string());
+        let live = db_client.url_item_to_item(&url_item(Utc::now(), None));
+        let mut expired_item = url_item(Utc::now(), Some(Utc::now().timestamp() - 3600));
+        expired_item.short_code = "old".to_string();
+        let expired = db_client.url_item_to_item(&expired_item);
+
+        let batch_rule = mock!(Client::batch_get_item)
+            .match_requests(|req| {
+                req.request_items()
+                    .and_then(|items| items.get("test-table"))
+                    .is_some_and(|request| request.keys().len() == 3)
+            })
+            .then_output(move || {
+                BatchGetItemOutput::builder()
+                    .responses("test-table", vec![live.clone(), expired.clone()])
+                    .build()
+            });
+        let client = mock_client!(aws_sdk_dynamodb, [&batch_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        // Twenty events for three distinct links, one of them deleted
+        let codes: Vec<String> = ["abc123", "old", "gone"]
+            .iter()
+            .cycle()
+            .take(20)
+            .map(|code| code.to_string())
+            .collect();
+        let found = db_client.batch_get_urls(&codes).await.unwrap();
+
+        assert_eq!(batch_rule.num_calls(), 1);
+        assert_eq!(found.len(), 1);
+        assert!(found.contains_key("abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_first_call_is_timed_whatever_the_operation() {
+        // In create-url the first call is a query or a write, not a read
+        let query_rule = mock!(Client::query).then_output(|| QueryOutput::builder().build());
+        let first_call = FirstCall::default();
+        let client = with_call_timer(
+            mock_client!(aws_sdk_dynamodb, [&query_rule]),
+            first_call.clone(),
+        );
+
+        client.query().table_name("t").send().await.unwrap();
+        assert_eq!(query_rule.num_calls(), 1);
+        assert!(!first_call.claim());
+    }
+
+    #[test]
+    fn test_first_call_flag_flips_once() {
+        let first_call = FirstCall::default();
+        let clone = first_call.clone();
+
+        assert!(first_call.claim());
+        assert!(!first_call.claim());
+        // Shared with clones, but not with separately built flags
+        assert!(!clone.claim());
+        assert!(FirstCall::default().claim());
+
+        let flag = FirstCall::default();
+        let firsts = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| flag.clone().claim()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .filter(|first| *first)
+                .count()
+        });
+        assert_eq!(firsts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_exists_projects_only_the_key() {
+        let get_rule = mock!(Client::get_item)
+            .match_requests(|req| req.projection_expression() == Some("short_code"))
+            .sequence()
+            .output(|| {
+                GetItemOutput::builder()
+                    .item("short_code", AttributeValue::S("abc123".to_string()))
+                    .build()
+            })
+            .output(|| GetItemOutput::builder().build())
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(db_client.exists("abc123").await.unwrap());
+        assert!(!db_client.exists("missing").await.unwrap());
+        assert_eq!(get_rule.num_calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_url_consistent_read_flag() {
+        let consistent_rule = mock!(Client::get_item)
+            .match_requests(|req| req.consistent_read() == Some(true))
+            .then_output(|| GetItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&consistent_rule]);
+        let db_client =
+            DynamoDbClient::new(client, "test-table".to_string()).with_consistent_reads(true);
+
+        assert!(db_client.get_url("abc123").await.unwrap().is_none());
+        assert_eq!(consistent_rule.num_calls(), 1);
+
+        let eventual_rule = mock!(Client::get_item)
+            .match_requests(|req| req.consistent_read() == Some(false))
+            .then_output(|| GetItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&eventual_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(db_client.get_url("abc123").await.unwrap().is_none());
+        assert_eq!(eventual_rule.num_calls(), 1);
+    }
+
+    #[test]
+    fn test_created_ts_range() {
+        assert_eq!(
+            created_ts_range("2024-01-01T00:00:00Z", "2024-01-01T01:00:00+00:00").unwrap(),
+            (1_704_067_200, 1_704_070_800)
+        );
+        assert!(created_ts_range("2024-01-01T01:00:00Z", "2024-01-01T00:00:00Z").is_err());
+        assert!(created_ts_range("yesterday", "2024-01-01T00:00:00Z").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_urls_created_between_filters_on_created_ts() {
+        let stored = url_item(
+            DateTime::parse_from_rfc3339("2024-01-01T00:30:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            None,
+        );
+
+        let client = mock_client!(aws_sdk_dynamodb, []);
+        let item = DynamoDbClient::new(client, "test-table".to_string()).url_item_to_item(&stored);
+        assert_eq!(
+            item.get("created_ts"),
+            Some(&AttributeValue::N("1704069000".to_string()))
+        );
+
+        let scan_rule = mock!(Client::scan)
+            .match_requests(|req| {
+                let values = req.expression_attribute_values().unwrap();
+                req.filter_expression()
+                    .unwrap()
+                    .starts_with("created_ts BETWEEN :from AND :to")
+                    && values.get(":from") == Some(&AttributeValue::N("1704067200".to_string()))
+                    && values.get(":to") == Some(&AttributeValue::N("1704070800".to_string()))
+                    && req
+                        .exclusive_start_key()
+                        .and_then(|key| key.get("short_code"))
+                        == Some(&AttributeValue::S("prev".to_string()))
+            })
+            .then_output(move || {
+                ScanOutput::builder()
+                    .items(item.clone())
+                    .last_evaluated_key("short_code", AttributeValue::S("abc123".to_string()))
+                    .build()
+            });
+        let client = mock_client!(aws_sdk_dynamodb, [&scan_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let page = db_client
+            .list_urls_created_between(
+                "2024-01-01T00:00:00Z",
+                "2024-01-01T01:00:00Z",
+                50,
+                Some("prev".to_string()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].short_code, "abc123");
+        assert_eq!(page.next_cursor.as_deref(), Some("abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_find_expiring_within_window() {
+        let now = Utc::now();
+        let client = mock_client!(aws_sdk_dynamodb, []);
+        let to_item = |code: &str, expires_at: Option<i64>| {
+            let mut stored = url_item(now, expires_at);
+            stored.short_code = code.to_string();
+            DynamoDbClient::new(client.clone(), "test-table".to_string()).url_item_to_item(&stored)
+        };
+        let later = to_item("later", Some(now.timestamp() + 7200));
+        let sooner = to_item("sooner", Some(now.timestamp() + 600));
+        // DynamoDB filters these out; one that slips through is still dropped
+        let forever = to_item("forever", None);
+
+        let scan_rule = mock!(Client::scan)
+            .match_requests(move |req| {
+                let values = req.expression_attribute_values().unwrap();
+                let bound = |key: &str| -> i64 {
+                    values.get(key).unwrap().as_n().unwrap().parse().unwrap()
+                };
+                req.filter_expression()
+                    .unwrap()
+                    .starts_with("expires_at BETWEEN :now AND :until")
+                    && bound(":until") - bound(":now") == 24 * 3600
+                    && (bound(":now") - now.timestamp()).abs() < 60
+            })
+            .then_output(move || {
+                ScanOutput::builder()
+                    .items(later.clone())
+                    .items(forever.clone())
+                    .items(sooner.clone())
+                    .build()
+            });
+        let client = mock_client!(aws_sdk_dynamodb, [&scan_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let expiring = db_client.find_expiring_within(24, 10).await.unwrap();
+        let codes: Vec<_> = expiring.iter().map(|i| i.short_code.as_str()).collect();
+        assert_eq!(codes, ["sooner", "later"]);
+        assert_eq!(scan_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_redirect_target_projects_and_checks_expiry() {
+        let now = Utc::now().timestamp();
+        let mut live = url_item(Utc::now(), Some(now + 3600));
+        live.max_uses = Some(5);
+        let expired = url_item(Utc::now(), Some(now - 3600));
+
+        let client = mock_client!(aws_sdk_dynamodb, []);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+        let (live_item, expired_item) = (
+            db_client.url_item_to_item(&live),
+            db_client.url_item_to_item(&expired),
+        );
+
+        let get_rule = mock!(Client::get_item)
+            .sequence()
+            .output(move || {
+                GetItemOutput::builder()
+                    .set_item(Some(live_item.clone()))
+                    .build()
+            })
+            .times(2)
+            .output(move || {
+                GetItemOutput::builder()
+                    .set_item(Some(expired_item.clone()))
+                    .build()
+            })
+            .times(2)
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let target = db_client
+            .get_redirect_target("abc123")
+            .await
+            .unwrap()
+            .unwrap();
+        let full = db_client.get_url("abc123").await.unwrap().unwrap();
+        assert_eq!(target.original_url, full.original_url);
+        assert_eq!(target.expires_at, full.expires_at);
+        assert_eq!(target.max_uses, Some(5));
+        assert_eq!(target.status, "active");
+
+        assert!(matches!(
+            db_client.get_redirect_target("abc123").await,
+            Err(UrlShortenerError::UrlExpired)
+        ));
+        assert!(matches!(
+            db_client.get_url("abc123").await,
+            Err(UrlShortenerError::UrlExpired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_redirect_target_uses_projection() {
+        let get_rule = mock!(Client::get_item)
+            .match_requests(|req| {
+                req.projection_expression() == Some(REDIRECT_PROJECTION)
+                    && req
+                        .expression_attribute_names()
+                        .and_then(|names| names.get("#status"))
+                        .map(String::as_str)
+                        == Some("status")
+            })
+            .then_output(|| GetItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(
+            db_client
+                .get_redirect_target("missing")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(get_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_link_cache_serves_hits_until_overwritten() {
+        let stored = url_item(Utc::now(), Some(Utc::now().timestamp() + 3600));
+        let item = DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "t".to_string())
+            .url_item_to_item(&stored);
+        let get_rule = mock!(Client::get_item).then_output(move || {
+            GetItemOutput::builder()
+                .set_item(Some(item.clone()))
+                .build()
+        });
+        let put_rule = mock!(Client::put_item).then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&get_rule, &put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string()).with_link_cache(
+            Some(LinkCacheConfig {
+                capacity: 10,
+                ttl_seconds: 60,
+            }),
+        );
+
+        for _ in 0..3 {
+            let target = db_client.get_redirect_target(&stored.short_code).await;
+            assert!(target.unwrap().is_some());
+        }
+        db_client
+            .get_url(&stored.short_code)
+            .await
+            .unwrap()
+            .unwrap();
+        db_client
+            .get_url(&stored.short_code)
+            .await
+            .unwrap()
+            .unwrap();
+        // One read for the redirect target, one for the full item
+        assert_eq!(get_rule.num_calls(), 2);
+
+        db_client.put_url_overwrite(&stored).await.unwrap();
+        db_client
+            .get_redirect_target(&stored.short_code)
+            .await
+            .unwrap();
+        assert_eq!(get_rule.num_calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_link_cache_skips_misses_and_expired_links() {
+        let expired = url_item(Utc::now(), Some(Utc::now().timestamp() - 3600));
+        let item = DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "t".to_string())
+            .url_item_to_item(&expired);
+        let get_rule = mock!(Client::get_item)
+            .sequence()
+            .output(|| GetItemOutput::builder().build())
+            .times(2)
+            .output(move || {
+                GetItemOutput::builder()
+                    .set_item(Some(item.clone()))
+                    .build()
+            })
+            .times(2)
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&get_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string()).with_link_cache(
+            Some(LinkCacheConfig {
+                capacity: 10,
+                ttl_seconds: 60,
+            }),
+        );
+
+        // A code created moments later must not be hidden by a cached miss
+        assert!(db_client.get_redirect_target("x").await.unwrap().is_none());
+        assert!(db_client.get_redirect_target("x").await.unwrap().is_none());
+        for _ in 0..2 {
+            assert!(matches!(
+                db_client.get_redirect_target("x").await,
+                Err(UrlShortenerError::UrlExpired)
+            ));
+        }
+        assert_eq!(get_rule.num_calls(), 4);
+    }
+
+    fn get_item_rule(short_code: &'static str, output: fn() -> GetItemOutput) -> Rule {
+        mock!(Client::get_item)
+            .match_requests(move |req| {
+                req.key().and_then(|key| key.get("short_code"))
+                    == Some(&AttributeValue::S(short_code.to_string()))
+            })
+            .sequence()
+            .output(output)
+            .repeatedly()
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_redirect_target_follows_alias() {
+        let alias_rule = get_item_rule("summer", || {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("canonical_code", AttributeValue::S("abc123".to_string()))
+                .build()
+        });
+        let canonical_rule = get_item_rule("abc123", || {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("max_uses", AttributeValue::N("5".to_string()))
+                .item("click_count", AttributeValue::N("4".to_string()))
+                .build()
+        });
+        let orphan_rule = get_item_rule("orphan", || {
+            GetItemOutput::builder()
+                .item(
+                    "original_url",
+                    AttributeValue::S("https://example.com".to_string()),
+                )
+                .item("canonical_code", AttributeValue::S("deleted".to_string()))
+                .build()
+        });
+        let deleted_rule = get_item_rule("deleted", || GetItemOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&alias_rule, &canonical_rule, &orphan_rule, &deleted_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        // The alias redirects with the canonical link's limit and counter
+        let target = db_client
+            .get_redirect_target("summer")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(target.canonical_code.as_deref(), Some("abc123"));
+        assert_eq!(target.max_uses, Some(5));
+        assert_eq!(target.click_count, 4);
+
+        let target = db_client
+            .get_redirect_target("abc123")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(target.canonical_code, None);
+
+        // Removing the canonical link takes its aliases with it
+        assert!(
+            db_client
+                .get_redirect_target("orphan")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_alias_points_at_canonical() {
+        let mut canonical = url_item(Utc::now(), Some(Utc::now().timestamp() + 3600));
+        canonical.redirect_status = Some(302);
+        canonical.max_uses = Some(5);
+
+        let put_rule = mock!(Client::put_item)
+            .match_requests(|req| {
+                let item = req.item().unwrap();
+                req.condition_expression() == Some("attribute_not_exists(short_code)")
+                    && item.get("short_code") == Some(&AttributeValue::S("summer".to_string()))
+                    && item.get("canonical_code") == Some(&AttributeValue::S("abc123".to_string()))
+            })
+            .then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let alias = db_client.create_alias(&canonical, "summer").await.unwrap();
+        assert_eq!(alias.original_url, canonical.original_url);
+        assert_eq!(alias.expires_at, canonical.expires_at);
+        assert_eq!(alias.redirect_status, Some(302));
+        assert_eq!(alias.max_uses, Some(5));
+        assert!(!alias.is_dedup_candidate());
+
+        // Aliasing an alias still points at the canonical code
+        let put_rule = mock!(Client::put_item)
+            .match_requests(|req| {
+                req.item().unwrap().get("canonical_code")
+                    == Some(&AttributeValue::S("abc123".to_string()))
+            })
+            .then_output(|| PutItemOutput::builder().build());
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        db_client.create_alias(&alias, "sale").await.unwrap();
+        assert_eq!(put_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_alias_collision() {
+        let put_rule = mock!(Client::put_item).then_error(|| {
+            PutItemError::ConditionalCheckFailedException(
+                ConditionalCheckFailedException::builder().build(),
+            )
+        });
+        let client = mock_client!(aws_sdk_dynamodb, [&put_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let err = db_client
+            .create_alias(&url_item(Utc::now(), None), "taken")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::ShortCodeExists(code) if code == "taken"));
+    }
+
+    #[tokio::test]
+    async fn test_list_aliases_across_pages() {
+        let scan_rule = mock!(Client::scan)
+            .match_requests(|req| req.filter_expression() == Some("canonical_code = :code"))
+            .sequence()
+            .output(|| {
+                ScanOutput::builder()
+                    .items(HashMap::from([(
+                        "short_code".to_string(),
+                        AttributeValue::S("summer".to_string()),
+                    )]))
+                    .last_evaluated_key("short_code", AttributeValue::S("summer".to_string()))
+                    .build()
+            })
+            .output(|| {
+                ScanOutput::builder()
+                    .items(HashMap::from([(
+                        "short_code".to_string(),
+                        AttributeValue::S("promo".to_string()),
+                    )]))
+                    .build()
+            })
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&scan_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert_eq!(
+            db_client.list_aliases("abc123").await.unwrap(),
+            vec!["promo".to_string(), "summer".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_v1_item_reads_with_defaults() {
+        let db_client = DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "t".to_string());
+        // No schema_version and none of the later attributes
+        let v1 = HashMap::from([
+            (
+                "short_code".to_string(),
+                AttributeValue::S("old".to_string()),
+            ),
+            (
+                "original_url".to_string(),
+                AttributeValue::S("https://example.com".to_string()),
+            ),
+            (
+                "created_at".to_string(),
+                AttributeValue::S("2024-01-01T00:00:00Z".to_string()),
+            ),
+        ]);
+
+        let migrated = migrate_item(v1.clone());
+        assert_eq!(
+            migrated.get("schema_version"),
+            Some(&AttributeValue::N(CURRENT_SCHEMA_VERSION.to_string()))
+        );
+        assert_eq!(
+            migrated.get("click_count"),
+            Some(&AttributeValue::N("0".to_string()))
+        );
+
+        let url_item = db_client.item_to_url_item(v1).unwrap();
+        assert_eq!(url_item.short_code, "old");
+        assert_eq!(url_item.click_count, 0);
+        assert!(!url_item.custom_code);
+        assert_eq!(url_item.status, "active");
+        assert_eq!(url_item.password_hash, None);
+        assert_eq!(url_item.redirect_status, None);
+        assert!(!url_item.wildcard);
+        assert_eq!(url_item.creator_ip, None);
+    }
+
+    #[test]
+    fn test_v2_item_round_trips() {
+        let db_client = DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "t".to_string());
+        let mut stored = url_item(Utc::now(), Some(Utc::now().timestamp() + 3600));
+        stored.click_count = 7;
+        stored.custom_code = true;
+        stored.redirect_status = Some(302);
+        stored.wildcard = true;
+        stored.extra_headers = Some(HashMap::from([(
+            "X-Campaign".to_string(),
+            "spring".to_string(),
+        )]));
+
+        let item = db_client.url_item_to_item(&stored);
+        assert_eq!(
+            item.get("schema_version"),
+            Some(&AttributeValue::N("2".to_string()))
+        );
+
+        let read = db_client.item_to_url_item(item).unwrap();
+        assert_eq!(read.click_count, 7);
+        assert!(read.custom_code);
+        assert_eq!(read.redirect_status, Some(302));
+        assert!(read.wildcard);
+        assert_eq!(read.expires_at, stored.expires_at);
+        assert_eq!(read.extra_headers, stored.extra_headers);
+        assert_eq!(read.ttl_source, None);
+        assert_eq!(read.ttl_source(), TtlSource::User);
+
+        stored.ttl_source = Some(TtlSource::Default);
+        let read = db_client
+            .item_to_url_item(db_client.url_item_to_item(&stored))
+            .unwrap();
+        assert_eq!(read.ttl_source, Some(TtlSource::Default));
+    }
+
+    #[test]
+    fn test_resource_not_found_message_is_not_found() {
+        // As LocalStack renders a DescribeTable on a missing table
+        assert!(is_table_not_found(
+            "service error: ResourceNotFoundException: Cannot do operations on a non-existent table"
+        ));
+        assert!(!is_table_not_found(
+            "dispatch failure: io error: Connection refused"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_describe_table_status() {
+        let describe_rule = mock!(Client::describe_table)
+            .sequence()
+            .output(|| {
+                DescribeTableOutput::builder()
+                    .table(
+                        TableDescription::builder()
+                            .table_status(SdkTableStatus::Active)
+                            .build(),
+                    )
+                    .build()
+            })
+            .output(|| {
+                DescribeTableOutput::builder()
+                    .table(
+                        TableDescription::builder()
+                            .table_status(SdkTableStatus::Creating)
+                            .build(),
+                    )
+                    .build()
+            })
+            .error(|| {
+                DescribeTableError::ResourceNotFoundException(
+                    ResourceNotFoundException::builder()
+                        .message("Requested resource not found")
+                        .build(),
+                )
+            })
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&describe_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert_eq!(
+            db_client.describe_table_status().await.unwrap(),
+            TableStatus::Active
+        );
+        assert_eq!(
+            db_client.describe_table_status().await.unwrap(),
+            TableStatus::Creating
+        );
+        assert_eq!(
+            db_client.describe_table_status().await.unwrap(),
+            TableStatus::NotFound
+        );
+    }
+
+    fn describe_output(status: SdkTableStatus) -> DescribeTableOutput {
+        DescribeTableOutput::builder()
+            .table(TableDescription::builder().table_status(status).build())
+            .build()
+    }
+
+    fn table_missing() -> DescribeTableError {
+        DescribeTableError::ResourceNotFoundException(
+            ResourceNotFoundException::builder()
+                .message("Requested resource not found")
+                .build(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_ensure_schema_is_idempotent() {
+        let describe_rule = mock!(Client::describe_table)
+            .sequence()
+            .output(|| describe_output(SdkTableStatus::Active))
+            .repeatedly()
+            .build();
+        let create_rule =
+            mock!(Client::create_table).then_output(|| CreateTableOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&describe_rule, &create_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(!db_client.ensure_schema().await.unwrap());
+        assert!(!db_client.ensure_schema().await.unwrap());
+        assert_eq!(create_rule.num_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_schema_creates_missing_table() {
+        let describe_rule = mock!(Client::describe_table)
+            .sequence()
+            .error(table_missing)
+            .output(|| describe_output(SdkTableStatus::Active))
+            .build();
+        let create_rule = mock!(Client::create_table)
+            .match_requests(|req| {
+                req.key_schema().first().map(|key| key.attribute_name()) == Some("short_code")
+                    && req
+                        .global_secondary_indexes()
+                        .first()
+                        .map(|gsi| gsi.index_name())
+                        == Some(ORIGINAL_URL_INDEX)
+            })
+            .then_output(|| CreateTableOutput::builder().build());
+        let ttl_rule = mock!(Client::update_time_to_live)
+            .then_output(|| UpdateTimeToLiveOutput::builder().build());
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&describe_rule, &create_rule, &ttl_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(db_client.ensure_schema().await.unwrap());
+        assert_eq!(create_rule.num_calls(), 1);
+        assert_eq!(ttl_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_schema_tolerates_concurrent_create() {
+        let describe_rule = mock!(Client::describe_table)
+            .sequence()
+            .error(table_missing)
+            .output(|| describe_output(SdkTableStatus::Active))
+            .build();
+        let create_rule = mock!(Client::create_table).then_error(|| {
+            CreateTableError::ResourceInUseException(
+                ResourceInUseException::builder()
+                    .message("Table already exists")
+                    .build(),
+            )
+        });
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&describe_rule, &create_rule]
+        );
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        assert!(!db_client.ensure_schema().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_find_by_creator_ip_hashes_query() {
+        let hasher = CreatorIpHasher::new("pepper");
+        let hashed = hasher.hash("203.0.113.7");
+
+        let mut stored = url_item(Utc::now(), None);
+        stored.creator_ip = Some(hashed.clone());
+        let stored = DynamoDbClient::new(mock_client!(aws_sdk_dynamodb, []), "t".to_string())
+            .url_item_to_item(&stored);
+
+        let expected = hashed.clone();
+        let scan_rule = mock!(Client::scan)
+            .match_requests(move |req| {
+                req.filter_expression() == Some("creator_ip = :creator_ip")
+                    && req
+                        .expression_attribute_values()
+                        .and_then(|values| values.get(":creator_ip"))
+                        == Some(&AttributeValue::S(expected.clone()))
+            })
+            .then_output(move || ScanOutput::builder().items(stored.clone()).build());
+        let client = mock_client!(aws_sdk_dynamodb, [&scan_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let found = db_client
+            .find_by_creator_ip("203.0.113.7", &hasher)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].creator_ip.as_deref(), Some(hashed.as_str()));
+        assert_eq!(scan_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_limited_increment_race_has_one_winner() {
+        // Two redirects racing at click_count == max_uses - 1: DynamoDB applies
+        // the conditional updates one at a time, so the second sees the
+        // condition fail
+        let update_rule = mock!(Client::update_item)
+            .sequence()
+            .output(|| UpdateItemOutput::builder().build())
+            .error(|| {
+                UpdateItemError::ConditionalCheckFailedException(
+                    ConditionalCheckFailedException::builder().build(),
+                )
+            })
+            .build();
+        let client = mock_client!(aws_sdk_dynamodb, [&update_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let (first, second) = tokio::join!(
+            db_client.increment_click_count_limited("abc123", 1),
+            db_client.increment_click_count_limited("abc123", 1)
+        );
+
+        let results = [first, second];
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(
+            results
+                .iter()
+                .any(|r| matches!(r, Err(UrlShortenerError::UrlExhausted)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limited_increment_other_errors_stay_database_errors() {
+        let update_rule = mock!(Client::update_item).then_error(|| {
+            UpdateItemError::InternalServerError(InternalServerError::builder().build())
+        });
+        let client = mock_client!(aws_sdk_dynamodb, [&update_rule]);
+        let db_client = DynamoDbClient::new(client, "test-table".to_string());
+
+        let err = db_client
+            .increment_click_count_limited("abc123", 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UrlShortenerError::DatabaseError(_)));
+    }
+}
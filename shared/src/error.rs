@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -8,21 +9,76 @@ pub enum UrlShortenerError {
     #[error("Short code already exists: {0}")]
     ShortCodeExists(String),
 
+    #[error("Short code already exists: {code}")]
+    CustomCodeTaken {
+        code: String,
+        suggestions: Vec<String>,
+    },
+
+    /// The idempotency key was already used; the earlier create's link can
+    /// be read back with `DynamoDbClient::idempotent_result`
+    #[error("Idempotency key already used: {0}")]
+    IdempotencyConflict(String),
+
+    /// The idempotency key was first used with a different request body
+    #[error("Idempotency key was used with a different request: {0}")]
+    IdempotencyKeyMismatch(String),
+
     #[error("Short code not found: {0}")]
     ShortCodeNotFound(String),
 
     #[error("URL has expired")]
     UrlExpired,
 
+    #[error("URL has reached its maximum number of uses")]
+    UrlExhausted,
+
     #[error("Database error: {0}")]
     DatabaseError(String),
 
+    /// A DynamoDB call ran past its deadline; worth a retry or an alert,
+    /// unlike most database errors
+    #[error("Database timed out: {0}")]
+    DatabaseTimeout(String),
+
     #[error("Validation error: {0}")]
     ValidationError(String),
 
+    /// The event or body couldn't be parsed at all, as opposed to parsing
+    /// into a request that then fails validation
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    /// A body, or the link it would store, is over a size limit. Limits on
+    /// counts and field formats stay `ValidationError`.
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    /// `limit` requests are allowed per window; the window resets in
+    /// `reset_seconds`. Nothing raises this yet: it is the response shape
+    /// for an in-function limiter, should one be added.
     #[error("Rate limit exceeded")]
-    RateLimitExceeded,
+    RateLimitExceeded { limit: u32, reset_seconds: u64 },
+
+    #[error("This link is password protected")]
+    PasswordRequired,
+
+    #[error("An API key is required")]
+    ApiKeyMissing,
+
+    #[error("Invalid API key")]
+    ApiKeyInvalid,
+
+    #[error("Invalid request signature")]
+    SignatureInvalid,
 
+    /// Click deltas that could not be written, to be re-queued
+    #[error("Failed to record clicks for {} links", failed.len())]
+    ClickFlushIncomplete { failed: HashMap<String, u64> },
+
+    /// Encoding one of our own values failed, which is a bug on our side.
+    /// Client input that doesn't parse is an `InvalidRequest` instead; see
+    /// [`UrlShortenerError::invalid_input`].
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
@@ -31,14 +87,32 @@ pub enum UrlShortenerError {
 }
 
 impl UrlShortenerError {
+    /// A client's event or body that didn't deserialize. Parse failures on
+    /// inbound data go through here rather than `?`, which would turn them
+    /// into a 500 `SerializationError`.
+    pub fn invalid_input(context: &str, err: serde_json::Error) -> Self {
+        UrlShortenerError::InvalidRequest(format!("{}: {}", context, err))
+    }
+
     pub fn status_code(&self) -> u16 {
         match self {
             UrlShortenerError::InvalidUrl(_) => 400,
             UrlShortenerError::ShortCodeExists(_) => 409,
+            UrlShortenerError::CustomCodeTaken { .. } => 409,
+            UrlShortenerError::IdempotencyConflict(_) => 409,
+            UrlShortenerError::IdempotencyKeyMismatch(_) => 422,
             UrlShortenerError::ShortCodeNotFound(_) => 404,
             UrlShortenerError::UrlExpired => 410,
+            UrlShortenerError::UrlExhausted => 410,
             UrlShortenerError::ValidationError(_) => 400,
-            UrlShortenerError::RateLimitExceeded => 429,
+            UrlShortenerError::InvalidRequest(_) => 400,
+            UrlShortenerError::PayloadTooLarge(_) => 413,
+            UrlShortenerError::RateLimitExceeded { .. } => 429,
+            UrlShortenerError::PasswordRequired => 401,
+            UrlShortenerError::ApiKeyMissing => 401,
+            UrlShortenerError::ApiKeyInvalid => 403,
+            UrlShortenerError::SignatureInvalid => 401,
+            UrlShortenerError::DatabaseTimeout(_) => 503,
             UrlShortenerError::SerializationError(_) => 500,
             _ => 500,
         }
@@ -48,12 +122,148 @@ impl UrlShortenerError {
         match self {
             UrlShortenerError::InvalidUrl(_) => "InvalidUrl",
             UrlShortenerError::ShortCodeExists(_) => "ConflictError",
+            UrlShortenerError::CustomCodeTaken { .. } => "ConflictError",
+            UrlShortenerError::IdempotencyConflict(_) => "IdempotencyConflict",
+            UrlShortenerError::IdempotencyKeyMismatch(_) => "IdempotencyKeyMismatch",
             UrlShortenerError::ShortCodeNotFound(_) => "NotFound",
             UrlShortenerError::UrlExpired => "Gone",
+            UrlShortenerError::UrlExhausted => "Gone",
             UrlShortenerError::ValidationError(_) => "ValidationError",
-            UrlShortenerError::RateLimitExceeded => "RateLimitExceeded",
+            UrlShortenerError::InvalidRequest(_) => "InvalidRequest",
+            UrlShortenerError::PayloadTooLarge(_) => "PayloadTooLarge",
+            UrlShortenerError::RateLimitExceeded { .. } => "RateLimitExceeded",
+            UrlShortenerError::PasswordRequired => "PasswordRequired",
+            UrlShortenerError::ApiKeyMissing => "Unauthorized",
+            UrlShortenerError::ApiKeyInvalid => "Forbidden",
+            UrlShortenerError::SignatureInvalid => "SignatureInvalid",
+            UrlShortenerError::DatabaseTimeout(_) => "ServiceUnavailable",
             UrlShortenerError::SerializationError(_) => "SerializationError",
             _ => "InternalServerError",
         }
     }
+
+    /// Whether a failed create may be retried with a fresh code. Only a
+    /// collision on a generated code qualifies; a taken custom code is the
+    /// caller's to resolve.
+    pub fn is_collision_retryable(&self, custom_code: bool) -> bool {
+        matches!(self, UrlShortenerError::ShortCodeExists(_)) && !custom_code
+    }
+
+    /// Structured data for the `details` field of the error response, if any
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            UrlShortenerError::PasswordRequired => {
+                Some(serde_json::json!({ "requires_password": true }))
+            }
+            UrlShortenerError::CustomCodeTaken { suggestions, .. } => {
+                Some(serde_json::json!({ "suggestions": suggestions }))
+            }
+            UrlShortenerError::RateLimitExceeded {
+                limit,
+                reset_seconds,
+            } => Some(serde_json::json!({
+                "limit": limit,
+                "remaining": 0,
+                "reset_seconds": reset_seconds,
+            })),
+            _ => None,
+        }
+    }
+
+    /// Extra response headers for this error, mirroring `details` where
+    /// clients conventionally look for it
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        match self {
+            UrlShortenerError::RateLimitExceeded {
+                limit,
+                reset_seconds,
+            } => vec![
+                ("X-RateLimit-Limit", limit.to_string()),
+                ("X-RateLimit-Remaining", "0".to_string()),
+                ("X-RateLimit-Reset", reset_seconds.to_string()),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_generated_code_collisions_are_retryable() {
+        let collision = UrlShortenerError::ShortCodeExists("abc123".to_string());
+        assert!(collision.is_collision_retryable(false));
+        assert!(!collision.is_collision_retryable(true));
+
+        let taken = UrlShortenerError::CustomCodeTaken {
+            code: "mine".to_string(),
+            suggestions: Vec::new(),
+        };
+        assert!(!taken.is_collision_retryable(false));
+        assert!(
+            !UrlShortenerError::DatabaseError("boom".to_string()).is_collision_retryable(false)
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_carries_reset_info() {
+        let err = UrlShortenerError::RateLimitExceeded {
+            limit: 100,
+            reset_seconds: 42,
+        };
+
+        assert_eq!(err.status_code(), 429);
+        assert_eq!(
+            err.details(),
+            Some(serde_json::json!({"limit": 100, "remaining": 0, "reset_seconds": 42}))
+        );
+        assert_eq!(
+            err.headers(),
+            [
+                ("X-RateLimit-Limit", "100".to_string()),
+                ("X-RateLimit-Remaining", "0".to_string()),
+                ("X-RateLimit-Reset", "42".to_string()),
+            ]
+        );
+        assert!(UrlShortenerError::UrlExpired.headers().is_empty());
+    }
+
+    #[test]
+    fn test_inbound_parse_errors_are_client_errors() {
+        let err = serde_json::from_str::<HashMap<String, u32>>(r#"{"a": "x"}"#).unwrap_err();
+        let inbound = UrlShortenerError::invalid_input("Invalid body", err);
+
+        assert_eq!(inbound.status_code(), 400);
+        assert_eq!(inbound.error_type(), "InvalidRequest");
+        assert!(
+            inbound
+                .to_string()
+                .starts_with("Invalid request: Invalid body: ")
+        );
+    }
+
+    #[test]
+    fn test_outbound_serialization_errors_are_server_errors() {
+        // Maps with non-string keys can't be written as JSON
+        let unencodable = HashMap::from([((1, 2), "x")]);
+        let outbound: UrlShortenerError = serde_json::to_value(unencodable).unwrap_err().into();
+
+        assert_eq!(outbound.status_code(), 500);
+        assert_eq!(outbound.error_type(), "SerializationError");
+    }
+
+    #[test]
+    fn test_payload_too_large_is_413() {
+        let err = UrlShortenerError::PayloadTooLarge("Request body is 20000 bytes".to_string());
+
+        assert_eq!(err.status_code(), 413);
+        assert_eq!(err.error_type(), "PayloadTooLarge");
+        assert_eq!(err.details(), None);
+        assert_eq!(
+            err.to_string(),
+            "Payload too large: Request body is 20000 bytes"
+        );
+    }
 }
@@ -0,0 +1,109 @@
+use sha2::{Digest, Sha256};
+
+use crate::error::UrlShortenerError;
+use crate::models::CreateUrlRequest;
+
+/// Request header that makes a create safe to retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Longest idempotency key accepted. Keys are stored as a digest, so this
+/// only keeps clients to something sensible, such as a UUID.
+pub const MAX_IDEMPOTENCY_KEY_BYTES: usize = 255;
+
+/// A create's idempotency key, with a fingerprint of the request it came with
+/// so the key can't be replayed for a different link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyKey {
+    key: String,
+    fingerprint: String,
+}
+
+impl IdempotencyKey {
+    /// The key from an `Idempotency-Key` header value, if one was sent
+    pub fn from_header(
+        header: Option<&str>,
+        request: &CreateUrlRequest,
+    ) -> Result<Option<Self>, UrlShortenerError> {
+        let Some(key) = header.map(str::trim) else {
+            return Ok(None);
+        };
+
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_BYTES {
+            return Err(UrlShortenerError::ValidationError(format!(
+                "{} must be 1 to {} bytes",
+                IDEMPOTENCY_KEY_HEADER, MAX_IDEMPOTENCY_KEY_BYTES
+            )));
+        }
+
+        Ok(Some(Self {
+            key: key.to_string(),
+            fingerprint: request.fingerprint(),
+        }))
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+
+    /// Hex SHA-256 of the key, which the key's record is stored under
+    pub fn digest(&self) -> String {
+        hex::encode(Sha256::digest(self.key.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(original_url: &str) -> CreateUrlRequest {
+        CreateUrlRequest::from_json(&format!(r#"{{"original_url": "{}"}}"#, original_url)).unwrap()
+    }
+
+    #[test]
+    fn test_idempotency_key_from_header() {
+        let req = request("https://example.com");
+        assert_eq!(IdempotencyKey::from_header(None, &req).unwrap(), None);
+
+        let key = IdempotencyKey::from_header(Some(" key-1 "), &req)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key.key(), "key-1");
+        assert_eq!(key.digest().len(), 64);
+
+        for bad in ["", "  ", &"k".repeat(MAX_IDEMPOTENCY_KEY_BYTES + 1)] {
+            let err = IdempotencyKey::from_header(Some(bad), &req).unwrap_err();
+            assert_eq!(err.status_code(), 400);
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_follows_the_request() {
+        let key = |req: &CreateUrlRequest| {
+            IdempotencyKey::from_header(Some("key-1"), req)
+                .unwrap()
+                .unwrap()
+        };
+
+        let first = key(&request("https://example.com"));
+        assert_eq!(first, key(&request("https://example.com")));
+        assert_ne!(
+            first.fingerprint(),
+            key(&request("https://example.org")).fingerprint()
+        );
+
+        // Header order doesn't change the request
+        let with_headers = |body: &str| key(&CreateUrlRequest::from_json(body).unwrap());
+        assert_eq!(
+            with_headers(
+                r#"{"original_url": "https://example.com", "extra_headers": {"A": "1", "B": "2"}}"#
+            ),
+            with_headers(
+                r#"{"original_url": "https://example.com", "extra_headers": {"B": "2", "A": "1"}}"#
+            )
+        );
+    }
+}
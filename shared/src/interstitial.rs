@@ -0,0 +1,70 @@
+/// Set to `true` to answer redirects with a short HTML page naming the
+/// destination, instead of an immediate 301/302
+pub const INTERSTITIAL_ENV: &str = "INTERSTITIAL";
+
+/// Seconds the interstitial is shown before the browser moves on
+pub const INTERSTITIAL_DELAY_SECONDS: u32 = 5;
+
+/// Escape `text` for use in HTML text and quoted attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The page served in place of a redirect to `destination`: a meta refresh
+/// after `INTERSTITIAL_DELAY_SECONDS`, plus a visible link for anyone who
+/// doesn't want to wait. The destination is only ever inserted escaped.
+pub fn interstitial_page(destination: &str) -> String {
+    let destination = escape_html(destination);
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="referrer" content="no-referrer">
+<meta http-equiv="refresh" content="{delay}; url={destination}">
+<title>Redirecting</title>
+</head>
+<body>
+<p>You are being redirected to:</p>
+<p><a href="{destination}" rel="noopener noreferrer">{destination}</a></p>
+</body>
+</html>
+"#,
+        delay = INTERSTITIAL_DELAY_SECONDS,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_links_escaped_destination() {
+        let page = interstitial_page("https://example.com/a?x=1&y=2");
+
+        assert!(page.contains(r#"content="5; url=https://example.com/a?x=1&amp;y=2""#));
+        assert!(page.contains(r#"<a href="https://example.com/a?x=1&amp;y=2""#));
+        assert!(!page.contains("x=1&y=2"));
+    }
+
+    #[test]
+    fn test_script_payload_is_neutralized() {
+        let page = interstitial_page(r#"https://example.com/"><script>alert(1)</script>"#);
+
+        assert!(!page.contains("<script>"));
+        assert!(!page.contains(r#""><"#));
+        assert!(page.contains("&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert_eq!(escape_html("'"), "&#x27;");
+    }
+}
@@ -1,5 +1,18 @@
+pub mod analytics;
+pub mod api_key;
 pub mod base62;
+pub mod client_ip;
+pub mod config;
+pub mod creator_ip;
 pub mod dynamodb;
 pub mod error;
+pub mod idempotency;
+pub mod interstitial;
+pub mod link_cache;
 pub mod models;
+pub mod password;
+pub mod short_code;
+pub mod signing;
+pub mod telemetry;
 pub mod validation;
+pub mod visitors;
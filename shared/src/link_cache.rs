@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+
+/// Links kept in each instance's cache; unset or 0 disables caching
+pub const LINK_CACHE_SIZE_ENV: &str = "LINK_CACHE_SIZE";
+
+/// Seconds a cached link is served before it is read again
+pub const LINK_CACHE_TTL_SECONDS_ENV: &str = "LINK_CACHE_TTL_SECONDS";
+
+pub const DEFAULT_LINK_CACHE_TTL_SECONDS: i64 = 30;
+
+/// Size and lifetime of the in-instance link cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkCacheConfig {
+    pub capacity: usize,
+    pub ttl_seconds: i64,
+}
+
+impl LinkCacheConfig {
+    /// Read from the environment; `None` unless `LINK_CACHE_SIZE` is a
+    /// positive number
+    pub fn from_env() -> Option<Self> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let capacity = lookup(LINK_CACHE_SIZE_ENV)
+            .and_then(|n| n.trim().parse().ok())
+            .filter(|n| *n > 0)?;
+        let ttl_seconds = lookup(LINK_CACHE_TTL_SECONDS_ENV)
+            .and_then(|s| s.trim().parse().ok())
+            .filter(|s| *s > 0)
+            .unwrap_or(DEFAULT_LINK_CACHE_TTL_SECONDS);
+
+        Some(Self {
+            capacity,
+            ttl_seconds,
+        })
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    /// Unix seconds after which the entry is stale
+    fresh_until: i64,
+    /// Tick of the last read or write, for least-recently-used eviction
+    last_used: u64,
+}
+
+struct Entries<V> {
+    map: HashMap<String, Entry<V>>,
+    tick: u64,
+}
+
+/// Least-recently-used cache of links by short code. Entries never outlive
+/// the link's own `expires_at`, so an expiring link is read again, and
+/// refused, on time.
+pub struct LinkCache<V> {
+    config: LinkCacheConfig,
+    entries: Mutex<Entries<V>>,
+}
+
+impl<V: Clone> LinkCache<V> {
+    pub fn new(config: LinkCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Entries {
+                map: HashMap::with_capacity(config.capacity),
+                tick: 0,
+            }),
+        }
+    }
+
+    /// The cached value for `short_code`, if still fresh at `now`
+    pub fn get(&self, short_code: &str, now: i64) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        match entries.map.get_mut(short_code) {
+            Some(entry) if entry.fresh_until > now => {
+                entry.last_used = tick;
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                entries.map.remove(short_code);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Cache `value` from `now` for the configured TTL, cut short at
+    /// `expires_at`. A link already past it isn't cached.
+    pub fn insert(&self, short_code: &str, value: V, expires_at: Option<i64>, now: i64) {
+        let fresh_until = expires_at.map_or(now + self.config.ttl_seconds, |expires_at| {
+            expires_at.min(now + self.config.ttl_seconds)
+        });
+        if fresh_until <= now {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+
+        if !entries.map.contains_key(short_code) && entries.map.len() >= self.config.capacity {
+            // A linear scan; the cache is meant for a few hundred hot links
+            if let Some(oldest) = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(code, _)| code.clone())
+            {
+                entries.map.remove(&oldest);
+            }
+        }
+
+        entries.map.insert(
+            short_code.to_string(),
+            Entry {
+                value,
+                fresh_until,
+                last_used: tick,
+            },
+        );
+    }
+
+    /// Drop `short_code`, after it was changed or deleted
+    pub fn invalidate(&self, short_code: &str) {
+        self.entries.lock().unwrap().map.remove(short_code);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(capacity: usize) -> LinkCache<String> {
+        LinkCache::new(LinkCacheConfig {
+            capacity,
+            ttl_seconds: 30,
+        })
+    }
+
+    #[test]
+    fn test_hit_until_ttl_expires() {
+        let cache = cache(10);
+        cache.insert("abc123", "https://example.com".to_string(), None, 1000);
+
+        assert_eq!(
+            cache.get("abc123", 1029).as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(cache.get("abc123", 1030), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_entries_stop_at_link_expiry() {
+        let cache = cache(10);
+        cache.insert("soon", "a".to_string(), Some(1010), 1000);
+        cache.insert("gone", "b".to_string(), Some(1000), 1000);
+
+        assert!(cache.get("soon", 1009).is_some());
+        assert_eq!(cache.get("soon", 1010), None);
+        // Already expired: never cached
+        assert_eq!(cache.get("gone", 1000), None);
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let cache = cache(2);
+        cache.insert("a", "a".to_string(), None, 1000);
+        cache.insert("b", "b".to_string(), None, 1000);
+        cache.get("a", 1001);
+        cache.insert("c", "c".to_string(), None, 1001);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a", 1002).is_some());
+        assert_eq!(cache.get("b", 1002), None);
+        assert!(cache.get("c", 1002).is_some());
+    }
+
+    #[test]
+    fn test_invalidate_and_config() {
+        let cache = cache(10);
+        cache.insert("abc123", "x".to_string(), None, 1000);
+        cache.invalidate("abc123");
+        assert_eq!(cache.get("abc123", 1000), None);
+
+        assert_eq!(LinkCacheConfig::from_lookup(|_| None), None);
+        assert_eq!(
+            LinkCacheConfig::from_lookup(|key| (key == LINK_CACHE_SIZE_ENV).then(|| "0".into())),
+            None
+        );
+        assert_eq!(
+            LinkCacheConfig::from_lookup(|key| match key {
+                LINK_CACHE_SIZE_ENV => Some("500".to_string()),
+                LINK_CACHE_TTL_SECONDS_ENV => Some("5".to_string()),
+                _ => None,
+            }),
+            Some(LinkCacheConfig {
+                capacity: 500,
+                ttl_seconds: 5
+            })
+        );
+    }
+}
@@ -1,17 +1,420 @@
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
 use validator::Validate;
 
-#[derive(Debug, Deserialize, Validate)]
+use crate::client_ip::extract_client_ip;
+use crate::error::UrlShortenerError;
+use crate::interstitial::interstitial_page;
+use crate::validation::sanitize_location;
+
+/// Allowed CORS origin(s): `*` (the default) or a comma-separated allowlist of
+/// exact origins such as `https://sqrl.co,https://admin.sqrl.co`.
+pub const CORS_ALLOW_ORIGIN_ENV: &str = "CORS_ALLOW_ORIGIN";
+
+fn cors_allow_origin_config() -> String {
+    env::var(CORS_ALLOW_ORIGIN_ENV).unwrap_or_else(|_| "*".to_string())
+}
+
+/// Base of returned short URLs when the request's host isn't one we serve
+pub const SHORT_URL_BASE_ENV: &str = "SHORT_URL_BASE";
+
+/// Comma-separated hosts (e.g. `sqrl.co,go.example.com`) whose requests get
+/// short URLs on their own domain. Unlisted hosts fall back to
+/// `SHORT_URL_BASE`, so a spoofed `Host` header can't choose the domain.
+pub const SHORT_URL_HOSTS_ENV: &str = "SHORT_URL_HOSTS";
+
+pub const DEFAULT_SHORT_URL_BASE: &str = "https://sqrl.co";
+
+/// Longest `max-age` (seconds) a redirect may be cached for by CloudFront and
+/// browsers. Links expiring sooner get their remaining lifetime instead.
+pub const REDIRECT_CACHE_SECONDS_ENV: &str = "REDIRECT_CACHE_SECONDS";
+
+pub const DEFAULT_REDIRECT_CACHE_SECONDS: u64 = 3600;
+
+/// `max-age` (seconds) for 404s, so bursts of misses are absorbed by
+/// CloudFront while a code created moments later soon resolves. `0` stops
+/// caching them.
+pub const NOT_FOUND_CACHE_SECONDS_ENV: &str = "NOT_FOUND_CACHE_SECONDS";
+
+pub const DEFAULT_NOT_FOUND_CACHE_SECONDS: u64 = 10;
+
+/// Deployment-wide redirect status for links that don't choose one: `301`
+/// (the default) or `302`
+pub const DEFAULT_REDIRECT_STATUS_ENV: &str = "DEFAULT_REDIRECT_STATUS";
+
+pub fn default_redirect_status() -> u16 {
+    match env::var(DEFAULT_REDIRECT_STATUS_ENV).as_deref() {
+        Ok("302") => 302,
+        _ => 301,
+    }
+}
+
+/// Seconds past `expires_at` during which a link still resolves, to absorb
+/// clock skew between callers near the boundary
+pub const EXPIRY_GRACE_SECONDS_ENV: &str = "EXPIRY_GRACE_SECONDS";
+
+pub fn expiry_grace_seconds() -> i64 {
+    env::var(EXPIRY_GRACE_SECONDS_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|grace: &i64| *grace >= 0)
+        .unwrap_or(0)
+}
+
+/// Set to `false` to let search engines index short links
+pub const REDIRECT_NOINDEX_ENV: &str = "REDIRECT_NOINDEX";
+
+/// `X-Robots-Tag` value sent with redirects
+pub const REDIRECT_ROBOTS_TAG: &str = "noindex, nofollow";
+
+pub fn redirect_noindex() -> bool {
+    env::var(REDIRECT_NOINDEX_ENV)
+        .map(|v| v.to_lowercase() != "false")
+        .unwrap_or(true)
+}
+
+/// Set to `false` to stop advertising a link's canonical short URL in a
+/// `Link: <...>; rel="shortlink"` header on redirects
+pub const REDIRECT_SHORTLINK_ENV: &str = "REDIRECT_SHORTLINK";
+
+/// `Link` header value naming `short_url` as the canonical short link
+pub fn shortlink_header(short_url: &str) -> String {
+    format!("<{}>; rel=\"shortlink\"", short_url)
+}
+
+/// `Cache-Control` for `status` given the hit and 404 lifetimes, see
+/// `RuntimeConfig::cache_control_for`
+pub fn cache_control_with(
+    status: u16,
+    remaining_ttl: Option<i64>,
+    hit_seconds: u64,
+    not_found_seconds: u64,
+) -> String {
+    let max_age = match status {
+        200 | 301 | 302 | 304 => match remaining_ttl {
+            Some(remaining) => remaining.clamp(0, hit_seconds as i64) as u64,
+            None => hit_seconds,
+        },
+        404 if not_found_seconds > 0 => not_found_seconds,
+        _ => return "no-store".to_string(),
+    };
+
+    format!("public, max-age={}", max_age)
+}
+
+/// Pick the base for returned short URLs: the request's `Host` when it is in
+/// `allowed_hosts` (keeping the default base's scheme), otherwise
+/// `default_base`. The result is checked to be an absolute http(s) URL.
+pub fn resolve_short_url_base(
+    host: Option<&str>,
+    default_base: &str,
+    allowed_hosts: &str,
+) -> Result<String, UrlShortenerError> {
+    let invalid_base = |base: &str| {
+        UrlShortenerError::InternalError(anyhow::anyhow!(
+            "Invalid short URL base '{}': must be an absolute http(s) URL",
+            base
+        ))
+    };
+
+    let default_url = url::Url::parse(default_base).map_err(|_| invalid_base(default_base))?;
+    if !matches!(default_url.scheme(), "http" | "https") || default_url.host().is_none() {
+        return Err(invalid_base(default_base));
+    }
+
+    let host = host.map(str::trim).filter(|host| {
+        allowed_hosts
+            .split(',')
+            .map(str::trim)
+            .any(|allowed| !allowed.is_empty() && allowed.eq_ignore_ascii_case(host))
+    });
+
+    let base = match host {
+        Some(host) => {
+            let base = format!("{}://{}", default_url.scheme(), host);
+            url::Url::parse(&base).map_err(|_| invalid_base(&base))?;
+            base
+        }
+        None => default_base.to_string(),
+    };
+
+    Ok(base.trim_end_matches('/').to_string())
+}
+
+/// The short URL for `code` under `base`. The code is joined as a path
+/// segment, so a trailing slash on the base doesn't double up and a path
+/// prefix (`https://x.co/go`) is kept.
+pub fn build_short_url(base: &str, code: &str) -> Result<String, UrlShortenerError> {
+    let invalid_base = || {
+        UrlShortenerError::InternalError(anyhow::anyhow!(
+            "Invalid short URL base '{}': must be an absolute http(s) URL",
+            base
+        ))
+    };
+
+    let mut base_url = url::Url::parse(base).map_err(|_| invalid_base())?;
+    if !matches!(base_url.scheme(), "http" | "https") || base_url.host().is_none() {
+        return Err(invalid_base());
+    }
+
+    // Without a trailing slash, `join` would replace the last path segment
+    if !base_url.path().ends_with('/') {
+        base_url.set_path(&format!("{}/", base_url.path()));
+    }
+
+    base_url
+        .join(code)
+        .map(String::from)
+        .map_err(|e| UrlShortenerError::InternalError(anyhow::anyhow!(e)))
+}
+
+/// Longest lifetime a link can be given: 10 years
+pub const MAX_TTL_HOURS: u32 = 87600;
+
+/// Strip the surrounding whitespace users paste along with URLs and codes
+fn trimmed<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|value| value.trim().to_string())
+}
+
+fn trimmed_opt<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(|value| value.map(|v| v.trim().to_string()))
+}
+
+/// Unknown fields are rejected rather than ignored, so a misspelt option
+/// (`ttl_hour`) fails loudly instead of silently creating a different link
+#[derive(Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
 pub struct CreateUrlRequest {
+    #[serde(alias = "url", deserialize_with = "trimmed")]
     #[validate(url)]
     pub original_url: String,
 
+    #[serde(default, deserialize_with = "trimmed_opt")]
     #[validate(length(min = 3, max = 20))]
     pub custom_code: Option<String>,
 
-    #[validate(range(min = 1, max = 87600))]
+    #[validate(range(min = 1, max = MAX_TTL_HOURS))]
     pub ttl_hours: Option<u32>,
+
+    /// Relative lifetime such as `"90m"`, `"2h"` or `"30d"`; alternative to
+    /// `ttl_hours`
+    pub expires_in: Option<String>,
+
+    #[validate(length(min = 1, max = 128))]
+    pub password: Option<String>,
+
+    #[validate(range(min = 1))]
+    pub max_uses: Option<u64>,
+
+    /// `"permanent"` (301) or `"temporary"` (302); unset uses the deployment
+    /// default
+    pub redirect_type: Option<String>,
+
+    /// Treat `original_url` as a base: `/{code}/rest/of/path` redirects to
+    /// `{original_url}/rest/of/path`
+    #[serde(default)]
+    pub wildcard: bool,
+
+    /// Extra response headers for the link's redirects, e.g.
+    /// `{"X-Partner-Id": "acme"}`. See `validation::validate_extra_headers`.
+    pub extra_headers: Option<HashMap<String, String>>,
+
+    /// Validate without creating; see the create handler's `is_dry_run`
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl CreateUrlRequest {
+    /// Parse a JSON create body. An unknown field is a `ValidationError`
+    /// naming it; a body that isn't a create request at all is
+    /// `InvalidRequest`.
+    pub fn from_json(body: &str) -> Result<Self, UrlShortenerError> {
+        serde_json::from_str(body).map_err(|e| create_request_error("Invalid JSON in body", e))
+    }
+
+    /// `from_json` for a payload that is already a JSON value
+    pub fn from_value(value: serde_json::Value) -> Result<Self, UrlShortenerError> {
+        serde_json::from_value(value).map_err(|e| create_request_error("Invalid create request", e))
+    }
+
+    /// Hex SHA-256 over every field that shapes the link, so a retried
+    /// create can be told apart from a different one reusing its key
+    pub fn fingerprint(&self) -> String {
+        let extra_headers: Option<BTreeMap<&String, &String>> = self
+            .extra_headers
+            .as_ref()
+            .map(|headers| headers.iter().collect());
+        let fields = serde_json::json!([
+            self.original_url,
+            self.custom_code,
+            self.ttl_hours,
+            self.expires_in,
+            self.password,
+            self.max_uses,
+            self.redirect_type,
+            self.wildcard,
+            extra_headers,
+        ]);
+
+        hex::encode(Sha256::digest(fields.to_string()))
+    }
+
+    /// Whether an existing link for the same URL may be returned instead of
+    /// creating a new one. Links with per-link access rules are always fresh.
+    pub fn allows_dedup(&self) -> bool {
+        self.password.is_none()
+            && self.max_uses.is_none()
+            && self.redirect_type.is_none()
+            && !self.wildcard
+            && self.extra_headers.is_none()
+    }
+
+    /// HTTP status requested through `redirect_type`
+    pub fn redirect_status(&self) -> Result<Option<u16>, UrlShortenerError> {
+        match self.redirect_type.as_deref() {
+            None => Ok(None),
+            Some("permanent") => Ok(Some(301)),
+            Some("temporary") => Ok(Some(302)),
+            Some(other) => Err(UrlShortenerError::ValidationError(format!(
+                "redirect_type must be \"permanent\" or \"temporary\", got \"{}\"",
+                other
+            ))),
+        }
+    }
+
+    /// Requested lifetime from either `ttl_hours` or `expires_in`
+    pub fn ttl(&self) -> Result<Option<Duration>, UrlShortenerError> {
+        match (self.ttl_hours, self.expires_in.as_deref()) {
+            (Some(_), Some(_)) => Err(UrlShortenerError::ValidationError(
+                "Provide either ttl_hours or expires_in, not both".to_string(),
+            )),
+            (Some(hours), None) => Ok(Some(Duration::hours(hours as i64))),
+            (None, Some(expires_in)) => parse_expires_in(expires_in).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The lifetime to give the link and where it came from: the request's
+    /// own, else `default_hours` (`DEFAULT_TTL_HOURS`), else none
+    pub fn effective_ttl(
+        &self,
+        default_hours: Option<u32>,
+    ) -> Result<(Option<Duration>, TtlSource), UrlShortenerError> {
+        Ok(match (self.ttl()?, default_hours) {
+            (Some(ttl), _) => (Some(ttl), TtlSource::User),
+            (None, Some(hours)) => (Some(Duration::hours(hours as i64)), TtlSource::Default),
+            (None, None) => (None, TtlSource::None),
+        })
+    }
+}
+
+/// Where a link's expiry came from, reported as `ttl_source` on create
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TtlSource {
+    /// `ttl_hours` or `expires_in` on the request
+    User,
+    /// The deployment's `DEFAULT_TTL_HOURS`
+    Default,
+    /// The link never expires
+    None,
+}
+
+impl TtlSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TtlSource::User => "user",
+            TtlSource::Default => "default",
+            TtlSource::None => "none",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(TtlSource::User),
+            "default" => Some(TtlSource::Default),
+            "none" => Some(TtlSource::None),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a relative duration made of `<number><unit>` parts, e.g. `"90m"` or
+/// `"1d12h"`. Units: `s`, `m`, `h`, `d`, `w`.
+pub fn parse_expires_in(value: &str) -> Result<Duration, UrlShortenerError> {
+    let invalid = || {
+        UrlShortenerError::ValidationError(format!(
+            "Invalid expires_in '{}': use e.g. 90m, 2h, 30d",
+            value
+        ))
+    };
+
+    let mut total = Duration::zero();
+    let mut digits = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let amount: i64 = digits.parse().map_err(|_| invalid())?;
+        digits.clear();
+        let part = match c {
+            's' => Duration::try_seconds(amount),
+            'm' => Duration::try_minutes(amount),
+            'h' => Duration::try_hours(amount),
+            'd' => Duration::try_days(amount),
+            'w' => Duration::try_weeks(amount),
+            _ => None,
+        }
+        .ok_or_else(invalid)?;
+        total = total.checked_add(&part).ok_or_else(invalid)?;
+    }
+
+    // Trailing digits without a unit, or an empty string
+    if !digits.is_empty() || total.is_zero() {
+        return Err(invalid());
+    }
+
+    if total > Duration::hours(MAX_TTL_HOURS as i64) {
+        return Err(UrlShortenerError::ValidationError(
+            "expires_in cannot exceed 10 years".to_string(),
+        ));
+    }
+
+    Ok(total)
+}
+
+fn create_request_error(context: &str, e: serde_json::Error) -> UrlShortenerError {
+    let message = e.to_string();
+    if e.is_data() && message.starts_with("unknown field") {
+        UrlShortenerError::ValidationError(message)
+    } else {
+        UrlShortenerError::invalid_input(context, e)
+    }
+}
+
+// Hand-written so the plaintext password never reaches the logs
+impl std::fmt::Debug for CreateUrlRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CreateUrlRequest")
+            .field("original_url", &self.original_url)
+            .field("custom_code", &self.custom_code)
+            .field("ttl_hours", &self.ttl_hours)
+            .field("expires_in", &self.expires_in)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("max_uses", &self.max_uses)
+            .field("redirect_type", &self.redirect_type)
+            .field("wildcard", &self.wildcard)
+            .field("extra_headers", &self.extra_headers)
+            .field("dry_run", &self.dry_run)
+            .finish()
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -21,19 +424,288 @@ pub struct CreateUrlResponse {
     pub short_url: String,
     pub created_at: String,
     pub expires_at: Option<String>,
+    pub ttl_source: TtlSource,
+}
+
+/// `CreateUrlResponse` cut down to what high-volume clients use, sent for
+/// `Prefer: return=minimal` or `?fields=short_code`
+#[derive(Debug, Serialize)]
+pub struct MinimalCreateUrlResponse {
+    pub short_code: String,
+    pub short_url: String,
+}
+
+impl From<CreateUrlResponse> for MinimalCreateUrlResponse {
+    fn from(response: CreateUrlResponse) -> Self {
+        Self {
+            short_code: response.short_code,
+            short_url: response.short_url,
+        }
+    }
+}
+
+/// Whether a create asks for `MinimalCreateUrlResponse`: a `Prefer` header
+/// listing `return=minimal`, or a `fields` parameter naming only
+/// `short_code` and `short_url`
+pub fn prefers_minimal(prefer: Option<&str>, fields: Option<&str>) -> bool {
+    let in_prefer = prefer.is_some_and(|prefer| {
+        prefer
+            .split(',')
+            .any(|preference| preference.trim().eq_ignore_ascii_case("return=minimal"))
+    });
+    let in_fields = fields.is_some_and(|fields| {
+        !fields.trim().is_empty()
+            && fields
+                .split(',')
+                .all(|field| matches!(field.trim(), "short_code" | "short_url"))
+    });
+
+    in_prefer || in_fields
+}
+
+/// Result of a validation-only create (`?dry_run=1`); nothing is written
+#[derive(Debug, Serialize)]
+pub struct CreateDryRunResponse {
+    pub dry_run: bool,
+    pub valid: bool,
+    /// Whether `short_code_preview` is free right now. A later real create
+    /// may still lose it to someone else.
+    pub available: bool,
+    pub short_code_preview: String,
+}
+
+/// Public safe-preview of a link: where it goes, without following it.
+/// Unlike stats this is meant for anyone holding the short code.
+#[derive(Debug, Serialize)]
+pub struct PreviewResponse {
+    pub short_code: String,
+    pub original_url: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub clicks: u64,
+}
+
+/// A stored `expires_at` (epoch seconds) as RFC3339 for responses. A
+/// timestamp chrono can't represent is left out and logged, rather than
+/// reported as some other time.
+pub fn expires_at_rfc3339(expires_at: Option<i64>) -> Option<String> {
+    let expires_at = expires_at?;
+    let rfc3339 = DateTime::from_timestamp(expires_at, 0).map(|at| at.to_rfc3339());
+    if rfc3339.is_none() {
+        tracing::warn!("Stored expires_at {} is out of range", expires_at);
+    }
+    rfc3339
+}
+
+impl From<UrlItem> for PreviewResponse {
+    fn from(url_item: UrlItem) -> Self {
+        Self {
+            short_code: url_item.short_code,
+            original_url: url_item.original_url,
+            created_at: url_item.created_at,
+            expires_at: expires_at_rfc3339(url_item.expires_at),
+            clicks: url_item.click_count,
+        }
+    }
+}
+
+/// Body of the stats endpoint, shared by the Lambda and the dev server so
+/// both return the same fields in the same formats
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    pub short_code: String,
+    pub original_url: String,
+    pub click_count: u64,
+    pub created_at: String,
+    /// RFC3339, like `CreateUrlResponse`, rather than the stored epoch seconds
+    pub expires_at: Option<String>,
+    /// Present once unique-visitor tracking has counted a visit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_visitors: Option<u64>,
+    /// With `?breakdown=referrer`: clicks per referrer host, most first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_referrers: Option<Vec<ReferrerCount>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReferrerCount {
+    pub host: String,
+    pub clicks: u64,
+}
+
+impl From<UrlItem> for StatsResponse {
+    fn from(url_item: UrlItem) -> Self {
+        Self {
+            short_code: url_item.short_code,
+            original_url: url_item.original_url,
+            click_count: url_item.click_count,
+            created_at: url_item.created_at,
+            expires_at: expires_at_rfc3339(url_item.expires_at),
+            unique_visitors: url_item.unique_visitors,
+            top_referrers: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct RedirectRequest {
     pub short_code: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Path after the code, for wildcard links
+    #[serde(default)]
+    pub path_suffix: Option<String>,
+}
+
+/// Kind of redirect a link answers with. Serialized as its status code
+/// (`"301"`/`"302"`), the form direct-invocation callers already read.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RedirectType {
+    #[serde(rename = "301")]
+    Permanent,
+    #[serde(rename = "302")]
+    Temporary,
+}
+
+impl RedirectType {
+    /// `302` is temporary; any other status is treated as permanent
+    pub fn from_status(status: u16) -> Self {
+        match status {
+            302 => Self::Temporary,
+            _ => Self::Permanent,
+        }
+    }
+
+    pub fn status_code(self) -> u16 {
+        match self {
+            Self::Permanent => 301,
+            Self::Temporary => 302,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct RedirectResponse {
     pub original_url: String,
-    pub redirect_type: String,
+    pub redirect_type: RedirectType,
+    /// Status the caller should redirect with, so direct invokers need not
+    /// interpret `redirect_type`
+    pub status_code: u16,
+    pub cache_control: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// Canonical short URL, sent as a `rel="shortlink"` `Link` header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub short_url: Option<String>,
+    /// Serve an HTML page linking to the destination instead of redirecting
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interstitial: bool,
+    /// The link's own response headers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_headers: Option<HashMap<String, String>>,
 }
 
+impl RedirectResponse {
+    pub fn new(original_url: String, redirect_type: RedirectType, cache_control: String) -> Self {
+        Self {
+            original_url,
+            redirect_type,
+            status_code: redirect_type.status_code(),
+            cache_control,
+            etag: None,
+            short_url: None,
+            interstitial: false,
+            extra_headers: None,
+        }
+    }
+
+    pub fn with_etag(mut self, etag: String) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+
+    pub fn with_short_url(mut self, short_url: String) -> Self {
+        self.short_url = Some(short_url);
+        self
+    }
+
+    pub fn with_interstitial(mut self, interstitial: bool) -> Self {
+        self.interstitial = interstitial;
+        self
+    }
+
+    pub fn with_extra_headers(mut self, extra_headers: Option<HashMap<String, String>>) -> Self {
+        self.extra_headers = extra_headers;
+        self
+    }
+}
+
+/// Answer to a redirect request whose `Accept` prefers JSON: the destination,
+/// without a `Location` to follow
+#[derive(Debug, Serialize)]
+pub struct ResolvedUrlResponse {
+    pub original_url: String,
+    pub clicks: u64,
+}
+
+/// Whether an `Accept` header asks for JSON over HTML. `application/json` must
+/// be listed explicitly (a `*/*` wildcard doesn't count) and rank above any
+/// `text/html`, so browsers keep getting redirects.
+pub fn prefers_json(accept: Option<&str>) -> bool {
+    let Some(accept) = accept else {
+        return false;
+    };
+
+    let quality = |media_type: &str| {
+        accept
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                if !params.next()?.trim().eq_ignore_ascii_case(media_type) {
+                    return None;
+                }
+                Some(
+                    params
+                        .find_map(|param| param.trim().strip_prefix("q="))
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .unwrap_or(1.0),
+                )
+            })
+            .reduce(f32::max)
+    };
+
+    match quality("application/json") {
+        Some(json) if json > 0.0 => quality("text/html").is_none_or(|html| json > html),
+        _ => false,
+    }
+}
+
+/// Body of `POST /api/expand`: a full short URL to resolve
+#[derive(Debug, Deserialize)]
+pub struct ExpandRequest {
+    pub short_url: String,
+}
+
+/// Body of `POST /api/urls/:short_code/aliases`
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateAliasRequest {
+    #[validate(length(min = 3, max = 20))]
+    pub alias: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasListResponse {
+    pub canonical_code: String,
+    pub aliases: Vec<String>,
+}
+
+/// One page of a table listing. Pass `next_cursor` back to get the next page;
+/// it is `None` once the table is exhausted.
+#[derive(Debug, Serialize)]
+pub struct UrlPage {
+    pub items: Vec<UrlItem>,
+    pub next_cursor: Option<String>,
+}
 
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
@@ -42,7 +714,7 @@ pub struct ErrorResponse {
     pub details: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UrlItem {
     pub short_code: String,
     pub original_url: String,
@@ -51,12 +723,186 @@ pub struct UrlItem {
     pub click_count: u64,
     pub custom_code: bool,
     pub status: String,
+    /// Argon2 PHC string for password-protected links
+    pub password_hash: Option<String>,
+    /// Number of redirects after which the link stops working
+    pub max_uses: Option<u64>,
+    /// Per-link 301/302 choice; `None` follows the deployment default
+    pub redirect_status: Option<u16>,
+    /// For an alias, the code whose destination it shares
+    pub canonical_code: Option<String>,
+    /// `original_url` is a base that the requested path suffix is appended to
+    #[serde(default)]
+    pub wildcard: bool,
+    /// Salted hash of the creating client's IP, when `HASH_CREATOR_IP` is on
+    #[serde(default)]
+    pub creator_ip: Option<String>,
+    /// Distinct visitors per day, summed over days, when
+    /// `TRACK_UNIQUE_VISITORS` is on. See `visitors::MAX_DAILY_VISITOR_KEYS`.
+    #[serde(default)]
+    pub unique_visitors: Option<u64>,
+    /// Set on the link's redirects, besides the ones the service sets
+    #[serde(default)]
+    pub extra_headers: Option<HashMap<String, String>>,
+    /// How `expires_at` was chosen; unset on links created before this was
+    /// recorded, see `UrlItem::ttl_source`
+    #[serde(default)]
+    pub ttl_source: Option<TtlSource>,
+}
+
+impl UrlItem {
+    /// Whether this item may be handed out to another creator via dedup
+    pub fn is_dedup_candidate(&self) -> bool {
+        self.password_hash.is_none()
+            && self.max_uses.is_none()
+            && self.redirect_status.is_none()
+            && self.canonical_code.is_none()
+            && !self.wildcard
+            && self.extra_headers.is_none()
+    }
+
+    /// Where `expires_at` came from. Older links didn't record it, but could
+    /// only expire on request: there was no default TTL then.
+    pub fn ttl_source(&self) -> TtlSource {
+        self.ttl_source.unwrap_or(if self.expires_at.is_some() {
+            TtlSource::User
+        } else {
+            TtlSource::None
+        })
+    }
+
+    /// Status to redirect with: the link's own choice, else `default_status`
+    pub fn redirect_status_or(&self, default_status: u16) -> u16 {
+        self.redirect_status.unwrap_or(default_status)
+    }
+
+    /// Whether the link has expired at unix time `now`, allowing
+    /// `grace_seconds` past `expires_at`
+    pub fn is_expired(&self, now: i64, grace_seconds: i64) -> bool {
+        is_expired_at(self.expires_at, now, grace_seconds)
+    }
+
+    /// Whether a use-limited link has already served all of its redirects
+    pub fn is_exhausted(&self) -> bool {
+        self.max_uses
+            .is_some_and(|max_uses| self.click_count >= max_uses)
+    }
+
+    /// `Cache-Control` for a redirect to this link at unix time `now`.
+    ///
+    /// Cached for at most `max_seconds`, and never past `expires_at`, so
+    /// caches stop serving the redirect once the link expires. Password and
+    /// use-limited links must reach the Lambda on every request.
+    pub fn redirect_cache_control(&self, now: i64, max_seconds: u64) -> String {
+        redirect_cache_control(
+            self.password_hash.is_some() || self.max_uses.is_some(),
+            self.expires_at,
+            now,
+            max_seconds,
+        )
+    }
+}
+
+/// The subset of a link the redirect path reads, fetched with a projection
+/// so large unrelated attributes aren't transferred
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectTarget {
+    pub original_url: String,
+    pub expires_at: Option<i64>,
+    pub status: String,
+    pub password_hash: Option<String>,
+    pub max_uses: Option<u64>,
+    pub redirect_status: Option<u16>,
+    pub wildcard: bool,
+    pub click_count: u64,
+    pub extra_headers: Option<HashMap<String, String>>,
+    /// Set when the requested code is an alias: the code whose link this
+    /// is, and which clicks should be counted against
+    pub canonical_code: Option<String>,
+}
+
+impl RedirectTarget {
+    /// See `UrlItem::is_expired`
+    pub fn is_expired(&self, now: i64, grace_seconds: i64) -> bool {
+        is_expired_at(self.expires_at, now, grace_seconds)
+    }
+
+    /// See `UrlItem::redirect_status_or`
+    pub fn redirect_status_or(&self, default_status: u16) -> u16 {
+        self.redirect_status.unwrap_or(default_status)
+    }
+
+    /// See `UrlItem::redirect_cache_control`
+    pub fn redirect_cache_control(&self, now: i64, max_seconds: u64) -> String {
+        redirect_cache_control(
+            self.password_hash.is_some() || self.max_uses.is_some(),
+            self.expires_at,
+            now,
+            max_seconds,
+        )
+    }
+
+    /// See `redirect_etag`
+    pub fn etag(&self) -> String {
+        redirect_etag(&self.original_url, self.expires_at)
+    }
+
+    /// Whether a conditional request can be answered with 304. Use-limited
+    /// links always redirect, since every use has to be counted.
+    pub fn not_modified(&self, if_none_match: Option<&str>) -> bool {
+        self.max_uses.is_none() && etag_matches(if_none_match, &self.etag())
+    }
+}
+
+/// Weak ETag over what a redirect depends on: the destination and expiry
+pub fn redirect_etag(original_url: &str, expires_at: Option<i64>) -> String {
+    let digest = Sha256::new()
+        .chain_update(original_url)
+        .chain_update([0])
+        .chain_update(expires_at.map(|ts| ts.to_string()).unwrap_or_default())
+        .finalize();
+
+    format!("W/\"{}\"", hex::encode(&digest[..8]))
+}
+
+/// Weak comparison of an `If-None-Match` header against `etag`
+pub fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+
+    if_none_match.is_some_and(|header| {
+        header
+            .split(',')
+            .any(|candidate| candidate.trim() == "*" || opaque(candidate) == opaque(etag))
+    })
+}
+
+fn is_expired_at(expires_at: Option<i64>, now: i64, grace_seconds: i64) -> bool {
+    expires_at.is_some_and(|expires_at| now > expires_at.saturating_add(grace_seconds))
+}
+
+fn redirect_cache_control(
+    restricted: bool,
+    expires_at: Option<i64>,
+    now: i64,
+    max_seconds: u64,
+) -> String {
+    if restricted {
+        return "private, no-store".to_string();
+    }
+
+    cache_control_with(
+        301,
+        expires_at.map(|expires_at| expires_at - now),
+        max_seconds,
+        0,
+    )
 }
 
 // API Gateway event structures
 #[derive(Debug, Deserialize)]
 pub struct ApiGatewayProxyEvent {
     pub body: Option<String>,
+    pub path: Option<String>,
     #[serde(rename = "pathParameters")]
     pub path_parameters: Option<HashMap<String, String>>,
     #[serde(rename = "queryStringParameters")]
@@ -68,6 +914,110 @@ pub struct ApiGatewayProxyEvent {
     pub request_context: Option<RequestContext>,
 }
 
+impl ApiGatewayProxyEvent {
+    /// Parse any HTTP event. HTTP API (2.0) events carry the method and path
+    /// under other names, so they are mapped onto the 1.0 fields first.
+    pub fn from_payload(mut payload: serde_json::Value) -> Result<Self, UrlShortenerError> {
+        if classify_event(&payload) == EventSource::ApiGatewayV2 {
+            let method = payload
+                .pointer("/requestContext/http/method")
+                .cloned()
+                .unwrap_or_default();
+            let raw_path = payload.get("rawPath").cloned();
+            if let Some(fields) = payload.as_object_mut() {
+                fields.entry("httpMethod").or_insert(method);
+                if let Some(raw_path) = raw_path {
+                    fields.entry("path").or_insert(raw_path);
+                }
+            }
+        }
+
+        serde_json::from_value(payload)
+            .map_err(|e| UrlShortenerError::invalid_input("Invalid API Gateway event", e))
+    }
+
+    /// Look up a request header by name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .as_ref()?
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The `short_code` path parameter and any greedy `proxy` segment after
+    /// it. ALB events carry no path parameters, so for them both come from
+    /// `path`, after the first of `route_prefixes` it starts with (e.g.
+    /// `/stats/`).
+    pub fn short_code_and_suffix(
+        &self,
+        route_prefixes: &[&str],
+    ) -> Option<(String, Option<String>)> {
+        if let Some(short_code) = self
+            .path_parameters
+            .as_ref()
+            .and_then(|params| params.get("short_code"))
+        {
+            let suffix = self
+                .path_parameters
+                .as_ref()
+                .and_then(|params| params.get("proxy"))
+                .cloned();
+            return Some((short_code.clone(), suffix));
+        }
+
+        let path = self.path.as_deref()?;
+        let rest = route_prefixes
+            .iter()
+            .find_map(|prefix| path.strip_prefix(prefix))?;
+        let (short_code, suffix) = match rest.split_once('/') {
+            Some((short_code, suffix)) => (short_code, Some(suffix)),
+            None => (rest, None),
+        };
+
+        (!short_code.is_empty()).then(|| {
+            (
+                short_code.to_string(),
+                suffix
+                    .filter(|suffix| !suffix.is_empty())
+                    .map(str::to_string),
+            )
+        })
+    }
+
+    /// This request's context for `short_code`, as of `now`. The client
+    /// address is `extract_client_ip`'s, so `X-Forwarded-For` counts only
+    /// with `trust_xff`.
+    pub fn analytics_event(
+        &self,
+        short_code: &str,
+        now: DateTime<Utc>,
+        trust_xff: bool,
+    ) -> AnalyticsEvent {
+        AnalyticsEvent {
+            source_ip: extract_client_ip(self, trust_xff),
+            ..AnalyticsEvent::from_request(
+                short_code,
+                now,
+                self.request_context
+                    .as_ref()
+                    .and_then(|context| context.identity.as_ref()),
+                self.headers.as_ref(),
+            )
+        }
+    }
+
+    /// The client address API Gateway saw
+    pub fn source_ip(&self) -> Option<&str> {
+        self.request_context
+            .as_ref()?
+            .identity
+            .as_ref()?
+            .source_ip
+            .as_deref()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RequestContext {
     pub identity: Option<Identity>,
@@ -81,6 +1031,54 @@ pub struct Identity {
     pub user_agent: Option<String>,
 }
 
+/// Request context of one redirect, gathered in one place so every consumer
+/// reads the client the same way. Held in memory only: the raw address is
+/// hashed before anything is stored (see `VisitorKeys`), and the agent and
+/// referrer are never logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyticsEvent {
+    pub short_code: String,
+    /// Unix seconds
+    pub timestamp: i64,
+    pub source_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    /// Not derived yet; reserved for edge-location data
+    pub country: Option<String>,
+    pub city: Option<String>,
+}
+
+impl AnalyticsEvent {
+    /// Build from API Gateway's `identity` and the request headers (matched
+    /// case-insensitively). The agent falls back to the `User-Agent` header
+    /// for events whose identity doesn't carry it.
+    pub fn from_request(
+        short_code: &str,
+        now: DateTime<Utc>,
+        identity: Option<&Identity>,
+        headers: Option<&HashMap<String, String>>,
+    ) -> Self {
+        let header = |name: &str| {
+            headers?
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+
+        Self {
+            short_code: short_code.to_string(),
+            timestamp: now.timestamp(),
+            source_ip: identity.and_then(|identity| identity.source_ip.clone()),
+            user_agent: identity
+                .and_then(|identity| identity.user_agent.clone())
+                .or_else(|| header("User-Agent")),
+            referer: header("Referer"),
+            country: None,
+            city: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ApiGatewayProxyResponse {
     #[serde(rename = "statusCode")]
@@ -95,7 +1093,6 @@ impl ApiGatewayProxyResponse {
     pub fn new(status_code: u16, body: String) -> Self {
         let mut headers = HashMap::new();
         headers.insert("Content-Type".to_string(), "application/json".to_string());
-        headers.insert("Access-Control-Allow-Origin".to_string(), "*".to_string());
         headers.insert(
             "Access-Control-Allow-Headers".to_string(),
             "Content-Type".to_string(),
@@ -111,23 +1108,876 @@ impl ApiGatewayProxyResponse {
             body,
             is_base64_encoded: false,
         }
+        .apply_cors(&cors_allow_origin_config(), None)
     }
 
-    pub fn redirect(location: String) -> Self {
+    pub fn redirect(location: String) -> Result<Self, UrlShortenerError> {
+        Self::redirect_with_status(location, 301)
+    }
+
+    /// Fails if `location` can't be sent as a header verbatim
+    pub fn redirect_with_status(
+        location: String,
+        status_code: u16,
+    ) -> Result<Self, UrlShortenerError> {
         let mut headers = HashMap::new();
-        headers.insert("Location".to_string(), location);
-        headers.insert("Access-Control-Allow-Origin".to_string(), "*".to_string());
+        headers.insert("Location".to_string(), sanitize_location(&location)?);
 
-        Self {
-            status_code: 301,
+        Ok(Self {
+            status_code,
             headers: Some(headers),
             body: "".to_string(),
             is_base64_encoded: false,
         }
+        .apply_cors(&cors_allow_origin_config(), None)
+        .apply_noindex(redirect_noindex()))
+    }
+
+    /// A `200` HTML page pointing at `location`, for `INTERSTITIAL` mode.
+    /// Refuses the same locations `redirect_with_status` does.
+    pub fn interstitial(location: &str) -> Result<Self, UrlShortenerError> {
+        let page = interstitial_page(&sanitize_location(location)?);
+
+        Ok(Self::new(200, page)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .apply_noindex(redirect_noindex()))
     }
+
+    fn apply_noindex(mut self, noindex: bool) -> Self {
+        let headers = self.headers.get_or_insert_with(HashMap::new);
+        if noindex {
+            headers.insert("X-Robots-Tag".to_string(), REDIRECT_ROBOTS_TAG.to_string());
+        } else {
+            headers.remove("X-Robots-Tag");
+        }
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .get_or_insert_with(HashMap::new)
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the CORS headers for a request sent from `origin`.
+    ///
+    /// With the default `*` setting this keeps the wildcard. With an allowlist
+    /// configured, an allowed origin is echoed back along with `Vary: Origin`,
+    /// and any other origin gets no `Access-Control-Allow-Origin` at all.
+    pub fn with_cors_origin(self, origin: &str) -> Self {
+        self.apply_cors(&cors_allow_origin_config(), Some(origin))
+    }
+
+    fn apply_cors(mut self, allowed: &str, origin: Option<&str>) -> Self {
+        let headers = self.headers.get_or_insert_with(HashMap::new);
+
+        if allowed.trim() == "*" {
+            headers.insert("Access-Control-Allow-Origin".to_string(), "*".to_string());
+            return self;
+        }
+
+        headers.remove("Access-Control-Allow-Origin");
+        if let Some(origin) = origin
+            && allowed.split(',').map(str::trim).any(|a| a == origin)
+        {
+            headers.insert(
+                "Access-Control-Allow-Origin".to_string(),
+                origin.to_string(),
+            );
+            headers.insert("Vary".to_string(), "Origin".to_string());
+        }
+
+        self
+    }
+}
+
+/// Which kind of trigger invoked a Lambda, as far as the payload shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    /// API Gateway REST API (payload format 1.0)
+    ApiGatewayV1,
+    /// API Gateway HTTP API (payload format 2.0)
+    ApiGatewayV2,
+    /// Application Load Balancer target group
+    Alb,
+    /// Plain `aws lambda invoke` with the request as the payload
+    Direct,
+}
+
+impl EventSource {
+    /// Adapt an API Gateway-style response to what this source expects. ALB
+    /// rejects responses without `statusDescription` and `isBase64Encoded`;
+    /// the other sources take the response as is.
+    pub fn shape_response(self, mut response: serde_json::Value) -> serde_json::Value {
+        if self == EventSource::Alb
+            && let Some(fields) = response.as_object_mut()
+        {
+            let status_code = fields
+                .get("statusCode")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(200);
+            fields.insert(
+                "statusDescription".to_string(),
+                serde_json::Value::String(status_description(status_code)),
+            );
+            fields
+                .entry("isBase64Encoded")
+                .or_insert(serde_json::Value::Bool(false));
+        }
+
+        response
+    }
+}
+
+// "404 Not Found", as ALB wants it
+fn status_description(status_code: u64) -> String {
+    let reason = match status_code {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        410 => "Gone",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => return status_code.to_string(),
+    };
+    format!("{} {}", status_code, reason)
 }
 
-// Helper function to detect if event is from API Gateway
+pub fn classify_event(payload: &serde_json::Value) -> EventSource {
+    let request_context = payload.get("requestContext");
+
+    if request_context.is_some_and(|context| context.get("elb").is_some()) {
+        EventSource::Alb
+    } else if payload.get("version").and_then(serde_json::Value::as_str) == Some("2.0")
+        || request_context.is_some_and(|context| context.get("http").is_some())
+    {
+        EventSource::ApiGatewayV2
+    } else if payload.get("httpMethod").is_some() || request_context.is_some() {
+        EventSource::ApiGatewayV1
+    } else {
+        EventSource::Direct
+    }
+}
+
+/// Whether the event came over HTTP (API Gateway or ALB) rather than a direct
+/// invoke
 pub fn is_api_gateway_event(payload: &serde_json::Value) -> bool {
-    payload.get("httpMethod").is_some() || payload.get("requestContext").is_some()
+    classify_event(payload) != EventSource::Direct
+}
+
+/// `source` of serverless-plugin-warmup's keep-warm pings
+pub const WARMUP_SOURCE: &str = "serverless-plugin-warmup";
+
+/// Whether the event is a scheduled keep-warm ping rather than a request:
+/// `{"source": "serverless-plugin-warmup"}` or `{"warmup": true}`. Only
+/// direct invokes count, so HTTP clients can't use it to skip a handler.
+pub fn is_warmup_event(payload: &serde_json::Value) -> bool {
+    classify_event(payload) == EventSource::Direct
+        && (payload.get("source").and_then(serde_json::Value::as_str) == Some(WARMUP_SOURCE)
+            || payload.get("warmup").and_then(serde_json::Value::as_bool) == Some(true))
+}
+
+/// What every function answers a keep-warm ping with, without doing any work
+pub fn warmup_response() -> serde_json::Value {
+    serde_json::json!({"statusCode": 200, "warmup": true})
+}
+
+// Helper function to read the request's `Origin` header (case-insensitive)
+pub fn request_origin(payload: &serde_json::Value) -> Option<String> {
+    payload
+        .get("headers")
+        .and_then(|h| h.as_object())?
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("origin"))
+        .and_then(|(_, value)| value.as_str())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn create_request(ttl_hours: Option<u32>, expires_in: Option<&str>) -> CreateUrlRequest {
+        CreateUrlRequest {
+            original_url: "https://example.com".to_string(),
+            custom_code: None,
+            ttl_hours,
+            expires_in: expires_in.map(str::to_string),
+            password: None,
+            max_uses: None,
+            redirect_type: None,
+            wildcard: false,
+            extra_headers: None,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn test_redirect_etag() {
+        let etag = redirect_etag("https://example.com", Some(1_700_000_000));
+        assert!(etag.starts_with("W/\""));
+        assert_eq!(
+            etag,
+            redirect_etag("https://example.com", Some(1_700_000_000))
+        );
+        assert_ne!(etag, redirect_etag("https://example.com", None));
+        assert_ne!(
+            etag,
+            redirect_etag("https://example.org", Some(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = redirect_etag("https://example.com", None);
+        let strong = etag.trim_start_matches("W/");
+
+        assert!(etag_matches(Some(&etag), &etag));
+        assert!(etag_matches(Some(strong), &etag));
+        assert!(etag_matches(Some(&format!("\"stale\", {}", etag)), &etag));
+        assert!(etag_matches(Some("*"), &etag));
+
+        assert!(!etag_matches(None, &etag));
+        assert!(!etag_matches(Some("W/\"stale\""), &etag));
+    }
+
+    #[test]
+    fn test_prefers_json() {
+        assert!(prefers_json(Some("application/json")));
+        assert!(prefers_json(Some("text/html;q=0.5, application/json")));
+        assert!(prefers_json(Some("Application/JSON; charset=utf-8")));
+
+        assert!(!prefers_json(None));
+        assert!(!prefers_json(Some("*/*")));
+        assert!(!prefers_json(Some("text/html,application/json")));
+        assert!(!prefers_json(Some("application/json;q=0")));
+        assert!(!prefers_json(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        )));
+    }
+
+    #[test]
+    fn test_create_request_trims_padded_fields() {
+        let request: CreateUrlRequest = serde_json::from_str(
+            r#"{"original_url":"  https://example.com/a b \n","custom_code":"\tpromo "}"#,
+        )
+        .unwrap();
+
+        assert_eq!(request.original_url, "https://example.com/a b");
+        assert_eq!(request.custom_code.as_deref(), Some("promo"));
+        assert!(request.validate().is_ok());
+
+        let request: CreateUrlRequest =
+            serde_json::from_str(r#"{"original_url":" https://example.com "}"#).unwrap();
+        assert_eq!(request.custom_code, None);
+        assert!(request.validate().is_ok());
+    }
+
+    #[test]
+    fn test_analytics_event_from_request() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap();
+        let identity = Identity {
+            source_ip: Some("203.0.113.7".to_string()),
+            user_agent: Some("curl/8.0".to_string()),
+        };
+        let headers = HashMap::from([
+            ("referer".to_string(), "https://t.co/x".to_string()),
+            ("User-Agent".to_string(), "Mozilla/5.0".to_string()),
+        ]);
+
+        let event = AnalyticsEvent::from_request("abc123", now, Some(&identity), Some(&headers));
+        assert_eq!(event.short_code, "abc123");
+        assert_eq!(event.timestamp, now.timestamp());
+        assert_eq!(event.source_ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(event.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(event.referer.as_deref(), Some("https://t.co/x"));
+        assert_eq!((event.country, event.city), (None, None));
+
+        // Without an identity the agent still comes from the header
+        let event = AnalyticsEvent::from_request("abc123", now, None, Some(&headers));
+        assert_eq!(event.source_ip, None);
+        assert_eq!(event.user_agent.as_deref(), Some("Mozilla/5.0"));
+
+        let event = AnalyticsEvent::from_request("abc123", now, None, None);
+        assert_eq!(
+            (event.source_ip, event.user_agent, event.referer),
+            (None, None, None)
+        );
+    }
+
+    #[test]
+    fn test_prefers_minimal() {
+        assert!(prefers_minimal(Some("return=minimal"), None));
+        assert!(prefers_minimal(Some("respond-async, Return=Minimal"), None));
+        assert!(prefers_minimal(None, Some("short_code")));
+        assert!(prefers_minimal(None, Some("short_code,short_url")));
+
+        assert!(!prefers_minimal(None, None));
+        assert!(!prefers_minimal(Some("return=representation"), None));
+        assert!(!prefers_minimal(None, Some("short_code,created_at")));
+        assert!(!prefers_minimal(None, Some("")));
+    }
+
+    #[test]
+    fn test_create_request_rejects_unknown_fields() {
+        let err = CreateUrlRequest::from_json(r#"{"url":"https://example.com","foo":1}"#)
+            .err()
+            .unwrap();
+        match err {
+            UrlShortenerError::ValidationError(message) => {
+                assert!(message.starts_with("unknown field `foo`"), "{}", message)
+            }
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+
+        // The `url` alias and every optional field still parse
+        let request = CreateUrlRequest::from_value(json!({
+            "url": "https://example.com",
+            "custom_code": "promo",
+            "ttl_hours": 2,
+            "password": "hunter22",
+            "max_uses": 3,
+            "redirect_type": "temporary",
+            "wildcard": true,
+            "dry_run": true
+        }))
+        .unwrap();
+        assert_eq!(request.original_url, "https://example.com");
+        assert!(request.wildcard && request.dry_run);
+        assert!(
+            CreateUrlRequest::from_json(r#"{"url":"https://example.com","expires_in":"2h"}"#)
+                .is_ok()
+        );
+
+        // A wrong type is still a malformed request
+        assert!(matches!(
+            CreateUrlRequest::from_json(r#"{"url":"https://example.com","ttl_hours":"2"}"#),
+            Err(UrlShortenerError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn test_short_url_base_uses_allowed_host() {
+        let base = resolve_short_url_base(
+            Some("go.example.com"),
+            "https://sqrl.co",
+            "sqrl.co,go.example.com",
+        )
+        .unwrap();
+        assert_eq!(base, "https://go.example.com");
+    }
+
+    #[test]
+    fn test_short_url_base_falls_back_to_env() {
+        assert_eq!(
+            resolve_short_url_base(None, "https://sqrl.co/", "").unwrap(),
+            "https://sqrl.co"
+        );
+        // Hosts outside the allowlist can't pick the domain
+        assert_eq!(
+            resolve_short_url_base(Some("evil.example"), "https://sqrl.co", "sqrl.co").unwrap(),
+            "https://sqrl.co"
+        );
+    }
+
+    #[test]
+    fn test_build_short_url() {
+        assert_eq!(
+            build_short_url("https://sqrl.co", "abc123").unwrap(),
+            "https://sqrl.co/abc123"
+        );
+        // Trailing slash doesn't double up
+        assert_eq!(
+            build_short_url("https://sqrl.co/", "abc123").unwrap(),
+            "https://sqrl.co/abc123"
+        );
+    }
+
+    #[test]
+    fn test_shortlink_header() {
+        assert_eq!(
+            shortlink_header("https://sqrl.co/abc123"),
+            "<https://sqrl.co/abc123>; rel=\"shortlink\""
+        );
+    }
+
+    #[test]
+    fn test_build_short_url_keeps_path_prefix() {
+        assert_eq!(
+            build_short_url("https://example.com/go", "abc123").unwrap(),
+            "https://example.com/go/abc123"
+        );
+        assert_eq!(
+            build_short_url("https://example.com/go/", "abc123").unwrap(),
+            "https://example.com/go/abc123"
+        );
+    }
+
+    #[test]
+    fn test_build_short_url_rejects_malformed_base() {
+        assert!(matches!(
+            build_short_url("sqrl.co", "abc123"),
+            Err(UrlShortenerError::InternalError(_))
+        ));
+        assert!(build_short_url("ftp://sqrl.co", "abc123").is_err());
+    }
+
+    #[test]
+    fn test_short_url_base_rejects_malformed_base() {
+        assert!(resolve_short_url_base(None, "sqrl.co", "").is_err());
+        assert!(resolve_short_url_base(None, "ftp://sqrl.co", "").is_err());
+    }
+
+    fn link(expires_at: Option<i64>) -> UrlItem {
+        UrlItem {
+            short_code: "abc123".to_string(),
+            original_url: "https://example.com".to_string(),
+            created_at: "2024-01-01T00:00:00+00:00".to_string(),
+            expires_at,
+            click_count: 0,
+            custom_code: false,
+            status: "active".to_string(),
+            password_hash: None,
+            max_uses: None,
+            redirect_status: None,
+            canonical_code: None,
+            wildcard: false,
+            creator_ip: None,
+            unique_visitors: None,
+            extra_headers: None,
+            ttl_source: None,
+        }
+    }
+
+    #[test]
+    fn test_stats_response_field_set() {
+        let mut item = link(Some(1_700_000_000));
+        item.click_count = 7;
+
+        let stats = serde_json::to_value(StatsResponse::from(item)).unwrap();
+        let mut fields: Vec<&str> = stats
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        fields.sort_unstable();
+        assert_eq!(
+            fields,
+            [
+                "click_count",
+                "created_at",
+                "expires_at",
+                "original_url",
+                "short_code"
+            ]
+        );
+        assert_eq!(stats["click_count"], 7);
+        assert_eq!(stats["expires_at"], "2023-11-14T22:13:20+00:00");
+
+        let mut tracked = link(None);
+        tracked.unique_visitors = Some(2);
+        let stats = serde_json::to_value(StatsResponse::from(tracked)).unwrap();
+        assert!(stats["expires_at"].is_null());
+        assert_eq!(stats["unique_visitors"], 2);
+    }
+
+    #[test]
+    fn test_is_expired_at_boundary() {
+        let expires_at = 1_700_000_000;
+        let item = link(Some(expires_at));
+
+        assert!(!item.is_expired(expires_at, 0));
+        assert!(item.is_expired(expires_at + 1, 0));
+
+        assert!(!item.is_expired(expires_at + 1, 30));
+        assert!(!item.is_expired(expires_at + 30, 30));
+        assert!(item.is_expired(expires_at + 31, 30));
+
+        assert!(!link(None).is_expired(i64::MAX, 0));
+    }
+
+    #[test]
+    fn test_redirect_cache_control_far_future_expiry() {
+        let now = 1_700_000_000;
+        assert_eq!(
+            link(Some(now + 86_400 * 365)).redirect_cache_control(now, 3600),
+            "public, max-age=3600"
+        );
+    }
+
+    #[test]
+    fn test_redirect_cache_control_near_expiry() {
+        let now = 1_700_000_000;
+        assert_eq!(
+            link(Some(now + 90)).redirect_cache_control(now, 3600),
+            "public, max-age=90"
+        );
+        assert_eq!(
+            link(Some(now - 5)).redirect_cache_control(now, 3600),
+            "public, max-age=0"
+        );
+    }
+
+    #[test]
+    fn test_redirect_cache_control_never_expiring() {
+        assert_eq!(
+            link(None).redirect_cache_control(1_700_000_000, 3600),
+            "public, max-age=3600"
+        );
+
+        let mut limited = link(None);
+        limited.max_uses = Some(1);
+        assert_eq!(
+            limited.redirect_cache_control(1_700_000_000, 3600),
+            "private, no-store"
+        );
+    }
+
+    #[test]
+    fn test_cache_control_with() {
+        assert_eq!(cache_control_with(301, None, 600, 0), "public, max-age=600");
+        assert_eq!(cache_control_with(404, None, 600, 0), "no-store");
+        assert_eq!(cache_control_with(404, None, 600, 30), "public, max-age=30");
+    }
+
+    #[test]
+    fn test_expires_at_rfc3339() {
+        assert_eq!(expires_at_rfc3339(None), None);
+        assert_eq!(
+            expires_at_rfc3339(Some(1_700_000_000)).as_deref(),
+            Some("2023-11-14T22:13:20+00:00")
+        );
+        assert_eq!(expires_at_rfc3339(Some(i64::MAX)), None);
+    }
+
+    #[test]
+    fn test_parse_expires_in() {
+        assert_eq!(parse_expires_in("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_expires_in("90m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_expires_in("1d12h").unwrap(), Duration::hours(36));
+    }
+
+    #[test]
+    fn test_parse_expires_in_rejects_invalid() {
+        for value in ["", "soon", "30", "30x", "0h", "11y", "3651d"] {
+            assert!(parse_expires_in(value).is_err(), "accepted {:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_redirect_type_maps_to_status() {
+        let mut request = create_request(None, None);
+        assert_eq!(request.redirect_status().unwrap(), None);
+
+        request.redirect_type = Some("temporary".to_string());
+        assert_eq!(request.redirect_status().unwrap(), Some(302));
+
+        request.redirect_type = Some("permanent".to_string());
+        assert_eq!(request.redirect_status().unwrap(), Some(301));
+
+        request.redirect_type = Some("302".to_string());
+        assert!(request.redirect_status().is_err());
+
+        // The link's own choice wins over the deployment default
+        let mut temporary = link(None);
+        temporary.redirect_status = Some(302);
+        assert_eq!(temporary.redirect_status_or(301), 302);
+        assert_eq!(link(None).redirect_status_or(301), 301);
+    }
+
+    #[test]
+    fn test_redirect_type_serde_round_trip() {
+        for (redirect_type, json) in [
+            (RedirectType::Permanent, "\"301\""),
+            (RedirectType::Temporary, "\"302\""),
+        ] {
+            assert_eq!(serde_json::to_string(&redirect_type).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<RedirectType>(json).unwrap(),
+                redirect_type
+            );
+        }
+
+        let response = serde_json::to_value(RedirectResponse::new(
+            "https://example.com".to_string(),
+            RedirectType::from_status(302),
+            "private, no-store".to_string(),
+        ))
+        .unwrap();
+        assert_eq!(response["redirect_type"], "302");
+        assert_eq!(response["status_code"], 302);
+    }
+
+    #[test]
+    fn test_warmup_events() {
+        assert!(is_warmup_event(&json!({"source": WARMUP_SOURCE})));
+        assert!(is_warmup_event(&json!({"warmup": true})));
+        assert!(!is_warmup_event(&json!({"warmup": false})));
+        assert!(!is_warmup_event(&json!({"short_code": "abc123"})));
+        // An HTTP request carrying the field is still a request
+        assert!(!is_warmup_event(&json!({
+            "httpMethod": "GET",
+            "warmup": true
+        })));
+    }
+
+    #[test]
+    fn test_ttl_rejects_both_inputs() {
+        assert!(create_request(Some(2), Some("2h")).ttl().is_err());
+        assert_eq!(
+            create_request(Some(2), None).ttl().unwrap(),
+            Some(Duration::hours(2))
+        );
+        assert_eq!(
+            create_request(None, Some("2h")).ttl().unwrap(),
+            Some(Duration::hours(2))
+        );
+        assert_eq!(create_request(None, None).ttl().unwrap(), None);
+    }
+
+    #[test]
+    fn test_effective_ttl_sources() {
+        assert_eq!(
+            create_request(Some(2), None)
+                .effective_ttl(Some(24))
+                .unwrap(),
+            (Some(Duration::hours(2)), TtlSource::User)
+        );
+        assert_eq!(
+            create_request(None, None).effective_ttl(Some(24)).unwrap(),
+            (Some(Duration::hours(24)), TtlSource::Default)
+        );
+        assert_eq!(
+            create_request(None, None).effective_ttl(None).unwrap(),
+            (None, TtlSource::None)
+        );
+        assert_eq!(
+            serde_json::to_value(TtlSource::Default).unwrap(),
+            serde_json::json!("default")
+        );
+    }
+
+    #[test]
+    fn test_cors_wildcard_default() {
+        let response = ApiGatewayProxyResponse::new(200, "{}".to_string())
+            .with_cors_origin("https://example.com");
+        let headers = response.headers.unwrap();
+
+        assert_eq!(headers["Access-Control-Allow-Origin"], "*");
+        assert!(!headers.contains_key("Vary"));
+    }
+
+    #[test]
+    fn test_redirect_rejects_header_injection() {
+        let response =
+            ApiGatewayProxyResponse::redirect("https://example.com/a".to_string()).unwrap();
+        assert_eq!(
+            response.headers.unwrap()["Location"],
+            "https://example.com/a"
+        );
+
+        assert!(
+            ApiGatewayProxyResponse::redirect("https://example.com/\r\nX-Evil: 1".to_string())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_redirect_noindex_header() {
+        let response =
+            ApiGatewayProxyResponse::redirect("https://example.com".to_string()).unwrap();
+        assert_eq!(
+            response.headers.as_ref().unwrap()["X-Robots-Tag"],
+            "noindex, nofollow"
+        );
+
+        let response = response.apply_noindex(false);
+        assert!(!response.headers.unwrap().contains_key("X-Robots-Tag"));
+    }
+
+    #[test]
+    fn test_cors_reflects_allowed_origin() {
+        let allowlist = "https://sqrl.co, https://admin.sqrl.co";
+
+        let allowed = ApiGatewayProxyResponse::redirect("https://example.com".to_string())
+            .unwrap()
+            .apply_cors(allowlist, Some("https://admin.sqrl.co"));
+        let headers = allowed.headers.unwrap();
+        assert_eq!(
+            headers["Access-Control-Allow-Origin"],
+            "https://admin.sqrl.co"
+        );
+        assert_eq!(headers["Vary"], "Origin");
+
+        let rejected = ApiGatewayProxyResponse::new(200, "{}".to_string())
+            .apply_cors(allowlist, Some("https://evil.example"));
+        let headers = rejected.headers.unwrap();
+        assert!(!headers.contains_key("Access-Control-Allow-Origin"));
+        assert!(!headers.contains_key("Vary"));
+    }
+
+    #[test]
+    fn test_create_request_debug_redacts_password() {
+        let request: CreateUrlRequest = serde_json::from_value(json!({
+            "original_url": "https://example.com",
+            "password": "hunter2"
+        }))
+        .unwrap();
+
+        let debug = format!("{:?}", request);
+        assert!(!debug.contains("hunter2"));
+        assert!(debug.contains("<redacted>"));
+    }
+
+    #[test]
+    fn test_request_origin_case_insensitive() {
+        let event = json!({
+            "httpMethod": "GET",
+            "headers": {"origin": "https://sqrl.co"}
+        });
+        assert_eq!(request_origin(&event), Some("https://sqrl.co".to_string()));
+        assert_eq!(request_origin(&json!({"httpMethod": "GET"})), None);
+    }
+
+    fn alb_event() -> serde_json::Value {
+        json!({
+            "requestContext": {
+                "elb": {"targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/squrl/abc"}
+            },
+            "httpMethod": "GET",
+            "path": "/abc123",
+            "queryStringParameters": {},
+            "headers": {"host": "sqrl.co"},
+            "body": "",
+            "isBase64Encoded": false
+        })
+    }
+
+    fn http_api_event() -> serde_json::Value {
+        json!({
+            "version": "2.0",
+            "routeKey": "GET /{short_code}",
+            "rawPath": "/abc123",
+            "rawQueryString": "",
+            "headers": {"host": "sqrl.co"},
+            "pathParameters": {"short_code": "abc123"},
+            "requestContext": {
+                "http": {"method": "GET", "path": "/abc123", "sourceIp": "192.168.1.1"}
+            },
+            "isBase64Encoded": false
+        })
+    }
+
+    #[test]
+    fn test_short_code_from_alb_path() {
+        let alb = ApiGatewayProxyEvent::from_payload(alb_event()).unwrap();
+        assert_eq!(
+            alb.short_code_and_suffix(&["/"]),
+            Some(("abc123".to_string(), None))
+        );
+
+        let mut wildcard = alb_event();
+        wildcard["path"] = json!("/docs/guide/intro");
+        let wildcard = ApiGatewayProxyEvent::from_payload(wildcard).unwrap();
+        assert_eq!(
+            wildcard.short_code_and_suffix(&["/"]),
+            Some(("docs".to_string(), Some("guide/intro".to_string())))
+        );
+
+        let mut stats = alb_event();
+        stats["path"] = json!("/stats/abc123");
+        let stats = ApiGatewayProxyEvent::from_payload(stats).unwrap();
+        assert_eq!(
+            stats.short_code_and_suffix(&["/stats/", "/preview/"]),
+            Some(("abc123".to_string(), None))
+        );
+        assert_eq!(stats.short_code_and_suffix(&["/preview/"]), None);
+
+        let mut root = alb_event();
+        root["path"] = json!("/");
+        let root = ApiGatewayProxyEvent::from_payload(root).unwrap();
+        assert_eq!(root.short_code_and_suffix(&["/"]), None);
+
+        // Path parameters win when the route provides them
+        let http_api = ApiGatewayProxyEvent::from_payload(http_api_event()).unwrap();
+        assert_eq!(
+            http_api.short_code_and_suffix(&["/stats/"]),
+            Some(("abc123".to_string(), None))
+        );
+    }
+
+    #[test]
+    fn test_classify_event() {
+        assert_eq!(classify_event(&alb_event()), EventSource::Alb);
+        assert_eq!(classify_event(&http_api_event()), EventSource::ApiGatewayV2);
+        assert_eq!(
+            classify_event(&json!({
+                "httpMethod": "GET",
+                "path": "/abc123",
+                "pathParameters": {"short_code": "abc123"},
+                "requestContext": {"identity": {"sourceIp": "192.168.1.1"}}
+            })),
+            EventSource::ApiGatewayV1
+        );
+        assert_eq!(
+            classify_event(&json!({"short_code": "abc123"})),
+            EventSource::Direct
+        );
+
+        assert!(is_api_gateway_event(&alb_event()));
+        assert!(!is_api_gateway_event(&json!({"short_code": "abc123"})));
+    }
+
+    #[test]
+    fn test_alb_response_shape() {
+        let response =
+            serde_json::to_value(ApiGatewayProxyResponse::new(404, "{}".to_string())).unwrap();
+
+        let alb = EventSource::Alb.shape_response(response.clone());
+        assert_eq!(alb["statusCode"], 404);
+        assert_eq!(alb["statusDescription"], "404 Not Found");
+        assert_eq!(alb["isBase64Encoded"], false);
+
+        // Bare error bodies from direct-style responses gain the flag too
+        let alb = EventSource::Alb.shape_response(json!({"statusCode": 301, "body": ""}));
+        assert_eq!(alb["statusDescription"], "301 Moved Permanently");
+        assert_eq!(alb["isBase64Encoded"], false);
+
+        for source in [EventSource::ApiGatewayV1, EventSource::ApiGatewayV2] {
+            assert_eq!(source.shape_response(response.clone()), response);
+        }
+    }
+
+    #[test]
+    fn test_proxy_event_from_http_api_payload() {
+        let event = ApiGatewayProxyEvent::from_payload(http_api_event()).unwrap();
+        assert_eq!(event.http_method, "GET");
+        assert_eq!(event.path.as_deref(), Some("/abc123"));
+        assert_eq!(
+            event
+                .path_parameters
+                .as_ref()
+                .and_then(|params| params.get("short_code"))
+                .map(String::as_str),
+            Some("abc123")
+        );
+
+        let event = ApiGatewayProxyEvent::from_payload(alb_event()).unwrap();
+        assert_eq!(event.http_method, "GET");
+        assert_eq!(event.header("Host"), Some("sqrl.co"));
+    }
 }
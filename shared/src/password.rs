@@ -0,0 +1,83 @@
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+
+use crate::error::UrlShortenerError;
+
+/// Request header carrying the passphrase for a password-protected link
+pub const LINK_PASSWORD_HEADER: &str = "X-Link-Password";
+
+/// Hash a link password with a random salt. Only the returned PHC string is
+/// ever stored; the plaintext must not outlive the request.
+pub fn hash_password(password: &str) -> Result<String, UrlShortenerError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            UrlShortenerError::InternalError(anyhow::anyhow!("Password hashing failed: {}", e))
+        })
+}
+
+/// Check the password supplied with a redirect against the link's stored hash.
+///
+/// Links without a hash are open. For protected links a missing or wrong
+/// password yields `PasswordRequired` (401) - the two cases are deliberately
+/// indistinguishable to the caller.
+pub fn verify_link_password(
+    password_hash: Option<&str>,
+    provided: Option<&str>,
+) -> Result<(), UrlShortenerError> {
+    let Some(password_hash) = password_hash else {
+        return Ok(());
+    };
+
+    let provided = provided.ok_or(UrlShortenerError::PasswordRequired)?;
+    let parsed = PasswordHash::new(password_hash).map_err(|e| {
+        UrlShortenerError::InternalError(anyhow::anyhow!("Invalid password hash: {}", e))
+    })?;
+
+    Argon2::default()
+        .verify_password(provided.as_bytes(), &parsed)
+        .map_err(|_| UrlShortenerError::PasswordRequired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_is_salted_and_not_plaintext() {
+        let first = hash_password("hunter2").unwrap();
+        let second = hash_password("hunter2").unwrap();
+
+        assert!(!first.contains("hunter2"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_correct_password_allows_redirect() {
+        let hash = hash_password("hunter2").unwrap();
+
+        assert!(verify_link_password(Some(&hash), Some("hunter2")).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_or_missing_password_is_401() {
+        let hash = hash_password("hunter2").unwrap();
+
+        let wrong = verify_link_password(Some(&hash), Some("letmein")).unwrap_err();
+        assert!(matches!(wrong, UrlShortenerError::PasswordRequired));
+        assert_eq!(wrong.status_code(), 401);
+
+        let missing = verify_link_password(Some(&hash), None).unwrap_err();
+        assert!(matches!(missing, UrlShortenerError::PasswordRequired));
+    }
+
+    #[test]
+    fn test_unprotected_link_ignores_password() {
+        assert!(verify_link_password(None, None).is_ok());
+        assert!(verify_link_password(None, Some("anything")).is_ok());
+    }
+}
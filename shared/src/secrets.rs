@@ -4,6 +4,7 @@ use std::collections::HashMap;
 use std::env;
 use tracing::{debug, error, info, warn};
 
+use crate::api_key::api_keys_from_env;
 use crate::error::UrlShortenerError;
 
 /// Configuration struct for Secrets Manager
@@ -203,13 +204,7 @@ impl AppConfig {
             .unwrap_or_else(|_| "info".to_string());
 
         // Load any API keys from environment (format: API_KEY_<NAME>=<value>)
-        let mut api_keys = HashMap::new();
-        for (key, value) in env::vars() {
-            if key.starts_with("API_KEY_") {
-                let key_name = key.strip_prefix("API_KEY_").unwrap().to_lowercase();
-                api_keys.insert(key_name, value);
-            }
-        }
+        let api_keys = api_keys_from_env();
 
         Ok(Self {
             dynamodb_table_name,
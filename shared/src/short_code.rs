@@ -0,0 +1,277 @@
+use crate::dynamodb::INTERNAL_KEY_PREFIX;
+use crate::error::UrlShortenerError;
+use nanoid::nanoid;
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+pub const CODE_ALPHABET_ENV: &str = "CODE_ALPHABET";
+
+/// Strategy for auto-generated codes; only `nanoid` (the default) so far
+pub const CODE_GENERATOR_ENV: &str = "CODE_GENERATOR";
+
+/// Length of auto-generated short codes
+pub const SHORT_CODE_LENGTH: usize = 8;
+
+/// Below this many possible codes, random generation collides often enough
+/// to be worth flagging
+const MIN_COMFORTABLE_CODE_SPACE: u128 = 1_000_000_000_000;
+
+/// Number of distinct codes of `code_len` characters from `alphabet_len`
+/// symbols (saturating)
+pub fn code_space(alphabet_len: usize, code_len: usize) -> u128 {
+    (alphabet_len as u128).saturating_pow(code_len as u32)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CodeSpaceCheck {
+    Ok,
+    /// Small enough that collisions will be noticeable
+    Small,
+    /// No room left for the items already stored
+    Exhausted,
+}
+
+/// Judge the configured code space against the number of stored items, when
+/// known, and log the verdict. Meant to run once at startup.
+pub fn check_code_space(alphabet: &CodeAlphabet, stored_items: Option<u64>) -> CodeSpaceCheck {
+    let space = code_space(alphabet.chars().len(), SHORT_CODE_LENGTH);
+
+    if stored_items.is_some_and(|count| count as u128 >= space) {
+        error!(
+            "Short code space exhausted: {} possible codes but {} items stored. Widen {}.",
+            space,
+            stored_items.unwrap_or_default(),
+            CODE_ALPHABET_ENV
+        );
+        CodeSpaceCheck::Exhausted
+    } else if space < MIN_COMFORTABLE_CODE_SPACE {
+        warn!(
+            "Short code space is only {} codes ({} symbols, length {}); expect collisions",
+            space,
+            alphabet.chars().len(),
+            SHORT_CODE_LENGTH
+        );
+        CodeSpaceCheck::Small
+    } else {
+        info!("Short code space: {} codes", space);
+        CodeSpaceCheck::Ok
+    }
+}
+
+/// Characters a custom code may use, so generated codes pass the same
+/// checks. `.` and `~` are URL-safe too but would be rejected as custom codes.
+fn is_code_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_')
+}
+
+/// Character set used for auto-generated short codes
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeAlphabet(Vec<char>);
+
+impl Default for CodeAlphabet {
+    fn default() -> Self {
+        Self(nanoid::alphabet::SAFE.to_vec())
+    }
+}
+
+impl CodeAlphabet {
+    /// Parse `safe`, `alphanumeric`, or a literal character set
+    pub fn parse(value: &str) -> Result<Self, UrlShortenerError> {
+        match value.trim() {
+            "safe" => Ok(Self::default()),
+            "alphanumeric" => Ok(Self(
+                nanoid::alphabet::SAFE
+                    .iter()
+                    .copied()
+                    .filter(char::is_ascii_alphanumeric)
+                    .collect(),
+            )),
+            custom => {
+                let chars: Vec<char> = custom.chars().collect();
+
+                if chars.len() < 2 {
+                    return Err(UrlShortenerError::ValidationError(format!(
+                        "{} must contain at least 2 characters",
+                        CODE_ALPHABET_ENV
+                    )));
+                }
+
+                if let Some(bad) = chars.iter().find(|c| !is_code_char(**c)) {
+                    return Err(UrlShortenerError::ValidationError(format!(
+                        "{} contains '{}'; only letters, digits, '-' and '_' are allowed",
+                        CODE_ALPHABET_ENV, bad
+                    )));
+                }
+
+                let mut seen = HashSet::new();
+                if let Some(dup) = chars.iter().find(|c| !seen.insert(**c)) {
+                    return Err(UrlShortenerError::ValidationError(format!(
+                        "{} contains '{}' more than once",
+                        CODE_ALPHABET_ENV, dup
+                    )));
+                }
+
+                Ok(Self(chars))
+            }
+        }
+    }
+
+    /// Read `CODE_ALPHABET`, defaulting to nanoid's SAFE alphabet when unset
+    pub fn from_env() -> Result<Self, UrlShortenerError> {
+        match env::var(CODE_ALPHABET_ENV) {
+            Ok(value) => Self::parse(&value),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn chars(&self) -> &[char] {
+        &self.0
+    }
+}
+
+/// Source of auto-generated short codes. Custom codes bypass it.
+pub trait CodeGenerator: Send + Sync {
+    fn generate(&self) -> Result<String, UrlShortenerError>;
+}
+
+/// Random codes of `SHORT_CODE_LENGTH` characters from an alphabet
+#[derive(Debug, Clone, Default)]
+pub struct NanoidGenerator {
+    alphabet: CodeAlphabet,
+}
+
+impl NanoidGenerator {
+    pub fn new(alphabet: CodeAlphabet) -> Self {
+        Self { alphabet }
+    }
+}
+
+impl CodeGenerator for NanoidGenerator {
+    fn generate(&self) -> Result<String, UrlShortenerError> {
+        // Codes starting with the reserved prefix would collide with internal
+        // items, so draw again; with `_` in the alphabet that's rare
+        loop {
+            let code = nanoid!(SHORT_CODE_LENGTH, self.alphabet.chars());
+            if !code.starts_with(INTERNAL_KEY_PREFIX) {
+                return Ok(code);
+            }
+        }
+    }
+}
+
+/// The generator named by `name`, drawing from `alphabet` where it applies
+pub fn code_generator(
+    name: &str,
+    alphabet: CodeAlphabet,
+) -> Result<Arc<dyn CodeGenerator>, UrlShortenerError> {
+    match name.trim() {
+        "nanoid" => Ok(Arc::new(NanoidGenerator::new(alphabet))),
+        other => Err(UrlShortenerError::ValidationError(format!(
+            "{} \"{}\" is not a known generator",
+            CODE_GENERATOR_ENV, other
+        ))),
+    }
+}
+
+/// Read `CODE_GENERATOR`, defaulting to `nanoid` when unset
+pub fn code_generator_from_env(
+    alphabet: CodeAlphabet,
+) -> Result<Arc<dyn CodeGenerator>, UrlShortenerError> {
+    code_generator(
+        &env::var(CODE_GENERATOR_ENV).unwrap_or_else(|_| "nanoid".to_string()),
+        alphabet,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::validate_custom_code;
+
+    #[test]
+    fn test_code_space() {
+        assert_eq!(code_space(64, 8), 281_474_976_710_656);
+        assert_eq!(code_space(62, 8), 218_340_105_584_896);
+        assert_eq!(code_space(2, 1), 2);
+        assert_eq!(code_space(64, 100), u128::MAX);
+    }
+
+    #[test]
+    fn test_check_code_space() {
+        assert_eq!(
+            check_code_space(&CodeAlphabet::default(), Some(1_000)),
+            CodeSpaceCheck::Ok
+        );
+
+        let tiny = CodeAlphabet::parse("ab").unwrap();
+        assert_eq!(check_code_space(&tiny, None), CodeSpaceCheck::Small);
+        assert_eq!(
+            check_code_space(&tiny, Some(256)),
+            CodeSpaceCheck::Exhausted
+        );
+    }
+
+    #[test]
+    fn test_safe_alphabet_is_default() {
+        let safe = CodeAlphabet::parse("safe").unwrap();
+        assert_eq!(safe, CodeAlphabet::default());
+        assert!(safe.chars().contains(&'-'));
+        assert!(safe.chars().contains(&'_'));
+    }
+
+    #[test]
+    fn test_alphanumeric_alphabet_has_no_symbols() {
+        let alphanumeric = CodeAlphabet::parse("alphanumeric").unwrap();
+        assert_eq!(alphanumeric.chars().len(), 62);
+        assert!(alphanumeric.chars().iter().all(char::is_ascii_alphanumeric));
+    }
+
+    #[test]
+    fn test_custom_alphabet() {
+        let custom = CodeAlphabet::parse("abc123").unwrap();
+        assert_eq!(custom.chars(), ['a', 'b', 'c', '1', '2', '3']);
+    }
+
+    #[test]
+    fn test_nanoid_generator_codes_are_valid() {
+        for alphabet in [
+            CodeAlphabet::default(),
+            CodeAlphabet::parse("alphanumeric").unwrap(),
+        ] {
+            let generator = code_generator("nanoid", alphabet.clone()).unwrap();
+            for _ in 0..100 {
+                let code = generator.generate().unwrap();
+                assert_eq!(code.chars().count(), SHORT_CODE_LENGTH);
+                assert!(code.chars().all(|c| alphabet.chars().contains(&c)));
+                // Auto codes must be storable like custom ones
+                assert!(validate_custom_code(&code).is_ok(), "{}", code);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nanoid_generator_skips_reserved_prefix() {
+        // A quarter of raw draws from this alphabet start with `__`
+        let generator = NanoidGenerator::new(CodeAlphabet::parse("_a").unwrap());
+        for _ in 0..200 {
+            let code = generator.generate().unwrap();
+            assert!(!code.starts_with(INTERNAL_KEY_PREFIX), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_unknown_generator_rejected() {
+        assert!(code_generator("sequential", CodeAlphabet::default()).is_err());
+    }
+
+    #[test]
+    fn test_bad_custom_alphabet() {
+        assert!(CodeAlphabet::parse("ab/c").is_err());
+        assert!(CodeAlphabet::parse("abc.").is_err());
+        assert!(CodeAlphabet::parse("abc~").is_err());
+        assert!(CodeAlphabet::parse("abca").is_err());
+        assert!(CodeAlphabet::parse("a").is_err());
+    }
+}
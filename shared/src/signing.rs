@@ -0,0 +1,160 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::env;
+
+use crate::error::UrlShortenerError;
+
+/// Hex HMAC-SHA256 of `"{timestamp}.{body}"`
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Unix seconds at which the client signed the request
+pub const SIGNATURE_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+
+/// Set to `true` to reject unsigned create requests
+pub const REQUIRE_SIGNED_CREATES_ENV: &str = "REQUIRE_SIGNED_CREATES";
+
+/// Shared secret for signed creates. Deployments inject it from Secrets
+/// Manager rather than setting it in plain configuration.
+pub const SIGNING_SECRET_ENV: &str = "CREATE_SIGNING_SECRET";
+
+/// Signatures older (or further in the future) than this are rejected, which
+/// bounds how long a captured request can be replayed
+pub const MAX_SIGNATURE_AGE_SECONDS: i64 = 300;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secret used to verify signed requests
+#[derive(Clone)]
+pub struct SigningSecret(Vec<u8>);
+
+impl std::fmt::Debug for SigningSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SigningSecret(<redacted>)")
+    }
+}
+
+impl SigningSecret {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+
+    /// The secret when `REQUIRE_SIGNED_CREATES=true`, `None` when signing is
+    /// off. Requiring signatures without a secret is a configuration error.
+    pub fn from_env() -> Result<Option<Self>, UrlShortenerError> {
+        let required = env::var(REQUIRE_SIGNED_CREATES_ENV)
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+        if !required {
+            return Ok(None);
+        }
+
+        match env::var(SIGNING_SECRET_ENV) {
+            Ok(secret) if !secret.is_empty() => Ok(Some(Self::new(secret))),
+            _ => Err(UrlShortenerError::InternalError(anyhow::anyhow!(
+                "{} is set but {} is missing",
+                REQUIRE_SIGNED_CREATES_ENV,
+                SIGNING_SECRET_ENV
+            ))),
+        }
+    }
+
+    fn mac(&self, timestamp: &str, body: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(timestamp.as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        mac
+    }
+
+    /// Signature a client sends for `body` at `timestamp`
+    pub fn sign(&self, timestamp: i64, body: &str) -> String {
+        hex::encode(
+            self.mac(&timestamp.to_string(), body)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    /// Check a request's signature headers against `body` at unix time `now`.
+    /// Every failure is `SignatureInvalid`; the reason is only logged.
+    pub fn verify(
+        &self,
+        body: &str,
+        signature: Option<&str>,
+        timestamp: Option<&str>,
+        now: i64,
+    ) -> Result<(), UrlShortenerError> {
+        let reject = |reason: &str| {
+            tracing::warn!("Rejected signed request: {}", reason);
+            UrlShortenerError::SignatureInvalid
+        };
+
+        let (Some(signature), Some(timestamp)) = (signature, timestamp) else {
+            return Err(reject("missing signature headers"));
+        };
+
+        let signed_at: i64 = timestamp
+            .parse()
+            .map_err(|_| reject("malformed timestamp"))?;
+        if (now - signed_at).abs() > MAX_SIGNATURE_AGE_SECONDS {
+            return Err(reject("timestamp outside the replay window"));
+        }
+
+        let signature = hex::decode(signature).map_err(|_| reject("malformed signature"))?;
+
+        // verify_slice compares in constant time
+        self.mac(timestamp, body)
+            .verify_slice(&signature)
+            .map_err(|_| reject("signature mismatch"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+    const BODY: &str = r#"{"original_url":"https://example.com"}"#;
+
+    #[test]
+    fn test_valid_signature() {
+        let secret = SigningSecret::new("s3cret");
+        let signature = secret.sign(NOW, BODY);
+
+        assert!(
+            secret
+                .verify(BODY, Some(&signature), Some(&NOW.to_string()), NOW + 10)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_tampered_body_is_rejected() {
+        let secret = SigningSecret::new("s3cret");
+        let signature = secret.sign(NOW, BODY);
+        let tampered = r#"{"original_url":"https://evil.example"}"#;
+
+        assert!(matches!(
+            secret.verify(tampered, Some(&signature), Some(&NOW.to_string()), NOW),
+            Err(UrlShortenerError::SignatureInvalid)
+        ));
+        assert!(
+            SigningSecret::new("other")
+                .verify(BODY, Some(&signature), Some(&NOW.to_string()), NOW)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_stale_timestamp_is_rejected() {
+        let secret = SigningSecret::new("s3cret");
+        let signature = secret.sign(NOW, BODY);
+        let later = NOW + MAX_SIGNATURE_AGE_SECONDS + 1;
+
+        assert!(matches!(
+            secret.verify(BODY, Some(&signature), Some(&NOW.to_string()), later),
+            Err(UrlShortenerError::SignatureInvalid)
+        ));
+        assert!(secret.verify(BODY, Some(&signature), None, NOW).is_err());
+    }
+}
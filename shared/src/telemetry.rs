@@ -0,0 +1,257 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use serde_json::{Value, json};
+use std::env;
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::error::UrlShortenerError;
+
+/// Base URL of an OTLP/HTTP collector. When set, spans are exported there as
+/// well as logged.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Set to `false` to stop logging incoming events at all, redacted or not
+pub const LOG_BODIES_ENV: &str = "LOG_BODIES";
+
+/// Body and payload fields masked before an event is logged
+const SENSITIVE_FIELDS: &[&str] = &["password"];
+
+/// Request headers masked before an event is logged; the forwarded-for
+/// chain is the client's IP
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "x-api-key",
+    "x-link-password",
+    "x-forwarded-for",
+];
+
+const REDACTED: &str = "<redacted>";
+
+pub fn log_bodies() -> bool {
+    env::var(LOG_BODIES_ENV)
+        .map(|v| v.to_lowercase() != "false")
+        .unwrap_or(true)
+}
+
+/// A copy of `payload` fit for the logs: passwords in a direct payload or an
+/// API Gateway body, credential headers and the client's IP are masked
+pub fn redact_event_for_log(payload: &Value) -> Value {
+    let mut redacted = payload.clone();
+
+    redact_fields(&mut redacted);
+
+    for pointer in [
+        "/requestContext/identity/sourceIp",
+        "/requestContext/http/sourceIp",
+    ] {
+        if let Some(ip) = redacted.pointer_mut(pointer) {
+            *ip = json!(REDACTED);
+        }
+    }
+    for map in ["headers", "multiValueHeaders"] {
+        if let Some(headers) = redacted.get_mut(map).and_then(Value::as_object_mut) {
+            for (name, value) in headers.iter_mut() {
+                if SENSITIVE_HEADERS
+                    .iter()
+                    .any(|sensitive| name.eq_ignore_ascii_case(sensitive))
+                {
+                    *value = json!(REDACTED);
+                }
+            }
+        }
+    }
+
+    if let Some(body) = redacted.get("body").and_then(Value::as_str)
+        && let Ok(mut body_json) = serde_json::from_str::<Value>(body)
+        && redact_fields(&mut body_json)
+    {
+        redacted["body"] = Value::String(body_json.to_string());
+    }
+
+    redacted
+}
+
+/// Mask the sensitive top-level fields of `value`; `true` if any were present
+fn redact_fields(value: &mut Value) -> bool {
+    let Some(fields) = value.as_object_mut() else {
+        return false;
+    };
+    let mut found = false;
+    for (name, value) in fields.iter_mut() {
+        if SENSITIVE_FIELDS.contains(&name.as_str()) {
+            *value = json!(REDACTED);
+            found = true;
+        }
+    }
+    found
+}
+
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One JSON object per line, for CloudWatch
+    Json,
+    /// Human-readable, for local runs
+    Text,
+}
+
+/// Holds the OTLP exporter, if any. Dropping it flushes buffered spans, so
+/// keep it alive for the life of the process.
+#[must_use]
+pub struct TelemetryGuard(Option<SdkTracerProvider>);
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.0.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// Tracer provider that batches spans to `endpoint`. Like the OpenTelemetry
+/// SDKs, `/v1/traces` is appended to the base endpoint.
+pub fn otlp_tracer_provider(
+    service_name: &str,
+    endpoint: &str,
+) -> Result<SdkTracerProvider, UrlShortenerError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(|e| {
+            UrlShortenerError::InternalError(anyhow::anyhow!(
+                "Failed to build OTLP exporter for {}: {}",
+                endpoint,
+                e
+            ))
+        })?;
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(service_name.to_string())
+                .build(),
+        )
+        .build())
+}
+
+/// Install the global subscriber: `log_filter` (a `RUST_LOG` directive) over
+/// stdout logs in `format`, plus OTLP export when `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// is set. Spans go out in batches; a Lambda frozen between invocations
+/// sends the rest when it next runs.
+pub fn init_tracing(
+    service_name: &str,
+    log_filter: &str,
+    format: LogFormat,
+) -> Result<TelemetryGuard, UrlShortenerError> {
+    let provider = env::var(OTLP_ENDPOINT_ENV)
+        .ok()
+        .filter(|endpoint| !endpoint.trim().is_empty())
+        .map(|endpoint| otlp_tracer_provider(service_name, &endpoint))
+        .transpose()?;
+
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name.to_string()))
+    });
+    let fmt_layer = match format {
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(log_filter))
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+
+    Ok(TelemetryGuard(provider))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::Registry;
+
+    #[test]
+    fn test_otlp_layer_builds() {
+        let provider = otlp_tracer_provider("squrl-test", "http://localhost:4318/").unwrap();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("squrl-test"));
+
+        // Spans are buffered, not sent, so no collector is needed
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("redirect", short_code = "abc123").in_scope(|| {});
+        });
+
+        drop(TelemetryGuard(Some(provider)));
+    }
+
+    #[test]
+    fn test_redact_event_for_log_masks_password() {
+        let api_gateway_event = json!({
+            "httpMethod": "POST",
+            "body": "{\"original_url\": \"https://example.com\", \"password\": \"hunter2\"}"
+        });
+        let redacted = redact_event_for_log(&api_gateway_event).to_string();
+        assert!(!redacted.contains("hunter2"));
+        assert!(redacted.contains("https://example.com"));
+
+        let direct_event = json!({
+            "original_url": "https://example.com",
+            "password": "hunter2"
+        });
+        let redacted = redact_event_for_log(&direct_event);
+        assert_eq!(redacted["password"], "<redacted>");
+        assert_eq!(redacted["original_url"], "https://example.com");
+    }
+
+    #[test]
+    fn test_redact_event_for_log_masks_client_ip() {
+        let api_gateway_event = json!({
+            "httpMethod": "POST",
+            "headers": {"X-Forwarded-For": "203.0.113.7, 10.0.0.1"},
+            "requestContext": {"identity": {"sourceIp": "203.0.113.7"}},
+            "body": "{\"original_url\": \"https://example.com\"}"
+        });
+        let redacted = redact_event_for_log(&api_gateway_event).to_string();
+        assert!(!redacted.contains("203.0.113.7"));
+        assert!(redacted.contains("https://example.com"));
+    }
+
+    #[test]
+    fn test_redact_event_for_log_masks_credential_headers() {
+        let api_gateway_event = json!({
+            "httpMethod": "POST",
+            "headers": {
+                "Authorization": "Bearer secret-token",
+                "x-api-key": "sk_live_123",
+                "X-Link-Password": "hunter2",
+                "Content-Type": "application/json"
+            },
+            "multiValueHeaders": {"X-Api-Key": ["sk_live_123"]},
+            "body": "{\"original_url\": \"https://example.com\"}"
+        });
+        let redacted = redact_event_for_log(&api_gateway_event);
+
+        let text = redacted.to_string();
+        assert!(!text.contains("secret-token"));
+        assert!(!text.contains("sk_live_123"));
+        assert!(!text.contains("hunter2"));
+        assert_eq!(redacted["headers"]["Authorization"], "<redacted>");
+        assert_eq!(redacted["headers"]["Content-Type"], "application/json");
+        // A body without secrets is logged as sent
+        assert_eq!(redacted["body"], api_gateway_event["body"]);
+    }
+
+    #[tokio::test]
+    async fn test_otlp_layer_builds_inside_runtime() {
+        // Lambdas set tracing up from within `#[tokio::main]`
+        let provider = otlp_tracer_provider("squrl-test", "http://localhost:4318").unwrap();
+        drop(TelemetryGuard(Some(provider)));
+    }
+}
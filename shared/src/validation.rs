@@ -1,16 +1,161 @@
+use chrono::Duration;
+use std::collections::HashMap;
+use url::{Host, Url};
+use validator::Validate;
+
+use crate::dynamodb::INTERNAL_KEY_PREFIX;
 use crate::error::UrlShortenerError;
-use url::Url;
+use crate::models::{CreateUrlRequest, TtlSource};
+
+/// Most dot-separated labels a target host may have, e.g. 3 for
+/// `www.example.com`. Dozens of labels are a phishing tell.
+pub const MAX_HOST_LABELS_ENV: &str = "MAX_HOST_LABELS";
+
+pub const DEFAULT_MAX_HOST_LABELS: usize = 10;
+
+/// DNS limit on a full host name, without its trailing dot
+const MAX_HOST_LEN: usize = 253;
+
+/// Longest target URL accepted, in bytes. The URL is the key of the
+/// `original_url` index, and DynamoDB caps index keys at 2048 bytes.
+pub const MAX_URL_BYTES: usize = 2000;
 
 pub fn validate_url(url_str: &str) -> Result<Url, UrlShortenerError> {
+    validate_url_with(url_str, false, DEFAULT_MAX_HOST_LABELS)
+}
+
+/// `validate_url` with a configured host label limit, plus with
+/// `require_tld` (`REQUIRE_TLD=true`) a check that the host is an IP address
+/// or has a dot, so `https://foo` is caught as a likely typo. Off by
+/// default, since intranet links use bare hosts.
+pub fn validate_url_with(
+    url_str: &str,
+    require_tld: bool,
+    max_host_labels: usize,
+) -> Result<Url, UrlShortenerError> {
+    check_url_length(url_str)?;
     let url =
         Url::parse(url_str).map_err(|_| UrlShortenerError::InvalidUrl(url_str.to_string()))?;
+    // Parsing can percent-encode, so the stored form may be longer
+    check_url_length(url.as_str())?;
 
-    match url.scheme() {
-        "http" | "https" => Ok(url),
-        _ => Err(UrlShortenerError::InvalidUrl(
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(UrlShortenerError::InvalidUrl(
             "Only HTTP and HTTPS URLs are allowed".to_string(),
-        )),
+        ));
+    }
+
+    if let Some(Host::Domain(domain)) = url.host() {
+        check_host_shape(domain, max_host_labels)?;
+    }
+
+    if require_tld && let Some(Host::Domain(domain)) = url.host() {
+        let labels = domain.trim_end_matches('.').split('.');
+        if labels.filter(|label| !label.is_empty()).count() < 2 {
+            return Err(UrlShortenerError::InvalidUrl(format!(
+                "Host '{}' has no top-level domain (expected e.g. {}.com)",
+                domain, domain
+            )));
+        }
+    }
+
+    Ok(url)
+}
+
+fn check_url_length(url: &str) -> Result<(), UrlShortenerError> {
+    if url.len() > MAX_URL_BYTES {
+        return Err(UrlShortenerError::PayloadTooLarge(format!(
+            "URL is {} bytes; at most {} allowed",
+            url.len(),
+            MAX_URL_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// Reject domains longer than DNS allows or split into more than
+/// `max_labels` labels. `domain` is the parsed (punycode) form.
+fn check_host_shape(domain: &str, max_labels: usize) -> Result<(), UrlShortenerError> {
+    let domain = domain.trim_end_matches('.');
+    if domain.len() > MAX_HOST_LEN {
+        return Err(UrlShortenerError::InvalidUrl(format!(
+            "Host is {} characters long (at most {} allowed)",
+            domain.len(),
+            MAX_HOST_LEN
+        )));
+    }
+
+    let labels = domain.split('.').count();
+    if labels > max_labels {
+        return Err(UrlShortenerError::InvalidUrl(format!(
+            "Host has {} labels (at most {} allowed)",
+            labels, max_labels
+        )));
+    }
+
+    Ok(())
+}
+
+/// Check a URL is safe to emit as a `Location` header. Stored links are
+/// validated on create, but an item written by other means could carry CR/LF
+/// and split the response.
+pub fn sanitize_location(url: &str) -> Result<String, UrlShortenerError> {
+    if url.chars().any(char::is_control) {
+        return Err(UrlShortenerError::InternalError(anyhow::anyhow!(
+            "Redirect target contains control characters"
+        )));
+    }
+
+    Ok(url.to_string())
+}
+
+/// Append the path captured after a wildcard link's code to its stored base.
+/// The result must stay under the base's origin and path, so a crafted suffix
+/// can't turn the link into an open redirect.
+pub fn compose_wildcard_url(base: &str, suffix: &str) -> Result<String, UrlShortenerError> {
+    let suffix = suffix.trim_start_matches('/');
+    if suffix.is_empty() {
+        return Ok(base.to_string());
+    }
+
+    let base_url = validate_url(base)?;
+    let base_path = base_url.path().trim_end_matches('/');
+
+    let mut composed = base_url.clone();
+    composed.set_path(&format!("{}/{}", base_path, suffix));
+
+    let escapes = composed.origin() != base_url.origin()
+        || !composed.path().starts_with(&format!("{}/", base_path));
+    if escapes {
+        return Err(UrlShortenerError::ValidationError(
+            "Path suffix leaves the link's destination".to_string(),
+        ));
     }
+
+    Ok(validate_url(composed.as_str())?.to_string())
+}
+
+/// Reject a target on one of our own short hosts. It would redirect into
+/// another short link, or back to itself.
+pub fn reject_self_link<'a>(
+    url: &Url,
+    own_hosts: impl IntoIterator<Item = &'a str>,
+) -> Result<(), UrlShortenerError> {
+    let Some(host) = url.host_str() else {
+        return Ok(());
+    };
+
+    if own_hosts
+        .into_iter()
+        .any(|own| own.eq_ignore_ascii_case(host))
+    {
+        return Err(UrlShortenerError::ValidationError(format!(
+            "Links to {} would point back at this shortener",
+            host
+        )));
+    }
+
+    Ok(())
 }
 
 pub fn validate_custom_code(code: &str) -> Result<(), UrlShortenerError> {
@@ -29,9 +174,178 @@ pub fn validate_custom_code(code: &str) -> Result<(), UrlShortenerError> {
         ));
     }
 
+    // Reserved for internal items such as the code counter
+    if code.starts_with(INTERNAL_KEY_PREFIX) {
+        return Err(UrlShortenerError::ValidationError(format!(
+            "Custom code cannot start with \"{}\"",
+            INTERNAL_KEY_PREFIX
+        )));
+    }
+
+    Ok(())
+}
+
+/// Most extra response headers one link may carry
+pub const MAX_EXTRA_HEADERS: usize = 10;
+const MAX_EXTRA_HEADER_NAME_LEN: usize = 64;
+const MAX_EXTRA_HEADER_VALUE_LEN: usize = 512;
+
+/// Headers the redirect path sets itself, or that change how a response is
+/// framed, cached or shared. A link's extra headers may not touch them.
+const PROTECTED_HEADERS: &[&str] = &[
+    "location",
+    "content-length",
+    "content-type",
+    "transfer-encoding",
+    "connection",
+    "set-cookie",
+    "cache-control",
+    "etag",
+    "vary",
+    "link",
+    "x-robots-tag",
+];
+
+pub fn is_protected_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    PROTECTED_HEADERS.contains(&name.as_str()) || name.starts_with("access-control-")
+}
+
+/// Check a link's extra redirect headers: RFC 9110 token names that aren't
+/// protected, and values of visible ASCII, spaces and tabs only, so nothing
+/// can split the response or smuggle in another header
+pub fn validate_extra_headers(headers: &HashMap<String, String>) -> Result<(), UrlShortenerError> {
+    if headers.len() > MAX_EXTRA_HEADERS {
+        return Err(UrlShortenerError::ValidationError(format!(
+            "At most {} extra headers are allowed",
+            MAX_EXTRA_HEADERS
+        )));
+    }
+
+    for (name, value) in headers {
+        let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if name.is_empty()
+            || name.len() > MAX_EXTRA_HEADER_NAME_LEN
+            || !name.chars().all(is_token_char)
+        {
+            return Err(UrlShortenerError::ValidationError(format!(
+                "Invalid header name {:?}",
+                name
+            )));
+        }
+
+        if is_protected_header(name) {
+            return Err(UrlShortenerError::ValidationError(format!(
+                "Header {} can't be set on a link",
+                name
+            )));
+        }
+
+        if value.len() > MAX_EXTRA_HEADER_VALUE_LEN
+            || !value
+                .chars()
+                .all(|c| c == ' ' || c == '\t' || c.is_ascii_graphic())
+        {
+            return Err(UrlShortenerError::ValidationError(format!(
+                "Invalid value for header {}",
+                name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuse a request body over `max_bytes` before anything parses it
+pub fn check_body_size(body: &str, max_bytes: usize) -> Result<(), UrlShortenerError> {
+    if body.len() > max_bytes {
+        return Err(UrlShortenerError::PayloadTooLarge(format!(
+            "Request body is {} bytes; the limit is {}",
+            body.len(),
+            max_bytes
+        )));
+    }
     Ok(())
 }
 
+/// Deployment settings the create checks depend on; see
+/// `RuntimeConfig::validation_config`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationConfig {
+    /// See `validate_url_with`
+    pub require_tld: bool,
+    /// Skip `reject_self_link`
+    pub allow_self_links: bool,
+    /// Our own short hosts, which targets may not point at
+    pub own_hosts: Vec<String>,
+    /// Lifetime for requests that don't set one
+    pub default_ttl_hours: Option<u32>,
+    /// Most labels a target host may have, see `MAX_HOST_LABELS`
+    pub max_host_labels: usize,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            require_tld: false,
+            allow_self_links: false,
+            own_hosts: Vec::new(),
+            default_ttl_hours: None,
+            max_host_labels: DEFAULT_MAX_HOST_LABELS,
+        }
+    }
+}
+
+/// A create request that passed every check, with the values derived from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizedCreate {
+    /// The parsed target
+    pub url: Url,
+    pub ttl: Option<Duration>,
+    pub ttl_source: TtlSource,
+    pub redirect_status: Option<u16>,
+}
+
+/// Run every check a create makes before touching the table, in the same
+/// order for the Lambda and the dev server: field rules, the target URL and
+/// self-links, extra headers, the custom code, then lifetime and redirect
+/// type
+pub fn validate_create_request(
+    request: &CreateUrlRequest,
+    config: &ValidationConfig,
+) -> Result<NormalizedCreate, UrlShortenerError> {
+    request
+        .validate()
+        .map_err(|e| UrlShortenerError::ValidationError(e.to_string()))?;
+
+    let url = validate_url_with(
+        &request.original_url,
+        config.require_tld,
+        config.max_host_labels,
+    )?;
+    if !config.allow_self_links {
+        reject_self_link(&url, config.own_hosts.iter().map(String::as_str))?;
+    }
+
+    if let Some(extra_headers) = &request.extra_headers {
+        validate_extra_headers(extra_headers)?;
+    }
+
+    if let Some(custom_code) = &request.custom_code {
+        validate_custom_code(custom_code)?;
+    }
+
+    let (ttl, ttl_source) = request.effective_ttl(config.default_ttl_hours)?;
+    let redirect_status = request.redirect_status()?;
+
+    Ok(NormalizedCreate {
+        url,
+        ttl,
+        ttl_source,
+        redirect_status,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,12 +356,176 @@ mod tests {
         assert!(validate_url("http://localhost:3000").is_ok());
     }
 
+    #[test]
+    fn test_host_shape_limits() {
+        let long_host = format!("{}.com", vec!["a".repeat(59); 5].join("."));
+        assert!(long_host.len() > 300);
+        assert!(matches!(
+            validate_url(&format!("https://{}/", long_host)),
+            Err(UrlShortenerError::InvalidUrl(_))
+        ));
+
+        let many_labels = format!("{}example.com", "x.".repeat(38));
+        assert!(matches!(
+            validate_url(&format!("https://{}/", many_labels)),
+            Err(UrlShortenerError::InvalidUrl(message)) if message.contains("40 labels")
+        ));
+
+        assert!(validate_url("https://www.example.com/path").is_ok());
+        // A trailing dot doesn't count as a label, and IPs aren't domains
+        assert!(validate_url("https://www.example.com./").is_ok());
+        assert!(validate_url("http://192.168.1.10/").is_ok());
+
+        assert!(check_host_shape("a.b.c.example.com", 5).is_ok());
+        assert!(check_host_shape("a.b.c.d.example.com", 5).is_err());
+    }
+
+    #[test]
+    fn test_url_length_cap() {
+        let at_limit = format!("https://example.com/{}", "a".repeat(MAX_URL_BYTES - 20));
+        assert_eq!(at_limit.len(), MAX_URL_BYTES);
+        assert!(validate_url(&at_limit).is_ok());
+
+        let err = validate_url(&format!("{}a", at_limit)).unwrap_err();
+        assert_eq!(err.status_code(), 413);
+
+        // Counted after percent-encoding
+        let encoded = format!("https://example.com/{}x", " ".repeat(MAX_URL_BYTES / 2));
+        assert!(matches!(
+            validate_url(&encoded),
+            Err(UrlShortenerError::PayloadTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_require_tld() {
+        assert!(validate_url_with("https://foo", false, DEFAULT_MAX_HOST_LABELS).is_ok());
+        assert!(matches!(
+            validate_url_with("https://foo", true, DEFAULT_MAX_HOST_LABELS),
+            Err(UrlShortenerError::InvalidUrl(_))
+        ));
+        assert!(validate_url_with("https://foo./path", true, DEFAULT_MAX_HOST_LABELS).is_err());
+        assert!(validate_url_with("http://localhost:3000", true, DEFAULT_MAX_HOST_LABELS).is_err());
+
+        assert!(validate_url_with("https://foo.com", true, DEFAULT_MAX_HOST_LABELS).is_ok());
+        assert!(
+            validate_url_with(
+                "https://intranet.corp.example/wiki",
+                true,
+                DEFAULT_MAX_HOST_LABELS
+            )
+            .is_ok()
+        );
+        assert!(
+            validate_url_with("http://192.168.1.10/admin", true, DEFAULT_MAX_HOST_LABELS).is_ok()
+        );
+        assert!(validate_url_with("http://[::1]:8080/", true, DEFAULT_MAX_HOST_LABELS).is_ok());
+    }
+
+    #[test]
+    fn test_validate_extra_headers() {
+        let headers =
+            |name: &str, value: &str| HashMap::from([(name.to_string(), value.to_string())]);
+
+        assert!(validate_extra_headers(&headers("X-Partner-Id", "acme-42")).is_ok());
+        assert!(validate_extra_headers(&HashMap::new()).is_ok());
+
+        for forbidden in [
+            "Location",
+            "content-length",
+            "Set-Cookie",
+            "Access-Control-Allow-Origin",
+        ] {
+            assert!(
+                matches!(
+                    validate_extra_headers(&headers(forbidden, "x")),
+                    Err(UrlShortenerError::ValidationError(_))
+                ),
+                "accepted {}",
+                forbidden
+            );
+        }
+
+        // Nothing that could split the response or fake another header
+        assert!(validate_extra_headers(&headers("X-Partner-Id", "a\r\nSet-Cookie: b=c")).is_err());
+        assert!(validate_extra_headers(&headers("X-Partner Id", "a")).is_err());
+        assert!(validate_extra_headers(&headers("X-Partner:Id", "a")).is_err());
+        assert!(validate_extra_headers(&headers("", "a")).is_err());
+
+        let too_many = (0..=MAX_EXTRA_HEADERS)
+            .map(|i| (format!("X-Extra-{}", i), "1".to_string()))
+            .collect();
+        assert!(validate_extra_headers(&too_many).is_err());
+    }
+
     #[test]
     fn test_validate_url_invalid() {
         assert!(validate_url("ftp://example.com").is_err());
         assert!(validate_url("not-a-url").is_err());
     }
 
+    #[test]
+    fn test_sanitize_location() {
+        assert_eq!(
+            sanitize_location("https://example.com/a?b=c").unwrap(),
+            "https://example.com/a?b=c"
+        );
+        assert!(sanitize_location("https://example.com/\r\nSet-Cookie: a=b").is_err());
+        assert!(sanitize_location("https://example.com/\t").is_err());
+    }
+
+    #[test]
+    fn test_compose_wildcard_url() {
+        // Empty suffix is the base itself
+        assert_eq!(
+            compose_wildcard_url("https://target.com/docs", "").unwrap(),
+            "https://target.com/docs"
+        );
+        assert_eq!(
+            compose_wildcard_url("https://target.com/docs", "/").unwrap(),
+            "https://target.com/docs"
+        );
+
+        assert_eq!(
+            compose_wildcard_url("https://target.com", "intro").unwrap(),
+            "https://target.com/intro"
+        );
+        assert_eq!(
+            compose_wildcard_url("https://target.com/docs/", "guide/intro").unwrap(),
+            "https://target.com/docs/guide/intro"
+        );
+    }
+
+    #[test]
+    fn test_compose_wildcard_url_stays_under_base() {
+        assert!(compose_wildcard_url("https://target.com/docs", "../admin").is_err());
+        assert!(compose_wildcard_url("https://target.com/docs", "a/../../admin").is_err());
+
+        // Anything that looks like a host ends up in the path
+        let composed = compose_wildcard_url("https://target.com", "/evil.com").unwrap();
+        assert_eq!(composed, "https://target.com/evil.com");
+        let composed = compose_wildcard_url("https://target.com", "@evil.com").unwrap();
+        assert!(composed.starts_with("https://target.com/"));
+        let composed = compose_wildcard_url("https://target.com", "x?next=//evil.com").unwrap();
+        assert!(composed.starts_with("https://target.com/x%3F"));
+    }
+
+    #[test]
+    fn test_reject_self_link() {
+        let own = ["sqrl.co", "go.example.com"];
+
+        let url = validate_url("https://SQRL.co/abc123").unwrap();
+        assert!(matches!(
+            reject_self_link(&url, own),
+            Err(UrlShortenerError::ValidationError(_))
+        ));
+        let url = validate_url("https://go.example.com/abc123").unwrap();
+        assert!(reject_self_link(&url, own).is_err());
+
+        let url = validate_url("https://example.com/abc123").unwrap();
+        assert!(reject_self_link(&url, own).is_ok());
+    }
+
     #[test]
     fn test_validate_custom_code_valid() {
         assert!(validate_custom_code("abc123").is_ok());
@@ -61,4 +539,123 @@ mod tests {
         assert!(validate_custom_code("a".repeat(21).as_str()).is_err()); // Too long
         assert!(validate_custom_code("test@code").is_err()); // Invalid character
     }
+
+    #[test]
+    fn test_validate_custom_code_rejects_reserved_prefix() {
+        assert!(validate_custom_code("__counter__").is_err());
+        assert!(validate_custom_code("__dedup").is_err());
+        assert!(validate_custom_code("_promo").is_ok());
+        assert!(validate_custom_code("summer_sale").is_ok());
+    }
+
+    fn create(body: &str) -> CreateUrlRequest {
+        CreateUrlRequest::from_json(body).unwrap()
+    }
+
+    fn config() -> ValidationConfig {
+        ValidationConfig {
+            require_tld: true,
+            allow_self_links: false,
+            own_hosts: vec!["sqrl.co".to_string()],
+            default_ttl_hours: Some(24),
+            max_host_labels: 3,
+        }
+    }
+
+    #[test]
+    fn test_validate_create_request_normalizes() {
+        let normalized = validate_create_request(
+            &create(r#"{"original_url": "https://Example.com", "redirect_type": "temporary"}"#),
+            &config(),
+        )
+        .unwrap();
+
+        assert_eq!(normalized.url.as_str(), "https://example.com/");
+        assert_eq!(normalized.ttl, Some(Duration::hours(24)));
+        assert_eq!(normalized.ttl_source, TtlSource::Default);
+        assert_eq!(normalized.redirect_status, Some(302));
+
+        let normalized = validate_create_request(
+            &create(r#"{"original_url": "https://example.com", "expires_in": "2h"}"#),
+            &ValidationConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(normalized.ttl, Some(Duration::hours(2)));
+        assert_eq!(normalized.ttl_source, TtlSource::User);
+        assert_eq!(normalized.redirect_status, None);
+    }
+
+    #[test]
+    fn test_validate_create_request_rejections() {
+        let cases = [
+            // Field rules
+            (
+                r#"{"original_url": "https://example.com", "ttl_hours": 0}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "max_uses": 0}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "custom_code": "ab"}"#,
+                "ValidationError",
+            ),
+            (r#"{"original_url": "not a url"}"#, "ValidationError"),
+            // Target URL
+            (
+                r#"{"original_url": "ftp://example.com/file"}"#,
+                "InvalidUrl",
+            ),
+            (r#"{"original_url": "https://intranet/wiki"}"#, "InvalidUrl"),
+            (
+                r#"{"original_url": "https://a.b.c.example.com"}"#,
+                "InvalidUrl",
+            ),
+            (
+                r#"{"original_url": "https://sqrl.co/abc123"}"#,
+                "ValidationError",
+            ),
+            // Per-link options
+            (
+                r#"{"original_url": "https://example.com", "extra_headers": {"Location": "x"}}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "custom_code": "bad@code"}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "custom_code": "__counter__"}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "ttl_hours": 2, "expires_in": "2h"}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "expires_in": "soon"}"#,
+                "ValidationError",
+            ),
+            (
+                r#"{"original_url": "https://example.com", "redirect_type": "sideways"}"#,
+                "ValidationError",
+            ),
+        ];
+
+        for (body, error_type) in cases {
+            let err = validate_create_request(&create(body), &config()).unwrap_err();
+            assert_eq!(err.error_type(), error_type, "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_validate_create_request_allows_self_links_when_configured() {
+        let request = create(r#"{"original_url": "https://sqrl.co/abc123"}"#);
+        let config = ValidationConfig {
+            allow_self_links: true,
+            ..config()
+        };
+        assert!(validate_create_request(&request, &config).is_ok());
+    }
 }
@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+use crate::creator_ip::CreatorIpHasher;
+use crate::error::UrlShortenerError;
+
+/// Set to `true` to count unique visitors per link alongside total clicks
+pub const TRACK_UNIQUE_VISITORS_ENV: &str = "TRACK_UNIQUE_VISITORS";
+
+/// Salt for visitor keys. Like `CREATOR_IP_SALT`, it comes from Secrets
+/// Manager; raw visitor IPs are never stored.
+pub const VISITOR_SALT_ENV: &str = "VISITOR_SALT";
+
+/// Most visitor keys kept for one link on one day. Keys are 16 hex chars, so
+/// a full set stays around 20 KB, well under DynamoDB's 400 KB item limit.
+/// Visitors past the cap on a busy day aren't counted, so `unique_visitors`
+/// is a lower bound for popular links.
+pub const MAX_DAILY_VISITOR_KEYS: usize = 1000;
+
+/// The UTC day bucket a visit falls in, as `YYYY-MM-DD`
+pub fn visit_day(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d").to_string()
+}
+
+/// What recording a visit did to a link's counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitOutcome {
+    /// First visit from this key today: added to today's set and counted
+    Counted,
+    /// The stored set is from an earlier day; it is replaced by one holding
+    /// this key, and the visit counted
+    NewDay,
+    /// Already seen today
+    Duplicate,
+    /// Today's set is full; not counted
+    Capped,
+    /// Another visit started today's set at the same moment, or the link
+    /// is gone; not counted
+    Skipped,
+}
+
+/// Decide what a visit does given the link's stored day bucket and keys.
+/// `record_click_with_visit` makes the same decision inside DynamoDB conditions
+/// and uses this to interpret a failed one.
+pub fn classify_visit(
+    stored_day: Option<&str>,
+    stored_keys: &HashSet<String>,
+    visitor_key: &str,
+    day: &str,
+) -> VisitOutcome {
+    if stored_day != Some(day) {
+        VisitOutcome::NewDay
+    } else if stored_keys.contains(visitor_key) {
+        VisitOutcome::Duplicate
+    } else if stored_keys.len() >= MAX_DAILY_VISITOR_KEYS {
+        VisitOutcome::Capped
+    } else {
+        VisitOutcome::Counted
+    }
+}
+
+/// Derives the per-day visitor keys stored in a link's visitor set
+#[derive(Debug, Clone, PartialEq)]
+pub struct VisitorKeys(CreatorIpHasher);
+
+impl VisitorKeys {
+    pub fn new(salt: impl Into<Vec<u8>>) -> Self {
+        Self(CreatorIpHasher::new(salt))
+    }
+
+    /// The key deriver when `TRACK_UNIQUE_VISITORS=true`, `None` when uniques
+    /// aren't tracked. Tracking without a salt is a configuration error.
+    pub fn from_lookup(
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Self>, UrlShortenerError> {
+        let enabled = lookup(TRACK_UNIQUE_VISITORS_ENV)
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+        if !enabled {
+            return Ok(None);
+        }
+
+        match lookup(VISITOR_SALT_ENV) {
+            Some(salt) if !salt.is_empty() => Ok(Some(Self::new(salt))),
+            _ => Err(UrlShortenerError::InternalError(anyhow::anyhow!(
+                "{} is set but {} is missing",
+                TRACK_UNIQUE_VISITORS_ENV,
+                VISITOR_SALT_ENV
+            ))),
+        }
+    }
+
+    pub fn from_env() -> Result<Option<Self>, UrlShortenerError> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Key for `ip` on `day`. The day is mixed in so keys can't be linked
+    /// across days, and the hash is truncated to keep the set small.
+    pub fn key(&self, ip: &str, day: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(day.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self.0.hash(ip).as_bytes());
+        hex::encode(&hasher.finalize()[..8])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_visit_day_and_keys() {
+        let late = Utc.with_ymd_and_hms(2024, 3, 9, 23, 59, 59).unwrap();
+        let next = Utc.with_ymd_and_hms(2024, 3, 10, 0, 0, 0).unwrap();
+        assert_eq!(visit_day(late), "2024-03-09");
+        assert_eq!(visit_day(next), "2024-03-10");
+
+        let keys = VisitorKeys::new("pepper");
+        let key = keys.key("203.0.113.7", "2024-03-09");
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, keys.key(" 203.0.113.7", "2024-03-09"));
+        assert_ne!(key, keys.key("203.0.113.7", "2024-03-10"));
+        assert_ne!(key, keys.key("203.0.113.8", "2024-03-09"));
+        assert_ne!(
+            key,
+            VisitorKeys::new("salt").key("203.0.113.7", "2024-03-09")
+        );
+    }
+
+    #[test]
+    fn test_classify_visit_dedups_within_a_day() {
+        let keys = VisitorKeys::new("pepper");
+        let day = "2024-03-09";
+        let alice = keys.key("203.0.113.7", day);
+        let bob = keys.key("198.51.100.2", day);
+
+        let mut seen = HashSet::new();
+        assert_eq!(
+            classify_visit(None, &seen, &alice, day),
+            VisitOutcome::NewDay
+        );
+        seen.insert(alice.clone());
+        assert_eq!(
+            classify_visit(Some(day), &seen, &alice, day),
+            VisitOutcome::Duplicate
+        );
+        assert_eq!(
+            classify_visit(Some(day), &seen, &bob, day),
+            VisitOutcome::Counted
+        );
+
+        // Yesterday's set doesn't suppress today's visit
+        let tomorrow = "2024-03-10";
+        assert_eq!(
+            classify_visit(
+                Some(day),
+                &seen,
+                &keys.key("203.0.113.7", tomorrow),
+                tomorrow
+            ),
+            VisitOutcome::NewDay
+        );
+
+        let full: HashSet<String> = (0..MAX_DAILY_VISITOR_KEYS)
+            .map(|i| format!("{:016x}", i))
+            .collect();
+        assert_eq!(
+            classify_visit(Some(day), &full, &bob, day),
+            VisitOutcome::Capped
+        );
+    }
+
+    #[test]
+    fn test_from_lookup() {
+        assert_eq!(VisitorKeys::from_lookup(|_| None).unwrap(), None);
+
+        let enabled = |key: &str| match key {
+            TRACK_UNIQUE_VISITORS_ENV => Some("true".to_string()),
+            _ => None,
+        };
+        assert!(VisitorKeys::from_lookup(enabled).is_err());
+    }
+}
@@ -1,102 +1,551 @@
 use aws_config::BehaviorVersion;
-use aws_sdk_dynamodb::Client as DynamoDbClient;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Json},
+    async_trait,
+    body::Body,
+    extract::{DefaultBodyLimit, FromRequest, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Json, Response},
     routing::{get, post},
-    Router,
+    Form, Router,
 };
-use chrono::{DateTime, Utc};
-use nanoid::nanoid;
+use chrono::Utc;
+use futures::TryStreamExt;
+use serde::Deserialize;
 use serde_json::{json, Value};
-use std::{env, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    env,
+    future::Future,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::services::ServeDir;
 use tracing::{error, info, warn};
 use validator::Validate;
 
-use squrl_shared::dynamodb::DynamoDbClient as UrlDynamoDbClient;
+use squrl_shared::api_key::{api_keys_from_env, check_api_key, API_KEY_HEADER};
+use squrl_shared::config::RuntimeConfig;
+use squrl_shared::dynamodb::{
+    auto_create_table_from_env, strong_reads_from_env, ClientTuning, CreateOutcome, DedupPolicy,
+    DynamoDbClient as UrlDynamoDbClient, TableStatus,
+};
 use squrl_shared::error::UrlShortenerError;
-use squrl_shared::models::{CreateUrlRequest, CreateUrlResponse, UrlItem};
-use squrl_shared::validation::{validate_custom_code, validate_url};
+use squrl_shared::idempotency::{IdempotencyKey, IDEMPOTENCY_KEY_HEADER};
+use squrl_shared::interstitial::interstitial_page;
+use squrl_shared::models::{
+    build_short_url, default_redirect_status, expires_at_rfc3339, prefers_minimal,
+    redirect_noindex, shortlink_header, AliasListResponse, CreateAliasRequest, CreateUrlRequest,
+    CreateUrlResponse, ExpandRequest, MinimalCreateUrlResponse, PreviewResponse, RedirectType,
+    StatsResponse, UrlItem, REDIRECT_ROBOTS_TAG,
+};
+use squrl_shared::password::{hash_password, verify_link_password, LINK_PASSWORD_HEADER};
+use squrl_shared::short_code::{
+    check_code_space, code_generator_from_env, CodeAlphabet, CodeGenerator,
+};
+use squrl_shared::validation::{
+    is_protected_header, sanitize_location, validate_create_request, validate_custom_code,
+    validate_extra_headers, validate_url_with, NormalizedCreate, ValidationConfig,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     db_client: UrlDynamoDbClient,
+    admin_import_enabled: bool,
+    code_generator: Arc<dyn CodeGenerator>,
+    admin_auth: Arc<AdminAuth>,
+    runtime_config: Arc<RuntimeConfig>,
+    /// Served at `/` for anything the API routes don't match
+    static_dir: Option<PathBuf>,
+}
+
+/// Directory of static files, such as the web UI, served at `/`
+const STATIC_DIR_ENV: &str = "STATIC_DIR";
+
+const DEFAULT_STATIC_DIR: &str = "./web";
+
+/// The static directory, if it exists; without one only the API is served
+fn static_dir_from_env() -> Option<PathBuf> {
+    let dir =
+        PathBuf::from(env::var(STATIC_DIR_ENV).unwrap_or_else(|_| DEFAULT_STATIC_DIR.to_string()));
+    dir.is_dir().then_some(dir)
+}
+
+/// API keys required on admin routes, and the path prefixes they guard
+struct AdminAuth {
+    api_keys: HashMap<String, String>,
+    protected_prefixes: Vec<String>,
+}
+
+/// Comma-separated path prefixes that require an API key
+const ADMIN_PROTECTED_PREFIXES_ENV: &str = "ADMIN_PROTECTED_PREFIXES";
+
+const DEFAULT_ADMIN_PROTECTED_PREFIXES: &str = "/api/admin/,/api/urls/";
+
+impl AdminAuth {
+    fn from_env() -> Self {
+        let prefixes = env::var(ADMIN_PROTECTED_PREFIXES_ENV)
+            .unwrap_or_else(|_| DEFAULT_ADMIN_PROTECTED_PREFIXES.to_string());
+
+        Self::new(api_keys_from_env(), &prefixes)
+    }
+
+    fn new(api_keys: HashMap<String, String>, prefixes: &str) -> Self {
+        Self {
+            api_keys,
+            protected_prefixes: prefixes
+                .split(',')
+                .map(str::trim)
+                .filter(|prefix| !prefix.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    fn protects(&self, path: &str) -> bool {
+        self.protected_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
+
+/// Port the dev server listens on; change it to run beside another instance
+const DEV_SERVER_PORT_ENV: &str = "DEV_SERVER_PORT";
+
+const DEFAULT_DEV_SERVER_PORT: u16 = 3000;
+
+pub async fn run_dev_server(
+    runtime_config: RuntimeConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let app_state = build_app_state(runtime_config).await?;
+    let static_dir = app_state.static_dir.clone();
+    let app = build_router(app_state);
+
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let app = app.layer(middleware::from_fn_with_state(
+        in_flight.clone(),
+        track_in_flight,
+    ));
+
+    // Start the server
+    let port = env::var(DEV_SERVER_PORT_ENV)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_DEV_SERVER_PORT);
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = TcpListener::bind(addr).await?;
+
+    info!("🚀 Local development server started!");
+    info!("📍 Listening on: http://{}", addr);
+    info!("🌐 API endpoints:");
+    info!("   • POST http://{}/api/create-url", addr);
+    info!(
+        "   • GET  http://{}/api/redirect/:short_code (also HEAD)",
+        addr
+    );
+    info!(
+        "   • GET  http://{}/api/stats/:short_code (also HEAD)",
+        addr
+    );
+    info!("   • GET  http://{}/api/preview/:short_code", addr);
+    info!("   • POST http://{}/api/expand", addr);
+    info!(
+        "   • POST http://{}/api/urls/:short_code/aliases (also GET)",
+        addr
+    );
+    info!("   • GET  http://{}/api/admin/count", addr);
+    info!("   • GET  http://{}/api/admin/urls?from=&to=", addr);
+    info!("   • POST http://{}/api/admin/import", addr);
+    info!("   • GET  http://{}/api/admin/export", addr);
+    info!("   • POST http://{}/api/admin/sweep", addr);
+    info!("   • POST http://{}/api/admin/reindex-dedup", addr);
+    info!("");
+    if let Some(dir) = &static_dir {
+        info!("🗂️  Serving {} at http://{}/", dir.display(), addr);
+    }
+    info!("💡 Update your web UI to use: http://{}/api/", addr);
+
+    serve_until(listener, app, in_flight, shutdown_signal()).await?;
+    info!("👋 Server stopped");
+    Ok(())
 }
 
-pub async fn run_dev_server() -> Result<(), Box<dyn std::error::Error>> {
+/// Connect to DynamoDB and read the remaining environment configuration
+async fn build_app_state(
+    runtime_config: RuntimeConfig,
+) -> Result<AppState, Box<dyn std::error::Error>> {
     // Initialize AWS config
     let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
 
-    let dynamodb_client = if let Ok(endpoint_url) = env::var("AWS_ENDPOINT_URL") {
-        info!("Using LocalStack endpoint: {}", endpoint_url);
-        let dynamodb_config = aws_sdk_dynamodb::config::Builder::from(&config)
-            .endpoint_url(endpoint_url)
-            .build();
-        DynamoDbClient::from_conf(dynamodb_config)
-    } else {
-        info!("Using AWS DynamoDB");
-        DynamoDbClient::new(&config)
-    };
+    let db_client = UrlDynamoDbClient::from_config(
+        &config,
+        runtime_config.aws_endpoint_url.as_deref(),
+        runtime_config.table_name.clone(),
+        ClientTuning::from_env(),
+    )
+    .with_dedup_policy(DedupPolicy::from_env())
+    .with_consistent_reads(strong_reads_from_env());
+    // LocalStack convenience; against AWS the table comes from Terraform
+    if auto_create_table_from_env() {
+        db_client.ensure_schema().await?;
+    }
+    check_table(&db_client).await?;
 
-    let table_name = env::var("DYNAMODB_TABLE_NAME").unwrap_or_else(|_| "squrl-urls".to_string());
-    info!("Using DynamoDB table: {}", table_name);
+    // Imports overwrite existing codes, so they stay off unless asked for
+    let admin_import_enabled = env::var("SQURL_ENABLE_ADMIN_IMPORT")
+        .map(|v| v.to_lowercase() == "true")
+        .unwrap_or(false);
+    if admin_import_enabled {
+        warn!("Admin import is enabled - POST /api/admin/import overwrites existing codes");
+    }
 
-    let db_client = UrlDynamoDbClient::new(dynamodb_client, table_name);
-    let app_state = AppState { db_client };
+    let code_alphabet = CodeAlphabet::from_env()?;
+    let stored_items = db_client
+        .approximate_item_count()
+        .await
+        .map_err(|e| warn!("Could not read table item count: {}", e))
+        .ok();
+    check_code_space(&code_alphabet, stored_items);
+    let code_generator = code_generator_from_env(code_alphabet)?;
 
+    let admin_auth = AdminAuth::from_env();
+    if admin_auth.api_keys.is_empty() {
+        warn!(
+            "No API_KEY_* variables set; requests to {:?} will be refused",
+            admin_auth.protected_prefixes
+        );
+    }
+
+    Ok(AppState {
+        db_client,
+        admin_import_enabled,
+        code_generator,
+        admin_auth: Arc::new(admin_auth),
+        runtime_config: Arc::new(runtime_config),
+        static_dir: static_dir_from_env(),
+    })
+}
+
+/// Refuse to start without the table, instead of failing every request.
+/// A table still being created only gets a warning.
+async fn check_table(db_client: &UrlDynamoDbClient) -> Result<(), Box<dyn std::error::Error>> {
+    let table = db_client.table_name();
+
+    match db_client.describe_table_status().await {
+        Ok(TableStatus::Active) => Ok(()),
+        Ok(TableStatus::Creating) => {
+            warn!(
+                "Table {} is still being created; requests may fail until it is active",
+                table
+            );
+            Ok(())
+        }
+        Ok(TableStatus::NotFound) => {
+            error!(
+                "Table {} not found - run scripts/setup-local-dynamodb.sh or set AUTO_CREATE_TABLE=true",
+                table
+            );
+            Err(format!("table {} not found", table).into())
+        }
+        Ok(TableStatus::Unavailable(status)) => {
+            error!("Table {} is {} and can't serve requests", table, status);
+            Err(format!("table {} is {}", table, status).into())
+        }
+        // Unreachable endpoint or missing credentials: report it, but let the
+        // server come up so the health check can say so too
+        Err(err) => {
+            warn!("Could not check table {}: {}", table, err);
+            Ok(())
+        }
+    }
+}
+
+fn build_router(app_state: AppState) -> Router {
     // Configure CORS to allow web UI to connect
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    // Build our application with routes
-    let app = Router::new()
-        .route("/api/create-url", post(create_url_handler))
-        .route("/api/redirect/:short_code", get(redirect_handler))
+    // Only the small JSON bodies of the public create routes are capped at
+    // MAX_BODY_BYTES; admin import keeps axum's larger default
+    let body_limit = DefaultBodyLimit::max(app_state.runtime_config.max_body_bytes);
+
+    // axum answers HEAD on a GET route with the GET response minus its body.
+    // Redirects count clicks, so their HEAD gets its own side-effect-free handler.
+    let router = Router::new()
+        .route(
+            "/api/create-url",
+            post(create_url_handler).layer(body_limit),
+        )
+        .route(
+            "/api/redirect/:short_code",
+            get(redirect_handler).head(redirect_head_handler),
+        )
         .route("/api/stats/:short_code", get(stats_handler))
-        .layer(ServiceBuilder::new().layer(cors))
-        .with_state(app_state);
+        .route(
+            "/api/urls/:short_code/aliases",
+            get(list_aliases_handler).merge(post(create_alias_handler).layer(body_limit)),
+        )
+        .route("/api/preview/:short_code", get(preview_handler))
+        .route("/api/expand", post(expand_handler).layer(body_limit))
+        .route("/api/health", get(health_handler))
+        .route("/api/admin/count", get(count_handler))
+        .route("/api/admin/urls", get(list_urls_handler))
+        .route("/api/admin/import", post(import_handler))
+        .route("/api/admin/export", get(export_handler))
+        .route("/api/admin/sweep", post(sweep_handler))
+        .route("/api/admin/reindex-dedup", post(reindex_dedup_handler));
 
-    // Start the server
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    let listener = TcpListener::bind(addr).await?;
+    // Static files only answer paths no route claims, so they can't shadow
+    // the API
+    let router = match &app_state.static_dir {
+        Some(dir) => router.fallback_service(ServeDir::new(dir)),
+        None => router,
+    };
 
-    info!("🚀 Local development server started!");
-    info!("📍 Listening on: http://{}", addr);
-    info!("🌐 API endpoints:");
-    info!("   • POST http://localhost:3000/api/create-url");
-    info!("   • GET  http://localhost:3000/api/redirect/:short_code");
-    info!("   • GET  http://localhost:3000/api/stats/:short_code");
-    info!("");
-    info!("💡 Update your web UI to use: http://localhost:3000/api/");
+    router
+        .layer(
+            ServiceBuilder::new()
+                // Default predicate skips tiny bodies (redirects) and images
+                .layer(CompressionLayer::new())
+                .layer(cors)
+                .layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    require_api_key,
+                )),
+        )
+        .with_state(app_state)
+}
 
-    axum::serve(listener, app).await?;
-    Ok(())
+/// Serve `app` until `shutdown` resolves, then stop accepting connections and
+/// let in-flight requests finish before returning.
+async fn serve_until(
+    listener: TcpListener,
+    app: Router,
+    in_flight: Arc<AtomicUsize>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            // Nothing is buffered in memory yet (click counts are written
+            // through), so draining requests is all the flushing there is
+            info!(
+                "Shutting down, draining {} in-flight requests",
+                in_flight.load(Ordering::SeqCst)
+            );
+        })
+        .await
+}
+
+/// Resolves on ctrl-c, or SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+async fn track_in_flight(
+    State(in_flight): State<Arc<AtomicUsize>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    in_flight.fetch_add(1, Ordering::SeqCst);
+    let response = next.run(request).await;
+    in_flight.fetch_sub(1, Ordering::SeqCst);
+    response
+}
+
+/// Reject requests to protected paths that lack a valid `X-Api-Key`
+async fn require_api_key(
+    State(app_state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let admin_auth = &app_state.admin_auth;
+    if !admin_auth.protects(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let provided = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    match check_api_key(provided, &admin_auth.api_keys) {
+        Ok(()) => next.run(request).await,
+        Err(err) => {
+            warn!(
+                "Rejected admin request to {}: {}",
+                request.uri().path(),
+                err
+            );
+            let status = error_status(&err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string()
+            });
+
+            (status, Json(error_body)).into_response()
+        }
+    }
+}
+
+/// Body extractor accepting either JSON or a browser form post
+/// (`application/x-www-form-urlencoded`), picked by `Content-Type`
+struct JsonOrForm<T>(T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for JsonOrForm<T>
+where
+    S: Send + Sync,
+    Json<T>: FromRequest<S, Rejection = axum::extract::rejection::JsonRejection>,
+    Form<T>: FromRequest<S, Rejection = axum::extract::rejection::FormRejection>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"));
+
+        if is_form {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        } else {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        }
+    }
 }
 
 async fn create_url_handler(
     State(app_state): State<AppState>,
-    Json(payload): Json<CreateUrlRequest>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    JsonOrForm(payload): JsonOrForm<CreateUrlRequest>,
 ) -> impl IntoResponse {
     info!("Received create-url request: {:?}", payload);
 
-    match create_url_impl(payload, &app_state.db_client).await {
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    match create_url_impl(
+        payload,
+        host,
+        idempotency_key,
+        &app_state.db_client,
+        app_state.code_generator.as_ref(),
+        &app_state.runtime_config,
+    )
+    .await
+    {
         Ok(response) => {
             info!("Create URL successful");
-            Json(response).into_response()
+            let prefer = headers.get("prefer").and_then(|v| v.to_str().ok());
+            if prefers_minimal(prefer, params.get("fields").map(String::as_str)) {
+                Json(MinimalCreateUrlResponse::from(response)).into_response()
+            } else {
+                Json(response).into_response()
+            }
         }
         Err(err) => {
             error!("Create URL failed: {}", err);
-            let status = match &err {
-                UrlShortenerError::ValidationError(_) => StatusCode::BAD_REQUEST,
-                UrlShortenerError::ShortCodeExists(_) => StatusCode::CONFLICT,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
+            let status = error_status(&err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string(),
+                "details": err.details()
+            });
+
+            (status, Json(error_body)).into_response()
+        }
+    }
+}
+
+async fn create_alias_handler(
+    State(app_state): State<AppState>,
+    Path(short_code): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateAliasRequest>,
+) -> impl IntoResponse {
+    info!("Received alias request for {}: {:?}", short_code, payload);
+
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+
+    match create_alias_impl(
+        short_code,
+        payload,
+        host,
+        &app_state.db_client,
+        &app_state.runtime_config,
+    )
+    .await
+    {
+        Ok(response) => Json(response).into_response(),
+        Err(err) => {
+            error!("Create alias failed: {}", err);
+            let status = error_status(&err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string(),
+                "details": err.details()
+            });
+
+            (status, Json(error_body)).into_response()
+        }
+    }
+}
+
+async fn list_aliases_handler(
+    State(app_state): State<AppState>,
+    Path(short_code): Path<String>,
+) -> impl IntoResponse {
+    info!("Received alias list request for: {}", short_code);
+
+    match list_aliases_impl(short_code, &app_state.db_client).await {
+        Ok(response) => Json(response).into_response(),
+        Err(err) => {
+            error!("List aliases failed: {}", err);
+            let status = error_status(&err);
 
             let error_body = json!({
                 "error": err.error_type(),
@@ -111,54 +560,155 @@ async fn create_url_handler(
 async fn redirect_handler(
     State(app_state): State<AppState>,
     Path(short_code): Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     info!("Received redirect request for: {}", short_code);
 
-    match redirect_impl(short_code.clone(), &app_state.db_client).await {
-        Ok(original_url) => {
+    let password = headers
+        .get(LINK_PASSWORD_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    let if_none_match = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok());
+
+    match redirect_impl(
+        short_code.clone(),
+        password,
+        if_none_match,
+        app_state.runtime_config.redirect_cache_seconds,
+        &app_state.db_client,
+    )
+    .await
+    {
+        Ok(RedirectOutcome::NotModified {
+            etag,
+            cache_control,
+        }) => {
+            info!("Redirect not modified: {}", short_code);
+            (
+                StatusCode::NOT_MODIFIED,
+                [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+            )
+                .into_response()
+        }
+        Ok(RedirectOutcome::Redirect {
+            original_url,
+            redirect_status,
+            etag,
+            cache_control,
+            extra_headers,
+        }) => {
             info!("Redirect successful to: {}", original_url);
             // Return the redirect URL as JSON for API testing
             // In a real redirect, this would be a 301/302 redirect
-            Json(json!({
-                "original_url": original_url,
-                "redirect_type": "301"
-            }))
-            .into_response()
+            let redirect_type = RedirectType::from_status(redirect_status);
+            let cache_headers = [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)];
+            let mut response = if app_state.runtime_config.interstitial {
+                (cache_headers, Html(interstitial_page(&original_url))).into_response()
+            } else {
+                (
+                    cache_headers,
+                    Json(json!({
+                        "original_url": original_url,
+                        "redirect_type": redirect_type,
+                        "status_code": redirect_type.status_code()
+                    })),
+                )
+                    .into_response()
+            };
+            for (name, value) in extra_headers.iter().flatten() {
+                if is_protected_header(name) {
+                    continue;
+                }
+                // Validated on create; anything unparseable is dropped
+                if let (Ok(name), Ok(value)) = (
+                    header::HeaderName::from_bytes(name.as_bytes()),
+                    header::HeaderValue::from_str(value),
+                ) {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+            if redirect_noindex() {
+                response.headers_mut().insert(
+                    header::HeaderName::from_static("x-robots-tag"),
+                    header::HeaderValue::from_static(REDIRECT_ROBOTS_TAG),
+                );
+            }
+            if app_state.runtime_config.redirect_shortlink {
+                let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+                let link = app_state
+                    .runtime_config
+                    .short_url_base_for(host)
+                    .and_then(|base| build_short_url(&base, &short_code));
+                match link {
+                    Ok(short_url) => {
+                        // Serialized URLs are always valid header text
+                        if let Ok(value) =
+                            header::HeaderValue::from_str(&shortlink_header(&short_url))
+                        {
+                            response.headers_mut().insert(header::LINK, value);
+                        }
+                    }
+                    Err(e) => warn!("Skipping shortlink header: {}", e),
+                }
+            }
+            response
         }
         Err(err) => {
             error!("Redirect failed: {}", err);
-            let status = match &err {
-                UrlShortenerError::ShortCodeNotFound(_) => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
+            let status = error_status(&err);
 
             let error_body = json!({
                 "error": err.error_type(),
-                "message": err.to_string()
+                "message": err.to_string(),
+                "details": err.details()
             });
 
-            (status, Json(error_body)).into_response()
+            (
+                status,
+                [(
+                    header::CACHE_CONTROL,
+                    app_state
+                        .runtime_config
+                        .cache_control_for(status.as_u16(), None),
+                )],
+                Json(error_body),
+            )
+                .into_response()
         }
     }
 }
 
+/// Same checks as a redirect (existence, expiry, password, use limit) without
+/// counting a click. axum drops the body.
+async fn redirect_head_handler(
+    state: State<AppState>,
+    path: Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    preview_handler(state, path, headers).await.into_response()
+}
+
 async fn stats_handler(
     State(app_state): State<AppState>,
     Path(short_code): Path<String>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     info!("Received stats request for: {}", short_code);
 
-    match stats_impl(short_code.clone(), &app_state.db_client).await {
+    let password = headers
+        .get(LINK_PASSWORD_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    match stats_impl(short_code.clone(), password, &app_state.db_client).await {
         Ok(response) => {
             info!("Stats request successful");
             Json(response).into_response()
         }
         Err(err) => {
             error!("Stats request failed: {}", err);
-            let status = match &err {
-                UrlShortenerError::ShortCodeNotFound(_) => StatusCode::NOT_FOUND,
-                _ => StatusCode::INTERNAL_SERVER_ERROR,
-            };
+            let status = error_status(&err);
 
             let error_body = json!({
                 "error": err.error_type(),
@@ -170,56 +720,331 @@ async fn stats_handler(
     }
 }
 
-// Implementation functions that mirror the Lambda handlers
+async fn preview_handler(
+    State(app_state): State<AppState>,
+    Path(short_code): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    info!("Received preview request for: {}", short_code);
 
-async fn create_url_impl(
-    request: CreateUrlRequest,
-    db_client: &UrlDynamoDbClient,
-) -> Result<CreateUrlResponse, UrlShortenerError> {
-    // Validate the request
-    request
-        .validate()
-        .map_err(|e| UrlShortenerError::ValidationError(e.to_string()))?;
+    let password = headers
+        .get(LINK_PASSWORD_HEADER)
+        .and_then(|v| v.to_str().ok());
 
-    let _validated_url = validate_url(&request.original_url)?;
+    match preview_impl(short_code.clone(), password, &app_state.db_client).await {
+        Ok(response) => {
+            info!("Preview request successful");
+            Json(response).into_response()
+        }
+        Err(err) => {
+            error!("Preview request failed: {}", err);
+            preview_error_response(err)
+        }
+    }
+}
+
+/// Preview by full short URL rather than by code. Dev server only: no
+/// Lambda serves this route.
+async fn expand_handler(
+    State(app_state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<ExpandRequest>,
+) -> Response {
+    info!("Received expand request for: {}", request.short_url);
+
+    let password = headers
+        .get(LINK_PASSWORD_HEADER)
+        .and_then(|v| v.to_str().ok());
+
+    let expanded = match app_state
+        .runtime_config
+        .short_code_from_url(&request.short_url)
+    {
+        Ok(short_code) => preview_impl(short_code, password, &app_state.db_client).await,
+        Err(err) => Err(err),
+    };
 
-    if let Some(custom_code) = &request.custom_code {
-        validate_custom_code(custom_code)?;
+    match expanded {
+        Ok(response) => {
+            info!("Expand request successful");
+            Json(response).into_response()
+        }
+        Err(err) => {
+            error!("Expand request failed: {}", err);
+            preview_error_response(err)
+        }
     }
+}
+
+/// The HTTP status for `err`, as the Lambdas would answer it
+fn error_status(err: &UrlShortenerError) -> StatusCode {
+    StatusCode::from_u16(err.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn preview_error_response(err: UrlShortenerError) -> Response {
+    let status = error_status(&err);
+
+    let error_body = json!({
+        "error": err.error_type(),
+        "message": err.to_string(),
+        "details": err.details()
+    });
+
+    (status, Json(error_body)).into_response()
+}
+
+async fn health_handler(State(app_state): State<AppState>) -> impl IntoResponse {
+    let table = app_state.db_client.table_name();
+
+    match app_state.db_client.describe_table_status().await {
+        Ok(TableStatus::Active) => Json(json!({ "status": "ok", "table": table })).into_response(),
+        Ok(status) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "status": "unavailable",
+                "table": table,
+                "table_status": format!("{:?}", status)
+            })),
+        )
+            .into_response(),
+        Err(err) => {
+            error!("Health check failed: {}", err);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({
+                    "status": "unavailable",
+                    "table": table,
+                    "error": err.error_type()
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn count_handler(State(app_state): State<AppState>) -> impl IntoResponse {
+    info!("Received admin count request");
+
+    // Full table scan - fine for local admin use, not for hot paths
+    match app_state.db_client.count_urls().await {
+        Ok(count) => Json(json!({ "count": count })).into_response(),
+        Err(err) => {
+            error!("Count request failed: {}", err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string()
+            });
+
+            (error_status(&err), Json(error_body)).into_response()
+        }
+    }
+}
 
-    // Check for existing URL
-    if let Some(existing_item) = db_client.find_existing_url(&request.original_url).await? {
-        let base_url = env::var("SHORT_URL_BASE").unwrap_or_else(|_| "https://sqrl.co".to_string());
-        let short_url = format!("{}/{}", base_url, existing_item.short_code);
-        let expires_at = existing_item.expires_at.map(|ts| {
-            DateTime::from_timestamp(ts, 0)
-                .unwrap_or_else(Utc::now)
-                .to_rfc3339()
+#[derive(Debug, Deserialize)]
+struct ListUrlsParams {
+    from: String,
+    to: String,
+    #[serde(default = "default_list_limit")]
+    limit: i32,
+    cursor: Option<String>,
+}
+
+fn default_list_limit() -> i32 {
+    100
+}
+
+async fn list_urls_handler(
+    State(app_state): State<AppState>,
+    Query(params): Query<ListUrlsParams>,
+) -> impl IntoResponse {
+    info!("Received admin list request: {:?}", params);
+
+    match app_state
+        .db_client
+        .list_urls_created_between(&params.from, &params.to, params.limit, params.cursor)
+        .await
+    {
+        Ok(page) => Json(page).into_response(),
+        Err(err) => {
+            error!("List request failed: {}", err);
+            let status = error_status(&err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string()
+            });
+
+            (status, Json(error_body)).into_response()
+        }
+    }
+}
+
+async fn import_handler(
+    State(app_state): State<AppState>,
+    Json(items): Json<Vec<UrlItem>>,
+) -> impl IntoResponse {
+    info!("Received admin import request for {} items", items.len());
+
+    if !app_state.admin_import_enabled {
+        let error_body = json!({
+            "error": "Forbidden",
+            "message": "Admin import is disabled; set SQURL_ENABLE_ADMIN_IMPORT=true to enable it"
         });
+        return (StatusCode::FORBIDDEN, Json(error_body)).into_response();
+    }
+
+    let validation_config = app_state.runtime_config.validation_config();
+    let mut imported = 0;
+    let mut rejected = Vec::new();
+    for item in &items {
+        // Imported items skip the create path, so they get its checks here
+        if let Err(err) = validate_import_item(item, &validation_config) {
+            warn!("Import rejected {}: {}", item.short_code, err);
+            rejected.push(json!({
+                "short_code": item.short_code,
+                "error": err.error_type(),
+                "message": err.to_string()
+            }));
+            continue;
+        }
+
+        if let Err(err) = app_state.db_client.put_url_overwrite(item).await {
+            error!("Import failed at {}: {}", item.short_code, err);
 
-        return Ok(CreateUrlResponse {
-            short_code: existing_item.short_code,
-            original_url: existing_item.original_url,
-            short_url,
-            created_at: existing_item.created_at,
-            expires_at,
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string(),
+                "imported": imported,
+                "rejected": rejected
+            });
+
+            return (error_status(&err), Json(error_body)).into_response();
+        }
+        imported += 1;
+    }
+
+    Json(json!({ "imported": imported, "rejected": rejected })).into_response()
+}
+
+/// The checks a create would have made on `item`'s code, target and headers
+fn validate_import_item(
+    item: &UrlItem,
+    config: &ValidationConfig,
+) -> Result<(), UrlShortenerError> {
+    validate_custom_code(&item.short_code)?;
+    validate_url_with(
+        &item.original_url,
+        config.require_tld,
+        config.max_host_labels,
+    )?;
+    if let Some(extra_headers) = &item.extra_headers {
+        validate_extra_headers(extra_headers)?;
+    }
+    Ok(())
+}
+
+async fn sweep_handler(State(app_state): State<AppState>) -> impl IntoResponse {
+    info!("Received admin sweep request");
+
+    match app_state.db_client.sweep_expired(25).await {
+        Ok(purged) => Json(json!({ "purged": purged })).into_response(),
+        Err(err) => {
+            error!("Sweep request failed: {}", err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string()
+            });
+
+            (error_status(&err), Json(error_body)).into_response()
+        }
+    }
+}
+
+async fn reindex_dedup_handler(State(app_state): State<AppState>) -> impl IntoResponse {
+    info!("Received admin dedup reindex request");
+
+    match app_state.db_client.reindex_dedup(100).await {
+        Ok(report) => Json(json!({
+            "scanned": report.scanned,
+            "restored": report.restored
+        }))
+        .into_response(),
+        Err(err) => {
+            error!("Dedup reindex failed: {}", err);
+
+            let error_body = json!({
+                "error": err.error_type(),
+                "message": err.to_string()
+            });
+
+            (error_status(&err), Json(error_body)).into_response()
+        }
+    }
+}
+
+async fn export_handler(State(app_state): State<AppState>) -> impl IntoResponse {
+    info!("Received admin export request");
+
+    // One UrlItem per line (the item shape /api/admin/import takes). A scan
+    // error mid-stream aborts the body, so truncated exports are detectable.
+    let lines = app_state
+        .db_client
+        .scan_stream()
+        .and_then(|item| async move {
+            let mut line = serde_json::to_string(&item)?;
+            line.push('\n');
+            Ok(line)
         });
+
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+}
+
+// Implementation functions that mirror the Lambda handlers
+
+async fn create_url_impl(
+    request: CreateUrlRequest,
+    host: Option<&str>,
+    idempotency_key: Option<&str>,
+    db_client: &UrlDynamoDbClient,
+    code_generator: &dyn CodeGenerator,
+    runtime_config: &RuntimeConfig,
+) -> Result<CreateUrlResponse, UrlShortenerError> {
+    let short_url_base = runtime_config.short_url_base_for(host)?;
+
+    let NormalizedCreate {
+        url: validated_url,
+        ttl,
+        ttl_source,
+        redirect_status,
+    } = validate_create_request(&request, &runtime_config.validation_config())?;
+
+    // A retry of a create that already went through gets the same link back
+    let idempotency_key = IdempotencyKey::from_header(idempotency_key, &request)?;
+    if let Some(idempotency_key) = &idempotency_key {
+        if let Some(prior) = db_client.idempotent_result(idempotency_key).await? {
+            return create_url_response(prior, &short_url_base);
+        }
     }
 
     // Generate short code
     let short_code = if let Some(ref custom_code) = request.custom_code {
         custom_code.clone()
     } else {
-        generate_short_code()
+        code_generator.generate()?
     };
 
     // Calculate expiration
     let now = Utc::now();
-    let expires_at = request
-        .ttl_hours
-        .map(|hours| (now + chrono::Duration::hours(hours as i64)).timestamp());
+    let expires_at = ttl.map(|ttl| (now + ttl).timestamp());
+
+    let password_hash = request.password.as_deref().map(hash_password).transpose()?;
 
-    let url_item = UrlItem {
+    let mut url_item = UrlItem {
         short_code: short_code.clone(),
         original_url: request.original_url.clone(),
         created_at: now.to_rfc3339(),
@@ -227,49 +1052,202 @@ async fn create_url_impl(
         click_count: 0,
         custom_code: request.custom_code.is_some(),
         status: "active".to_string(),
+        password_hash,
+        max_uses: request.max_uses,
+        redirect_status,
+        canonical_code: None,
+        wildcard: request.wildcard,
+        creator_ip: None,
+        unique_visitors: None,
+        extra_headers: request.extra_headers.clone(),
+        ttl_source: Some(ttl_source),
     };
 
-    // Store in DynamoDB
-    db_client.put_url(&url_item).await?;
+    // A generated code may already be taken; draw a fresh one and try again
+    let mut retries_left = runtime_config.code_collision_retries;
+    let stored = loop {
+        // Store in DynamoDB
+        // Dedup-eligible links reuse a live link for the URL, or claim it so
+        // concurrent creates of one URL can't mint two codes. Links with
+        // access rules (password, use limit) are never shared through dedup,
+        // in either direction. A create with an idempotency key reserves the
+        // key instead, and isn't deduplicated.
+        let stored = if let Some(idempotency_key) = &idempotency_key {
+            db_client
+                .create_with_idempotency(&url_item, idempotency_key)
+                .await
+        } else if request.allows_dedup() {
+            db_client
+                .get_or_create(validated_url.as_str(), url_item.clone())
+                .await
+                .map(CreateOutcome::from_get_or_create)
+        } else {
+            db_client
+                .put_url(&url_item)
+                .await
+                .map(|_| CreateOutcome::Created)
+        };
 
-    // Create response
-    let base_url = env::var("SHORT_URL_BASE").unwrap_or_else(|_| "https://sqrl.co".to_string());
-    let short_url = format!("{}/{}", base_url, url_item.short_code);
-    let expires_at = url_item.expires_at.map(|ts| {
-        DateTime::from_timestamp(ts, 0)
-            .unwrap_or_else(Utc::now)
-            .to_rfc3339()
-    });
+        match stored {
+            Err(e) if retries_left > 0 && e.is_collision_retryable(url_item.custom_code) => {
+                warn!(
+                    "Generated code {} is taken, drawing another",
+                    url_item.short_code
+                );
+                retries_left -= 1;
+                url_item.short_code = code_generator.generate()?;
+            }
+            stored => break stored,
+        }
+    };
 
+    match stored {
+        Ok(CreateOutcome::Created) => create_url_response(url_item, &short_url_base),
+        // Lost a race with a concurrent create of the same URL
+        Ok(CreateOutcome::Existing(existing_item)) => {
+            create_url_response(*existing_item, &short_url_base)
+        }
+        Err(UrlShortenerError::ShortCodeExists(code)) if url_item.custom_code => {
+            let suggestions = db_client
+                .suggest_available_codes(&code, 3)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to look up custom code suggestions: {}", e);
+                    Vec::new()
+                });
+            Err(UrlShortenerError::CustomCodeTaken { code, suggestions })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn create_url_response(
+    url_item: UrlItem,
+    short_url_base: &str,
+) -> Result<CreateUrlResponse, UrlShortenerError> {
+    let short_url = build_short_url(short_url_base, &url_item.short_code)?;
+    let expires_at = expires_at_rfc3339(url_item.expires_at);
+
+    let ttl_source = url_item.ttl_source();
     Ok(CreateUrlResponse {
         short_code: url_item.short_code,
         original_url: url_item.original_url,
         short_url,
         created_at: url_item.created_at,
         expires_at,
+        ttl_source,
     })
 }
 
+async fn create_alias_impl(
+    short_code: String,
+    request: CreateAliasRequest,
+    host: Option<&str>,
+    db_client: &UrlDynamoDbClient,
+    runtime_config: &RuntimeConfig,
+) -> Result<CreateUrlResponse, UrlShortenerError> {
+    request
+        .validate()
+        .map_err(|e| UrlShortenerError::ValidationError(e.to_string()))?;
+    validate_custom_code(&request.alias)?;
+
+    let short_url_base = runtime_config.short_url_base_for(host)?;
+
+    let canonical = db_client
+        .get_url(&short_code)
+        .await?
+        .ok_or_else(|| UrlShortenerError::ShortCodeNotFound(short_code.clone()))?;
+
+    let alias = db_client.create_alias(&canonical, &request.alias).await?;
+
+    create_url_response(alias, &short_url_base)
+}
+
+async fn list_aliases_impl(
+    short_code: String,
+    db_client: &UrlDynamoDbClient,
+) -> Result<AliasListResponse, UrlShortenerError> {
+    let item = db_client
+        .get_url(&short_code)
+        .await?
+        .ok_or_else(|| UrlShortenerError::ShortCodeNotFound(short_code.clone()))?;
+
+    // Asking about an alias lists its siblings under the canonical code
+    let canonical_code = item.canonical_code.unwrap_or(item.short_code);
+    let aliases = db_client.list_aliases(&canonical_code).await?;
+
+    Ok(AliasListResponse {
+        canonical_code,
+        aliases,
+    })
+}
+
+enum RedirectOutcome {
+    Redirect {
+        original_url: String,
+        redirect_status: u16,
+        etag: String,
+        cache_control: String,
+        extra_headers: Option<HashMap<String, String>>,
+    },
+    /// `If-None-Match` still matches; no click is counted
+    NotModified { etag: String, cache_control: String },
+}
+
 async fn redirect_impl(
     short_code: String,
+    password: Option<&str>,
+    if_none_match: Option<&str>,
+    redirect_cache_seconds: u64,
     db_client: &UrlDynamoDbClient,
-) -> Result<String, UrlShortenerError> {
-    // Look up the URL
+) -> Result<RedirectOutcome, UrlShortenerError> {
+    // Look up only what the redirect needs
     let url_item = db_client
-        .get_url(&short_code)
+        .get_redirect_target(&short_code)
         .await?
         .ok_or_else(|| UrlShortenerError::ShortCodeNotFound(short_code.clone()))?;
 
-    // Increment click count asynchronously
-    if let Err(e) = db_client.increment_click_count(&short_code).await {
+    verify_link_password(url_item.password_hash.as_deref(), password)?;
+
+    // Checked before the click is counted, so an unusable target costs nothing
+    let original_url = sanitize_location(&url_item.original_url)?;
+
+    let cache_control =
+        url_item.redirect_cache_control(Utc::now().timestamp(), redirect_cache_seconds);
+
+    if url_item.not_modified(if_none_match) {
+        return Ok(RedirectOutcome::NotModified {
+            etag: url_item.etag(),
+            cache_control,
+        });
+    }
+
+    // An alias counts against its canonical link
+    let counter_code = url_item.canonical_code.as_deref().unwrap_or(&short_code);
+    if let Some(max_uses) = url_item.max_uses {
+        // Limited links only redirect once the use has been recorded
+        db_client
+            .increment_click_count_limited(counter_code, max_uses)
+            .await?;
+    } else if let Err(e) = db_client.increment_click_count(counter_code).await {
+        // Increment click count asynchronously
         warn!("Failed to increment click count: {}", e);
     }
 
-    Ok(url_item.original_url)
+    let redirect_status = url_item.redirect_status_or(default_redirect_status());
+
+    Ok(RedirectOutcome::Redirect {
+        original_url,
+        redirect_status,
+        etag: url_item.etag(),
+        cache_control,
+        extra_headers: url_item.extra_headers,
+    })
 }
 
 async fn stats_impl(
     short_code: String,
+    password: Option<&str>,
     db_client: &UrlDynamoDbClient,
 ) -> Result<Value, UrlShortenerError> {
     // Get the URL item from DynamoDB
@@ -278,17 +1256,812 @@ async fn stats_impl(
         .await?
         .ok_or_else(|| UrlShortenerError::ShortCodeNotFound(short_code.clone()))?;
 
-    Ok(json!({
-        "short_code": url_item.short_code,
-        "original_url": url_item.original_url,
-        "click_count": url_item.click_count,
-        "created_at": url_item.created_at,
-        "expires_at": url_item.expires_at
-    }))
+    // Stats carry the destination, so they need the password too
+    verify_link_password(url_item.password_hash.as_deref(), password)?;
+
+    Ok(serde_json::to_value(StatsResponse::from(url_item))?)
+}
+
+async fn preview_impl(
+    short_code: String,
+    password: Option<&str>,
+    db_client: &UrlDynamoDbClient,
+) -> Result<PreviewResponse, UrlShortenerError> {
+    let url_item = db_client
+        .get_url(&short_code)
+        .await?
+        .ok_or_else(|| UrlShortenerError::ShortCodeNotFound(short_code.clone()))?;
+
+    // Same gates as a redirect, but the click count is left alone
+    verify_link_password(url_item.password_hash.as_deref(), password)?;
+    if url_item.is_exhausted() {
+        return Err(UrlShortenerError::UrlExhausted);
+    }
+
+    Ok(PreviewResponse::from(url_item))
 }
 
-fn generate_short_code() -> String {
-    // Use nanoid for collision-resistant ID generation
-    let id = nanoid!(8, &nanoid::alphabet::SAFE);
-    id
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::operation::get_item::GetItemOutput;
+    use aws_sdk_dynamodb::operation::put_item::PutItemOutput;
+    use aws_sdk_dynamodb::operation::query::QueryOutput;
+    use aws_sdk_dynamodb::operation::scan::ScanOutput;
+    use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsOutput;
+    use aws_sdk_dynamodb::operation::update_item::UpdateItemOutput;
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use aws_sdk_dynamodb::Client as DynamoDbClient;
+    use aws_smithy_http_client::test_util::NeverClient;
+    use aws_smithy_mocks::{mock, mock_client, RuleMode};
+    use nanoid::nanoid;
+    use squrl_shared::config::DEFAULT_MAX_BODY_BYTES;
+    use squrl_shared::models::redirect_etag;
+    use squrl_shared::short_code::NanoidGenerator;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+    use tower::ServiceExt;
+
+    /// POSTs `body` to the real create route over mocked DynamoDB; returns the
+    /// status, the response body and the link that was stored, if any
+    async fn post_create(
+        content_type: &str,
+        body: &'static str,
+    ) -> (StatusCode, Value, Option<HashMap<String, AttributeValue>>) {
+        let stored: Arc<Mutex<Option<HashMap<String, AttributeValue>>>> = Arc::default();
+        let transact_rule = mock!(DynamoDbClient::transact_write_items)
+            .match_requests({
+                let stored = stored.clone();
+                move |req| {
+                    *stored.lock().unwrap() =
+                        req.transact_items()[0].put().map(|put| put.item().clone());
+                    true
+                }
+            })
+            .then_output(|| TransactWriteItemsOutput::builder().build());
+        let query_rule =
+            mock!(DynamoDbClient::query).then_output(|| QueryOutput::builder().build());
+        let app = build_router(AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(
+                    aws_sdk_dynamodb,
+                    RuleMode::MatchAny,
+                    [&transact_rule, &query_rule]
+                ),
+                "test-table".to_string(),
+            ),
+            ..mock_state()
+        });
+        let request = Request::post("/api/create-url")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        let stored = stored.lock().unwrap().take();
+        (status, body, stored)
+    }
+
+    #[tokio::test]
+    async fn test_create_accepts_json_and_form_bodies() {
+        let (json_status, json_body, json_item) = post_create(
+            "application/json",
+            r#"{"original_url":"https://example.com/a b","custom_code":"promo","ttl_hours":24}"#,
+        )
+        .await;
+        let (form_status, form_body, form_item) = post_create(
+            "application/x-www-form-urlencoded",
+            "original_url=https%3A%2F%2Fexample.com%2Fa+b&custom_code=promo&ttl_hours=24",
+        )
+        .await;
+
+        assert_eq!(json_status, StatusCode::OK);
+        assert_eq!(form_status, StatusCode::OK);
+        for field in ["short_code", "original_url", "short_url", "ttl_source"] {
+            assert_eq!(json_body[field], form_body[field], "{} differs", field);
+        }
+        assert_eq!(form_body["short_code"], "promo");
+        assert!(!json_body["expires_at"].is_null() && !form_body["expires_at"].is_null());
+
+        // Both bodies store the same link
+        let (json_item, form_item) = (json_item.unwrap(), form_item.unwrap());
+        for attribute in ["short_code", "original_url", "custom_code", "ttl_source"] {
+            assert_eq!(json_item.get(attribute), form_item.get(attribute));
+        }
+        assert_eq!(
+            form_item.get("original_url"),
+            Some(&AttributeValue::S("https://example.com/a b".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_rejects_unsupported_content_type() {
+        let (status, _, stored) =
+            post_create("text/plain", "original_url=https://example.com").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(stored.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_stops_accepting_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/ping", get(|| async { "pong" }));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let server = tokio::spawn(serve_until(
+            listener,
+            app,
+            Arc::new(AtomicUsize::new(0)),
+            async move {
+                let _ = shutdown_rx.await;
+            },
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with("pong"));
+
+        shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap()
+            .unwrap();
+
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    /// One request over a fresh connection; returns the status and body.
+    /// HTTP/1.0 so the response body comes back unchunked.
+    async fn send(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap_or(Value::Null))
+    }
+
+    /// Serves `app` on an ephemeral port, so it can run beside a dev server
+    /// on 3000, and runs create → redirect → stats against it
+    async fn smoke_create_redirect_stats(app: Router) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_until(
+            listener,
+            app,
+            Arc::new(AtomicUsize::new(0)),
+            async move {
+                let _ = shutdown_rx.await;
+            },
+        ));
+
+        let target = format!("https://example.com/smoke/{}", nanoid!(8));
+        let (status, created) = send(
+            addr,
+            "POST",
+            "/api/create-url",
+            &json!({ "original_url": target }).to_string(),
+        )
+        .await;
+        assert_eq!(status, 200, "create failed: {}", created);
+        let short_code = created["short_code"].as_str().unwrap();
+
+        let (status, redirect) =
+            send(addr, "GET", &format!("/api/redirect/{}", short_code), "").await;
+        assert_eq!(status, 200);
+        assert_eq!(redirect["original_url"], target.as_str());
+
+        let (status, stats) = send(addr, "GET", &format!("/api/stats/{}", short_code), "").await;
+        assert_eq!(status, 200);
+        assert_eq!(stats["click_count"], 1);
+
+        shutdown_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    /// The real server against LocalStack. `AWS_ENDPOINT_URL` and
+    /// `DYNAMODB_TABLE_NAME` pick the LocalStack instance and table.
+    #[tokio::test]
+    #[ignore = "needs LocalStack; run with --ignored"]
+    async fn test_localstack_create_redirect_stats() {
+        let mut runtime_config = RuntimeConfig::from_env().unwrap();
+        runtime_config
+            .aws_endpoint_url
+            .get_or_insert_with(|| "http://localhost:4566".to_string());
+        smoke_create_redirect_stats(build_router(build_app_state(runtime_config).await.unwrap()))
+            .await;
+    }
+
+    /// The same flow with DynamoDB mocked by a one-table in-memory store, so
+    /// it runs without LocalStack
+    #[tokio::test]
+    async fn test_mocked_create_redirect_stats() {
+        type Item = HashMap<String, AttributeValue>;
+        let key_of = |key: Option<&Item>| {
+            key.and_then(|key| key.get("short_code"))
+                .and_then(|code| code.as_s().ok())
+                .cloned()
+                .unwrap_or_default()
+        };
+        let table: Arc<Mutex<HashMap<String, Item>>> = Arc::default();
+        let requested = Arc::new(Mutex::new(String::new()));
+
+        // Creates go through the dedup transaction: the link and its marker
+        let transact_rule = mock!(DynamoDbClient::transact_write_items)
+            .match_requests({
+                let table = table.clone();
+                move |req| {
+                    for put in req.transact_items().iter().filter_map(|item| item.put()) {
+                        table
+                            .lock()
+                            .unwrap()
+                            .insert(key_of(Some(put.item())), put.item().clone());
+                    }
+                    true
+                }
+            })
+            .sequence()
+            .output(|| TransactWriteItemsOutput::builder().build())
+            .repeatedly()
+            .build();
+        let get_rule = mock!(DynamoDbClient::get_item)
+            .match_requests({
+                let requested = requested.clone();
+                move |req| {
+                    *requested.lock().unwrap() = key_of(req.key());
+                    true
+                }
+            })
+            .sequence()
+            .output({
+                let table = table.clone();
+                move || {
+                    let code = requested.lock().unwrap().clone();
+                    GetItemOutput::builder()
+                        .set_item(table.lock().unwrap().get(&code).cloned())
+                        .build()
+                }
+            })
+            .repeatedly()
+            .build();
+        let update_rule = mock!(DynamoDbClient::update_item)
+            .match_requests({
+                let table = table.clone();
+                move |req| {
+                    if req.update_expression() == Some("ADD click_count :inc") {
+                        if let Some(item) = table.lock().unwrap().get_mut(&key_of(req.key())) {
+                            let clicks = item
+                                .get("click_count")
+                                .and_then(|v| v.as_n().ok())
+                                .and_then(|n| n.parse::<u64>().ok())
+                                .unwrap_or(0);
+                            item.insert(
+                                "click_count".to_string(),
+                                AttributeValue::N((clicks + 1).to_string()),
+                            );
+                        }
+                    }
+                    true
+                }
+            })
+            .sequence()
+            .output(|| UpdateItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let query_rule = mock!(DynamoDbClient::query)
+            .sequence()
+            .output(|| QueryOutput::builder().build())
+            .repeatedly()
+            .build();
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&transact_rule, &get_rule, &update_rule, &query_rule]
+        );
+
+        smoke_create_redirect_stats(build_router(AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            ..mock_state()
+        }))
+        .await;
+        assert_eq!(table.lock().unwrap().len(), 2);
+    }
+
+    fn stored_item() -> HashMap<String, AttributeValue> {
+        HashMap::from([
+            (
+                "short_code".to_string(),
+                AttributeValue::S("abc123".to_string()),
+            ),
+            (
+                "original_url".to_string(),
+                AttributeValue::S("https://example.com".to_string()),
+            ),
+            (
+                "created_at".to_string(),
+                AttributeValue::S("2024-01-01T00:00:00+00:00".to_string()),
+            ),
+            (
+                "click_count".to_string(),
+                AttributeValue::N("0".to_string()),
+            ),
+        ])
+    }
+
+    fn mock_app() -> Router {
+        build_router(mock_state())
+    }
+
+    fn mock_state() -> AppState {
+        let get_rule = mock!(DynamoDbClient::get_item)
+            .sequence()
+            .output(|| {
+                GetItemOutput::builder()
+                    .set_item(Some(stored_item()))
+                    .build()
+            })
+            .output(|| GetItemOutput::builder().build())
+            .build();
+        let scan_rule = mock!(DynamoDbClient::scan)
+            .sequence()
+            .output(|| ScanOutput::builder().count(7).items(stored_item()).build())
+            .repeatedly()
+            .build();
+        let update_rule = mock!(DynamoDbClient::update_item)
+            .sequence()
+            .output(|| UpdateItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let client = mock_client!(
+            aws_sdk_dynamodb,
+            RuleMode::MatchAny,
+            [&get_rule, &scan_rule, &update_rule]
+        );
+
+        AppState {
+            db_client: UrlDynamoDbClient::new(client, "test-table".to_string()),
+            admin_import_enabled: false,
+            code_generator: Arc::new(NanoidGenerator::default()),
+            admin_auth: Arc::new(AdminAuth::new(
+                HashMap::from([("ops".to_string(), "s3cret".to_string())]),
+                DEFAULT_ADMIN_PROTECTED_PREFIXES,
+            )),
+            runtime_config: Arc::new(RuntimeConfig::from_lookup(|_| None).unwrap()),
+            static_dir: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_files_served_without_shadowing_api() {
+        let dir = env::temp_dir().join(format!("squrl-static-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("api/stats")).unwrap();
+        std::fs::write(dir.join("index.html"), "<h1>squrl</h1>").unwrap();
+        std::fs::write(dir.join("api/stats/abc123"), "shadowed").unwrap();
+        let app = build_router(AppState {
+            static_dir: Some(dir.clone()),
+            ..mock_state()
+        });
+
+        let (status, body) = body_text(&app, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "<h1>squrl</h1>");
+
+        // A file at an API path never wins over the handler
+        let (status, body) = body_text(&app, "/api/stats/abc123").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"short_code\":\"abc123\""));
+
+        let (status, _) = body_text(&app, "/missing.css").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn body_text(app: &Router, uri: &str) -> (StatusCode, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    async fn admin_count(api_key: Option<&str>) -> StatusCode {
+        let mut request = Request::get("/api/admin/count");
+        if let Some(api_key) = api_key {
+            request = request.header(API_KEY_HEADER, api_key);
+        }
+
+        let response = mock_app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.status()
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes_require_api_key() {
+        assert_eq!(admin_count(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(admin_count(Some("guess")).await, StatusCode::FORBIDDEN);
+        assert_eq!(admin_count(Some("s3cret")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_alias_routes_require_api_key() {
+        let alias_request = |api_key: Option<&str>| {
+            let mut request = Request::post("/api/urls/abc123/aliases")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(key) = api_key {
+                request = request.header(API_KEY_HEADER, key);
+            }
+            request.body(Body::from(r#"{"alias": "summer"}"#)).unwrap()
+        };
+
+        let response = mock_app().oneshot(alias_request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = mock_app()
+            .oneshot(alias_request(Some("guess")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let request = Request::get("/api/urls/abc123/aliases")
+            .body(Body::empty())
+            .unwrap();
+        let response = mock_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    async fn conditional_redirect(if_none_match: &str) -> (StatusCode, Option<String>) {
+        let request = Request::get("/api/redirect/abc123")
+            .header(header::IF_NONE_MATCH, if_none_match)
+            .body(Body::empty())
+            .unwrap();
+        let response = mock_app().oneshot(request).await.unwrap();
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .map(|v| v.to_str().unwrap().to_string());
+        (response.status(), etag)
+    }
+
+    #[tokio::test]
+    async fn test_redirect_carries_shortlink() {
+        let request = Request::get("/api/redirect/abc123")
+            .body(Body::empty())
+            .unwrap();
+        let response = mock_app().oneshot(request).await.unwrap();
+        let base = RuntimeConfig::from_lookup(|_| None).unwrap().short_url_base;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::LINK],
+            format!("<{}/abc123>; rel=\"shortlink\"", base).as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect_cache_control_by_status() {
+        // The mock finds the link once, then nothing
+        let app = mock_app();
+        let cache_control = |response: Response| {
+            (
+                response.status(),
+                response.headers()[header::CACHE_CONTROL].clone(),
+            )
+        };
+
+        let request = Request::get("/api/redirect/abc123")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            cache_control(app.clone().oneshot(request).await.unwrap()),
+            (StatusCode::OK, "public, max-age=3600".parse().unwrap())
+        );
+
+        let request = Request::get("/api/redirect/abc123")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            cache_control(app.oneshot(request).await.unwrap()),
+            (StatusCode::NOT_FOUND, "public, max-age=10".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect_honors_if_none_match() {
+        let etag = redirect_etag("https://example.com", None);
+
+        assert_eq!(
+            conditional_redirect(&etag).await,
+            (StatusCode::NOT_MODIFIED, Some(etag.clone()))
+        );
+        assert_eq!(
+            conditional_redirect("W/\"stale\"").await,
+            (StatusCode::OK, Some(etag))
+        );
+    }
+
+    async fn expand(short_url: &str) -> (StatusCode, Value) {
+        let request = Request::post("/api/expand")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "short_url": short_url }).to_string()))
+            .unwrap();
+        let response = mock_app().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_expand_short_url() {
+        let base = RuntimeConfig::from_lookup(|_| None).unwrap().short_url_base;
+
+        let (status, body) = expand(&format!("{}/abc123", base)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["original_url"], "https://example.com");
+        assert_eq!(body["short_code"], "abc123");
+
+        let (status, body) = expand("https://elsewhere.example/abc123").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "InvalidUrl");
+
+        let (status, _) = expand("not a url").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    async fn head(app: Router, uri: &str) -> (StatusCode, usize) {
+        let request = Request::head(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, bytes.len())
+    }
+
+    #[tokio::test]
+    async fn test_protected_stats_need_the_password() {
+        let get_rule = mock!(DynamoDbClient::get_item)
+            .sequence()
+            .output(|| {
+                let mut item = stored_item();
+                item.insert(
+                    "password_hash".to_string(),
+                    AttributeValue::S(hash_password("s3cret").unwrap()),
+                );
+                GetItemOutput::builder().set_item(Some(item)).build()
+            })
+            .repeatedly()
+            .build();
+        let app = build_router(AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, [&get_rule]),
+                "test-table".to_string(),
+            ),
+            ..mock_state()
+        });
+
+        let (status, body) = body_text(&app, "/api/stats/abc123").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(!body.contains("example.com"));
+
+        let request = Request::get("/api/stats/abc123")
+            .header(LINK_PASSWORD_HEADER, "s3cret")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_admin_timeouts_are_503() {
+        // A connection that never answers, cut off by the operation timeout
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(aws_sdk_dynamodb::config::Region::new("us-east-1"))
+            .credentials_provider(aws_sdk_dynamodb::config::Credentials::new(
+                "test", "test", None, None, "test",
+            ))
+            .http_client(NeverClient::new())
+            .timeout_config(
+                aws_sdk_dynamodb::config::timeout::TimeoutConfig::builder()
+                    .operation_timeout(std::time::Duration::from_millis(50))
+                    .build(),
+            )
+            .build();
+        let app = build_router(AppState {
+            db_client: UrlDynamoDbClient::new(
+                DynamoDbClient::from_conf(config),
+                "test-table".to_string(),
+            ),
+            ..mock_state()
+        });
+
+        for (method, uri) in [
+            ("GET", "/api/admin/count"),
+            ("POST", "/api/admin/sweep"),
+            ("POST", "/api/admin/reindex-dedup"),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(API_KEY_HEADER, "s3cret")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_head_on_redirect_and_stats() {
+        for path in ["/api/redirect", "/api/stats"] {
+            let app = mock_app();
+            assert_eq!(
+                head(app.clone(), &format!("{}/abc123", path)).await,
+                (StatusCode::OK, 0)
+            );
+            assert_eq!(
+                head(app, &format!("{}/missing", path)).await,
+                (StatusCode::NOT_FOUND, 0)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected() {
+        let body = format!(
+            r#"{{"original_url":"https://example.com/{}"}}"#,
+            "a".repeat(DEFAULT_MAX_BODY_BYTES)
+        );
+        let request = Request::post("/api/create-url")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = mock_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_import_is_not_held_to_the_create_body_limit() {
+        let item = json!({
+            "short_code": "abc123",
+            "original_url": format!("https://example.com/{}", "a".repeat(DEFAULT_MAX_BODY_BYTES)),
+            "created_at": "2024-01-01T00:00:00Z",
+            "click_count": 0,
+            "custom_code": false,
+            "status": "active"
+        });
+        let request = Request::post("/api/admin/import")
+            .header(API_KEY_HEADER, "s3cret")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!([item]).to_string()))
+            .unwrap();
+
+        // Import is off in the mock state, so reaching the handler means 403
+        let response = mock_app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_invalid_items() {
+        let put_rule = mock!(DynamoDbClient::put_item)
+            .sequence()
+            .output(|| PutItemOutput::builder().build())
+            .repeatedly()
+            .build();
+        let app = build_router(AppState {
+            db_client: UrlDynamoDbClient::new(
+                mock_client!(aws_sdk_dynamodb, RuleMode::MatchAny, [&put_rule]),
+                "test-table".to_string(),
+            ),
+            admin_import_enabled: true,
+            ..mock_state()
+        });
+        let item = |short_code: &str, original_url: &str, extra_headers: Value| {
+            json!({
+                "short_code": short_code,
+                "original_url": original_url,
+                "created_at": "2024-01-01T00:00:00Z",
+                "click_count": 0,
+                "custom_code": true,
+                "status": "active",
+                "extra_headers": extra_headers
+            })
+        };
+        let items = json!([
+            item("good1", "https://example.com", Value::Null),
+            item("bad-url", "ftp://example.com/file", Value::Null),
+            item("__counter__", "https://example.com", Value::Null),
+            item(
+                "bad-head",
+                "https://example.com",
+                json!({"Location": "https://evil.example"})
+            ),
+        ]);
+        let request = Request::post("/api/admin/import")
+            .header(API_KEY_HEADER, "s3cret")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(items.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["imported"], 1);
+        let rejected: Vec<_> = body["rejected"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|rejection| {
+                (
+                    rejection["short_code"].as_str().unwrap(),
+                    rejection["error"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                ("bad-url", "InvalidUrl"),
+                ("__counter__", "ValidationError"),
+                ("bad-head", "ValidationError"),
+            ]
+        );
+        assert_eq!(put_rule.num_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_gzip_compressed_on_request() {
+        let request =
+            Request::get("/api/admin/urls?from=2024-01-01T00:00:00Z&to=2024-01-02T00:00:00Z")
+                .header(API_KEY_HEADER, "s3cret")
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+
+        let response = mock_app().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    }
 }
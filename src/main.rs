@@ -1,23 +1,20 @@
 mod local_dev_server;
 
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-}
+use squrl_shared::config::RuntimeConfig;
+use squrl_shared::telemetry::{init_tracing, LogFormat};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_tracing();
+    let runtime_config = RuntimeConfig::from_env()?;
+    let _telemetry = init_tracing(
+        "squrl-dev-server",
+        &runtime_config.log_filter_or("info"),
+        LogFormat::Text,
+    )?;
 
     tracing::info!("Starting sqURL local development server");
 
-    local_dev_server::run_dev_server().await?;
+    local_dev_server::run_dev_server(runtime_config).await?;
 
     Ok(())
 }
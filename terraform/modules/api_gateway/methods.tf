@@ -11,6 +11,13 @@ resource "aws_api_gateway_resource" "short_code" {
   path_part   = "{short_code}"
 }
 
+# Everything below a code, for wildcard links
+resource "aws_api_gateway_resource" "short_code_proxy" {
+  rest_api_id = aws_api_gateway_rest_api.squrl_api.id
+  parent_id   = aws_api_gateway_resource.short_code.id
+  path_part   = "{proxy+}"
+}
+
 resource "aws_api_gateway_resource" "stats" {
   rest_api_id = aws_api_gateway_rest_api.squrl_api.id
   parent_id   = aws_api_gateway_rest_api.squrl_api.root_resource_id
@@ -241,6 +248,37 @@ resource "aws_lambda_permission" "redirect_get" {
   source_arn = "${aws_api_gateway_rest_api.squrl_api.execution_arn}/*/*"
 }
 
+# ============================================================================
+# GET /{short_code}/{proxy+} - Wildcard redirect endpoint
+# ============================================================================
+
+resource "aws_api_gateway_method" "redirect_wildcard_get" {
+  rest_api_id   = aws_api_gateway_rest_api.squrl_api.id
+  resource_id   = aws_api_gateway_resource.short_code_proxy.id
+  http_method   = "GET"
+  authorization = "NONE"
+
+  request_parameters = {
+    "method.request.path.short_code" = true
+    "method.request.path.proxy"      = true
+  }
+}
+
+resource "aws_api_gateway_integration" "redirect_wildcard_get" {
+  rest_api_id = aws_api_gateway_rest_api.squrl_api.id
+  resource_id = aws_api_gateway_resource.short_code_proxy.id
+  http_method = aws_api_gateway_method.redirect_wildcard_get.http_method
+
+  integration_http_method = "POST"
+  type                    = "AWS_PROXY"
+  uri                     = var.redirect_lambda_invoke_arn
+
+  request_parameters = {
+    "integration.request.path.short_code" = "method.request.path.short_code"
+    "integration.request.path.proxy"      = "method.request.path.proxy"
+  }
+}
+
 # HEAD /{short_code} - Head method for redirect endpoint (for cached_methods support)
 resource "aws_api_gateway_method" "redirect_head" {
   rest_api_id   = aws_api_gateway_rest_api.squrl_api.id
@@ -5,6 +5,8 @@ resource "aws_api_gateway_deployment" "main" {
     aws_api_gateway_integration.create_post,
     aws_api_gateway_method.redirect_get,
     aws_api_gateway_integration.redirect_get,
+    aws_api_gateway_method.redirect_wildcard_get,
+    aws_api_gateway_integration.redirect_wildcard_get,
     aws_api_gateway_method.stats_get,
     aws_api_gateway_integration.stats_get,
     aws_api_gateway_method.options,
@@ -18,13 +20,16 @@ resource "aws_api_gateway_deployment" "main" {
     redeployment = sha1(jsonencode([
       aws_api_gateway_resource.create.id,
       aws_api_gateway_resource.short_code.id,
+      aws_api_gateway_resource.short_code_proxy.id,
       aws_api_gateway_resource.stats.id,
       aws_api_gateway_resource.stats_short_code.id,
       aws_api_gateway_method.create_post.id,
       aws_api_gateway_method.redirect_get.id,
+      aws_api_gateway_method.redirect_wildcard_get.id,
       aws_api_gateway_method.stats_get.id,
       aws_api_gateway_integration.create_post.id,
       aws_api_gateway_integration.redirect_get.id,
+      aws_api_gateway_integration.redirect_wildcard_get.id,
       aws_api_gateway_integration.stats_get.id,
       aws_api_gateway_model.create_url_request.id,
       aws_api_gateway_model.create_url_response.id,
@@ -55,7 +55,8 @@ resource "aws_cloudfront_cache_policy" "redirect" {
       headers {
         items = [
           "User-Agent", # For analytics
-          "Referer"     # For analytics
+          "Referer",    # For analytics
+          "Accept"      # JSON resolution vs redirect, normalized to two values by normalize_accept
         ]
       }
     }
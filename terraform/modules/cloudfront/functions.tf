@@ -0,0 +1,46 @@
+# Collapse Accept to one of two values before it reaches the cache key.
+# Redirects only differ between JSON resolution and everything else, so
+# keying on the raw header would split the cache per browser.
+# Mirrors prefers_json in shared/src/models.rs.
+resource "aws_cloudfront_function" "normalize_accept" {
+  name    = "squrl-normalize-accept-${var.environment}"
+  runtime = "cloudfront-js-2.0"
+  comment = "Reduce Accept to application/json or text/html for redirect caching"
+  publish = true
+  code    = <<-JS
+    function quality(accept, mediaType) {
+      var best = null;
+      accept.split(',').forEach(function (range) {
+        var params = range.split(';');
+        if (params[0].trim().toLowerCase() !== mediaType) {
+          return;
+        }
+        var q = 1.0;
+        params.slice(1).forEach(function (param) {
+          param = param.trim();
+          if (param.indexOf('q=') === 0) {
+            var parsed = parseFloat(param.slice(2));
+            if (!isNaN(parsed)) {
+              q = parsed;
+            }
+          }
+        });
+        if (best === null || q > best) {
+          best = q;
+        }
+      });
+      return best;
+    }
+
+    function handler(event) {
+      var headers = event.request.headers;
+      var accept = headers.accept ? headers.accept.value : '';
+      var json = quality(accept, 'application/json');
+      var html = quality(accept, 'text/html');
+      var wantsJson = json !== null && json > 0 && (html === null || json > html);
+
+      headers.accept = { value: wantsJson ? 'application/json' : 'text/html' };
+      return event.request;
+    }
+  JS
+}
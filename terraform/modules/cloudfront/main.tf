@@ -100,6 +100,11 @@ resource "aws_cloudfront_distribution" "main" {
     cache_policy_id            = aws_cloudfront_cache_policy.redirect.id
     origin_request_policy_id   = aws_cloudfront_origin_request_policy.redirect.id
     response_headers_policy_id = aws_cloudfront_response_headers_policy.security_headers.id
+
+    function_association {
+      event_type   = "viewer-request"
+      function_arn = aws_cloudfront_function.normalize_accept.arn
+    }
   }
 
   # Cache behavior for /api/* paths - catch remaining API paths
@@ -113,6 +118,11 @@ resource "aws_cloudfront_distribution" "main" {
     cache_policy_id            = aws_cloudfront_cache_policy.redirect.id
     origin_request_policy_id   = aws_cloudfront_origin_request_policy.redirect.id
     response_headers_policy_id = aws_cloudfront_response_headers_policy.security_headers.id
+
+    function_association {
+      event_type   = "viewer-request"
+      function_arn = aws_cloudfront_function.normalize_accept.arn
+    }
   }
 
 
@@ -162,11 +162,14 @@ async fn test_basic_url_creation(client: &mut TestClient) -> Result<(), TestErro
         return Err(TestError::ValidationError("Invalid short URL format".to_string()));
     }
     
-    // Check that the short URL contains the short code
-    if !response.short_url.contains(&response.short_code) {
-        return Err(TestError::ValidationError(
-            "Short URL doesn't contain short code".to_string(),
-        ));
+    // Check that the short URL ends in exactly the short code
+    if utils::parse_short_code_from_url(&response.short_url).as_deref()
+        != Some(response.short_code.as_str())
+    {
+        return Err(TestError::ValidationError(format!(
+            "Short URL {} doesn't end in short code {}",
+            response.short_url, response.short_code
+        )));
     }
     
     info!("Created short URL: {} -> {}", response.short_url, test_url);
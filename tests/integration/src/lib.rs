@@ -5,6 +5,9 @@ use std::time::{Duration, Instant};
 use tokio::time::sleep;
 use url::Url;
 
+/// Header carrying the API key on admin requests
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
 /// Configuration for integration tests
 #[derive(Debug, Clone)]
 pub struct TestConfig {
@@ -20,6 +23,8 @@ pub struct TestConfig {
     pub max_request_rate: u32,
     /// Test timeout in seconds
     pub timeout_seconds: u64,
+    /// Key sent as `X-Api-Key` on admin requests; public endpoints never get it
+    pub api_key: Option<String>,
 }
 
 impl Default for TestConfig {
@@ -41,6 +46,7 @@ impl Default for TestConfig {
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(30),
+            api_key: std::env::var("API_KEY").ok().filter(|key| !key.is_empty()),
         }
     }
 }
@@ -59,6 +65,9 @@ pub struct CreateUrlResponse {
     pub short_url: String,
     pub short_code: String,
     pub expires_at: String,
+    /// `"user"`, `"default"` or `"none"`
+    #[serde(default)]
+    pub ttl_source: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -67,6 +76,25 @@ pub struct StatsResponse {
     pub clicks: u64,
     pub created_at: String,
     pub expires_at: Option<String>,
+    /// `(bucket, clicks)` pairs, oldest first, when the server reports them
+    #[serde(default)]
+    pub time_series: Option<Vec<(String, u64)>>,
+    #[serde(default)]
+    pub last_accessed: Option<String>,
+}
+
+impl StatsResponse {
+    /// Clicks are counted eventually, so tests assert a floor, not an exact
+    /// value
+    pub fn assert_clicks_at_least(&self, n: u64) {
+        assert!(
+            self.clicks >= n,
+            "expected at least {} clicks for {}, got {}",
+            n,
+            self.short_code,
+            self.clicks
+        );
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -77,10 +105,52 @@ pub struct ErrorResponse {
     pub details: Option<serde_json::Value>,
 }
 
+/// Retry policy for transient gateway failures (502/503/504) and connection
+/// errors, e.g. while a deploy swaps Lambda versions
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the given retry (1-based) plus up to one
+    /// `base_delay` of jitter so parallel tests don't retry in lockstep
+    pub fn delay_for(&self, retry: u32) -> Duration {
+        use rand::Rng;
+
+        let backoff = self.base_delay * 2u32.saturating_pow(retry.saturating_sub(1));
+        let jitter_ms = rand::thread_rng().gen_range(0..=self.base_delay.as_millis() as u64);
+        backoff + Duration::from_millis(jitter_ms)
+    }
+
+    fn is_retryable(result: &Result<reqwest::Response, reqwest::Error>) -> bool {
+        match result {
+            Ok(response) => matches!(response.status().as_u16(), 502..=504),
+            Err(e) => e.is_connect(),
+        }
+    }
+}
+
 /// Test client with built-in rate limiting and error handling
 pub struct TestClient {
     client: Client,
+    /// Same settings as `client` but never follows redirects, so the 301/302
+    /// itself can be inspected. Built once to keep its connection pool.
+    no_redirect_client: Client,
     config: TestConfig,
+    retry_policy: RetryPolicy,
     request_history: Vec<RequestRecord>,
 }
 
@@ -101,35 +171,90 @@ impl TestClient {
             .build()
             .expect("Failed to create HTTP client");
 
+        let no_redirect_client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(Duration::from_secs(config.timeout_seconds))
+            .build()
+            .expect("Failed to create no-redirect HTTP client");
+
         Self {
             client,
+            no_redirect_client,
             config,
+            retry_policy: RetryPolicy::default(),
             request_history: Vec::new(),
         }
     }
 
+    /// Retry GETs and creates on transient failures (off by default)
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     pub fn config(&self) -> &TestConfig {
         &self.config
     }
 
+    /// Request to an admin endpoint, carrying the configured API key
+    pub fn admin_request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.config.api_key {
+            Some(api_key) => request.header(API_KEY_HEADER, api_key),
+            None => request,
+        }
+    }
+
+    /// Send a request built by `build`, retrying per `retry_policy`. Every
+    /// attempt lands in `request_history`; connection failures as status 0.
+    async fn send_with_retry(
+        &mut self,
+        method: &str,
+        path: &str,
+        build: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, TestError> {
+        let mut retries = 0;
+
+        loop {
+            let start = Instant::now();
+            let result = build().send().await;
+
+            let status = result.as_ref().map_or(0, |r| r.status().as_u16());
+            self.record_request(method, path, status, start.elapsed());
+
+            if retries < self.retry_policy.max_retries && RetryPolicy::is_retryable(&result) {
+                retries += 1;
+                let delay = self.retry_policy.delay_for(retries);
+                tracing::warn!(
+                    "{} {} failed with {}, retry {}/{} in {:?}",
+                    method,
+                    path,
+                    status,
+                    retries,
+                    self.retry_policy.max_retries,
+                    delay
+                );
+                sleep(delay).await;
+                continue;
+            }
+
+            return result.map_err(TestError::Http);
+        }
+    }
+
     /// Create a shortened URL
     pub async fn create_url(
         &mut self,
         request: CreateUrlRequest,
     ) -> Result<CreateUrlResponse, TestError> {
-        let start = Instant::now();
         let url = format!("{}/create", self.config.base_url);
+        let client = self.client.clone();
 
         let response = self
-            .client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(TestError::Http)?;
+            .send_with_retry("POST", "/create", || client.post(&url).json(&request))
+            .await?;
 
         let status = response.status().as_u16();
-        self.record_request("POST", "/create", status, start.elapsed());
 
         match response.status().as_u16() {
             200..=299 => {
@@ -158,24 +283,14 @@ impl TestClient {
 
     /// Test redirect functionality
     pub async fn test_redirect(&mut self, short_code: &str) -> Result<String, TestError> {
-        let start = Instant::now();
         let url = format!("{}/{}", self.config.cloudfront_url, short_code);
+        let client = self.no_redirect_client.clone();
 
-        // Use a client that doesn't follow redirects to test the redirect response
-        let no_redirect_client = Client::builder()
-            .redirect(reqwest::redirect::Policy::none())
-            .timeout(Duration::from_secs(self.config.timeout_seconds))
-            .build()
-            .map_err(TestError::Http)?;
-
-        let response = no_redirect_client
-            .get(&url)
-            .send()
-            .await
-            .map_err(TestError::Http)?;
+        let response = self
+            .send_with_retry("GET", &format!("/{}", short_code), || client.get(&url))
+            .await?;
 
         let status = response.status().as_u16();
-        self.record_request("GET", &format!("/{}", short_code), status, start.elapsed());
 
         match status {
             301 | 302 => {
@@ -207,18 +322,16 @@ impl TestClient {
 
     /// Get statistics for a short code
     pub async fn get_stats(&mut self, short_code: &str) -> Result<StatsResponse, TestError> {
-        let start = Instant::now();
         let url = format!("{}/stats/{}", self.config.base_url, short_code);
+        let client = self.client.clone();
 
         let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .map_err(TestError::Http)?;
+            .send_with_retry("GET", &format!("/stats/{}", short_code), || {
+                client.get(&url)
+            })
+            .await?;
 
         let status = response.status().as_u16();
-        self.record_request("GET", &format!("/stats/{}", short_code), status, start.elapsed());
 
         match status {
             200 => {
@@ -313,6 +426,10 @@ impl TestClient {
                 .get("x-cache")
                 .and_then(|v| v.to_str().ok())
                 .map(|s| s.to_string()),
+            cloudfront_pop: headers
+                .get("x-amz-cf-pop")
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string()),
             age: headers
                 .get("age")
                 .and_then(|v| v.to_str().ok())
@@ -344,14 +461,61 @@ impl TestClient {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CacheInfo {
     pub cache_control: Option<String>,
+    /// CloudFront's `x-cache`, e.g. `"Hit from cloudfront"`
     pub cloudfront_cache_status: Option<String>,
+    /// CloudFront's `x-amz-cf-pop`, e.g. `"IAD89-C1"`
+    pub cloudfront_pop: Option<String>,
     pub age: Option<u64>,
     pub expires: Option<String>,
 }
 
+impl CacheInfo {
+    /// First word of `x-cache`, lowercased: `hit`, `refreshhit`, `miss`,
+    /// `error`, `redirect`, `limitexceeded`, ...
+    fn cache_result(&self) -> Option<String> {
+        self.cloudfront_cache_status
+            .as_deref()?
+            .split_whitespace()
+            .next()
+            .map(str::to_ascii_lowercase)
+    }
+
+    /// Served from the edge cache, including `RefreshHit` (revalidated with
+    /// the origin, which answered 304)
+    pub fn is_hit(&self) -> bool {
+        matches!(self.cache_result().as_deref(), Some("hit" | "refreshhit"))
+    }
+
+    /// Forwarded to the origin because the edge had no usable copy
+    pub fn is_miss(&self) -> bool {
+        self.cache_result().as_deref() == Some("miss")
+    }
+
+    /// The edge location that answered, from `x-amz-cf-pop` (`"IAD89-C1"`),
+    /// else from an `x-cache` that names one after "from"
+    /// (`"Hit from cloudfront (IAD89-C1)"`). Plain `"from cloudfront"`
+    /// names no location.
+    pub fn edge_location(&self) -> Option<String> {
+        if let Some(pop) = self
+            .cloudfront_pop
+            .as_deref()
+            .map(str::trim)
+            .filter(|pop| !pop.is_empty())
+        {
+            return Some(pop.to_string());
+        }
+
+        let status = self.cloudfront_cache_status.as_deref()?;
+        let (_, source) = status.split_once(" from ")?;
+        let (_, location) = source.split_once('(')?;
+        let location = location.trim_end_matches(')').trim();
+        (!location.is_empty()).then(|| location.to_string())
+    }
+}
+
 /// Test error types
 #[derive(Debug)]
 pub enum TestError {
@@ -384,6 +548,31 @@ impl std::fmt::Display for TestError {
 
 impl std::error::Error for TestError {}
 
+impl TestError {
+    /// HTTP status behind this error, if the server answered at all
+    pub fn status_code(&self) -> Option<u16> {
+        match self {
+            TestError::Api(status, _) => Some(*status),
+            TestError::RateLimit(_) => Some(429),
+            TestError::NotFound => Some(404),
+            TestError::Http(e) | TestError::Parsing(e) => e.status().map(|s| s.as_u16()),
+            _ => None,
+        }
+    }
+
+    pub fn is_conflict(&self) -> bool {
+        self.status_code() == Some(409)
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        self.status_code() == Some(429)
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status_code() == Some(404)
+    }
+}
+
 /// Utility functions for tests
 pub mod utils {
     use super::*;
@@ -414,38 +603,87 @@ pub mod utils {
         Url::parse(url).is_ok()
     }
 
-    /// Calculate requests per second from request history
-    pub fn calculate_rps(requests: &[RequestRecord], window_seconds: u64) -> f64 {
-        if requests.is_empty() {
-            return 0.0;
-        }
+    /// The short code in `short_url`: its last non-empty path segment, so a
+    /// trailing slash, query string or fragment doesn't matter. `None` for
+    /// an unparseable URL or one with no path.
+    pub fn parse_short_code_from_url(short_url: &str) -> Option<String> {
+        let url = Url::parse(short_url).ok()?;
+        url.path_segments()?
+            .rev()
+            .find(|segment| !segment.is_empty())
+            .map(str::to_string)
+    }
 
-        let now = Instant::now();
-        let window_start = now - Duration::from_secs(window_seconds);
+    /// Requests seen in a window and the rate they arrived at
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct RpsSample {
+        pub count: usize,
+        pub rate: f64,
+    }
 
-        let recent_requests: Vec<_> = requests
+    /// Calculate requests per second from request history.
+    ///
+    /// The rate uses the time actually spanned by the in-window requests, so
+    /// a short burst isn't averaged out over the whole window. A single
+    /// request (or a span of ~0) falls back to `window_seconds`.
+    pub fn calculate_rps(requests: &[RequestRecord], window_seconds: u64) -> RpsSample {
+        let now = Instant::now();
+        let window = Duration::from_secs(window_seconds);
+        let in_window: Vec<Instant> = requests
             .iter()
-            .filter(|r| r.timestamp >= window_start)
+            .map(|r| r.timestamp)
+            .filter(|&t| now.saturating_duration_since(t) <= window)
             .collect();
 
-        recent_requests.len() as f64 / window_seconds as f64
+        let count = in_window.len();
+        let (Some(oldest), Some(newest)) = (in_window.iter().min(), in_window.iter().max()) else {
+            return RpsSample { count, rate: 0.0 };
+        };
+
+        let span = newest.duration_since(*oldest);
+        let divisor = if span < Duration::from_millis(1) {
+            window_seconds.max(1) as f64
+        } else {
+            span.as_secs_f64()
+        };
+
+        RpsSample {
+            count,
+            rate: count as f64 / divisor,
+        }
+    }
+
+    /// Response time percentiles in milliseconds
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct Percentiles {
+        pub p50: u64,
+        pub p90: u64,
+        pub p95: u64,
+        pub p99: u64,
     }
 
-    /// Calculate response time percentiles
-    pub fn calculate_percentiles(requests: &[RequestRecord]) -> (u64, u64, u64) {
+    /// Calculate response time percentiles (nearest-rank method)
+    pub fn calculate_percentiles(requests: &[RequestRecord]) -> Percentiles {
         if requests.is_empty() {
-            return (0, 0, 0);
+            return Percentiles::default();
         }
 
         let mut response_times: Vec<u64> = requests.iter().map(|r| r.response_time_ms).collect();
         response_times.sort();
 
-        let len = response_times.len();
-        let p50 = response_times[len * 50 / 100];
-        let p95 = response_times[len * 95 / 100];
-        let p99 = response_times[len * 99 / 100];
+        // Nearest rank: the smallest value with at least p% of samples at or below it
+        let nearest_rank = |p: f64| {
+            let n = response_times.len();
+            let rank = (p / 100.0 * n as f64).ceil() as usize;
+            response_times[rank.saturating_sub(1).min(n - 1)]
+        };
 
-        (p50, p95, p99)
+        Percentiles {
+            p50: nearest_rank(50.0),
+            p90: nearest_rank(90.0),
+            p95: nearest_rank(95.0),
+            p99: nearest_rank(99.0),
+        }
     }
 
     /// Wait for a specified duration with logging
@@ -574,6 +812,58 @@ mod tests {
         assert_eq!(config.environment, "test");
     }
 
+    fn cache_info(x_cache: Option<&str>, pop: Option<&str>) -> CacheInfo {
+        CacheInfo {
+            cloudfront_cache_status: x_cache.map(str::to_string),
+            cloudfront_pop: pop.map(str::to_string),
+            ..CacheInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_hit_and_miss() {
+        for status in ["Hit from cloudfront", "RefreshHit from cloudfront", "hit"] {
+            let info = cache_info(Some(status), None);
+            assert!(info.is_hit(), "{}", status);
+            assert!(!info.is_miss(), "{}", status);
+        }
+
+        let miss = cache_info(Some("Miss from cloudfront"), None);
+        assert!(miss.is_miss());
+        assert!(!miss.is_hit());
+
+        for status in [
+            Some("Error from cloudfront"),
+            Some("LimitExceeded from cloudfront"),
+            Some(""),
+            None,
+        ] {
+            let info = cache_info(status, None);
+            assert!(!info.is_hit() && !info.is_miss(), "{:?}", status);
+        }
+    }
+
+    #[test]
+    fn test_cache_edge_location() {
+        assert_eq!(
+            cache_info(Some("Hit from cloudfront"), Some("IAD89-C1"))
+                .edge_location()
+                .as_deref(),
+            Some("IAD89-C1")
+        );
+        assert_eq!(
+            cache_info(Some("Miss from cloudfront (LHR61-P3)"), None)
+                .edge_location()
+                .as_deref(),
+            Some("LHR61-P3")
+        );
+        assert_eq!(
+            cache_info(Some("Hit from cloudfront"), Some(" ")).edge_location(),
+            None
+        );
+        assert_eq!(cache_info(None, None).edge_location(), None);
+    }
+
     #[test]
     fn test_url_validation() {
         assert!(utils::is_valid_url("https://example.com"));
@@ -582,6 +872,62 @@ mod tests {
         assert!(!utils::is_valid_url(""));
     }
 
+    #[test]
+    fn test_parse_short_code_from_url() {
+        for url in [
+            "https://sqrl.co/abc123",
+            "https://sqrl.co/abc123/",
+            "https://sqrl.co/abc123?utm_source=test",
+            "https://sqrl.co/abc123/?ref=x#top",
+            "https://example.com/go/abc123",
+        ] {
+            assert_eq!(
+                utils::parse_short_code_from_url(url).as_deref(),
+                Some("abc123"),
+                "{}",
+                url
+            );
+        }
+
+        assert_eq!(utils::parse_short_code_from_url("https://sqrl.co/"), None);
+        assert_eq!(utils::parse_short_code_from_url("https://sqrl.co"), None);
+        assert_eq!(utils::parse_short_code_from_url("not a url"), None);
+    }
+
+    #[test]
+    fn test_stats_response_with_time_series() {
+        let stats: StatsResponse = serde_json::from_value(serde_json::json!({
+            "short_code": "abc123",
+            "clicks": 3,
+            "created_at": "2024-01-01T00:00:00Z",
+            "expires_at": null,
+            "time_series": [["2024-01-01", 1], ["2024-01-02", 2]],
+            "last_accessed": "2024-01-02T12:00:00Z"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            stats.time_series,
+            Some(vec![
+                ("2024-01-01".to_string(), 1),
+                ("2024-01-02".to_string(), 2)
+            ])
+        );
+        assert_eq!(stats.last_accessed.as_deref(), Some("2024-01-02T12:00:00Z"));
+        stats.assert_clicks_at_least(3);
+
+        // Older servers leave both out
+        let stats: StatsResponse = serde_json::from_value(serde_json::json!({
+            "short_code": "abc123",
+            "clicks": 0,
+            "created_at": "2024-01-01T00:00:00Z",
+            "expires_at": null
+        }))
+        .unwrap();
+        assert_eq!(stats.time_series, None);
+        assert_eq!(stats.last_accessed, None);
+    }
+
     #[test]
     fn test_random_generators() {
         let url1 = utils::random_test_url();
@@ -596,4 +942,357 @@ mod tests {
         assert_eq!(code1.len(), 8);
         assert_eq!(code2.len(), 8);
     }
-}
\ No newline at end of file
+
+    fn error_body(error: &str) -> ErrorResponse {
+        ErrorResponse {
+            error: error.to_string(),
+            message: "test".to_string(),
+            details: None,
+        }
+    }
+
+    #[test]
+    fn test_error_status_predicates() {
+        let conflict = TestError::Api(409, error_body("ConflictError"));
+        assert_eq!(conflict.status_code(), Some(409));
+        assert!(conflict.is_conflict());
+        assert!(!conflict.is_not_found());
+
+        let rate_limited = TestError::RateLimit(error_body("RateLimitError"));
+        assert_eq!(rate_limited.status_code(), Some(429));
+        assert!(rate_limited.is_rate_limited());
+        assert!(!rate_limited.is_conflict());
+
+        assert!(TestError::NotFound.is_not_found());
+        assert!(TestError::Api(404, error_body("NotFound")).is_not_found());
+
+        assert_eq!(TestError::Timeout.status_code(), None);
+        assert!(!TestError::MissingRedirectLocation.is_not_found());
+        assert!(!TestError::ValidationError("bad".to_string()).is_conflict());
+    }
+
+    /// Minimal keep-alive HTTP server answering every request with a 302.
+    /// Returns its base URL and a counter of accepted TCP connections.
+    async fn spawn_redirect_server(
+        respond: bool,
+    ) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    if !respond {
+                        sleep(Duration::from_secs(5)).await;
+                        return;
+                    }
+
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = socket.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        let response = "HTTP/1.1 302 Found\r\nLocation: https://example.com/\r\nContent-Length: 0\r\n\r\n";
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        (format!("http://{}", addr), connections)
+    }
+
+    fn local_config(cloudfront_url: String, timeout_seconds: u64) -> TestConfig {
+        TestConfig {
+            base_url: cloudfront_url.clone(),
+            cloudfront_url,
+            environment: "test".to_string(),
+            run_load_tests: false,
+            max_request_rate: 100,
+            timeout_seconds,
+            api_key: None,
+        }
+    }
+
+    #[test]
+    fn test_admin_requests_carry_api_key_when_configured() {
+        let url = "http://localhost/api/admin/count";
+
+        let anonymous = TestClient::new(local_config("http://localhost".to_string(), 5));
+        let request = anonymous
+            .admin_request(reqwest::Method::GET, url)
+            .build()
+            .unwrap();
+        assert!(request.headers().get(API_KEY_HEADER).is_none());
+
+        let mut config = local_config("http://localhost".to_string(), 5);
+        config.api_key = Some("secret-key".to_string());
+        let client = TestClient::new(config);
+        let request = client
+            .admin_request(reqwest::Method::GET, url)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[API_KEY_HEADER], "secret-key");
+
+    }
+
+    #[tokio::test]
+    async fn test_public_requests_never_carry_api_key() {
+        let (url, requests) = spawn_recording_server().await;
+        let mut config = local_config(url, 5);
+        config.api_key = Some("secret-key".to_string());
+        let mut client = TestClient::new(config);
+
+        client.test_redirect("abc123").await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].to_ascii_lowercase().contains("x-api-key"));
+    }
+
+    /// Answers every request with a 302 and keeps the raw request heads
+    async fn spawn_recording_server() -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::sync::{Arc, Mutex};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = socket.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        recorded.lock().unwrap().push(String::from_utf8_lossy(&buf[..n]).into_owned());
+                        let response = "HTTP/1.1 302 Found\r\nLocation: https://example.com/\r\nContent-Length: 0\r\n\r\n";
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        (format!("http://{}", addr), requests)
+    }
+
+    #[tokio::test]
+    async fn test_redirects_reuse_pooled_connection() {
+        let (url, connections) = spawn_redirect_server(true).await;
+        let mut client = TestClient::new(local_config(url, 5));
+
+        let start = Instant::now();
+        for _ in 0..20 {
+            let location = client.test_redirect("abc123").await.unwrap();
+            assert_eq!(location, "https://example.com/");
+        }
+        tracing::info!("20 redirects took {:?}", start.elapsed());
+
+        // A fresh client per call would have opened 20 connections
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(client.get_request_history().len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_redirect_client_honours_timeout() {
+        let (url, _) = spawn_redirect_server(false).await;
+        let mut client = TestClient::new(local_config(url, 1));
+
+        match client.test_redirect("abc123").await {
+            Err(TestError::Http(e)) => assert!(e.is_timeout()),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    /// Serves the scripted raw HTTP responses in order, one per request
+    async fn spawn_scripted_server(responses: Vec<String>) -> String {
+        use std::collections::VecDeque;
+        use std::sync::{Arc, Mutex};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responses = Arc::new(Mutex::new(VecDeque::from(responses)));
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let responses = responses.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = socket.read(&mut buf).await {
+                        let Some(response) = (n > 0)
+                            .then(|| responses.lock().unwrap().pop_front())
+                            .flatten()
+                        else {
+                            break;
+                        };
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+    fn created_response() -> String {
+        let body = r#"{"short_url":"https://sqrl.co/abc123","short_code":"abc123","expires_at":"never"}"#;
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    fn fast_retries(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_retries_transient_failures() {
+        let url = spawn_scripted_server(vec![
+            UNAVAILABLE.to_string(),
+            UNAVAILABLE.to_string(),
+            created_response(),
+        ]).await;
+        let mut client =
+            TestClient::new(local_config(url, 5)).with_retry_policy(fast_retries(3));
+
+        let response = client
+            .create_url(CreateUrlRequest {
+                url: "https://example.com".to_string(),
+                custom_code: None,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(response.short_code, "abc123");
+        let statuses: Vec<u16> = client
+            .get_request_history()
+            .iter()
+            .map(|r| r.status)
+            .collect();
+        assert_eq!(statuses, [503, 503, 200]);
+    }
+
+    #[tokio::test]
+    async fn test_retries_surface_final_error_when_exhausted() {
+        let url = spawn_scripted_server(vec![
+            UNAVAILABLE.to_string(),
+            UNAVAILABLE.to_string(),
+            created_response(),
+        ]).await;
+        let mut client =
+            TestClient::new(local_config(url, 5)).with_retry_policy(fast_retries(1));
+
+        match client.get_stats("abc123").await {
+            Err(TestError::Api(503, _)) => {}
+            other => panic!("expected a 503, got {:?}", other),
+        }
+        assert_eq!(client.get_request_history().len(), 2);
+    }
+
+    fn records_with_times(times: &[u64]) -> Vec<RequestRecord> {
+        times
+            .iter()
+            .map(|&ms| RequestRecord {
+                timestamp: Instant::now(),
+                method: "GET".to_string(),
+                path: "/".to_string(),
+                status: 200,
+                response_time_ms: ms,
+                ip_address: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_percentiles_single_sample() {
+        let p = utils::calculate_percentiles(&records_with_times(&[42]));
+        assert_eq!((p.p50, p.p90, p.p95, p.p99), (42, 42, 42, 42));
+    }
+
+    #[test]
+    fn test_percentiles_two_samples() {
+        let p = utils::calculate_percentiles(&records_with_times(&[20, 10]));
+        assert_eq!(p.p50, 10);
+        assert_eq!(p.p90, 20);
+        assert_eq!(p.p99, 20);
+    }
+
+    #[test]
+    fn test_percentiles_known_distribution() {
+        // 1..=100 ms: the p-th percentile is exactly p
+        let times: Vec<u64> = (1..=100).rev().collect();
+        let p = utils::calculate_percentiles(&records_with_times(&times));
+        assert_eq!(
+            p,
+            utils::Percentiles {
+                p50: 50,
+                p90: 90,
+                p95: 95,
+                p99: 99,
+            }
+        );
+        assert_eq!(utils::calculate_percentiles(&[]), utils::Percentiles::default());
+    }
+
+    fn records_at(timestamps: &[Instant]) -> Vec<RequestRecord> {
+        timestamps
+            .iter()
+            .map(|&timestamp| RequestRecord {
+                timestamp,
+                ..records_with_times(&[0]).remove(0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rps_uses_actual_span_for_bursts() {
+        // 10 requests spread over 500ms of a 10s window
+        let now = Instant::now();
+        let timestamps: Vec<Instant> = (0..10)
+            .map(|i| now - Duration::from_millis(500) + Duration::from_micros(i * 500_000 / 9))
+            .collect();
+
+        let sample = utils::calculate_rps(&records_at(&timestamps), 10);
+
+        assert_eq!(sample.count, 10);
+        assert!((sample.rate - 20.0).abs() < 0.5, "rate was {}", sample.rate);
+    }
+
+    #[test]
+    fn test_rps_ignores_requests_outside_window_and_handles_zero_span() {
+        let now = Instant::now();
+        let timestamps = [now - Duration::from_secs(30), now, now];
+
+        let sample = utils::calculate_rps(&records_at(&timestamps), 10);
+
+        // Two simultaneous requests: no measurable span, so use the window
+        assert_eq!(sample.count, 2);
+        assert_eq!(sample.rate, 0.2);
+        assert_eq!(utils::calculate_rps(&[], 10).rate, 0.0);
+    }
+}